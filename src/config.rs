@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::errors::{ErrorKind, Result};
 use crate::url::TremorURL;
 use hashbrown::HashMap;
+use serde_yaml::Value as YamlValue;
+use tremor_pipeline::query::Query;
 
 pub(crate) type ID = String;
 pub(crate) type OnRampVec = Vec<OnRamp>;
@@ -72,6 +75,9 @@ pub struct OnRamp {
     pub(crate) metrics_interval_s: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) config: tremor_pipeline::ConfigMap,
+    /// the artefact as published, before references were resolved
+    #[serde(skip)]
+    pub(crate) template: Option<YamlValue>,
 }
 
 /// Configuration of an offramp
@@ -110,6 +116,9 @@ pub struct OffRamp {
     pub(crate) metrics_interval_s: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) config: tremor_pipeline::ConfigMap,
+    /// the artefact as published, before references were resolved
+    #[serde(skip)]
+    pub(crate) template: Option<YamlValue>,
 }
 
 /// Configuration for a Binding
@@ -121,4 +130,371 @@ pub struct Binding {
     #[serde(default = "Default::default")]
     pub(crate) description: String,
     pub(crate) links: BindingMap, // is this right? this should be url to url?
+    /// the links as published, before references were resolved
+    #[serde(skip)]
+    pub(crate) template: Option<BindingMap>,
+}
+
+macro_rules! interpolated_artefact {
+    ($artefact:ident) => {
+        impl $artefact {
+            /// Resolves `${env:..}` and `${file:..}` references in the artefact
+            pub(crate) fn interpolate(self) -> Result<Self> {
+                let template = serde_yaml::to_value(&self)?;
+                let mut resolved = template.clone();
+                let paths = interpolate_value(&mut resolved, "")?;
+                if paths.is_empty() {
+                    Ok(self)
+                } else {
+                    // the error would quote the resolved value, so we only
+                    // name the fields it came from
+                    let mut artefact: Self = serde_yaml::from_value(resolved).map_err(|_| {
+                        ErrorKind::InterpolationError(
+                            paths.join(", "),
+                            "the resolved value is invalid here".to_string(),
+                        )
+                    })?;
+                    artefact.template = Some(template);
+                    Ok(artefact)
+                }
+            }
+
+            /// Returns the artefact with all resolved references redacted,
+            /// that is replaced by the reference they were resolved from
+            #[must_use]
+            pub fn redacted(&self) -> Self {
+                if let Some(template) = &self.template {
+                    // the template was serialized from `Self` so this can't fail,
+                    // if it does anyway we leave out the config rather than leak it
+                    serde_yaml::from_value(template.clone()).unwrap_or_else(|_| Self {
+                        config: None,
+                        template: None,
+                        ..self.clone()
+                    })
+                } else {
+                    self.clone()
+                }
+            }
+        }
+    };
+}
+
+interpolated_artefact!(OnRamp);
+interpolated_artefact!(OffRamp);
+
+impl Binding {
+    /// Resolves `${env:..}` and `${file:..}` references in the links
+    pub(crate) fn interpolate(self) -> Result<Self> {
+        let mut links = BindingMap::new();
+        let mut resolved = false;
+        for (src, dsts) in &self.links {
+            let mut tos = Vec::with_capacity(dsts.len());
+            for dst in dsts {
+                let to = interpolate_url(dst)?;
+                resolved |= to.is_some();
+                tos.push(to.unwrap_or_else(|| dst.clone()));
+            }
+            let from = interpolate_url(src)?;
+            resolved |= from.is_some();
+            links.insert(from.unwrap_or_else(|| src.clone()), tos);
+        }
+        if resolved {
+            Ok(Self {
+                links,
+                template: Some(self.links.clone()),
+                ..self
+            })
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Returns the binding with all resolved references redacted,
+    /// that is replaced by the reference they were resolved from
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        if let Some(template) = &self.template {
+            Self {
+                links: template.clone(),
+                template: None,
+                ..self.clone()
+            }
+        } else {
+            self.clone()
+        }
+    }
+}
+
+/// Resolves references in an url, the braces of a reference are percent
+/// encoded in a parsed url. Returns `None` if there are no references.
+fn interpolate_url(url: &TremorURL) -> Result<Option<TremorURL>> {
+    let template = url.to_string().replace("%7B", "{").replace("%7D", "}");
+    if let Some(resolved) = interpolate_str(&template)? {
+        // the error would quote the resolved url
+        TremorURL::parse(&resolved).map(Some).map_err(|_| {
+            ErrorKind::InterpolationError(template, "the resolved url is invalid".to_string())
+                .into()
+        })
+    } else {
+        Ok(None)
+    }
+}
+
+/// Resolves `${env:..}` and `${file:..}` references in the source of a
+/// query, resolved values are escaped so they can be used in string
+/// literals. The query is compiled from the resolved source but keeps the
+/// source with the references, so resolved values are never handed out.
+pub(crate) fn interpolate_query(query: Query) -> Result<Query> {
+    if let Some(resolved) = interpolate_escaped(query.source(), escape_string_literal)? {
+        let module_path = tremor_script::path::load();
+        let aggr_reg = tremor_script::aggr_registry();
+        // the error would quote the resolved source
+        let mut resolved = Query::parse(
+            &module_path,
+            &resolved,
+            "<interpolated>",
+            Vec::new(),
+            &*tremor_pipeline::FN_REGISTRY.lock()?,
+            &aggr_reg,
+        )
+        .map_err(|_| {
+            ErrorKind::InterpolationError(
+                query.id().unwrap_or("query").to_string(),
+                "the query is invalid with the resolved references".to_string(),
+            )
+        })?;
+        resolved.0.source = query.0.source;
+        Ok(resolved)
+    } else {
+        Ok(query)
+    }
+}
+
+/// Escapes a resolved value for a trickle string literal
+fn escape_string_literal(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' | '"' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Resolves references in all strings of `value`, returns the paths of
+/// the resolved fields
+fn interpolate_value(value: &mut YamlValue, path: &str) -> Result<Vec<String>> {
+    let mut resolved = Vec::new();
+    match value {
+        YamlValue::String(s) => {
+            if let Some(r) = interpolate_str(s)? {
+                *s = r;
+                resolved.push(path.to_string());
+            }
+        }
+        YamlValue::Sequence(values) => {
+            for (i, v) in values.iter_mut().enumerate() {
+                resolved.append(&mut interpolate_value(v, &format!("{}[{}]", path, i))?);
+            }
+        }
+        YamlValue::Mapping(values) => {
+            for (k, v) in values.iter_mut() {
+                let key = match k {
+                    YamlValue::String(k) => k.clone(),
+                    YamlValue::Number(k) => k.to_string(),
+                    _ => String::from("?"),
+                };
+                let path = if path.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                resolved.append(&mut interpolate_value(v, &path)?);
+            }
+        }
+        YamlValue::Null | YamlValue::Bool(_) | YamlValue::Number(_) => (),
+    }
+    Ok(resolved)
+}
+
+/// Resolves `${env:NAME}` and `${file:/path}` references in a string, a
+/// default for unset variables or missing files can be given as
+/// `${env:NAME:-default}`, `$${` is a literal `${`.
+///
+/// Returns `None` if the string contains no references.
+///
+/// # Errors
+///  * if a reference can't be resolved
+pub fn interpolate_str(s: &str) -> Result<Option<String>> {
+    interpolate_escaped(s, ToString::to_string)
+}
+
+/// Like `interpolate_str` but resolved values are passed through `escape`
+fn interpolate_escaped<F>(s: &str, escape: F) -> Result<Option<String>>
+where
+    F: Fn(&str) -> String,
+{
+    if !s.contains("${") {
+        return Ok(None);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            // `$${` is an escaped `${`
+            out.push_str(&rest[..start]);
+            out.push('{');
+            rest = &rest[start + 2..];
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        let body = &rest[start + 2..];
+        let end = body.find('}').ok_or_else(|| {
+            ErrorKind::InterpolationError(rest[start..].to_string(), "missing `}`".to_string())
+        })?;
+        out.push_str(&escape(&resolve(&body[..end])?));
+        rest = &body[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(Some(out))
+}
+
+fn resolve(reference: &str) -> Result<String> {
+    let (reference, default) = match reference.find(":-") {
+        Some(i) => (&reference[..i], Some(&reference[i + 2..])),
+        None => (reference, None),
+    };
+    let value = if let Some(name) = reference.strip_prefix("env:") {
+        std::env::var(name).ok()
+    } else if let Some(path) = reference.strip_prefix("file:") {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Some(content.trim_end_matches(&['\r', '\n'][..]).to_string()),
+            Err(_) if default.is_some() => None,
+            Err(e) => {
+                return Err(
+                    ErrorKind::InterpolationError(reference.to_string(), e.to_string()).into(),
+                )
+            }
+        }
+    } else {
+        return Err(ErrorKind::InterpolationError(
+            reference.to_string(),
+            "expected an `env:` or `file:` reference".to_string(),
+        )
+        .into());
+    };
+    value
+        .or_else(|| default.map(ToString::to_string))
+        .ok_or_else(|| {
+            ErrorKind::InterpolationError(reference.to_string(), "not set".to_string()).into()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interpolate() -> Result<()> {
+        std::env::set_var("TREMOR_CONFIG_TEST_SECRET", "badger");
+        assert_eq!(interpolate_str("snot")?, None);
+        assert_eq!(
+            interpolate_str("${env:TREMOR_CONFIG_TEST_SECRET}")?,
+            Some("badger".to_string())
+        );
+        assert_eq!(
+            interpolate_str(
+                "snot:${env:TREMOR_CONFIG_TEST_SECRET}@${env:TREMOR_CONFIG_TEST_UNSET:-host}"
+            )?,
+            Some("snot:badger@host".to_string())
+        );
+        assert_eq!(
+            interpolate_str("$${env:TREMOR_CONFIG_TEST_SECRET}")?,
+            Some("${env:TREMOR_CONFIG_TEST_SECRET}".to_string())
+        );
+        assert_eq!(
+            interpolate_str("${file:/this/does/not/exist:-}")?,
+            Some(String::new())
+        );
+        assert!(interpolate_str("${env:TREMOR_CONFIG_TEST_UNSET}").is_err());
+        assert!(interpolate_str("${file:/this/does/not/exist}").is_err());
+        assert!(interpolate_str("${snot:badger}").is_err());
+        assert!(interpolate_str("${env:TREMOR_CONFIG_TEST_SECRET").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn redact() -> Result<()> {
+        std::env::set_var("TREMOR_CONFIG_TEST_PASSWORD", "badger");
+        let onramp: OnRamp = serde_yaml::from_str(
+            r#"
+id: test
+type: postgres
+config:
+  user: snot
+  password: ${env:TREMOR_CONFIG_TEST_PASSWORD}
+"#,
+        )?;
+        let onramp = onramp.interpolate()?;
+        assert_eq!(
+            onramp.config.as_ref().and_then(|c| c.get("password")),
+            Some(&YamlValue::from("badger"))
+        );
+        let redacted = serde_yaml::to_string(&onramp.redacted())?;
+        assert!(!redacted.contains("badger"));
+        assert!(redacted.contains("${env:TREMOR_CONFIG_TEST_PASSWORD}"));
+        Ok(())
+    }
+
+    #[test]
+    fn interpolate_query_literal() -> Result<()> {
+        std::env::set_var("TREMOR_CONFIG_TEST_QUERY", "snot\"{badger}");
+        let query = Query::parse(
+            &tremor_script::path::ModulePath { mounts: vec![] },
+            "select \"${env:TREMOR_CONFIG_TEST_QUERY}\" from in into out;",
+            "<test>",
+            vec![],
+            &*tremor_pipeline::FN_REGISTRY.lock()?,
+            &tremor_script::aggr_registry(),
+        )?;
+        let query = interpolate_query(query)?;
+        assert!(query.source().contains("${env:TREMOR_CONFIG_TEST_QUERY}"));
+
+        let mut returns = vec![];
+        let mut graph = query.to_pipe(&mut 0)?;
+        graph.enqueue("in", crate::Event::default(), &mut returns)?;
+        assert_eq!(
+            returns
+                .pop()
+                .and_then(|(_, e)| e.data.suffix().value().as_str().map(String::from)),
+            Some("snot\"{badger}".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn redact_binding() -> Result<()> {
+        std::env::set_var("TREMOR_CONFIG_TEST_PIPELINE", "badger");
+        let binding: Binding = serde_yaml::from_str(
+            r#"
+id: test
+links:
+  "/onramp/in/{instance}/out": ["/pipeline/${env:TREMOR_CONFIG_TEST_PIPELINE}/{instance}/in"]
+"#,
+        )?;
+        let binding = binding.interpolate()?;
+        let to = TremorURL::parse("/pipeline/badger/{instance}/in")?;
+        assert!(binding.links.values().any(|tos| tos.contains(&to)));
+        let redacted = serde_yaml::to_string(&binding.redacted())?;
+        assert!(!redacted.contains("badger"));
+        assert!(redacted.contains("env:TREMOR_CONFIG_TEST_PIPELINE"));
+        Ok(())
+    }
 }
@@ -254,6 +254,10 @@ error_chain! {
             description("Bad UTF8 in input string")
 
         }
+        InterpolationError(r: String, e: String) {
+            description("Failed to resolve a reference in the configuration")
+                display("Failed to resolve `{}`: {}", r, e)
+        }
         InvalidCompression {
             description("Data can't be decompressed")
                 display("The data did not contain a known magic header to identify a supported compression")
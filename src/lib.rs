@@ -64,6 +64,8 @@ pub(crate) mod sink;
 pub(crate) mod source;
 /// Tremor runtime system
 pub mod system;
+/// Topology of the running servants
+pub mod topology;
 /// Tremor URI
 pub mod url;
 /// Utility functions
@@ -15,13 +15,153 @@
 use crate::pipeline;
 use crate::url::TremorURL;
 use halfbrown::HashMap;
+use hdrhistogram::Histogram;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
 use tremor_pipeline::Event;
 use tremor_script::prelude::*;
 
 /// Metrics instance name
 pub static mut INSTANCE: &str = "tremor";
 
+lazy_static! {
+    static ref SCRAPE: Mutex<Scrape> = Mutex::new(Scrape::default());
+}
+
+/// A prometheus metric family
+#[derive(Debug, Default)]
+struct Family {
+    counter: bool,
+    /// samples by their rendered label set
+    samples: BTreeMap<String, f64>,
+}
+
+/// The last observed value of every metric, for scraping
+#[derive(Debug, Default)]
+struct Scrape {
+    families: BTreeMap<String, Family>,
+}
+
+/// Turns a string into a valid prometheus metric or label name
+fn prometheus_name(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn prometheus_label_value(v: &Value) -> String {
+    let s = v.as_str().map_or_else(|| v.encode(), ToString::to_string);
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Records a metric as the influx-shaped value that is sent to the
+/// `system::metrics` pipeline, replacing the previous value of the same
+/// measurement, field and tags. Counters are tracked in their `count`
+/// field, every other numeric field is exposed as a gauge.
+pub(crate) fn observe(value: &Value) {
+    let measurement = if let Some(m) = value.get("measurement").and_then(ValueTrait::as_str) {
+        prometheus_name(m)
+    } else {
+        return;
+    };
+    let fields = if let Some(fields) = value.get("fields").and_then(ValueTrait::as_object) {
+        fields
+    } else {
+        return;
+    };
+    // sorted so the same tags always end up as the same sample
+    let mut labels: BTreeMap<String, String> = BTreeMap::new();
+    if let Some(tags) = value.get("tags").and_then(ValueTrait::as_object) {
+        for (k, v) in tags {
+            labels.insert(prometheus_name(k), prometheus_label_value(v));
+        }
+    }
+    labels.insert("instance".to_string(), instance!());
+    let labels = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    if let Ok(mut scrape) = SCRAPE.lock() {
+        for (field, v) in fields {
+            if let Some(v) = v.cast_f64() {
+                let (name, counter) = if field == "count" {
+                    (format!("tremor_{}_total", measurement), true)
+                } else {
+                    (
+                        format!("tremor_{}_{}", measurement, prometheus_name(field)),
+                        false,
+                    )
+                };
+                let family = scrape.families.entry(name).or_default();
+                family.counter = counter;
+                family.samples.insert(labels.clone(), v);
+            }
+        }
+    }
+}
+
+/// Drops every series reported by the pipeline or ramp instance `id`, so
+/// unbound artefacts no longer show up in scrapes
+pub(crate) fn forget(id: &TremorURL) {
+    let mut id = id.clone();
+    id.trim_to_instance();
+    let value = prometheus_label_value(&Value::from(id.to_string()));
+    let needles: Vec<String> = ["pipeline", "ramp"]
+        .iter()
+        .map(|label| format!("{}=\"{}\"", label, value))
+        .collect();
+    // labels are rendered as `k="v"` joined by `,` and quotes in values are
+    // escaped, so a needle can only match a whole label
+    let owned = |labels: &str| {
+        needles.iter().any(|needle| {
+            labels.starts_with(needle.as_str()) || labels.contains(&format!(",{}", needle))
+        })
+    };
+    if let Ok(mut scrape) = SCRAPE.lock() {
+        for family in scrape.families.values_mut() {
+            let gone: Vec<String> = family
+                .samples
+                .keys()
+                .filter(|labels| owned(labels))
+                .cloned()
+                .collect();
+            for labels in gone {
+                family.samples.remove(&labels);
+            }
+        }
+        let empty: Vec<String> = scrape
+            .families
+            .iter()
+            .filter(|(_, family)| family.samples.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in empty {
+            scrape.families.remove(&name);
+        }
+    }
+}
+
+/// Renders all observed metrics in the prometheus text exposition format
+#[must_use]
+pub fn prometheus() -> String {
+    let mut res = String::new();
+    if let Ok(scrape) = SCRAPE.lock() {
+        for (name, family) in &scrape.families {
+            let kind = if family.counter { "counter" } else { "gauge" };
+            res.push_str(&format!("# TYPE {} {}\n", name, kind));
+            for (labels, v) in &family.samples {
+                res.push_str(&format!("{}{{{}}} {}\n", name, labels, v));
+            }
+        }
+    }
+    res
+}
+
 #[derive(Debug)]
 pub(crate) struct Ramp {
     r#in: u64,
@@ -33,6 +173,10 @@ pub(crate) struct Ramp {
 pub(crate) struct RampReporter {
     artefact_url: TremorURL,
     metrics: Ramp,
+    /// end to end latency in nanoseconds since the last flush
+    latency: Option<Histogram<u64>>,
+    /// last and maximum queue depth since the last flush
+    queue_depth: Option<(usize, usize)>,
     metrics_pipeline: Option<(TremorURL, pipeline::Addr)>,
     flush_interval: Option<u64>, // as nano-seconds
     last_flush_ns: u64,
@@ -47,6 +191,8 @@ impl RampReporter {
                 out: 0,
                 error: 0,
             },
+            latency: None,
+            queue_depth: None,
             metrics_pipeline: None,
             flush_interval: flush_interval_s.map(|n| n * 1_000_000_000),
             last_flush_ns: 0,
@@ -73,6 +219,26 @@ impl RampReporter {
         self.metrics.error += 1;
     }
 
+    /// Records the time between ingestion and delivery of an event
+    #[inline]
+    pub fn record_latency(&mut self, ns: u64) {
+        if self.latency.is_none() {
+            // auto resizing, so this only fails on invalid significant figures
+            self.latency = Histogram::new(2).ok();
+        }
+        if let Some(latency) = &mut self.latency {
+            latency.saturating_record(ns);
+        }
+    }
+
+    /// Records the number of events waiting in the ramps queue
+    #[inline]
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        let (last, max) = self.queue_depth.get_or_insert((0, 0));
+        *last = depth;
+        *max = (*max).max(depth);
+    }
+
     #[inline]
     pub fn periodic_flush(&mut self, timestamp: u64) -> Option<u64> {
         if let Some(interval) = self.flush_interval {
@@ -88,6 +254,29 @@ impl RampReporter {
         self.send_metric(timestamp, "in", self.metrics.r#in);
         self.send_metric(timestamp, "out", self.metrics.out);
         self.send_metric(timestamp, "error", self.metrics.error);
+        if let Some(latency) = &self.latency {
+            if latency.len() > 0 {
+                let value = tremor_pipeline::influx_histogram(
+                    Cow::Borrowed("ramp_latency"),
+                    self.tags(),
+                    latency,
+                    timestamp,
+                );
+                self.send_value(value, timestamp);
+            }
+        }
+        if let Some(latency) = &mut self.latency {
+            latency.reset();
+        }
+        if let Some((last, max)) = self.queue_depth.take() {
+            let value = tremor_pipeline::influx_gauges(
+                Cow::Borrowed("ramp_queue"),
+                self.tags(),
+                vec![("depth", last.into()), ("max_depth", max.into())],
+                timestamp,
+            );
+            self.send_value(value, timestamp);
+        }
         self.last_flush_ns = timestamp;
     }
 
@@ -103,14 +292,23 @@ impl RampReporter {
             }
         }
     }
-    fn send_metric(&self, timestamp: u64, port: &'static str, count: u64) {
-        // metrics tags
+    fn tags(&self) -> HashMap<Cow<'static, str>, Value<'static>> {
         let mut tags: HashMap<Cow<'static, str>, Value<'static>> = HashMap::new();
         tags.insert(Cow::Borrowed("ramp"), self.artefact_url.to_string().into());
+        tags
+    }
 
+    fn send_metric(&self, timestamp: u64, port: &'static str, count: u64) {
+        // metrics tags
+        let mut tags = self.tags();
         tags.insert(Cow::Borrowed("port"), port.into());
         let value: Value =
             tremor_pipeline::influx_value(Cow::Borrowed("ramp_events"), tags, count, timestamp);
+        self.send_value(value, timestamp);
+    }
+
+    fn send_value(&self, value: Value<'static>, timestamp: u64) {
+        observe(&value);
 
         // full metrics payload
         let metrics_event = Event {
@@ -122,3 +320,73 @@ impl RampReporter {
         self.send(metrics_event)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prometheus_text() {
+        let mut tags: HashMap<Cow<'static, str>, Value<'static>> = HashMap::new();
+        tags.insert("pipeline".into(), "main\"01".into());
+        tags.insert("port".into(), "out".into());
+        observe(&tremor_pipeline::influx_value(
+            "test_events".into(),
+            tags.clone(),
+            42,
+            0,
+        ));
+        let mut gauge = Value::object();
+        gauge
+            .insert("measurement", "test_bucket")
+            .expect("not an object");
+        gauge
+            .insert("tags", Value::from(tags))
+            .expect("not an object");
+        let mut fields = Value::object();
+        fields.insert("buckets", 3).expect("not an object");
+        gauge.insert("fields", fields).expect("not an object");
+        observe(&gauge);
+
+        let text = prometheus();
+        assert!(text.contains("# TYPE tremor_test_events_total counter\n"));
+        assert!(text.contains(
+            "tremor_test_events_total{instance=\"tremor\",pipeline=\"main\\\"01\",port=\"out\"} 42\n"
+        ));
+        assert!(text.contains("# TYPE tremor_test_bucket_buckets gauge\n"));
+        assert!(text.contains(
+            "tremor_test_bucket_buckets{instance=\"tremor\",pipeline=\"main\\\"01\",port=\"out\"} 3\n"
+        ));
+    }
+
+    #[test]
+    fn forget_unbound() -> crate::errors::Result<()> {
+        let kept = TremorURL::parse("/pipeline/forget/02")?;
+        let gone = TremorURL::parse("/pipeline/forget/01")?;
+        for id in &[&kept, &gone] {
+            let mut tags: HashMap<Cow<'static, str>, Value<'static>> = HashMap::new();
+            tags.insert("pipeline".into(), id.to_string().into());
+            observe(&tremor_pipeline::influx_value(
+                "forget_events".into(),
+                tags,
+                1,
+                0,
+            ));
+        }
+        let mut ramp: HashMap<Cow<'static, str>, Value<'static>> = HashMap::new();
+        ramp.insert("ramp".into(), gone.to_string().into());
+        observe(&tremor_pipeline::influx_value(
+            "forget_only_events".into(),
+            ramp,
+            1,
+            0,
+        ));
+
+        forget(&gone);
+        let text = prometheus();
+        assert!(text.contains(&format!("pipeline=\"{}\"", kept)));
+        assert!(!text.contains(&format!("pipeline=\"{}\"", gone)));
+        assert!(!text.contains("tremor_forget_only_events_total"));
+        Ok(())
+    }
+}
@@ -23,6 +23,7 @@ use crate::sink::{
     stderr, stdout, tcp, udp, ws,
 };
 use crate::source::Processors;
+use crate::topology::{Edge, Status};
 use crate::url::ports::{IN, METRICS};
 use crate::url::TremorURL;
 use crate::{Event, OpConfig};
@@ -50,6 +51,10 @@ pub enum Msg {
         id: TremorURL,
         tx: async_channel::Sender<bool>,
     },
+    /// Reports if the sink is active and the links of the offramp
+    Status(async_channel::Sender<Status>),
+    /// Replied to once everything queued before it is processed
+    Drain(async_channel::Sender<()>),
 }
 
 pub(crate) type Sender = async_channel::Sender<ManagerMsg>;
@@ -191,6 +196,8 @@ impl Manager {
         offramp_uid: u64,
     ) -> Result<()> {
         let (msg_tx, msg_rx) = bounded::<Msg>(self.qsize);
+        // a receiver only used to look at the queue depth
+        let queue = msg_rx.clone();
         let (cf_tx, cf_rx) = unbounded::<sink::Reply>(); // we might need to wrap that somehow, but *shrug*
 
         if let Err(e) = offramp
@@ -223,6 +230,8 @@ impl Manager {
             // for linked offramp output (port to pipeline(s) mapping)
             let mut dest_pipelines: HashMap<Cow<'static, str>, Vec<(TremorURL, pipeline::Addr)>> =
                 HashMap::new();
+            // responses sent on each outgoing port
+            let mut responses: HashMap<Cow<'static, str>, u64> = HashMap::new();
 
             info!("[Offramp::{}] started", offramp_url);
 
@@ -240,22 +249,29 @@ impl Manager {
                                 let transactional = event.transactional;
                                 let ids = event.id.clone();
 
+                                metrics_reporter.set_queue_depth(queue.len());
                                 metrics_reporter.periodic_flush(ingest_ns);
                                 metrics_reporter.increment_in();
 
-                                let fail = if let Err(err) = offramp
+                                let failure = if let Err(err) = offramp
                                     .on_event(codec.borrow(), &codec_map, input.borrow(), event)
                                     .await
                                 {
                                     error!("[Offramp::{}] On Event error: {}", offramp_url, err);
                                     metrics_reporter.increment_error();
-                                    true
+                                    Some(err.to_string())
                                 } else {
                                     metrics_reporter.increment_out();
-                                    false
+                                    metrics_reporter
+                                        .record_latency(nanotime().saturating_sub(ingest_ns));
+                                    None
                                 };
                                 if offramp.auto_ack() && transactional {
-                                    let e = Event::ack_or_fail(!fail, ingest_ns, ids);
+                                    let e = if let Some(reason) = failure {
+                                        Event::cb_fail(ingest_ns, ids).with_reason(&reason)
+                                    } else {
+                                        Event::cb_ack(ingest_ns, ids)
+                                    };
                                     send_to_pipelines(&offramp_url, &mut pipelines, e).await;
                                 }
                             }
@@ -328,6 +344,33 @@ impl Manager {
                                     offramp_url, &id, &port
                                 );
                             }
+                            Msg::Status(tx) => {
+                                let mut edges = Vec::new();
+                                for (port, pipelines) in &dest_pipelines {
+                                    let mut from = offramp_url.clone();
+                                    from.set_port(port.to_string());
+                                    let count = responses.get(port).copied().unwrap_or_default();
+                                    edges.extend(pipelines.iter().map(|(id, _)| Edge {
+                                        from: from.clone(),
+                                        to: id.clone(),
+                                        count,
+                                    }));
+                                }
+                                let status = Status {
+                                    ready: offramp.is_active(),
+                                    edges,
+                                    dot: None,
+                                };
+                                // the requester may have given up waiting
+                                if tx.send(status).await.is_err() {
+                                    debug!("[Offramp::{}] status request timed out.", offramp_url);
+                                }
+                            }
+                            Msg::Drain(tx) => {
+                                if tx.send(()).await.is_err() {
+                                    debug!("[Offramp::{}] drain request timed out.", offramp_url);
+                                }
+                            }
                         }
                     }
                     OfframpMsg::Reply(sink::Reply::Insight(event)) => {
@@ -335,6 +378,7 @@ impl Manager {
                     }
                     OfframpMsg::Reply(sink::Reply::Response(port, event)) => {
                         if let Some(pipelines) = dest_pipelines.get_mut(&port) {
+                            *responses.entry(port.clone()).or_insert(0) += 1;
                             if let Err(e) = handle_response(event, pipelines.iter()).await {
                                 error!("[Offramp::{}] Response error: {}", offramp_url, e)
                             }
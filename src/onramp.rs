@@ -17,6 +17,7 @@ use crate::pipeline;
 use crate::repository::ServantId;
 use crate::source::prelude::*;
 use crate::source::{blaster, crononome, file, kafka, metronome, postgres, rest, tcp, udp, ws};
+use crate::topology::Status;
 use crate::url::TremorURL;
 use async_std::task::{self, JoinHandle};
 use serde_yaml::Value;
@@ -39,6 +40,14 @@ pub enum Msg {
     Cb(CBAction, Ids),
     // TODO pick good naming here: LinkedEvent / Response / Result?
     Response(tremor_pipeline::Event),
+    /// Stops pulling events and flushes the connected pipelines, replies once
+    /// all in-flight events are acknowledged or the deadline (in ns) passed
+    Drain {
+        deadline: u64,
+        tx: async_channel::Sender<()>,
+    },
+    /// Reports the connection state and links of the onramp
+    Status(async_channel::Sender<Status>),
 }
 
 pub type Addr = async_channel::Sender<Msg>;
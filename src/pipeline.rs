@@ -11,10 +11,13 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
-use crate::errors::Result;
+use crate::errors::{ErrorKind, Result};
+use crate::metrics;
 use crate::permge::{PriorityMerge, M};
 use crate::registry::ServantId;
 use crate::repository::PipelineArtefact;
+use crate::topology::{Edge, Status};
+use crate::url::ports::METRICS;
 use crate::url::TremorURL;
 use crate::{offramp, onramp};
 use async_channel::{bounded, unbounded};
@@ -24,13 +27,23 @@ use std::borrow::Cow;
 use std::fmt;
 use std::time::Duration;
 use tremor_common::time::nanotime;
+use tremor_pipeline::checkpoint::Store as CheckpointStore;
 use tremor_pipeline::{CBAction, Event, ExecutableGraph, SignalKind};
 
 const TICK_MS: u64 = 100;
+const TAP_WINDOW_NS: u64 = 1_000_000_000;
 pub(crate) type Sender = async_channel::Sender<ManagerMsg>;
 type Onramps = halfbrown::HashMap<TremorURL, (bool, onramp::Addr)>;
 type Dests = halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>>;
 type Eventset = Vec<(Cow<'static, str>, Event)>;
+/// Events sent to each destination by output
+type Sent = halfbrown::HashMap<Cow<'static, str>, halfbrown::HashMap<TremorURL, u64>>;
+/// A swap waiting for a number of queued messages to drain
+type PendingSwap = (
+    usize,
+    Box<ExecutableGraph>,
+    async_channel::Sender<Result<()>>,
+);
 /// Address for a a pipeline
 #[derive(Clone)]
 pub struct Addr {
@@ -63,6 +76,10 @@ impl Addr {
     pub(crate) async fn send_mgmt(&self, msg: MgmtMsg) -> Result<()> {
         Ok(self.mgmt_addr.send(msg).await?)
     }
+
+    pub(crate) fn try_send_mgmt(&self, msg: MgmtMsg) -> bool {
+        self.mgmt_addr.try_send(msg).is_ok()
+    }
 }
 
 impl fmt::Debug for Addr {
@@ -87,6 +104,14 @@ pub(crate) enum MgmtMsg {
     },
     DisconnectOutput(Cow<'static, str>, TremorURL),
     DisconnectInput(TremorURL),
+    Swap(Box<ExecutableGraph>, async_channel::Sender<Result<()>>),
+    ConnectTap(
+        Cow<'static, str>,
+        TremorURL,
+        Tap,
+        async_channel::Sender<Result<()>>,
+    ),
+    Status(async_channel::Sender<Status>),
 }
 
 #[derive(Debug)]
@@ -96,6 +121,44 @@ pub(crate) enum Msg {
         input: Cow<'static, str>,
     },
     Signal(Event),
+    /// Replied to once everything queued before it is processed
+    Drain(async_channel::Sender<()>),
+}
+
+/// A sampled copy of the events leaving a pipeline port, used for debugging
+#[derive(Debug)]
+pub struct Tap {
+    tx: async_channel::Sender<Event>,
+    /// maximum number of events per second
+    rate: u64,
+    /// start of the current one second window
+    window: u64,
+    /// events sent in the current window
+    sent: u64,
+}
+
+impl Tap {
+    pub(crate) fn new(tx: async_channel::Sender<Event>, rate: u64) -> Self {
+        Self {
+            tx,
+            rate,
+            window: 0,
+            sent: 0,
+        }
+    }
+
+    /// Taps never apply back pressure, events over the rate limit or that
+    /// don't fit into the channel are dropped
+    fn send(&mut self, event: Event) {
+        let now = nanotime();
+        if now.saturating_sub(self.window) >= TAP_WINDOW_NS {
+            self.window = now;
+            self.sent = 0;
+        }
+        if self.sent < self.rate && self.tx.try_send(event).is_ok() {
+            self.sent += 1;
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -103,6 +166,7 @@ pub enum Dest {
     Offramp(async_channel::Sender<offramp::Msg>),
     Pipeline(Addr),
     LinkedOnramp(async_channel::Sender<onramp::Msg>),
+    Tap(Tap),
 }
 
 impl Dest {
@@ -111,6 +175,7 @@ impl Dest {
             Self::Offramp(addr) => addr.send(offramp::Msg::Event { input, event }).await?,
             Self::Pipeline(addr) => addr.send(Msg::Event { input, event }).await?,
             Self::LinkedOnramp(addr) => addr.send(onramp::Msg::Response(event)).await?,
+            Self::Tap(tap) => tap.send(event),
         }
         Ok(())
     }
@@ -128,9 +193,19 @@ impl Dest {
                 // TODO implement?
                 //addr.send(onramp::Msg::Signal(signal)).await?
             }
+            // taps only see events
+            Self::Tap(_) => (),
         }
         Ok(())
     }
+    /// A tap is closed once its client went away
+    fn is_closed(&self) -> bool {
+        if let Self::Tap(tap) = self {
+            tap.tx.is_closed()
+        } else {
+            false
+        }
+    }
 }
 
 pub struct Create {
@@ -141,31 +216,94 @@ pub struct Create {
 pub(crate) enum ManagerMsg {
     Stop,
     Create(async_channel::Sender<Result<Addr>>, Create),
+    Swap(async_channel::Sender<Result<()>>, Addr, PipelineArtefact),
 }
 
 #[derive(Default, Debug)]
 pub(crate) struct Manager {
     qsize: usize,
     uid: u64,
+    checkpoints: Option<CheckpointStore>,
+}
+
+fn count_sent(sent: &mut Sent, output: &Cow<'static, str>, id: &TremorURL) {
+    if let Some(count) = sent.get_mut(output).and_then(|s| s.get_mut(id)) {
+        *count += 1;
+    } else {
+        sent.entry(output.clone())
+            .or_insert_with(halfbrown::HashMap::new)
+            .insert(id.clone(), 1);
+    }
+}
+
+/// The links leaving the pipeline, taps are not part of the topology
+fn status(id: &TremorURL, pipeline: &ExecutableGraph, dests: &Dests, sent: &Sent) -> Status {
+    let mut edges = Vec::new();
+    for (output, dests) in dests {
+        let mut from = id.clone();
+        from.set_port(output.to_string());
+        let sent = sent.get(output);
+        edges.extend(
+            dests
+                .iter()
+                .filter(|(_, dest)| !matches!(dest, Dest::Tap(_)))
+                .map(|(to, _)| Edge {
+                    from: from.clone(),
+                    to: to.clone(),
+                    count: sent.and_then(|s| s.get(to)).copied().unwrap_or_default(),
+                }),
+        );
+    }
+    Status {
+        ready: true,
+        edges,
+        dot: Some(pipeline.dot.clone()),
+    }
 }
 
 #[inline]
-async fn send_events(eventset: &mut Eventset, dests: &mut Dests) -> Result<()> {
+async fn send_events(eventset: &mut Eventset, dests: &mut Dests, sent: &mut Sent) -> Result<()> {
     for (output, event) in eventset.drain(..) {
+        if output == METRICS {
+            metrics::observe(event.data.suffix().value());
+        }
         if let Some(dest) = dests.get_mut(&output) {
             if let Some((last, rest)) = dest.split_last_mut() {
                 for (id, offramp) in rest {
                     let port = id.instance_port_required()?.to_string().into();
                     offramp.send_event(port, event.clone()).await?;
+                    count_sent(sent, &output, id);
                 }
                 let last_port = last.0.instance_port_required()?.to_string().into();
                 last.1.send_event(last_port, event).await?;
+                count_sent(sent, &output, &last.0);
+            }
+            // detach taps whose client disconnected
+            if dest.iter().any(|(_, d)| d.is_closed()) {
+                dest.retain(|(_, d)| !d.is_closed());
             }
         };
     }
     Ok(())
 }
 
+/// Detaches the taps whose client disconnected from all ports, including
+/// those that don't emit any events
+fn detach_closed_taps(dests: &mut Dests) {
+    let mut empty = Vec::new();
+    for (output, dest) in dests.iter_mut() {
+        if dest.iter().any(|(_, d)| d.is_closed()) {
+            dest.retain(|(_, d)| !d.is_closed());
+            if dest.is_empty() {
+                empty.push(output.clone());
+            }
+        }
+    }
+    for output in empty {
+        dests.remove(&output);
+    }
+}
+
 #[inline]
 async fn send_signal(own_id: &TremorURL, signal: Event, dests: &mut Dests) -> Result<()> {
     let mut offramps = dests.values_mut().flatten();
@@ -240,6 +378,34 @@ async fn handle_cf_msg(
     Ok(())
 }
 
+fn checkpoint(id: &TremorURL, pipeline: &mut ExecutableGraph, checkpoints: &CheckpointStore) {
+    if let Err(e) = pipeline.checkpoint(checkpoints, nanotime()) {
+        error!("[Pipeline:{}] failed to checkpoint state: {}", id, e)
+    }
+}
+
+/// Replaces the running graph with a new one, state is carried over
+/// through a checkpoint if the state versions of both graphs match.
+/// Operators that are unchanged keep their uid so outstanding contraflow
+/// is still handled by them.
+fn swap(
+    id: &TremorURL,
+    pipeline: &mut ExecutableGraph,
+    mut new: ExecutableGraph,
+    checkpoints: Option<&CheckpointStore>,
+) {
+    new.id = pipeline.id.clone();
+    new.adopt_uids(pipeline);
+    if let Some(checkpoints) = checkpoints {
+        checkpoint(id, pipeline, checkpoints);
+        if let Err(e) = new.restore(checkpoints) {
+            error!("[Pipeline:{}] failed to restore state: {}", id, e)
+        }
+    }
+    std::mem::swap(pipeline, &mut new);
+    info!("[Pipeline:{}] swapped to the new graph.", id);
+}
+
 fn maybe_send(r: Result<()>) {
     if let Err(e) = r {
         error!("Failed to send : {}", e)
@@ -252,17 +418,31 @@ async fn pipeline_task(
     rx: async_channel::Receiver<Msg>,
     cf_rx: async_channel::Receiver<CfMsg>,
     mgmt_rx: async_channel::Receiver<MgmtMsg>,
+    checkpoints: Option<CheckpointStore>,
 ) -> Result<()> {
     let mut pid = id.clone();
     pid.trim_to_instance();
     pipeline.id = pid.to_string();
 
+    if let Some(checkpoints) = &checkpoints {
+        match pipeline.restore(checkpoints) {
+            Ok(0) => (),
+            Ok(n) => info!("[Pipeline:{}] restored the state of {} operators.", id, n),
+            Err(e) => error!("[Pipeline:{}] failed to restore state: {}", id, e),
+        }
+    }
+
     let mut dests: Dests = halfbrown::HashMap::new();
+    let mut sent: Sent = halfbrown::HashMap::new();
     let mut onramps: Onramps = halfbrown::HashMap::new();
     let mut eventset: Vec<(Cow<'static, str>, Event)> = Vec::new();
+    // a swap waiting for the events queued before it to drain
+    let mut pending_swap: Option<PendingSwap> = None;
 
     info!("[Pipeline:{}] starting task.", id);
 
+    // a receiver only used to look at the queue depth
+    let queue = rx.clone();
     let ff = rx.map(M::F);
     let cf = cf_rx.map(M::C);
     let mf = mgmt_rx.map(M::M);
@@ -271,26 +451,47 @@ async fn pipeline_task(
 
     let mut s = PriorityMerge::new(mf, PriorityMerge::new(cf, ff));
     while let Some(msg) = s.next().await {
+        let forward = matches!(msg, M::F(_));
         match msg {
             M::C(msg) => {
                 handle_cf_msg(msg, &mut pipeline, &onramps).await?;
             }
             M::F(Msg::Event { input, event }) => {
+                pipeline.set_queue_depth(queue.len());
                 match pipeline.enqueue(&input, event, &mut eventset) {
                     Ok(()) => {
                         handle_insights(&mut pipeline, &onramps).await;
-                        maybe_send(send_events(&mut eventset, &mut dests).await);
+                        maybe_send(send_events(&mut eventset, &mut dests, &mut sent).await);
                     }
                     Err(e) => error!("error: {:?}", e),
                 }
             }
+            M::F(Msg::Drain(tx)) => {
+                if tx.send(()).await.is_err() {
+                    debug!("[Pipeline:{}] drain request timed out.", id);
+                }
+            }
             M::F(Msg::Signal(signal)) => {
+                let (kind, ingest_ns) = (signal.kind, signal.ingest_ns);
                 if let Err(e) = pipeline.enqueue_signal(signal.clone(), &mut eventset) {
                     error!("error: {:?}", e)
                 } else {
                     maybe_send(send_signal(&id, signal, &mut dests).await);
                     handle_insights(&mut pipeline, &onramps).await;
-                    maybe_send(send_events(&mut eventset, &mut dests).await);
+                    maybe_send(send_events(&mut eventset, &mut dests, &mut sent).await);
+                }
+                if kind == Some(SignalKind::Tick) {
+                    detach_closed_taps(&mut dests);
+                }
+                if let Some(checkpoints) = &checkpoints {
+                    let due = match kind {
+                        Some(SignalKind::Shutdown) => true,
+                        Some(SignalKind::Tick) => pipeline.checkpoint_due(ingest_ns),
+                        _ => false,
+                    };
+                    if due {
+                        checkpoint(&id, &mut pipeline, checkpoints);
+                    }
                 }
             }
             M::M(MgmtMsg::ConnectOfframp(output, offramp_id, offramp)) => {
@@ -326,6 +527,32 @@ async fn pipeline_task(
                     dests.insert(output, vec![(onramp_id, Dest::LinkedOnramp(onramp))]);
                 }
             }
+            M::M(MgmtMsg::ConnectTap(output, tap_id, tap, reply)) => {
+                let res = if pipeline.has_output(&output) {
+                    info!("[Pipeline:{}] connecting {} to tap {}", id, output, tap_id);
+                    if let Some(output_dests) = dests.get_mut(&output) {
+                        output_dests.push((tap_id, Dest::Tap(tap)));
+                    } else {
+                        dests.insert(output, vec![(tap_id, Dest::Tap(tap))]);
+                    }
+                    Ok(())
+                } else {
+                    let mut port = pid.clone();
+                    port.set_port(output.to_string());
+                    Err(ErrorKind::ArtifactNotFound(port.to_string()).into())
+                };
+                maybe_send(reply.send(res).await.map_err(Into::into));
+            }
+            M::M(MgmtMsg::Status(tx)) => {
+                // the requester may have given up waiting
+                if tx
+                    .send(status(&pid, &pipeline, &dests, &sent))
+                    .await
+                    .is_err()
+                {
+                    debug!("[Pipeline:{}] status request timed out.", id);
+                }
+            }
             M::M(MgmtMsg::ConnectOnramp { id, addr, reply }) => {
                 onramps.insert(id, (reply, addr));
             }
@@ -342,17 +569,51 @@ async fn pipeline_task(
             M::M(MgmtMsg::DisconnectInput(onramp_id)) => {
                 onramps.remove(&onramp_id);
             }
+            M::M(MgmtMsg::Swap(new, reply)) => {
+                let missing = pipeline.inputs().find(|input| !new.has_input(input));
+                let res = if pending_swap.is_some() {
+                    Err("A swap is already in progress".into())
+                } else if let Some(input) = missing {
+                    Err(format!("The new pipeline is missing the input {}", input).into())
+                } else {
+                    info!(
+                        "[Pipeline:{}] swapping graph after {} queued messages.",
+                        id,
+                        queue.len()
+                    );
+                    pending_swap = Some((queue.len(), new, reply.clone()));
+                    Ok(())
+                };
+                if res.is_err() {
+                    maybe_send(reply.send(res).await.map_err(Into::into));
+                }
+            }
+        }
+        if let Some((drain, ..)) = &mut pending_swap {
+            if forward {
+                *drain = drain.saturating_sub(1);
+            }
+            if *drain == 0 {
+                if let Some((_, new, reply)) = pending_swap.take() {
+                    swap(&id, &mut pipeline, *new, checkpoints.as_ref());
+                    maybe_send(reply.send(Ok(())).await.map_err(Into::into));
+                }
+            }
         }
     }
 
+    if let Some(checkpoints) = &checkpoints {
+        checkpoint(&id, &mut pipeline, checkpoints);
+    }
     info!("[Pipeline:{}] stopping task.", id);
     Ok(())
 }
 
 impl Manager {
-    pub fn new(qsize: usize) -> Self {
+    pub fn new(qsize: usize, checkpoints: Option<CheckpointStore>) -> Self {
         Self {
             qsize,
+            checkpoints,
             /// We're using a different 'numberspace' for operators so their ID's
             /// are unique from the onramps
             uid: 0b1000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_u64,
@@ -371,6 +632,15 @@ impl Manager {
                     Ok(ManagerMsg::Create(r, create)) => {
                         r.send(self.start_pipeline(create)).await?
                     }
+                    Ok(ManagerMsg::Swap(r, addr, config)) => match config.to_pipe(&mut self.uid) {
+                        Ok(pipeline) => {
+                            let msg = MgmtMsg::Swap(Box::new(pipeline), r.clone());
+                            if let Err(e) = addr.send_mgmt(msg).await {
+                                r.send(Err(e)).await?
+                            }
+                        }
+                        Err(e) => r.send(Err(e.into())).await?,
+                    },
                     Err(e) => {
                         info!("Stopping onramps... {}", e);
                         break;
@@ -412,7 +682,14 @@ impl Manager {
         task::spawn(tick(tx.clone()));
         task::Builder::new()
             .name(format!("pipeline-{}", id))
-            .spawn(pipeline_task(id, pipeline, rx, cf_rx, mgmt_rx))?;
+            .spawn(pipeline_task(
+                id,
+                pipeline,
+                rx,
+                cf_rx,
+                mgmt_rx,
+                self.checkpoints.clone(),
+            ))?;
         Ok(Addr {
             id: req.id,
             addr: tx,
@@ -421,3 +698,118 @@ impl Manager {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::url::ports::{IN, OUT};
+
+    fn tap_url(n: u64) -> Result<TremorURL> {
+        TremorURL::parse(&format!("/offramp/system::tap/{}/in", n))
+    }
+
+    #[test]
+    fn tap_rate_limit() {
+        let (tx, rx) = bounded(16);
+        let mut tap = Tap::new(tx, 2);
+        for _ in 0..5 {
+            tap.send(Event::default());
+        }
+        assert_eq!(rx.len(), 2);
+
+        // a new window lets events through again
+        tap.window = nanotime().saturating_sub(TAP_WINDOW_NS);
+        tap.send(Event::default());
+        assert_eq!(rx.len(), 3);
+    }
+
+    #[test]
+    fn tap_drops_when_full() {
+        let (tx, rx) = bounded(1);
+        let mut tap = Tap::new(tx, 10);
+        tap.send(Event::default());
+        tap.send(Event::default());
+        assert_eq!(rx.len(), 1);
+        assert_eq!(tap.sent, 1);
+    }
+
+    #[async_std::test]
+    async fn tap_detach() -> Result<()> {
+        let (tx, rx) = bounded(16);
+        let (silent_tx, silent_rx) = bounded(16);
+        let mut dests: Dests = halfbrown::HashMap::new();
+        dests.insert(OUT, vec![(tap_url(1)?, Dest::Tap(Tap::new(tx, 10)))]);
+        dests.insert(
+            "err".into(),
+            vec![(tap_url(2)?, Dest::Tap(Tap::new(silent_tx, 10)))],
+        );
+        let mut sent: Sent = halfbrown::HashMap::new();
+
+        let mut eventset = vec![(OUT, Event::default())];
+        send_events(&mut eventset, &mut dests, &mut sent).await?;
+        assert_eq!(rx.len(), 1);
+
+        // taps are detached once their port emits
+        drop(rx);
+        let mut eventset = vec![(OUT, Event::default())];
+        send_events(&mut eventset, &mut dests, &mut sent).await?;
+        assert!(dests.get(&OUT).map_or(true, Vec::is_empty));
+
+        // or on the next tick for ports that don't
+        assert!(dests.contains_key("err"));
+        drop(silent_rx);
+        detach_closed_taps(&mut dests);
+        assert!(!dests.contains_key("err"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn connect_tap() -> Result<()> {
+        let module_path = tremor_script::path::ModulePath { mounts: Vec::new() };
+        let aggr_reg = tremor_script::aggr_registry();
+        let config = tremor_pipeline::query::Query::parse(
+            &module_path,
+            "select event from in into out;",
+            "<test>",
+            Vec::new(),
+            &*tremor_pipeline::FN_REGISTRY.lock()?,
+            &aggr_reg,
+        )?;
+        let (_handle, manager) = Manager::new(16, None).start();
+        let (tx, rx) = bounded(1);
+        let id = TremorURL::parse("/pipeline/tap/01")?;
+        manager
+            .send(ManagerMsg::Create(tx, Create { config, id }))
+            .await?;
+        let addr = rx.recv().await??;
+
+        let (tap_tx, tap_rx) = bounded(16);
+        let (reply_tx, reply_rx) = bounded(1);
+        addr.send_mgmt(MgmtMsg::ConnectTap(
+            "snot".into(),
+            tap_url(1)?,
+            Tap::new(tap_tx.clone(), 10),
+            reply_tx.clone(),
+        ))
+        .await?;
+        assert!(reply_rx.recv().await?.is_err());
+
+        addr.send_mgmt(MgmtMsg::ConnectTap(
+            OUT,
+            tap_url(2)?,
+            Tap::new(tap_tx, 10),
+            reply_tx,
+        ))
+        .await?;
+        reply_rx.recv().await??;
+        addr.send(Msg::Event {
+            input: IN,
+            event: Event::default(),
+        })
+        .await?;
+        assert!(tap_rx.recv().await.is_ok());
+
+        manager.send(ManagerMsg::Stop).await?;
+        Ok(())
+    }
+}
@@ -50,7 +50,7 @@ pub(crate) struct Repository<A: Artefact> {
 }
 
 impl<A: Artefact> Repository<A> {
-    /// Retrives the wraped artefacts
+    /// Retrives the wraped artefacts, with resolved references redacted
     pub fn values(&self) -> Vec<A> {
         self.map
             .values()
@@ -58,7 +58,7 @@ impl<A: Artefact> Repository<A> {
                 if a.system {
                     None
                 } else {
-                    Some(a.artefact.clone())
+                    Some(a.artefact.redacted())
                 }
             })
             .collect()
@@ -79,18 +79,35 @@ impl<A: Artefact> Repository<A> {
         self.map.get(&id)
     }
 
-    /// Publishes an artefact
-    pub fn publish(&mut self, mut id: ArtefactId, system: bool, artefact: A) -> Result<&A> {
+    /// Publishes an artefact, resolving references in its configuration.
+    /// Returns the published artefact with resolved references redacted.
+    pub fn publish(&mut self, mut id: ArtefactId, system: bool, artefact: A) -> Result<A> {
         id.trim_to_artefact();
         match self.map.entry(id.clone()) {
             Entry::Occupied(_) => Err(ErrorKind::PublishFailedAlreadyExists(id.to_string()).into()),
-            Entry::Vacant(e) => Ok(&e
+            Entry::Vacant(e) => Ok(e
                 .insert(RepoWrapper {
                     instances: Vec::new(),
-                    artefact,
+                    artefact: artefact.interpolate()?,
                     system,
                 })
-                .artefact),
+                .artefact
+                .redacted()),
+        }
+    }
+    /// Replaces a published artefact, keeping its instances. Returns the
+    /// replaced artefact with resolved references redacted.
+    pub fn replace(&mut self, mut id: ArtefactId, artefact: A) -> Result<A> {
+        id.trim_to_artefact();
+        match self.map.get_mut(&id) {
+            Some(w) if w.system => {
+                Err(ErrorKind::UnpublishFailedSystemArtefact(id.to_string()).into())
+            }
+            Some(w) => {
+                let old = std::mem::replace(&mut w.artefact, artefact.interpolate()?);
+                Ok(old.redacted())
+            }
+            None => Err(ErrorKind::ArtifactNotFound(id.to_string()).into()),
         }
     }
     /// Unpublishes an artefact
@@ -104,7 +121,7 @@ impl<A: Artefact> Repository<A> {
                     Err(ErrorKind::UnpublishFailedSystemArtefact(id.to_string()).into())
                 } else if wrapper.instances.is_empty() {
                     let (_, w) = e.remove_entry();
-                    Ok(w.artefact)
+                    Ok(w.artefact.redacted())
                 } else {
                     Err(ErrorKind::UnpublishFailedNonZeroInstances(id.to_string()).into())
                 }
@@ -147,6 +164,7 @@ pub(crate) enum Msg<A: Artefact> {
     ),
     PublishArtefact(async_channel::Sender<Result<A>>, ArtefactId, bool, A),
     UnpublishArtefact(async_channel::Sender<Result<A>>, ArtefactId),
+    ReplaceArtefact(async_channel::Sender<Result<A>>, ArtefactId, A),
     RegisterInstance(async_channel::Sender<Result<A>>, ArtefactId, ServantId),
     UnregisterInstance(async_channel::Sender<Result<A>>, ArtefactId, ServantId),
 }
@@ -164,17 +182,17 @@ impl<A: Artefact + Send + Sync + 'static> Repository<A> {
                             .await?
                     }
                     Msg::PublishArtefact(r, id, sys, a) => {
-                        r.send(
-                            A::artefact_id(&id).and_then(|id| {
-                                self.publish(id, sys, a).map(std::clone::Clone::clone)
-                            }),
-                        )
-                        .await?
+                        r.send(A::artefact_id(&id).and_then(|id| self.publish(id, sys, a)))
+                            .await?
                     }
                     Msg::UnpublishArtefact(r, id) => {
                         r.send(A::artefact_id(&id).and_then(|id| self.unpublish(id)))
                             .await?
                     }
+                    Msg::ReplaceArtefact(r, id, a) => {
+                        r.send(A::artefact_id(&id).and_then(|id| self.replace(id, a)))
+                            .await?
+                    }
                     Msg::RegisterInstance(r, a_id, s_id) => {
                         r.send(
                             A::artefact_id(&a_id)
@@ -271,10 +289,12 @@ impl Repositories {
         rx.recv().await?
     }
 
-    /// Publish a pipeline
+    /// Publish a pipeline, `${env:..}` and `${file:..}` references in its
+    /// source are resolved and can be used in string literals
     ///
     /// # Errors
     ///  * if we can't publish a pipeline
+    ///  * if a reference can't be resolved
     pub async fn publish_pipeline(
         &self,
         id: &TremorURL,
@@ -300,6 +320,23 @@ impl Repositories {
         rx.recv().await?
     }
 
+    /// Replaces a published pipeline, running instances are not affected,
+    /// see `World::reload_pipeline` for swapping them
+    ///
+    /// # Errors
+    ///  * if the pipeline isn't published or is a system pipeline
+    pub async fn replace_pipeline(
+        &self,
+        id: &TremorURL,
+        artefact: PipelineArtefact,
+    ) -> Result<PipelineArtefact> {
+        let (tx, rx) = bounded(1);
+        self.pipeline
+            .send(Msg::ReplaceArtefact(tx, id.clone(), artefact))
+            .await?;
+        rx.recv().await?
+    }
+
     /// Bind a pipeline
     ///
     /// # Errors
@@ -334,7 +371,8 @@ impl Repositories {
         Ok(rx.recv().await?)
     }
 
-    /// serializes onramps
+    /// serializes onramps, resolved references in their configuration
+    /// are redacted
     ///
     /// # Errors
     ///  * if we can't serialize onramp
@@ -354,10 +392,12 @@ impl Repositories {
         rx.recv().await?
     }
 
-    /// Publish onramp
+    /// Publish onramp, `${env:..}` and `${file:..}` references in its
+    /// configuration are resolved
     ///
     /// # Errors
     ///  * if we can't publish the onramp
+    ///  * if a reference can't be resolved
     pub async fn publish_onramp(
         &self,
         id: &TremorURL,
@@ -417,7 +457,8 @@ impl Repositories {
         Ok(rx.recv().await?)
     }
 
-    /// Serialises offramps
+    /// Serialises offramps, resolved references in their configuration
+    /// are redacted
     ///
     /// # Errors
     ///  * if we cna't serialize a offramp
@@ -440,10 +481,12 @@ impl Repositories {
         rx.recv().await?
     }
 
-    /// Publishes an offramp
+    /// Publishes an offramp, `${env:..}` and `${file:..}` references in its
+    /// configuration are resolved
     ///
     /// # Errors
     ///  * if we can't publish a offramp
+    ///  * if a reference can't be resolved
     pub async fn publish_offramp(
         &self,
         id: &TremorURL,
@@ -526,10 +569,12 @@ impl Repositories {
         rx.recv().await?
     }
 
-    /// Publish a binding
+    /// Publish a binding, `${env:..}` and `${file:..}` references in its
+    /// links are resolved
     ///
     /// # Errors
     ///  * if we can't publish the binding
+    ///  * if a reference can't be resolved
     pub async fn publish_binding(
         &self,
         id: &TremorURL,
@@ -70,6 +70,16 @@ pub trait Artefact: Clone {
     ) -> Result<bool>;
     fn artefact_id(u: &TremorURL) -> Result<Id>;
     fn servant_id(u: &TremorURL) -> Result<ServantId>;
+    /// Resolves `${env:..}` and `${file:..}` references in the artefact,
+    /// this happens when it is published. Defaults to a noop.
+    fn interpolate(self) -> Result<Self> {
+        Ok(self)
+    }
+    /// The artefact with resolved references redacted, for handing it out
+    /// of the repository. Defaults to a clone.
+    fn redacted(&self) -> Self {
+        self.clone()
+    }
 }
 
 #[async_trait]
@@ -192,6 +202,10 @@ impl Artefact for Pipeline {
             _ => Err(format!("URL does not contain a pipeline servant id: {}", id).into()),
         }
     }
+
+    fn interpolate(self) -> Result<Self> {
+        crate::config::interpolate_query(self)
+    }
 }
 
 #[async_trait]
@@ -326,6 +340,14 @@ impl Artefact for OfframpArtefact {
             _ => Err(format!("URL does not contain a offramp servant id: {}", id).into()),
         }
     }
+
+    fn interpolate(self) -> Result<Self> {
+        crate::OffRamp::interpolate(self)
+    }
+
+    fn redacted(&self) -> Self {
+        crate::OffRamp::redacted(self)
+    }
 }
 #[async_trait]
 impl Artefact for OnrampArtefact {
@@ -471,6 +493,28 @@ impl Artefact for OnrampArtefact {
             _ => Err(format!("URL does not contain a onramp servant id: {}", id).into()),
         }
     }
+
+    fn interpolate(self) -> Result<Self> {
+        crate::OnRamp::interpolate(self)
+    }
+
+    fn redacted(&self) -> Self {
+        crate::OnRamp::redacted(self)
+    }
+}
+
+/// Replaces the `{name}` placeholders in the instance of a binding url by
+/// their mapping, `None` if the url has no instance
+fn map_instance(url: &TremorURL, mappings: &HashMap<String, String>) -> Option<TremorURL> {
+    let inst = url.instance()?;
+    let mut instance = String::new();
+    // This is because it is an URL and we have to use escape codes
+    for (map_name, map_replace) in mappings {
+        instance = inst.replace(&format!("%7B{}%7D", map_name), map_replace.as_str());
+    }
+    let mut url = url.clone();
+    url.set_instance(instance);
+    Some(url)
 }
 
 #[async_trait]
@@ -495,30 +539,29 @@ impl Artefact for Binding {
         let mut offramps: Vec<(TremorURL, TremorURL)> = Vec::new();
         let mut res = self.clone();
         res.binding.links.clear();
+        // the published links get the same mapping so the linked binding
+        // can still be redacted
+        res.binding.template = self.binding.template.as_ref().map(|template| {
+            template
+                .iter()
+                .filter_map(|(src, dsts)| {
+                    let tos = dsts
+                        .iter()
+                        .filter_map(|dst| map_instance(dst, &mappings))
+                        .collect();
+                    Some((map_instance(src, &mappings)?, tos))
+                })
+                .collect()
+        });
         for (src, dsts) in self.binding.links.clone() {
             // TODO: It should be validated ahead of time that every mapping has an instance!
             // * is a port
             // *  is a combination of on and offramp
-            if let Some(inst) = src.instance() {
-                let mut instance = String::new();
-                for (map_name, map_replace) in &mappings {
-                    instance = inst.replace(&format!("%7B{}%7D", map_name), map_replace.as_str());
-                }
-                let mut from = src.clone();
-                from.set_instance(instance);
+            if let Some(from) = map_instance(&src, &mappings) {
                 let mut tos: Vec<TremorURL> = Vec::new();
                 for dst in dsts {
                     // TODO: It should be validated ahead of time that every mapping has an instance!
-                    if let Some(inst) = dst.instance() {
-                        let mut instance = String::new();
-
-                        // This is because it is an URL and we have to use escape codes
-                        for (map_name, map_replace) in &mappings {
-                            instance =
-                                inst.replace(&format!("%7B{}%7D", map_name), map_replace.as_str());
-                        }
-                        let mut to = dst.clone();
-                        to.set_instance(instance);
+                    if let Some(to) = map_instance(&dst, &mappings) {
                         tos.push(to.clone());
                         match (from.resource_type(), to.resource_type()) {
                             (Some(ResourceType::Onramp), Some(ResourceType::Pipeline)) => {
@@ -689,4 +732,18 @@ impl Artefact for Binding {
             _ => Err(format!("URL does not contain a binding servant id: {}", id).into()),
         }
     }
+
+    fn interpolate(self) -> Result<Self> {
+        Ok(Self {
+            binding: self.binding.interpolate()?,
+            mapping: self.mapping,
+        })
+    }
+
+    fn redacted(&self) -> Self {
+        Self {
+            binding: self.binding.redacted(),
+            mapping: self.mapping.clone(),
+        }
+    }
 }
@@ -523,7 +523,7 @@ async fn codec_task(
                             );
                         }
                         // send CB_fail
-                        let mut insight = event.insight_fail();
+                        let mut insight = event.insight_fail().with_reason(&e.to_string());
                         insight.ingest_ns = nanotime();
                         if let Err(e) = reply_tx.send(sink::Reply::Insight(insight)).await {
                             error!("[Sink::{}] Error sending CB fail event {}", &sink_url, e);
@@ -650,7 +650,8 @@ async fn codec_task(
             CodecTaskInMsg::ReportFailure(id, op_meta, event_origin_uri, e) => {
                 // report send error as CB fail and response via ERROR port
                 // sending a CB close would mean we need to take measures to reopen - introduce a healthcheck
-                let mut insight = Event::cb_fail(nanotime(), id.clone());
+                let mut insight =
+                    Event::cb_fail(nanotime(), id.clone()).with_reason(&e.to_string());
                 insight.op_meta = op_meta;
                 if let Err(send_err) = reply_tx.send(sink::Reply::Insight(insight)).await {
                     error!(
@@ -18,6 +18,7 @@ use crate::metrics::RampReporter;
 use crate::onramp;
 use crate::pipeline;
 use crate::preprocessor::{make_preprocessors, preprocess, Preprocessors};
+use crate::topology::{Edge, Status};
 use crate::url::ports::{ERR, METRICS, OUT};
 use crate::url::TremorURL;
 
@@ -27,10 +28,10 @@ use async_std::task;
 use halfbrown::HashMap;
 use simd_json::Builder;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 use tremor_common::time::nanotime;
-use tremor_pipeline::{CBAction, Event, EventOriginUri, Ids};
+use tremor_pipeline::{CBAction, Event, EventOriginUri, Ids, SignalKind};
 use tremor_script::{LineValue, Value, ValueAndMeta};
 
 pub(crate) mod blaster;
@@ -172,6 +173,44 @@ where
     is_transactional: bool,
     /// Unique Id for the source
     uid: u64,
+    /// Ids of transactional events that are not yet acknowledged
+    in_flight: BTreeSet<u64>,
+    /// Deadline and reply channel once we are draining
+    draining: Option<(u64, Sender<()>)>,
+    /// If the source is connected
+    connected: bool,
+    /// Events sent to each pipeline connected to `out`
+    sent_out: HashMap<TremorURL, u64>,
+    /// Events sent to each pipeline connected to `err`
+    sent_err: HashMap<TremorURL, u64>,
+}
+
+/// Counts an event sent to a pipeline
+fn count_sent(sent: &mut HashMap<TremorURL, u64>, id: &TremorURL) {
+    if let Some(count) = sent.get_mut(id) {
+        *count += 1;
+    } else {
+        sent.insert(id.clone(), 1);
+    }
+}
+
+/// The links leaving a port of a source
+fn port_edges(
+    source_id: &TremorURL,
+    port: &Cow<'static, str>,
+    pipelines: &[(TremorURL, pipeline::Addr)],
+    sent: &HashMap<TremorURL, u64>,
+) -> Vec<Edge> {
+    let mut from = source_id.clone();
+    from.set_port(port.to_string());
+    pipelines
+        .iter()
+        .map(|(id, _)| Edge {
+            from: from.clone(),
+            to: id.clone(),
+            count: sent.get(id).copied().unwrap_or_default(),
+        })
+        .collect()
 }
 
 impl<T> SourceManager<T>
@@ -256,9 +295,76 @@ where
         results
     }
 
+    /// Sends a shutdown signal to all connected pipelines so they flush
+    /// their operators
+    async fn send_shutdown(&mut self) {
+        let signal = Event {
+            ingest_ns: nanotime(),
+            kind: Some(SignalKind::Shutdown),
+            ..Event::default()
+        };
+        for (id, addr) in self.pipelines_out.iter().chain(self.pipelines_err.iter()) {
+            if let Err(e) = addr.send(pipeline::Msg::Signal(signal.clone())).await {
+                error!(
+                    "[Source::{}] failed to send shutdown signal to {}: {}",
+                    self.source_id, id, e
+                );
+            }
+        }
+    }
+
+    /// Terminates the source once draining is done, only now the source
+    /// may commit what it has read
+    async fn finish_drain(&mut self) -> Result<()> {
+        if !self.in_flight.is_empty() {
+            warn!(
+                "[Source::{}] {} events were not acknowledged before the shutdown deadline.",
+                self.source_id,
+                self.in_flight.len()
+            );
+        }
+        self.source.terminate().await;
+        if let Some((_, tx)) = self.draining.take() {
+            tx.send(()).await?;
+        }
+        info!("[Source::{}] drained.", self.source_id);
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        let mut edges = port_edges(&self.source_id, &OUT, &self.pipelines_out, &self.sent_out);
+        edges.append(&mut port_edges(
+            &self.source_id,
+            &ERR,
+            &self.pipelines_err,
+            &self.sent_err,
+        ));
+        Status {
+            ready: self.connected,
+            edges,
+            dot: None,
+        }
+    }
+
+    /// Acknowledges or fails all in-flight events up to the given id
+    fn settle(&mut self, id: u64) {
+        self.in_flight = self.in_flight.split_off(&(id + 1));
+    }
+
     async fn handle_pipelines(&mut self) -> Result<bool> {
         loop {
-            let msg = if self.pipelines_out.is_empty() || self.triggered || !self.rx.is_empty() {
+            let msg = if let Some(deadline) = self.draining.as_ref().map(|(d, _)| *d) {
+                let now = nanotime();
+                if self.in_flight.is_empty() || now >= deadline {
+                    self.finish_drain().await?;
+                    return Ok(true);
+                }
+                let wait = Duration::from_nanos(deadline - now);
+                match async_std::future::timeout(wait, self.rx.recv()).await {
+                    Ok(msg) => msg?,
+                    Err(_) => continue,
+                }
+            } else if self.pipelines_out.is_empty() || self.triggered || !self.rx.is_empty() {
                 self.rx.recv().await?
             } else {
                 return Ok(false);
@@ -327,12 +433,14 @@ where
                 }
                 onramp::Msg::Cb(CBAction::Fail, ids) => {
                     if let Some(id) = ids.get(self.uid) {
+                        self.settle(id);
                         self.source.fail(id);
                     }
                 }
                 // Circuit breaker explicit acknowledgement of an event
                 onramp::Msg::Cb(CBAction::Ack, ids) => {
                     if let Some(id) = ids.get(self.uid) {
+                        self.settle(id);
                         self.source.ack(id);
                     }
                 }
@@ -348,6 +456,17 @@ where
                 }
                 onramp::Msg::Cb(CBAction::None, _ids) => {}
 
+                onramp::Msg::Drain { deadline, tx } => {
+                    info!("[Source::{}] draining.", self.source_id);
+                    self.draining = Some((deadline, tx));
+                    self.send_shutdown().await;
+                }
+                onramp::Msg::Status(tx) => {
+                    // the requester may have given up waiting
+                    if tx.send(self.status()).await.is_err() {
+                        debug!("[Source::{}] status request timed out.", self.source_id);
+                    }
+                }
                 onramp::Msg::Response(event) => {
                     if let Err(e) = self
                         .source
@@ -380,11 +499,12 @@ where
             ..Event::default()
         };
         let mut error = false;
+        let event_id = self.id;
         self.id += 1;
-        let pipelines = if OUT == port {
-            &mut self.pipelines_out
+        let (pipelines, sent) = if OUT == port {
+            (&mut self.pipelines_out, &mut self.sent_out)
         } else if ERR == port {
-            &mut self.pipelines_err
+            (&mut self.pipelines_err, &mut self.sent_err)
         } else {
             return false;
         };
@@ -402,8 +522,8 @@ where
                 self.metrics_reporter.increment_out();
             }
 
-            for (input, addr) in pipelines {
-                if let Some(input) = input.instance_port() {
+            for (input_id, addr) in pipelines {
+                if let Some(input) = input_id.instance_port() {
                     if let Err(e) = addr
                         .send(pipeline::Msg::Event {
                             input: input.to_string().into(),
@@ -416,6 +536,8 @@ where
                             self.source_id, e
                         );
                         error = true;
+                    } else {
+                        count_sent(sent, input_id);
                     }
                 }
             }
@@ -433,8 +555,14 @@ where
                         self.source_id, e
                     );
                     error = true;
+                } else {
+                    count_sent(sent, &last.0);
                 }
             }
+            // failed events are not tracked, the source fails them right away
+            if self.is_transactional && !error {
+                self.in_flight.insert(event_id);
+            }
         }
         error
     }
@@ -474,7 +602,7 @@ where
         let mut preprocessors = BTreeMap::new();
         preprocessors.insert(0, make_preprocessors(&&pp_template)?);
 
-        source.init().await?;
+        let state = source.init().await?;
         let is_transactional = source.is_transactional();
         Ok((
             Self {
@@ -494,6 +622,11 @@ where
                 pipelines_err: Vec::new(),
                 uid,
                 is_transactional,
+                in_flight: BTreeSet::new(),
+                draining: None,
+                connected: matches!(state, SourceState::Connected),
+                sent_out: HashMap::new(),
+                sent_err: HashMap::new(),
             },
             tx,
         ))
@@ -524,7 +657,7 @@ where
 
             // TODO: add a flag to the onramp to wait for the error pipelines to be populated as well
             //       lets call it `wait_for_error_pipelines` (horrible name)
-            if !self.triggered && !pipelines_out_empty {
+            if !self.triggered && !pipelines_out_empty && self.draining.is_none() {
                 match self.source.pull_event(self.id).await {
                     Ok(SourceReply::StartStream(id)) => {
                         self.preprocessors
@@ -600,7 +733,7 @@ where
                         }
                     }
                     Ok(SourceReply::StateChange(SourceState::Disconnected)) => return Ok(()),
-                    Ok(SourceReply::StateChange(SourceState::Connected)) => (),
+                    Ok(SourceReply::StateChange(SourceState::Connected)) => self.connected = true,
                     Ok(SourceReply::Empty(sleep_ms)) => {
                         task::sleep(Duration::from_millis(sleep_ms)).await
                     }
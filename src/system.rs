@@ -15,17 +15,23 @@
 use crate::config::{BindingVec, Config, MappingMap, OffRampVec, OnRampVec};
 use crate::errors::{Error, ErrorKind, Result};
 use crate::lifecycle::{ActivationState, ActivatorLifecycleFsm};
+use crate::metrics;
 use crate::registry::{Registries, ServantId};
 use crate::repository::{
     Artefact, BindingArtefact, OfframpArtefact, OnrampArtefact, PipelineArtefact, Repositories,
 };
+use crate::topology::{Status, Topology};
 use crate::url::ports::METRICS;
 use crate::url::TremorURL;
+use crate::Event;
 use async_channel::bounded;
 use async_std::io::prelude::*;
 use async_std::path::Path;
 use async_std::task::{self, JoinHandle};
 use hashbrown::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 use tremor_common::asy::file;
 use tremor_common::time::nanotime;
 
@@ -33,6 +39,17 @@ pub(crate) use crate::offramp;
 pub(crate) use crate::onramp;
 pub(crate) use crate::pipeline;
 
+/// Time we give onramps past the drain deadline to terminate
+const DRAIN_GRACE: Duration = Duration::from_secs(1);
+/// Time a servant has to report its status
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time listing the servants for a topology may take
+const TOPOLOGY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of tapped events buffered for a client before they are dropped
+const TAP_QSIZE: usize = 64;
+/// Unique ids of taps, they are not stored in the repository
+static TAP_ID: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     pub(crate) static ref METRICS_PIPELINE: TremorURL = {
         TremorURL::parse("/pipeline/system::metrics/system/in")
@@ -62,6 +79,11 @@ pub(crate) enum ManagerMsg {
         async_channel::Sender<Result<pipeline::Addr>>,
         pipeline::Create,
     ),
+    SwapPipeline(
+        async_channel::Sender<Result<()>>,
+        pipeline::Addr,
+        PipelineArtefact,
+    ),
     CreateOnramp(
         async_channel::Sender<Result<onramp::Addr>>,
         Box<onramp::Create>,
@@ -97,6 +119,11 @@ impl Manager {
                             .send(pipeline::ManagerMsg::Create(r, c))
                             .await?
                     }
+                    ManagerMsg::SwapPipeline(r, addr, c) => {
+                        self.pipeline
+                            .send(pipeline::ManagerMsg::Swap(r, addr, c))
+                            .await?
+                    }
                     ManagerMsg::CreateOnramp(r, c) => {
                         self.onramp.send(onramp::ManagerMsg::Create(r, c)).await?
                     }
@@ -121,6 +148,37 @@ impl Manager {
     }
 }
 
+/// Waits for the replies of draining servants, those that don't reply
+/// before the deadline are added to `failed`
+async fn await_drained(
+    replies: Vec<(TremorURL, async_channel::Receiver<()>)>,
+    deadline: u64,
+    failed: &mut Vec<String>,
+) {
+    for (id, rx) in replies {
+        let wait = Duration::from_nanos(deadline.saturating_sub(nanotime())) + DRAIN_GRACE;
+        match async_std::future::timeout(wait, rx.recv()).await {
+            Ok(Ok(())) => info!("{} drained", id),
+            Ok(Err(_)) => {
+                warn!("{} stopped before it was drained", id);
+                failed.push(id.to_string());
+            }
+            Err(_) => {
+                warn!("{} did not drain in time", id);
+                failed.push(id.to_string());
+            }
+        }
+    }
+}
+
+/// Waits for a servant to report its status
+async fn recv_status(rx: Option<async_channel::Receiver<Status>>) -> Option<Status> {
+    async_std::future::timeout(STATUS_TIMEOUT, rx?.recv())
+        .await
+        .ok()
+        .and_then(std::result::Result::ok)
+}
+
 /// Tremor runtime
 #[derive(Clone, Debug)]
 pub struct World {
@@ -223,6 +281,7 @@ impl World {
             (Some(_artefact), Some(_instance_id)) => {
                 let r = self.reg.unpublish_pipeline(id).await?;
                 self.repo.unbind_pipeline(id).await?;
+                metrics::forget(id);
                 Ok(r)
             }
             (None, _) => Err(ErrorKind::ArtifactNotFound(id.to_string()).into()),
@@ -230,6 +289,259 @@ impl World {
         }
     }
 
+    /// Reloads a pipeline artefact, swapping the graph of all its running
+    /// instances. Queued events are processed by the old graph before it is
+    /// swapped. If any instance fails to swap, already swapped instances are
+    /// rolled back and the artefact is left unchanged.
+    /// Returns the number of swapped instances.
+    ///
+    /// # Errors
+    ///  * if the pipeline isn't published
+    ///  * if the new pipeline can't be swapped in
+    pub async fn reload_pipeline(
+        &self,
+        id: &TremorURL,
+        artefact: PipelineArtefact,
+    ) -> Result<usize> {
+        info!("Reloading pipeline {}", id);
+        let current = self
+            .repo
+            .find_pipeline(id)
+            .await?
+            .ok_or_else(|| Error::from(ErrorKind::ArtifactNotFound(id.to_string())))?;
+        let mut swapped = Vec::with_capacity(current.instances.len());
+        for instance in &current.instances {
+            let res = match self.reg.find_pipeline(instance).await? {
+                Some(addr) => self
+                    .swap_pipeline(addr.clone(), artefact.clone())
+                    .await
+                    .map(|_| addr),
+                None => Err(ErrorKind::ArtifactNotFound(instance.to_string()).into()),
+            };
+            match res {
+                Ok(addr) => swapped.push(addr),
+                Err(e) => {
+                    error!("Failed to swap {}, rolling back: {}", instance, e);
+                    for addr in swapped {
+                        if let Err(e) = self
+                            .swap_pipeline(addr.clone(), current.artefact.clone())
+                            .await
+                        {
+                            error!("Failed to roll back {:?}: {}", addr, e);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.repo.replace_pipeline(id, artefact).await?;
+        Ok(swapped.len())
+    }
+
+    async fn swap_pipeline(&self, addr: pipeline::Addr, config: PipelineArtefact) -> Result<()> {
+        let (tx, rx) = bounded(1);
+        self.system
+            .send(ManagerMsg::SwapPipeline(tx, addr, config))
+            .await?;
+        rx.recv().await?
+    }
+
+    /// Drains all running servants before a shutdown. Onramps stop pulling
+    /// events and flush their pipelines with a shutdown signal, then wait for
+    /// their in-flight events to be acknowledged before they terminate and
+    /// commit. Once the onramps are done, pipelines and then offramps confirm
+    /// they processed everything that was sent to them until then.
+    ///
+    /// # Errors
+    ///  * if the servants can't be listed
+    ///  * if a servant didn't drain before the timeout passed
+    pub async fn drain(&self, timeout: Duration) -> Result<()> {
+        let timeout_ns = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        let deadline = nanotime().saturating_add(timeout_ns);
+        let mut failed = Vec::new();
+
+        let mut replies = Vec::new();
+        for id in self.repo.list_onramps().await? {
+            if let Some(wrapper) = self.repo.find_onramp(&id).await? {
+                for instance in wrapper.instances {
+                    if let Some(addr) = self.reg.find_onramp(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if let Err(e) = addr.send(onramp::Msg::Drain { deadline, tx }).await {
+                            warn!("Failed to drain onramp {}: {}", instance, e);
+                            failed.push(instance.to_string());
+                        } else {
+                            replies.push((instance, rx));
+                        }
+                    }
+                }
+            }
+        }
+        info!("Draining {} onramps", replies.len());
+        await_drained(replies, deadline, &mut failed).await;
+
+        // pipelines and offramps handle their messages in order, so once they
+        // replied they processed the shutdown signals and events before
+        let mut replies = Vec::new();
+        for id in self.repo.list_pipelines().await? {
+            if let Some(wrapper) = self.repo.find_pipeline(&id).await? {
+                for instance in wrapper.instances {
+                    if let Some(addr) = self.reg.find_pipeline(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if let Err(e) = addr.send(pipeline::Msg::Drain(tx)).await {
+                            warn!("Failed to drain pipeline {}: {}", instance, e);
+                            failed.push(instance.to_string());
+                        } else {
+                            replies.push((instance, rx));
+                        }
+                    }
+                }
+            }
+        }
+        info!("Draining {} pipelines", replies.len());
+        await_drained(replies, deadline, &mut failed).await;
+
+        let mut replies = Vec::new();
+        for id in self.repo.list_offramps().await? {
+            if let Some(wrapper) = self.repo.find_offramp(&id).await? {
+                for instance in wrapper.instances {
+                    if let Some(addr) = self.reg.find_offramp(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if let Err(e) = addr.send(offramp::Msg::Drain(tx)).await {
+                            warn!("Failed to drain offramp {}: {}", instance, e);
+                            failed.push(instance.to_string());
+                        } else {
+                            replies.push((instance, rx));
+                        }
+                    }
+                }
+            }
+        }
+        info!("Draining {} offramps", replies.len());
+        await_drained(replies, deadline, &mut failed).await;
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to drain {}", failed.join(", ")).into())
+        }
+    }
+
+    /// Taps a port of a running pipeline instance, a copy of the events
+    /// leaving the port is sent to the returned channel, limited to `rate`
+    /// events per second. Events are dropped rather than slowing the
+    /// pipeline down, the tap is detached once the receiver is dropped.
+    ///
+    /// # Errors
+    ///  * if the id isn't a pipeline port or the instance isn't running
+    ///  * if the pipeline doesn't have the port
+    pub async fn tap_pipeline(
+        &self,
+        id: &TremorURL,
+        rate: u64,
+    ) -> Result<async_channel::Receiver<Event>> {
+        let port = id.instance_port_required()?.to_string();
+        let addr = self
+            .reg
+            .find_pipeline(id)
+            .await?
+            .ok_or_else(|| Error::from(ErrorKind::ArtifactNotFound(id.to_string())))?;
+        let tap_id = TremorURL::parse(&format!(
+            "/offramp/system::tap/{}/in",
+            TAP_ID.fetch_add(1, Ordering::Relaxed)
+        ))?;
+        info!("Tapping {} as {}", id, tap_id);
+        let (tx, rx) = bounded(TAP_QSIZE);
+        let (reply_tx, reply_rx) = bounded(1);
+        addr.send_mgmt(pipeline::MgmtMsg::ConnectTap(
+            port.into(),
+            tap_id,
+            pipeline::Tap::new(tx, rate),
+            reply_tx,
+        ))
+        .await?;
+        reply_rx.recv().await??;
+        Ok(rx)
+    }
+
+    /// Checks if the runtime is still running
+    #[must_use]
+    pub fn is_live(&self) -> bool {
+        !self.system.is_closed()
+    }
+
+    /// Collects the running onramps, pipelines and offramps along with the
+    /// links between them. All servants are asked for their status at once,
+    /// those whose queue is full or that don't report in time are included
+    /// as not ready.
+    ///
+    /// # Errors
+    ///  * if the repository or registry can't be queried
+    ///  * if the servants can't be listed before the deadline
+    pub async fn topology(&self) -> Result<Topology> {
+        // we never block on a busy servant, so only listing them can take
+        // longer than the status timeout
+        let pending = async_std::future::timeout(TOPOLOGY_TIMEOUT, self.request_status())
+            .await
+            .map_err(|_| Error::from("Timed out listing the running servants"))??;
+        let (instances, pending): (Vec<_>, Vec<_>) = pending.into_iter().unzip();
+        let statuses = futures::future::join_all(pending.into_iter().map(recv_status)).await;
+        let mut topology = Topology::default();
+        for (instance, status) in instances.into_iter().zip(statuses) {
+            topology.add(instance, status);
+        }
+        Ok(topology)
+    }
+
+    /// Asks every running servant for its status without waiting for it
+    async fn request_status(
+        &self,
+    ) -> Result<Vec<(TremorURL, Option<async_channel::Receiver<Status>>)>> {
+        let mut pending = Vec::new();
+        for id in self.repo.list_onramps().await? {
+            if let Some(wrapper) = self.repo.find_onramp(&id).await? {
+                for instance in wrapper.instances {
+                    let mut status = None;
+                    if let Some(addr) = self.reg.find_onramp(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if addr.try_send(onramp::Msg::Status(tx)).is_ok() {
+                            status = Some(rx);
+                        }
+                    }
+                    pending.push((instance, status));
+                }
+            }
+        }
+        for id in self.repo.list_pipelines().await? {
+            if let Some(wrapper) = self.repo.find_pipeline(&id).await? {
+                for instance in wrapper.instances {
+                    let mut status = None;
+                    if let Some(addr) = self.reg.find_pipeline(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if addr.try_send_mgmt(pipeline::MgmtMsg::Status(tx)) {
+                            status = Some(rx);
+                        }
+                    }
+                    pending.push((instance, status));
+                }
+            }
+        }
+        for id in self.repo.list_offramps().await? {
+            if let Some(wrapper) = self.repo.find_offramp(&id).await? {
+                for instance in wrapper.instances {
+                    let mut status = None;
+                    if let Some(addr) = self.reg.find_offramp(&instance).await? {
+                        let (tx, rx) = bounded(1);
+                        if addr.try_send(offramp::Msg::Status(tx)).is_ok() {
+                            status = Some(rx);
+                        }
+                    }
+                    pending.push((instance, status));
+                }
+            }
+        }
+        Ok(pending)
+    }
+
     /// Stop the runtime
     ///
     /// # Errors
@@ -348,6 +660,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_onramp(id).await;
                 self.repo.unbind_onramp(id).await?;
+                metrics::forget(id);
                 r
             }
             (None, _) => Err(ErrorKind::ArtifactNotFound(id.to_string()).into()),
@@ -455,6 +768,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_offramp(id).await;
                 self.repo.unbind_offramp(id).await?;
+                metrics::forget(id);
                 r
             }
             (None, _) => Err(ErrorKind::ArtifactNotFound(id.to_string()).into()),
@@ -662,7 +976,13 @@ impl World {
     ) -> Result<(Self, JoinHandle<Result<()>>)> {
         let (onramp_h, onramp) = onramp::Manager::new(qsize).start();
         let (offramp_h, offramp) = offramp::Manager::new(qsize).start();
-        let (pipeline_h, pipeline) = pipeline::Manager::new(qsize).start();
+        let checkpoints = if let Some(storage_directory) = &storage_directory {
+            let path = std::path::Path::new(storage_directory).join("checkpoints");
+            Some(tremor_pipeline::checkpoint::Store::open(path)?)
+        } else {
+            None
+        };
+        let (pipeline_h, pipeline) = pipeline::Manager::new(qsize, checkpoints).start();
 
         let (system_h, system) = Manager {
             offramp,
@@ -0,0 +1,230 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::url::{ResourceType, TremorURL};
+use std::fmt::Write;
+
+/// A link between two ports of running servants
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Edge {
+    /// The sending port
+    pub from: TremorURL,
+    /// The receiving port
+    pub to: TremorURL,
+    /// Number of events sent over the link
+    pub count: u64,
+}
+
+/// The state a running servant reports about itself
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// If the servant is ready to process events, onramps are ready once
+    /// they are connected and offramps once their sink is active
+    pub ready: bool,
+    /// The links leaving the servant
+    pub edges: Vec<Edge>,
+    /// The graph of a pipeline in dot format
+    pub dot: Option<String>,
+}
+
+/// A running servant
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    /// The servant id
+    pub id: TremorURL,
+    /// If the servant is ready to process events, this is false for servants
+    /// that didn't report their state in time
+    pub ready: bool,
+    /// The graph of a pipeline in dot format
+    #[serde(skip)]
+    pub dot: Option<String>,
+}
+
+/// The running graph of onramps, pipelines and offramps
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Topology {
+    /// All running servants
+    pub nodes: Vec<Node>,
+    /// All links between them
+    pub edges: Vec<Edge>,
+}
+
+impl Topology {
+    /// Adds a servant, servants without a status are not ready
+    pub(crate) fn add(&mut self, id: TremorURL, status: Option<Status>) {
+        let Status {
+            ready,
+            mut edges,
+            dot,
+        } = status.unwrap_or_default();
+        self.nodes.push(Node { id, ready, dot });
+        self.edges.append(&mut edges);
+    }
+
+    /// Servants that are not ready, only onramps and offramps are considered
+    #[must_use]
+    pub fn pending(&self) -> Vec<&TremorURL> {
+        self.nodes
+            .iter()
+            .filter(|n| {
+                !n.ready
+                    && matches!(
+                        n.id.resource_type(),
+                        Some(ResourceType::Onramp) | Some(ResourceType::Offramp)
+                    )
+            })
+            .map(|n| &n.id)
+            .collect()
+    }
+
+    /// Renders the topology in dot format, pipelines are drawn as clusters
+    /// holding their own graph with links connecting to their port nodes
+    #[must_use]
+    pub fn dot(&self) -> String {
+        let mut out = String::from("digraph {\n");
+        for node in &self.nodes {
+            let id = node.id.to_string();
+            if let Some(dot) = &node.dot {
+                let _ = writeln!(out, "    subgraph \"cluster_{}\" {{", id);
+                let _ = writeln!(out, "        label=\"{}\"", id);
+                for line in graph_body(dot) {
+                    let _ = writeln!(out, "        {}", prefix_ids(line, &id));
+                }
+                out.push_str("    }\n");
+            } else {
+                let _ = writeln!(out, "    \"{}\" [label=\"{}\", shape=box]", id, id);
+            }
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "    {} -> {} [label=\"{}\"]",
+                self.endpoint(&edge.from, false),
+                self.endpoint(&edge.to, true),
+                edge.count
+            );
+        }
+        out.push('}');
+        out
+    }
+
+    /// The dot node of a port, for pipelines this is the node of the port
+    /// inside the pipeline graph
+    fn endpoint(&self, port: &TremorURL, input: bool) -> String {
+        let mut servant = port.clone();
+        servant.trim_to_instance();
+        let id = servant.to_string();
+        let port_node = port.instance_port().and_then(|port| {
+            let label = if input {
+                format!("\"--> {}\"", port)
+            } else {
+                format!("\"{} -->\"", port)
+            };
+            self.nodes
+                .iter()
+                .find(|n| n.id == servant)
+                .and_then(|n| n.dot.as_ref())
+                .and_then(|dot| {
+                    graph_body(dot)
+                        .find(|l| l.contains(&label))
+                        .and_then(|l| l.split_whitespace().next())
+                        .map(|idx| format!("\"{}#{}\"", id, idx))
+                })
+        });
+        port_node.unwrap_or_else(|| format!("\"{}\"", id))
+    }
+}
+
+/// The node and edge lines of a graph in dot format
+fn graph_body(dot: &str) -> impl Iterator<Item = &str> {
+    dot.lines()
+        .map(str::trim)
+        .filter(|l| l.chars().next().map_or(false, |c| c.is_ascii_digit()))
+}
+
+/// Prefixes the numeric node ids of a node or edge line so graphs of
+/// different pipelines don't collide
+fn prefix_ids(line: &str, prefix: &str) -> String {
+    let mut parts = line.splitn(2, ' ');
+    let from = parts.next().unwrap_or_default();
+    let rest = parts.next().unwrap_or_default();
+    if let Some(rest) = rest.strip_prefix("-> ") {
+        let mut parts = rest.splitn(2, ' ');
+        let to = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default();
+        format!("\"{}#{}\" -> \"{}#{}\" {}", prefix, from, prefix, to, rest)
+    } else {
+        format!("\"{}#{}\" {}", prefix, from, rest)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::Result;
+
+    #[test]
+    fn dot() -> Result<()> {
+        let pipeline = TremorURL::parse("/pipeline/main/01")?;
+        let topology = Topology {
+            nodes: vec![
+                Node {
+                    id: TremorURL::parse("/onramp/in/01")?,
+                    ready: true,
+                    dot: None,
+                },
+                Node {
+                    id: pipeline,
+                    ready: true,
+                    dot: Some(
+                        "digraph {\n    0 [ label = \"--> in\" ]\n    1 [ label = \"out -->\" ]\n    0 -> 1 [ ]\n}\n"
+                            .to_string(),
+                    ),
+                },
+                Node {
+                    id: TremorURL::parse("/offramp/out/01")?,
+                    ready: false,
+                    dot: None,
+                },
+            ],
+            edges: vec![
+                Edge {
+                    from: TremorURL::parse("/onramp/in/01/out")?,
+                    to: TremorURL::parse("/pipeline/main/01/in")?,
+                    count: 3,
+                },
+                Edge {
+                    from: TremorURL::parse("/pipeline/main/01/out")?,
+                    to: TremorURL::parse("/offramp/out/01/in")?,
+                    count: 2,
+                },
+            ],
+        };
+        let dot = topology.dot();
+        assert!(dot.contains(
+            "\"tremor://localhost/onramp/in/01\" -> \"tremor://localhost/pipeline/main/01#0\" [label=\"3\"]"
+        ));
+        assert!(dot.contains(
+            "\"tremor://localhost/pipeline/main/01#1\" -> \"tremor://localhost/offramp/out/01\" [label=\"2\"]"
+        ));
+        assert!(dot.contains(
+            "\"tremor://localhost/pipeline/main/01#0\" -> \"tremor://localhost/pipeline/main/01#1\" [ ]"
+        ));
+        assert_eq!(
+            topology.pending(),
+            vec![&TremorURL::parse("/offramp/out/01")?]
+        );
+        Ok(())
+    }
+}
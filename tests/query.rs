@@ -92,6 +92,7 @@ macro_rules! test_cases {
 }
 
 test_cases!(
+    anomaly,
     default_rule,
     dimensions,
     example_rule,
@@ -22,11 +22,16 @@ use tremor_runtime::system::World;
 use tremor_runtime::url::TremorURL;
 use tremor_script::prelude::CompilerError;
 
+pub mod auth;
 pub mod binding;
+pub mod dlq;
+pub mod health;
+pub mod metrics;
 pub mod offramp;
 pub mod onramp;
 pub mod pipeline;
 pub mod prelude;
+pub mod topology;
 pub mod version;
 
 pub type Request = tide::Request<State>;
@@ -0,0 +1,315 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication and role based access control for the API
+//!
+//! Requests authenticate with a bearer token, or with a TLS client
+//! certificate if the listener requires one. Every route requires one of
+//! three roles, a role grants access to everything the roles below it can
+//! access:
+//!
+//! * `read-only` can list and fetch artefacts and instances
+//! * `operator` can additionally link and unlink instances, tap pipelines
+//!   and inspect, re-inject and discard dead letters
+//! * `admin` can additionally publish and unpublish artefacts
+//!
+//! Health checks are not authenticated so orchestrators can probe them.
+
+use crate::api::prelude::*;
+use http_types::Method;
+use tide::{Middleware, Next};
+
+/// The role of a client
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+/// A bearer token and the role it grants
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Token {
+    /// the token, may be an `${env:..}` or `${file:..}` reference
+    pub token: String,
+    pub role: Role,
+}
+
+/// TLS settings of the API listener
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// PEM encoded certificate chain
+    pub cert: String,
+    /// PEM encoded private key
+    pub key: String,
+    /// PEM encoded CA to verify client certificates with, if set clients
+    /// have to present a certificate signed by it
+    pub client_ca: Option<String>,
+}
+
+/// Security settings of the API
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub tls: Option<Tls>,
+    #[serde(default)]
+    pub tokens: Vec<Token>,
+    /// role of clients authenticated by their TLS client certificate
+    pub client_cert_role: Option<Role>,
+    /// role of requests without credentials, they are rejected if unset
+    pub anonymous_role: Option<Role>,
+}
+
+impl Config {
+    /// Resolves `${env:..}` and `${file:..}` references in the tokens
+    pub fn resolve(mut self) -> tremor_runtime::errors::Result<Self> {
+        for token in &mut self.tokens {
+            if let Some(resolved) = tremor_runtime::config::interpolate_str(&token.token)? {
+                token.token = resolved;
+            }
+        }
+        Ok(self)
+    }
+
+    fn verifies_clients(&self) -> bool {
+        self.tls
+            .as_ref()
+            .map_or(false, |tls| tls.client_ca.is_some())
+    }
+}
+
+/// The role a route requires, `None` for routes open to everyone
+#[must_use]
+pub fn required_role(method: Method, path: &str) -> Option<Role> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match (method, segments.as_slice()) {
+        (Method::Get, ["health", ..]) => None,
+        // both expose event payloads
+        (Method::Get, [.., "tap"]) | (Method::Get, ["dlq", _, _]) => Some(Role::Operator),
+        (Method::Get, _) | (Method::Head, _) => Some(Role::ReadOnly),
+        // linking and unlinking instances, re-injecting and discarding dead letters
+        (Method::Post, [_, _, _]) | (Method::Delete, [_, _, _]) => Some(Role::Operator),
+        _ => Some(Role::Admin),
+    }
+}
+
+/// Compares two tokens in constant time
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0_u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Middleware authenticating requests and enforcing the role of each route
+pub struct Rbac {
+    config: Config,
+}
+
+impl Rbac {
+    #[must_use]
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// The highest role the `Authorization` header of a request grants
+    fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        // with a client CA the listener only accepts verified clients
+        let cert_role = if self.config.verifies_clients() {
+            self.config.client_cert_role
+        } else {
+            None
+        };
+        if let Some(authorization) = authorization {
+            // unknown, empty or malformed credentials are rejected rather
+            // than treated as anonymous
+            let bearer = authorization
+                .strip_prefix("Bearer ")
+                .filter(|b| !b.is_empty())?;
+            let token_role = self
+                .config
+                .tokens
+                .iter()
+                .filter(|t| !t.token.is_empty() && token_eq(&t.token, bearer))
+                .map(|t| t.role)
+                .max()?;
+            Some(token_role).max(cert_role)
+        } else {
+            cert_role.or(self.config.anonymous_role)
+        }
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for Rbac {
+    async fn handle(&self, req: Request, next: Next<'_, State>) -> tide::Result {
+        if let Some(required) = required_role(req.method(), req.url().path()) {
+            let authorization = req
+                .header(headers::AUTHORIZATION)
+                .map(headers::HeaderValues::last)
+                .map(headers::HeaderValue::as_str);
+            match self.role(authorization) {
+                Some(role) if role >= required => (),
+                Some(_) => return Ok(Response::new(StatusCode::Forbidden)),
+                None => {
+                    let mut r = Response::new(StatusCode::Unauthorized);
+                    r.insert_header(headers::WWW_AUTHENTICATE, "Bearer");
+                    return Ok(r);
+                }
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn token(token: &str, role: Role) -> Token {
+        Token {
+            token: token.to_string(),
+            role,
+        }
+    }
+
+    fn with_tokens(config: Config) -> Rbac {
+        Rbac::new(Config {
+            tokens: vec![
+                token("snot", Role::ReadOnly),
+                token("badger", Role::Admin),
+                token("", Role::Admin),
+            ],
+            ..config
+        })
+    }
+
+    fn client_ca() -> Option<Tls> {
+        Some(Tls {
+            cert: "cert".to_string(),
+            key: "key".to_string(),
+            client_ca: Some("ca".to_string()),
+        })
+    }
+
+    #[test]
+    fn required_roles() {
+        let open = [
+            (Method::Get, "/health/live"),
+            (Method::Get, "/health/ready"),
+        ];
+        for (method, path) in &open {
+            assert_eq!(required_role(*method, path), None, "{} {}", method, path);
+        }
+        let routes = [
+            (Method::Get, "/version", Role::ReadOnly),
+            (Method::Get, "/metrics", Role::ReadOnly),
+            (Method::Get, "/topology", Role::ReadOnly),
+            (Method::Get, "/pipeline", Role::ReadOnly),
+            (Method::Get, "/pipeline/main", Role::ReadOnly),
+            (Method::Head, "/binding/default/01", Role::ReadOnly),
+            (Method::Get, "/dlq", Role::ReadOnly),
+            (Method::Get, "/dlq/main", Role::ReadOnly),
+            (Method::Get, "/pipeline/main/01/out/tap", Role::Operator),
+            (Method::Get, "/dlq/main/01", Role::Operator),
+            (Method::Post, "/dlq/main/01", Role::Operator),
+            (Method::Delete, "/dlq/main/01", Role::Operator),
+            (Method::Post, "/binding/default/01", Role::Operator),
+            (Method::Delete, "/binding/default/01", Role::Operator),
+            (Method::Post, "/pipeline", Role::Admin),
+            (Method::Post, "/binding", Role::Admin),
+            (Method::Delete, "/pipeline/main", Role::Admin),
+            (Method::Delete, "/onramp/in", Role::Admin),
+            (Method::Put, "/offramp/out", Role::Admin),
+        ];
+        for (method, path, role) in &routes {
+            assert_eq!(
+                required_role(*method, path),
+                Some(*role),
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let rbac = with_tokens(Config::default());
+        assert_eq!(rbac.role(Some("Bearer snot")), Some(Role::ReadOnly));
+        assert_eq!(rbac.role(Some("Bearer badger")), Some(Role::Admin));
+        assert_eq!(rbac.role(Some("Bearer snotbadger")), None);
+        assert_eq!(rbac.role(Some("Bearer sno")), None);
+        assert_eq!(rbac.role(Some("Bearer ")), None);
+        assert_eq!(rbac.role(Some("Bearer")), None);
+        assert_eq!(rbac.role(Some("Basic c25vdDpiYWRnZXI=")), None);
+        assert_eq!(rbac.role(None), None);
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert!(token_eq("snot", "snot"));
+        assert!(token_eq("", ""));
+        assert!(!token_eq("snot", "snout"));
+        assert!(!token_eq("snot", "sno"));
+        assert!(!token_eq("snot", "snotbadger"));
+        assert!(!token_eq("", "snot"));
+        assert!(!token_eq("snot", "badg"));
+    }
+
+    #[test]
+    fn anonymous() {
+        let rbac = with_tokens(Config {
+            anonymous_role: Some(Role::ReadOnly),
+            ..Config::default()
+        });
+        assert_eq!(rbac.role(None), Some(Role::ReadOnly));
+        assert_eq!(rbac.role(Some("Bearer badger")), Some(Role::Admin));
+        // an unknown token doesn't fall back to the anonymous role
+        assert_eq!(rbac.role(Some("Bearer snout")), None);
+    }
+
+    #[test]
+    fn client_certificates() {
+        // without a client CA clients aren't verified, so certificates grant nothing
+        let rbac = with_tokens(Config {
+            client_cert_role: Some(Role::Operator),
+            ..Config::default()
+        });
+        assert_eq!(rbac.role(None), None);
+
+        let rbac = with_tokens(Config {
+            tls: client_ca(),
+            client_cert_role: Some(Role::Operator),
+            anonymous_role: Some(Role::ReadOnly),
+            ..Config::default()
+        });
+        assert_eq!(rbac.role(None), Some(Role::Operator));
+        // the higher of the token and the certificate role is granted
+        assert_eq!(rbac.role(Some("Bearer snot")), Some(Role::Operator));
+        assert_eq!(rbac.role(Some("Bearer badger")), Some(Role::Admin));
+        assert_eq!(rbac.role(Some("Bearer snout")), None);
+
+        let rbac = with_tokens(Config {
+            tls: client_ca(),
+            anonymous_role: Some(Role::ReadOnly),
+            ..Config::default()
+        });
+        assert_eq!(rbac.role(None), Some(Role::ReadOnly));
+    }
+}
@@ -72,7 +72,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
         .ok_or_else(Error::not_found)?;
 
     let result = BindingWrap {
-        artefact: result.artefact.binding,
+        artefact: result.artefact.binding.redacted(),
         instances: result
             .instances
             .iter()
@@ -93,7 +93,8 @@ pub async fn get_servant(req: Request) -> Result<Response> {
         .find_binding(&url)
         .await?
         .ok_or_else(Error::not_found)?
-        .binding;
+        .binding
+        .redacted();
 
     reply(req, result, false, StatusCode::Ok).await
 }
@@ -107,7 +108,11 @@ pub async fn link_servant(req: Request) -> Result<Response> {
     let url = build_url(&["binding", &a_id, &s_id])?;
     let world = &req.state().world;
 
-    let result = world.link_binding(&url, decoded_data).await?.binding;
+    let result = world
+        .link_binding(&url, decoded_data)
+        .await?
+        .binding
+        .redacted();
 
     reply(req, result, true, StatusCode::Created).await
 }
@@ -119,7 +124,11 @@ pub async fn unlink_servant(req: Request) -> Result<Response> {
     let url = build_url(&["binding", &a_id, &s_id])?;
 
     let world = &req.state().world;
-    let result = world.unlink_binding(&url, HashMap::new()).await?.binding;
+    let result = world
+        .unlink_binding(&url, HashMap::new())
+        .await?
+        .binding
+        .redacted();
 
     reply(req, result, true, StatusCode::NoContent).await
 }
@@ -0,0 +1,105 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::prelude::*;
+use simd_json::OwnedValue;
+use tremor_pipeline::dlq::{self, DeadLetter, Store};
+
+#[derive(Serialize)]
+struct Letter {
+    id: u64,
+    reason: String,
+    attempts: u64,
+    failed_ns: u64,
+    ingest_ns: u64,
+    /// the event is only included when a single letter is fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<OwnedValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<OwnedValue>,
+}
+
+impl Letter {
+    fn new(id: u64, letter: DeadLetter, with_event: bool) -> Self {
+        let (value, meta) = if with_event {
+            let payload = letter.event.data.suffix();
+            (
+                Some(OwnedValue::from(payload.value().clone())),
+                Some(OwnedValue::from(payload.meta().clone())),
+            )
+        } else {
+            (None, None)
+        };
+        Self {
+            id,
+            reason: letter.reason,
+            attempts: letter.attempts,
+            failed_ns: letter.failed_ns,
+            ingest_ns: letter.event.ingest_ns,
+            value,
+            meta,
+        }
+    }
+}
+
+fn store(req: &Request) -> Result<Store> {
+    let id: String = req.param("aid").unwrap_or_default();
+    dlq::store(&id)?.ok_or_else(Error::not_found)
+}
+
+fn letter_id(req: &Request) -> Result<u64> {
+    req.param("sid").map_err(|_| {
+        Error::json(
+            StatusCode::BadRequest,
+            &r#"{"error": "Dead letter ids are numeric"}"#,
+        )
+    })
+}
+
+pub async fn list_queues(req: Request) -> Result<Response> {
+    let result = dlq::stores()?;
+    reply(req, result, false, StatusCode::Ok).await
+}
+
+pub async fn list_letters(req: Request) -> Result<Response> {
+    let result: Vec<Letter> = store(&req)?
+        .list()?
+        .into_iter()
+        .map(|(id, letter)| Letter::new(id, letter, false))
+        .collect();
+    reply(req, result, false, StatusCode::Ok).await
+}
+
+pub async fn get_letter(req: Request) -> Result<Response> {
+    let id = letter_id(&req)?;
+    let letter = store(&req)?.get(id)?.ok_or_else(Error::not_found)?;
+    reply(req, Letter::new(id, letter, true), false, StatusCode::Ok).await
+}
+
+/// Hands a dead letter back to its operator to be delivered again
+pub async fn reinject_letter(req: Request) -> Result<Response> {
+    let id = letter_id(&req)?;
+    let store = store(&req)?;
+    let letter = store.get(id)?.ok_or_else(Error::not_found)?;
+    if !store.reinject(id)? {
+        return Err(Error::not_found());
+    }
+    reply(req, Letter::new(id, letter, false), false, StatusCode::Ok).await
+}
+
+pub async fn delete_letter(req: Request) -> Result<Response> {
+    let id = letter_id(&req)?;
+    let letter = store(&req)?.remove(id)?.ok_or_else(Error::not_found)?;
+    reply(req, Letter::new(id, letter, true), false, StatusCode::Ok).await
+}
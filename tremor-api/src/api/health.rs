@@ -0,0 +1,49 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::prelude::*;
+
+#[derive(Serialize)]
+struct Live {
+    live: bool,
+}
+
+#[derive(Serialize)]
+struct Ready {
+    ready: bool,
+    /// onramps that are not connected and offramps that are not active
+    pending: Vec<String>,
+}
+
+pub async fn live(req: Request) -> std::result::Result<Response, crate::Error> {
+    let live = req.state().world.is_live();
+    let code = if live {
+        StatusCode::Ok
+    } else {
+        StatusCode::ServiceUnavailable
+    };
+    reply(req, Live { live }, false, code).await
+}
+
+pub async fn ready(req: Request) -> std::result::Result<Response, crate::Error> {
+    let topology = req.state().world.topology().await?;
+    let pending: Vec<String> = topology.pending().iter().map(ToString::to_string).collect();
+    let ready = pending.is_empty();
+    let code = if ready {
+        StatusCode::Ok
+    } else {
+        StatusCode::ServiceUnavailable
+    };
+    reply(req, Ready { ready, pending }, false, code).await
+}
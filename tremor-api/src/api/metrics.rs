@@ -0,0 +1,25 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::prelude::*;
+
+/// Content type of the prometheus text exposition format
+const PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4";
+
+pub async fn get(_req: Request) -> std::result::Result<Response, crate::Error> {
+    let mut r = Response::new(StatusCode::Ok);
+    r.insert_header(headers::CONTENT_TYPE, PROMETHEUS_TEXT);
+    r.set_body(tremor_runtime::metrics::prometheus());
+    Ok(r)
+}
@@ -55,7 +55,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
         .await?
         .ok_or_else(Error::not_found)?;
     let result = OffRampWrap {
-        artefact: result.artefact,
+        artefact: result.artefact.redacted(),
         instances: result
             .instances
             .iter()
@@ -53,7 +53,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
     let repo = &req.state().world.repo;
     let result = repo.find_onramp(&url).await?.ok_or_else(Error::not_found)?;
     let result = OnRampWrap {
-        artefact: result.artefact,
+        artefact: result.artefact.redacted(),
         instances: result
             .instances
             .iter()
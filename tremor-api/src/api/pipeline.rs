@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use simd_json::prelude::*;
 use tremor_pipeline::{query::Query, FN_REGISTRY};
 
 use crate::api::prelude::*;
@@ -151,3 +152,43 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
     )
     .await
 }
+
+/// Events per second a tap sends unless a `rate` is requested
+const DEFAULT_TAP_RATE: u64 = 10;
+
+#[derive(Deserialize)]
+struct TapQuery {
+    rate: Option<u64>,
+}
+
+/// Streams a sampled copy of the events leaving a port of a running pipeline
+/// instance as server sent events, the tap is detached once the client goes
+/// away
+pub async fn tap(req: Request) -> Result<Response> {
+    let aid: String = req.param("aid").unwrap_or_default();
+    let sid: String = req.param("sid").unwrap_or_default();
+    let port: String = req.param("port").unwrap_or_default();
+    let rate = req
+        .query::<TapQuery>()
+        .ok()
+        .and_then(|q| q.rate)
+        .unwrap_or(DEFAULT_TAP_RATE);
+    let url = build_url(&["pipeline", &aid, &sid, &port])?;
+    let rx = req.state().world.tap_pipeline(&url, rate).await?;
+    Ok(tide::sse::upgrade(req, move |_req, sender| {
+        let rx = rx.clone();
+        async move {
+            while let Ok(event) = rx.recv().await {
+                let payload = event.data.suffix();
+                let data = format!(
+                    r#"{{"ingest_ns":{},"value":{},"meta":{}}}"#,
+                    event.ingest_ns,
+                    payload.value().encode(),
+                    payload.meta().encode()
+                );
+                sender.send("event", data, None).await?;
+            }
+            Ok(())
+        }
+    }))
+}
@@ -0,0 +1,35 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::prelude::*;
+
+/// Content type of graphviz dot files
+const DOT: &str = "text/vnd.graphviz";
+
+pub async fn get(req: Request) -> std::result::Result<Response, crate::Error> {
+    let topology = req.state().world.topology().await?;
+    let wants_dot = req
+        .header(headers::ACCEPT)
+        .map(headers::HeaderValues::last)
+        .map(headers::HeaderValue::as_str)
+        == Some(DOT);
+    if wants_dot {
+        let mut r = Response::new(StatusCode::Ok);
+        r.insert_header(headers::CONTENT_TYPE, DOT);
+        r.set_body(topology.dot());
+        Ok(r)
+    } else {
+        reply(req, topology, false, StatusCode::Ok).await
+    }
+}
@@ -16,12 +16,15 @@ use crate::{
     errors::{Error, Result},
     util::load_trickle,
 };
+use async_std::io::prelude::BufReadExt;
+use async_std::stream::StreamExt;
 use clap::{self, ArgMatches};
 use halfbrown::HashMap;
-use http_types::{headers, StatusCode};
+use http_types::{headers, Method, StatusCode};
 use simd_json::prelude::*;
+use tremor_runtime::url::{ResourceType, TremorURL};
 
-use crate::util::{accept, content_type, load, save_config, ser, TremorApp};
+use crate::util::{accept, content_type, load, save_config, ser, Credentials, TremorApp};
 
 pub(crate) async fn run_cmd(app: &mut TremorApp, cmd: &ArgMatches) -> Result<()> {
     if let Some(matches) = cmd.subcommand_matches("version") {
@@ -34,8 +37,14 @@ pub(crate) async fn run_cmd(app: &mut TremorApp, cmd: &ArgMatches) -> Result<()>
         conductor_onramp_cmd(app, &matches).await
     } else if let Some(matches) = cmd.subcommand_matches("offramp") {
         conductor_offramp_cmd(app, &matches).await
+    } else if let Some(matches) = cmd.subcommand_matches("dlq") {
+        conductor_dlq_cmd(app, &matches).await
     } else if let Some(matches) = cmd.subcommand_matches("target") {
         conductor_target_cmd(app, &matches).await
+    } else if let Some(matches) = cmd.subcommand_matches("plan") {
+        crate::deploy::run_cmd(app, &matches, false).await
+    } else if let Some(matches) = cmd.subcommand_matches("apply") {
+        crate::deploy::run_cmd(app, &matches, true).await
     } else {
         Err(Error::from("Invalid command"))
     }
@@ -94,19 +103,39 @@ async fn conductor_target_create_cmd(app: &mut TremorApp, cmd: &ArgMatches) -> R
     let id = cmd.value_of("TARGET_ID").ok_or("TARGET_ID not provided")?;
     let path_to_file = cmd.value_of("SOURCE").ok_or("SOURCE not provided")?;
     let json = load(path_to_file)?;
-    let endpoints: Vec<String> = json
+    // either a list of endpoints or an object with endpoints and credentials
+    let endpoints = json
+        .get("endpoints")
+        .unwrap_or(&json)
         .as_array()
         .ok_or_else(|| Error::from("Invalid Configuration"))?
         .iter()
         .filter_map(|v| (ValueTrait::as_str(v).map(String::from)))
         .collect();
+    let field = |name: &str| {
+        json.get(name)
+            .and_then(ValueTrait::as_str)
+            .map(String::from)
+    };
+    let credentials = Credentials {
+        token: field("token"),
+        ca: field("ca"),
+        cert: field("cert"),
+        key: field("key"),
+    };
     app.config.instances.insert(id.to_string(), endpoints);
+    if credentials.token.is_some() || credentials.ca.is_some() || credentials.cert.is_some() {
+        app.config.credentials.insert(id.to_string(), credentials);
+    } else {
+        app.config.credentials.remove(id);
+    }
     save_config(&app.config)
 }
 
 async fn conductor_target_delete_cmd(app: &mut TremorApp, cmd: &ArgMatches) -> Result<()> {
     let id = cmd.value_of("TARGET_ID").ok_or("TARGET_ID not provided")?;
     app.config.instances.remove(&id.to_string());
+    app.config.credentials.remove(id);
     save_config(&app.config)
 }
 
@@ -116,7 +145,7 @@ async fn conductor_target_delete_cmd(app: &mut TremorApp, cmd: &ArgMatches) -> R
 
 async fn conductor_version_cmd(app: &TremorApp, cmd: &ArgMatches) -> Result<()> {
     let endpoint = app.endpoint("version")?;
-    let mut response = surf::get(endpoint).await?;
+    let mut response = app.request(Method::Get, &endpoint)?.await?;
     let version: Version = response.body_json().await?;
     println!(
         "{}",
@@ -143,11 +172,48 @@ async fn conductor_pipeline_cmd(app: &TremorApp, cmd: &ArgMatches) -> Result<()>
         conductor_create_cmd_trickle(app, &matches, "pipeline").await
     } else if let Some(matches) = cmd.subcommand_matches("instance") {
         conductor_instance_cmd(app, &matches, "pipeline").await
+    } else if let Some(matches) = cmd.subcommand_matches("tap") {
+        conductor_tap_cmd(app, &matches).await
     } else {
         Err("Invalid command".into())
     }
 }
 
+async fn conductor_tap_cmd(app: &TremorApp, cmd: &ArgMatches) -> Result<()> {
+    let url = cmd.value_of("URL").ok_or("URL not provided")?;
+    let url = TremorURL::parse(url)?;
+    let (a_id, s_id, port) = match (
+        url.resource_type(),
+        url.artefact(),
+        url.instance(),
+        url.instance_port(),
+    ) {
+        (Some(ResourceType::Pipeline), Some(a_id), Some(s_id), Some(port)) => (a_id, s_id, port),
+        _ => return Err("URL needs to name a pipeline port".into()),
+    };
+    let mut endpoint = app.endpoint_id_instance("pipeline", a_id, s_id)?;
+    endpoint
+        .path_segments_mut()
+        .map_err(|_| Error::from("Bad endpoint api"))?
+        .push(port)
+        .push("tap");
+    if let Some(rate) = cmd.value_of("rate") {
+        endpoint.query_pairs_mut().append_pair("rate", rate);
+    }
+    let response = app.request(Method::Get, &endpoint)?.await?;
+    if response.status() != StatusCode::Ok {
+        return handle_response(response).await;
+    }
+    // the tap is a stream of server sent events, we only print their data
+    let mut lines = response.lines();
+    while let Some(line) = lines.next().await {
+        if let Some(data) = line?.strip_prefix("data: ") {
+            println!("{}", data);
+        }
+    }
+    Ok(())
+}
+
 /////////////////////////////
 // API Binding subcommands //
 /////////////////////////////
@@ -183,7 +249,8 @@ async fn conductor_binding_activate_cmd(app: &TremorApp, cmd: &ArgMatches) -> Re
     let path_to_file = cmd.value_of("SOURCE").ok_or("SOURCE not provided")?;
     let json = load(path_to_file)?;
     let ser = ser(&app, &json)?;
-    let response = surf::post(&endpoint)
+    let response = app
+        .request(Method::Post, &endpoint)?
         .header(headers::CONTENT_TYPE, content_type(app))
         .header(headers::ACCEPT, accept(app))
         .body(ser)
@@ -200,7 +267,7 @@ async fn conductor_binding_deactivate_cmd(app: &TremorApp, cmd: &ArgMatches) ->
         .ok_or("INSTANCE_ID not provided")?;
     let endpoint = app.endpoint_id_instance("binding", a_id, s_id)?;
 
-    let response = surf::delete(&endpoint).await?;
+    let response = app.request(Method::Delete, &endpoint)?.await?;
     handle_response(response).await
 }
 
@@ -244,6 +311,42 @@ async fn conductor_onramp_cmd(app: &TremorApp, cmd: &ArgMatches) -> Result<()> {
     }
 }
 
+//////////////////////
+// API DLQ commands //
+//////////////////////
+
+async fn conductor_dlq_cmd(app: &TremorApp, cmd: &ArgMatches) -> Result<()> {
+    if cmd.subcommand_matches("list").is_some() {
+        conductor_list_cmd(app, "dlq").await
+    } else if let Some(matches) = cmd.subcommand_matches("fetch") {
+        let id = matches
+            .value_of("QUEUE_ID")
+            .ok_or("QUEUE_ID not provided")?;
+        let endpoint = app.endpoint_id("dlq", id)?;
+        let response = app.request(Method::Get, &endpoint)?.await?;
+        handle_response(response).await
+    } else if let Some(matches) = cmd.subcommand_matches("inspect") {
+        conductor_dlq_letter_cmd(app, &matches, Method::Get).await
+    } else if let Some(matches) = cmd.subcommand_matches("reinject") {
+        conductor_dlq_letter_cmd(app, &matches, Method::Post).await
+    } else if let Some(matches) = cmd.subcommand_matches("delete") {
+        conductor_dlq_letter_cmd(app, &matches, Method::Delete).await
+    } else {
+        Err("Invalid command".into())
+    }
+}
+
+async fn conductor_dlq_letter_cmd(app: &TremorApp, cmd: &ArgMatches, method: Method) -> Result<()> {
+    let id = cmd.value_of("QUEUE_ID").ok_or("QUEUE_ID not provided")?;
+    let letter = cmd.value_of("LETTER_ID").ok_or("LETTER_ID not provided")?;
+    let endpoint = app.endpoint_id_instance("dlq", id, letter)?;
+    let response = app
+        .request(method, &endpoint)?
+        .header(headers::ACCEPT, accept(app))
+        .await?;
+    handle_response(response).await
+}
+
 /////////////////
 // Shared code //
 /////////////////
@@ -254,13 +357,13 @@ async fn conductor_get_cmd(app: &TremorApp, cmd: &ArgMatches, endpoint: &str) ->
         .ok_or("ARTEFACT_ID not provided")?;
     let endpoint = app.endpoint_id(endpoint, id)?;
 
-    let response = surf::get(&endpoint).await?;
+    let response = app.request(Method::Get, &endpoint)?.await?;
     handle_response(response).await
 }
 
 async fn conductor_list_cmd(app: &TremorApp, endpoint: &str) -> Result<()> {
     let endpoint = app.endpoint(endpoint)?;
-    let response = surf::get(&endpoint).await?;
+    let response = app.request(Method::Get, &endpoint)?.await?;
     handle_response(response).await
 }
 
@@ -269,7 +372,8 @@ async fn conductor_create_cmd(app: &TremorApp, cmd: &ArgMatches, endpoint: &str)
     let path_to_file = cmd.value_of("SOURCE").ok_or("SOURCE not provided")?;
     let json = load(path_to_file)?;
     let ser = ser(&app, &json)?;
-    let response = surf::post(&endpoint)
+    let response = app
+        .request(Method::Post, &endpoint)?
         .header(http_types::headers::CONTENT_TYPE, content_type(app))
         .header("accept", accept(app))
         .body(ser)
@@ -285,7 +389,8 @@ async fn conductor_create_cmd_trickle(
     let endpoint = app.endpoint(endpoint)?;
     let path_to_file = cmd.value_of("SOURCE").ok_or("SOURCE not provided")?;
     let ser = load_trickle(path_to_file)?;
-    let response = surf::post(&endpoint)
+    let response = app
+        .request(Method::Post, &endpoint)?
         .header(http_types::headers::CONTENT_TYPE, "application/vnd.trickle")
         .header("accept", accept(app))
         .body(ser)
@@ -298,7 +403,7 @@ async fn conductor_delete_cmd(app: &TremorApp, cmd: &ArgMatches, endpoint: &str)
         .value_of("ARTEFACT_ID")
         .ok_or("ARTEFACT_ID not provided")?;
     let endpoint = app.endpoint_id(endpoint, id)?;
-    let response = surf::delete(&endpoint).await?;
+    let response = app.request(Method::Delete, &endpoint)?.await?;
     handle_response(response).await
 }
 
@@ -310,7 +415,7 @@ async fn conductor_instance_cmd(app: &TremorApp, cmd: &ArgMatches, endpoint: &st
         .value_of("INSTANCE_ID")
         .ok_or("INSTANCE_ID not provided")?;
     let endpoint = app.endpoint_id_instance(endpoint, a_id, s_id)?;
-    let response = surf::get(&endpoint).await?;
+    let response = app.request(Method::Get, &endpoint)?.await?;
     handle_response(response).await
 }
 
@@ -320,6 +425,8 @@ async fn handle_response(mut response: surf::Response) -> Result<()> {
         StatusCode::Ok | StatusCode::Created => println!("{}", response.body_string().await?),
         StatusCode::NotFound => eprintln!("Not found"),
         StatusCode::Conflict => eprintln!("Conflict"),
+        StatusCode::Unauthorized => eprintln!("Unauthorized, check the target credentials"),
+        StatusCode::Forbidden => eprintln!("Forbidden, the target credentials lack the role"),
         _ => eprintln!(
             "Unexpected response ( status: {} )",
             status.canonical_reason()
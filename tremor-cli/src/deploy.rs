@@ -0,0 +1,586 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative deployments
+//!
+//! A manifest describes a whole deployment, it uses the layout of a static
+//! config file with an additional list of trickle files relative to the
+//! manifest:
+//!
+//! ```yaml
+//! onramp: [...]
+//! offramp: [...]
+//! pipeline:
+//!   - main.trickle
+//! binding: [...]
+//! mapping:
+//!   /binding/default/01:
+//!     instance: "01"
+//! ```
+//!
+//! `plan` diffs the manifest against the artefacts and binding instances of
+//! the targeted tremor server, `apply` carries the plan out. Changed
+//! artefacts are replaced, every instance using them is deactivated first
+//! and activated again afterwards. With `--prune` artefacts and instances
+//! missing from the manifest are removed.
+//!
+//! The mapping of an instance that is already running isn't compared, to
+//! change it give the instance a new id.
+//!
+//! System artefacts (ids starting with `system::`) are managed by the server
+//! and are neither compared nor pruned, a manifest can't contain them.
+
+use crate::errors::{Error, Result};
+use crate::server::parse_query_file;
+use crate::util::TremorApp;
+use clap::ArgMatches;
+use http_types::{headers, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use tremor_runtime::config::{OffRamp, OnRamp};
+use tremor_runtime::url::{ResourceType, TremorURL};
+
+/// Artefact kinds, ordered so that artefacts are published after the
+/// artefacts they depend on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Kind {
+    Offramp,
+    Pipeline,
+    Onramp,
+    Binding,
+}
+
+impl Kind {
+    fn endpoint(self) -> &'static str {
+        match self {
+            Kind::Offramp => "offramp",
+            Kind::Pipeline => "pipeline",
+            Kind::Onramp => "onramp",
+            Kind::Binding => "binding",
+        }
+    }
+
+    fn of(url: &TremorURL) -> Option<Self> {
+        match url.resource_type()? {
+            ResourceType::Offramp => Some(Kind::Offramp),
+            ResourceType::Pipeline => Some(Kind::Pipeline),
+            ResourceType::Onramp => Some(Kind::Onramp),
+            ResourceType::Binding => Some(Kind::Binding),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Binding {
+    id: String,
+    #[serde(default)]
+    description: String,
+    links: HashMap<TremorURL, Vec<TremorURL>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    onramp: Vec<OnRamp>,
+    #[serde(default)]
+    offramp: Vec<OffRamp>,
+    /// paths of trickle files, relative to the manifest
+    #[serde(default)]
+    pipeline: Vec<String>,
+    #[serde(default)]
+    binding: Vec<Binding>,
+    #[serde(default)]
+    mapping: HashMap<TremorURL, HashMap<String, String>>,
+}
+
+#[derive(Deserialize)]
+struct Wrap<T> {
+    artefact: T,
+    instances: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PipelineWrap {
+    query: String,
+}
+
+/// An artefact in a comparable form
+#[derive(Clone, Debug, PartialEq)]
+enum Spec {
+    /// onramps and offramps
+    Ramp(serde_yaml::Value),
+    Pipeline(String),
+    Binding(Binding),
+}
+
+impl Spec {
+    /// The body and content type to publish the artefact with
+    fn body(&self) -> Result<(String, &'static str)> {
+        Ok(match self {
+            Spec::Ramp(ramp) => (serde_yaml::to_string(ramp)?, "application/yaml"),
+            Spec::Pipeline(source) => (source.clone(), "application/vnd.trickle"),
+            Spec::Binding(binding) => (serde_yaml::to_string(binding)?, "application/yaml"),
+        })
+    }
+
+    /// The artefacts a binding links
+    fn dependencies(&self) -> HashSet<(Kind, String)> {
+        if let Spec::Binding(binding) = self {
+            binding
+                .links
+                .iter()
+                .flat_map(|(from, tos)| std::iter::once(from).chain(tos))
+                .filter_map(|url| Some((Kind::of(url)?, url.artefact()?.to_string())))
+                .collect()
+        } else {
+            HashSet::new()
+        }
+    }
+}
+
+type Instance = (String, String);
+
+const SYSTEM_PREFIX: &str = "system::";
+
+fn is_system(id: &str) -> bool {
+    id.starts_with(SYSTEM_PREFIX)
+}
+
+/// Artefacts and binding instances of a deployment
+#[derive(Debug, Default)]
+struct State {
+    artefacts: HashMap<(Kind, String), Spec>,
+    /// binding instances with their mapping, the mapping of running
+    /// instances isn't known
+    instances: HashMap<Instance, HashMap<String, String>>,
+}
+
+impl State {
+    fn add(&mut self, kind: Kind, id: String, spec: Spec) -> Result<()> {
+        if self.artefacts.insert((kind, id.clone()), spec).is_some() {
+            Err(format!("Duplicate {} `{}` in manifest", kind.endpoint(), id).into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Step {
+    Deactivate(Instance),
+    Unpublish(Kind, String),
+    Publish(Kind, String, Spec),
+    Activate(Instance, HashMap<String, String>),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Deactivate((binding, instance)) => {
+                write!(f, "deactivate binding {}/{}", binding, instance)
+            }
+            Step::Unpublish(kind, id) => write!(f, "unpublish {} {}", kind.endpoint(), id),
+            Step::Publish(kind, id, _) => write!(f, "publish {} {}", kind.endpoint(), id),
+            Step::Activate((binding, instance), _) => {
+                write!(f, "activate binding {}/{}", binding, instance)
+            }
+        }
+    }
+}
+
+pub(crate) async fn run_cmd(app: &TremorApp, cmd: &ArgMatches, apply: bool) -> Result<()> {
+    let path = cmd.value_of("MANIFEST").ok_or("MANIFEST not provided")?;
+    let desired = load_manifest(path)?;
+    let current = fetch_state(app).await?;
+    let steps = plan(&current, &desired, cmd.is_present("prune"))?;
+    if steps.is_empty() {
+        println!("No changes");
+    } else if apply {
+        for step in &steps {
+            println!("{}", step);
+            execute(app, step).await?;
+        }
+        println!("Applied {} changes", steps.len());
+    } else {
+        for step in &steps {
+            println!("{}", step);
+        }
+        println!("{} changes", steps.len());
+    }
+    Ok(())
+}
+
+fn load_manifest(path: &str) -> Result<State> {
+    let file = crate::open_file(path, None)?;
+    let manifest: Manifest = serde_yaml::from_reader(file)?;
+    let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut state = State::default();
+    for onramp in manifest.onramp {
+        let spec = Spec::Ramp(serde_yaml::to_value(&onramp)?);
+        state.add(Kind::Onramp, onramp.id, spec)?;
+    }
+    for offramp in manifest.offramp {
+        let spec = Spec::Ramp(serde_yaml::to_value(&offramp)?);
+        state.add(Kind::Offramp, offramp.id, spec)?;
+    }
+    for file in manifest.pipeline {
+        let file = base.join(file).to_string_lossy().to_string();
+        let (_, query) = parse_query_file(&file)?;
+        let id = query
+            .id()
+            .ok_or_else(|| format!("{} has no `#!config id` directive", file))?
+            .to_string();
+        state.add(
+            Kind::Pipeline,
+            id,
+            Spec::Pipeline(query.source().to_string()),
+        )?;
+    }
+    for binding in manifest.binding {
+        state.add(Kind::Binding, binding.id.clone(), Spec::Binding(binding))?;
+    }
+    for (url, mapping) in manifest.mapping {
+        match (url.resource_type(), url.artefact(), url.instance()) {
+            (Some(ResourceType::Binding), Some(binding), Some(instance)) => {
+                state
+                    .instances
+                    .insert((binding.to_string(), instance.to_string()), mapping);
+            }
+            _ => return Err(format!("Invalid binding instance `{}` in mapping", url).into()),
+        }
+    }
+    Ok(state)
+}
+
+async fn fetch<T: DeserializeOwned>(app: &TremorApp, endpoint: &url::Url) -> Result<T> {
+    let mut response = app
+        .request(Method::Get, endpoint)?
+        .header(headers::ACCEPT, "application/json")
+        .await?;
+    let mut body = response.body_string().await?;
+    if response.status() == StatusCode::Ok {
+        Ok(simd_json::from_str(body.as_mut_str())?)
+    } else {
+        Err(format!(
+            "Failed to fetch {}: {} {}",
+            endpoint,
+            response.status(),
+            body
+        )
+        .into())
+    }
+}
+
+async fn fetch_state(app: &TremorApp) -> Result<State> {
+    let mut state = State::default();
+    for id in fetch::<Vec<String>>(app, &app.endpoint("onramp")?).await? {
+        if is_system(&id) {
+            continue;
+        }
+        let wrap: Wrap<OnRamp> = fetch(app, &app.endpoint_id("onramp", &id)?).await?;
+        let spec = Spec::Ramp(serde_yaml::to_value(&wrap.artefact)?);
+        state.artefacts.insert((Kind::Onramp, id), spec);
+    }
+    for id in fetch::<Vec<String>>(app, &app.endpoint("offramp")?).await? {
+        if is_system(&id) {
+            continue;
+        }
+        let wrap: Wrap<OffRamp> = fetch(app, &app.endpoint_id("offramp", &id)?).await?;
+        let spec = Spec::Ramp(serde_yaml::to_value(&wrap.artefact)?);
+        state.artefacts.insert((Kind::Offramp, id), spec);
+    }
+    for id in fetch::<Vec<String>>(app, &app.endpoint("pipeline")?).await? {
+        if is_system(&id) {
+            continue;
+        }
+        let wrap: PipelineWrap = fetch(app, &app.endpoint_id("pipeline", &id)?).await?;
+        state
+            .artefacts
+            .insert((Kind::Pipeline, id), Spec::Pipeline(wrap.query));
+    }
+    for id in fetch::<Vec<String>>(app, &app.endpoint("binding")?).await? {
+        if is_system(&id) {
+            continue;
+        }
+        let wrap: Wrap<Binding> = fetch(app, &app.endpoint_id("binding", &id)?).await?;
+        for instance in wrap.instances {
+            state
+                .instances
+                .insert((id.clone(), instance), HashMap::new());
+        }
+        state
+            .artefacts
+            .insert((Kind::Binding, id), Spec::Binding(wrap.artefact));
+    }
+    Ok(state)
+}
+
+/// The steps turning `current` into `desired`: instances are deactivated,
+/// artefacts unpublished in reverse dependency order, published in
+/// dependency order and finally instances are activated.
+///
+/// The whole plan is checked before it is returned so that no step is run
+/// that the server would reject.
+fn plan(current: &State, desired: &State, prune: bool) -> Result<Vec<Step>> {
+    let mut system: Vec<String> = desired
+        .artefacts
+        .keys()
+        .filter(|(_, id)| is_system(id))
+        .map(|(kind, id)| format!("{} {}", kind.endpoint(), id))
+        .chain(
+            desired
+                .instances
+                .keys()
+                .filter(|(binding, _)| is_system(binding))
+                .map(|(binding, instance)| format!("binding {}/{}", binding, instance)),
+        )
+        .collect();
+    if !system.is_empty() {
+        system.sort();
+        return Err(format!("System artefacts can't be deployed: {}", system.join(", ")).into());
+    }
+    let mut unpublish: Vec<&(Kind, String)> = current
+        .artefacts
+        .iter()
+        .filter(|(key, spec)| match desired.artefacts.get(*key) {
+            Some(desired) => desired != *spec,
+            None => prune,
+        })
+        .map(|(key, _)| key)
+        .collect();
+    unpublish.sort_by(|a, b| b.cmp(a));
+    let mut publish: Vec<(&(Kind, String), &Spec)> = desired
+        .artefacts
+        .iter()
+        .filter(|(key, spec)| current.artefacts.get(*key) != Some(*spec))
+        .collect();
+    publish.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let unpublished: HashSet<(Kind, String)> = unpublish.iter().copied().cloned().collect();
+    let mut deactivate = Vec::new();
+    for instance in current.instances.keys() {
+        let binding = (Kind::Binding, instance.0.clone());
+        let replaced = unpublished.contains(&binding)
+            || current.artefacts.get(&binding).map_or(false, |spec| {
+                spec.dependencies()
+                    .iter()
+                    .any(|dep| unpublished.contains(dep))
+            });
+        let removed = !desired.instances.contains_key(instance);
+        if replaced && removed && !prune {
+            return Err(format!(
+                "Binding instance {}/{} has to be restarted, add it to the manifest or use --prune",
+                instance.0, instance.1
+            )
+            .into());
+        }
+        if replaced || (removed && prune) {
+            deactivate.push(instance);
+        }
+    }
+    deactivate.sort();
+    let mut activate: Vec<_> = desired
+        .instances
+        .iter()
+        .filter(|(instance, _)| {
+            !current.instances.contains_key(*instance) || deactivate.contains(instance)
+        })
+        .collect();
+    activate.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(deactivate
+        .into_iter()
+        .map(|instance| Step::Deactivate(instance.clone()))
+        .chain(
+            unpublish
+                .into_iter()
+                .map(|(kind, id)| Step::Unpublish(*kind, id.clone())),
+        )
+        .chain(
+            publish
+                .into_iter()
+                .map(|((kind, id), spec)| Step::Publish(*kind, id.clone(), spec.clone())),
+        )
+        .chain(
+            activate
+                .into_iter()
+                .map(|(instance, mapping)| Step::Activate(instance.clone(), mapping.clone())),
+        )
+        .collect())
+}
+
+async fn execute(app: &TremorApp, step: &Step) -> Result<()> {
+    let request = match step {
+        Step::Deactivate((binding, instance)) => {
+            let endpoint = app.endpoint_id_instance("binding", binding, instance)?;
+            app.request(Method::Delete, &endpoint)?
+        }
+        Step::Unpublish(kind, id) => {
+            let endpoint = app.endpoint_id(kind.endpoint(), id)?;
+            app.request(Method::Delete, &endpoint)?
+        }
+        Step::Publish(kind, _, spec) => {
+            let endpoint = app.endpoint(kind.endpoint())?;
+            let (body, content_type) = spec.body()?;
+            app.request(Method::Post, &endpoint)?
+                .header(headers::CONTENT_TYPE, content_type)
+                .body(body)
+        }
+        Step::Activate((binding, instance), mapping) => {
+            let endpoint = app.endpoint_id_instance("binding", binding, instance)?;
+            app.request(Method::Post, &endpoint)?
+                .header(headers::CONTENT_TYPE, "application/yaml")
+                .body(serde_yaml::to_string(mapping)?)
+        }
+    };
+    let mut response = request.header(headers::ACCEPT, "application/json").await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        let body = response.body_string().await?;
+        Err(Error::from(format!(
+            "Failed to {}: {} {}",
+            step,
+            response.status(),
+            body
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pipeline(state: &mut State, id: &str, source: &str) {
+        state.artefacts.insert(
+            (Kind::Pipeline, id.to_string()),
+            Spec::Pipeline(source.to_string()),
+        );
+    }
+
+    fn binding(state: &mut State, id: &str, instance: &str) -> Result<()> {
+        let mut links = HashMap::new();
+        links.insert(
+            TremorURL::parse("/onramp/in/{instance}/out")?,
+            vec![TremorURL::parse("/pipeline/main/{instance}/in")?],
+        );
+        let spec = Spec::Binding(Binding {
+            id: id.to_string(),
+            description: String::new(),
+            links,
+        });
+        state
+            .artefacts
+            .insert((Kind::Binding, id.to_string()), spec);
+        state
+            .instances
+            .insert((id.to_string(), instance.to_string()), HashMap::new());
+        Ok(())
+    }
+
+    fn steps(current: &State, desired: &State, prune: bool) -> Result<Vec<String>> {
+        Ok(plan(current, desired, prune)?
+            .iter()
+            .map(ToString::to_string)
+            .collect())
+    }
+
+    #[test]
+    fn plan_create() -> Result<()> {
+        let current = State::default();
+        let mut desired = State::default();
+        pipeline(&mut desired, "main", "select event from in into out;");
+        binding(&mut desired, "default", "01")?;
+        assert_eq!(
+            steps(&current, &desired, false)?,
+            vec![
+                "publish pipeline main",
+                "publish binding default",
+                "activate binding default/01"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_update() -> Result<()> {
+        let mut current = State::default();
+        pipeline(&mut current, "main", "select event from in into out;");
+        binding(&mut current, "default", "01")?;
+        let mut desired = State::default();
+        pipeline(&mut desired, "main", "select event.snot from in into out;");
+        binding(&mut desired, "default", "01")?;
+        assert_eq!(
+            steps(&current, &desired, false)?,
+            vec![
+                "deactivate binding default/01",
+                "unpublish pipeline main",
+                "publish pipeline main",
+                "activate binding default/01"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_prune() -> Result<()> {
+        let mut current = State::default();
+        pipeline(&mut current, "main", "select event from in into out;");
+        pipeline(&mut current, "old", "select event from in into out;");
+        binding(&mut current, "default", "01")?;
+        let mut desired = State::default();
+        pipeline(&mut desired, "main", "select event from in into out;");
+        assert!(steps(&current, &desired, false)?.is_empty());
+        assert_eq!(
+            steps(&current, &desired, true)?,
+            vec![
+                "deactivate binding default/01",
+                "unpublish binding default",
+                "unpublish pipeline old"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn plan_no_op() -> Result<()> {
+        let mut current = State::default();
+        pipeline(&mut current, "main", "select event from in into out;");
+        binding(&mut current, "default", "01")?;
+        let mut desired = State::default();
+        pipeline(&mut desired, "main", "select event from in into out;");
+        binding(&mut desired, "default", "01")?;
+        assert!(steps(&current, &desired, true)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn plan_rejects_system_artefacts() -> Result<()> {
+        let current = State::default();
+        let mut desired = State::default();
+        pipeline(
+            &mut desired,
+            "system::metrics",
+            "select event from in into out;",
+        );
+        binding(&mut desired, "default", "01")?;
+        assert!(plan(&current, &desired, false).is_err());
+        Ok(())
+    }
+}
@@ -50,10 +50,12 @@ mod alloc;
 mod api;
 mod completions;
 mod debug;
+mod deploy;
 mod doc;
 mod errors;
 // mod explain;
 mod job;
+mod repl;
 mod report;
 mod run;
 mod server;
@@ -143,6 +145,8 @@ fn run(mut app: App, cmd: &ArgMatches) -> Result<()> {
         server::run_cmd(app, matches)
     } else if let Some(matches) = cmd.subcommand_matches("run") {
         run::run_cmd(&matches)
+    } else if let Some(matches) = cmd.subcommand_matches("repl") {
+        repl::run_cmd(&matches)
     } else if let Some(matches) = cmd.subcommand_matches("doc") {
         doc::run_cmd(&matches)
     } else if let Some(matches) = cmd.subcommand_matches("api") {
@@ -0,0 +1,482 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::Result;
+use crate::util::{highlight, slurp_string};
+use clap::ArgMatches;
+use simd_json::borrowed::Value;
+use simd_json::prelude::*;
+use std::io::{self, BufRead, Write};
+use tremor_common::time::nanotime;
+use tremor_script::ctx::{EventContext, EventOriginUri};
+use tremor_script::errors::ErrorKind;
+use tremor_script::highlighter::Term as TermHighlighter;
+use tremor_script::lexer::{Token, Tokenizer};
+use tremor_script::path::{load as load_module_path, ModulePath};
+use tremor_script::registry;
+use tremor_script::registry::Registry;
+use tremor_script::script::{AggrType, Return, Script};
+
+const REPL_FILE: &str = "<repl>";
+
+const HELP: &str = r#"Enter tremor-script expressions to evaluate them against the current event.
+
+  let x = <expr>    binds `x` for all following lines
+  <expr> spanning several lines is evaluated once it is complete
+  use <module>      imports a module from the TREMOR_PATH mounts
+  :event <json>     sets the current event
+  :meta <json>      sets the current metadata ( `$` )
+  :load <file>      evaluates a tremor-script file
+  :type <expr>      prints the type of an expression
+  :reset            clears bindings, imports, event, state and metadata
+  :help             prints this help
+  :quit             exits the repl
+"#;
+
+/// What a line of input is, as far as the repl is concerned
+enum Input {
+    /// A `use` statement that is kept as prelude for all further lines
+    Use,
+    /// A `let` that binds a local, with the name of the local
+    Let(String),
+    /// Anything else
+    Expr,
+}
+
+impl Input {
+    fn classify(src: &str) -> Self {
+        let mut tokens = Tokenizer::new(src)
+            .filter_map(std::result::Result::ok)
+            .map(|t| t.value)
+            .filter(|t| {
+                !matches!(
+                    t,
+                    Token::Whitespace(_)
+                        | Token::NewLine
+                        | Token::SingleLineComment(_)
+                        | Token::DocComment(_)
+                        | Token::ModComment(_)
+                )
+            });
+        match (tokens.next(), tokens.next()) {
+            (Some(Token::Use), _) => Self::Use,
+            (Some(Token::Let), Some(Token::Ident(id, _))) => Self::Let(id.to_string()),
+            _ => Self::Expr,
+        }
+    }
+}
+
+// We need this since we call objects records
+fn type_name(v: &Value) -> &'static str {
+    match v.value_type() {
+        ValueType::Null => "null",
+        ValueType::Bool => "boolean",
+        ValueType::String => "string",
+        ValueType::I64 | ValueType::U64 => "integer",
+        ValueType::F64 => "float",
+        ValueType::Array => "array",
+        ValueType::Object => "record",
+    }
+}
+
+/// The outcome of evaluating input
+enum Outcome {
+    /// The input was evaluated
+    Done(Return<'static>),
+    /// The input ends before the expression does, we need more lines
+    Incomplete,
+    /// The input failed to compile or run, the error was reported
+    Failed,
+}
+
+/// If a compiler error means the input ended in the middle of an
+/// expression, such as an open `match` or string
+fn is_incomplete(e: &ErrorKind) -> bool {
+    match e {
+        ErrorKind::UnrecognizedToken(_, _, token, _) => token.is_empty(),
+        ErrorKind::UnexpectedEndOfStream
+        | ErrorKind::UnterminatedHereDoc(_, _, _)
+        | ErrorKind::UnterminatedStringLiteral(_, _, _)
+        | ErrorKind::UnterminatedExtractor(_, _, _) => true,
+        _ => false,
+    }
+}
+
+struct Repl {
+    reg: Registry,
+    module_path: ModulePath,
+    /// `use` statements entered so far
+    uses: Vec<String>,
+    /// names of the locals bound so far, in the order of their slots
+    locals: Vec<String>,
+    /// values of the locals bound so far
+    values: Vec<Option<Value<'static>>>,
+    event: Value<'static>,
+    state: Value<'static>,
+    meta: Value<'static>,
+}
+
+impl Repl {
+    fn new() -> Self {
+        Self {
+            reg: registry::registry(),
+            module_path: load_module_path(),
+            uses: vec![],
+            locals: vec![],
+            values: vec![],
+            event: Value::object(),
+            state: Value::null(),
+            meta: Value::object(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.uses.clear();
+        self.locals.clear();
+        self.values.clear();
+        self.event = Value::object();
+        self.state = Value::null();
+        self.meta = Value::object();
+    }
+
+    /// The value of the local `name`
+    fn local(&self, name: &str) -> Option<&Value<'static>> {
+        let idx = self.locals.iter().position(|l| l == name)?;
+        self.values.get(idx)?.as_ref()
+    }
+
+    /// Evaluates `src` with the imports of previous lines, locals bound by
+    /// previous lines are kept in the interpreter's local stack
+    fn eval(&mut self, src: &str) -> Result<Outcome> {
+        let mut source = String::new();
+        for u in &self.uses {
+            source.push_str(u);
+            source.push('\n');
+        }
+        source.push_str(src.trim().trim_end_matches(';'));
+        source.push('\n');
+        let script = match Script::parse_with_locals(
+            &self.module_path,
+            REPL_FILE,
+            source.clone(),
+            &self.reg,
+            &self.locals,
+        ) {
+            Ok(script) => script,
+            Err(e) if is_incomplete(e.error.kind()) => return Ok(Outcome::Incomplete),
+            Err(e) => {
+                let mut h = TermHighlighter::default();
+                if let Err(e) = Script::format_error_from_script(&source, &mut h, &e) {
+                    eprintln!("Error: {}", e);
+                };
+                return Ok(Outcome::Failed);
+            }
+        };
+
+        // We run against copies so a failing line does not leave a half
+        // mutated event or half bound locals behind
+        let mut event = self.event.clone();
+        let mut meta = self.meta.clone();
+        let mut state = self.state.clone();
+        let mut values = self.values.clone();
+        let ctx = EventContext::new(nanotime(), Some(EventOriginUri::default()));
+        match script.run_with_locals(
+            &ctx,
+            AggrType::Tick,
+            &mut event,
+            &mut state,
+            &mut meta,
+            &mut values,
+        ) {
+            Ok(r) => {
+                let r = match r {
+                    Return::Emit { value, port } => Return::Emit {
+                        value: value.clone_static(),
+                        port,
+                    },
+                    Return::EmitEvent { port } => Return::EmitEvent { port },
+                    Return::Drop => Return::Drop,
+                };
+                self.event = event.clone_static();
+                self.meta = meta.clone_static();
+                self.state = state;
+                self.locals = script.locals.clone();
+                self.values = values;
+                Ok(Outcome::Done(r))
+            }
+            Err(e) => {
+                let mut h = TermHighlighter::default();
+                if let Err(e) = script.format_error_with(&mut h, &e) {
+                    eprintln!("Error: {}", e);
+                };
+                Ok(Outcome::Failed)
+            }
+        }
+    }
+
+    /// Evaluates input that has to be complete, such as a loaded file
+    fn eval_complete(&mut self, src: &str) -> Result<Option<Return<'static>>> {
+        match self.eval(src)? {
+            Outcome::Done(r) => Ok(Some(r)),
+            Outcome::Incomplete => {
+                eprintln!("Error: unexpected end of input");
+                Ok(None)
+            }
+            Outcome::Failed => Ok(None),
+        }
+    }
+
+    /// Evaluates input that has to be complete and returns the type of the
+    /// result, bindings and changes to the event, state and metadata made
+    /// by the input are discarded
+    fn type_of(&mut self, src: &str) -> Result<Option<&'static str>> {
+        let locals = self.locals.clone();
+        let values = self.values.clone();
+        let event = self.event.clone();
+        let state = self.state.clone();
+        let meta = self.meta.clone();
+        let res = self.eval_complete(src).map(|r| {
+            r.map(|r| match r {
+                Return::Emit { value, .. } => type_name(&value),
+                Return::EmitEvent { .. } => type_name(&self.event),
+                Return::Drop => "drop",
+            })
+        });
+        self.locals = locals;
+        self.values = values;
+        self.event = event;
+        self.state = state;
+        self.meta = meta;
+        res
+    }
+
+    fn print(&self, r: &Return) -> Result<()> {
+        match r {
+            Return::Emit { value, port } => {
+                if let Some(port) = port {
+                    println!("emit => {}", port);
+                }
+                highlight(true, value)
+            }
+            Return::EmitEvent { port } => {
+                if let Some(port) = port {
+                    println!("emit event => {}", port);
+                }
+                highlight(true, &self.event)
+            }
+            Return::Drop => {
+                println!("drop");
+                Ok(())
+            }
+        }
+    }
+
+    /// Evaluates a line, or the lines of an expression spanning several,
+    /// returns `false` if the input is incomplete
+    fn line(&mut self, src: &str) -> Result<bool> {
+        match Input::classify(src) {
+            Input::Use => {
+                let stmt = format!("{};", src.trim().trim_end_matches(';'));
+                // Only keep the import if it resolves
+                match self.eval(&format!("{}\nnull", stmt))? {
+                    Outcome::Done(_) => self.uses.push(stmt),
+                    Outcome::Incomplete => return Ok(false),
+                    Outcome::Failed => (),
+                }
+            }
+            Input::Let(name) => match self.eval(src)? {
+                Outcome::Done(_) => {
+                    if let Some(value) = self.local(&name) {
+                        highlight(true, value)?;
+                    }
+                }
+                Outcome::Incomplete => return Ok(false),
+                Outcome::Failed => (),
+            },
+            Input::Expr => match self.eval(src)? {
+                Outcome::Done(r) => self.print(&r)?,
+                Outcome::Incomplete => return Ok(false),
+                Outcome::Failed => (),
+            },
+        }
+        Ok(true)
+    }
+
+    fn command(&mut self, cmd: &str, arg: &str) -> Result<bool> {
+        match cmd {
+            ":quit" | ":q" => return Ok(false),
+            ":help" | ":h" => print!("{}", HELP),
+            ":reset" => self.reset(),
+            ":event" => match simd_json::to_borrowed_value(&mut arg.as_bytes().to_vec()) {
+                Ok(event) => self.event = event.clone_static(),
+                Err(e) => eprintln!("Error: invalid event: {}", e),
+            },
+            ":meta" => match simd_json::to_borrowed_value(&mut arg.as_bytes().to_vec()) {
+                Ok(meta) => self.meta = meta.clone_static(),
+                Err(e) => eprintln!("Error: invalid metadata: {}", e),
+            },
+            ":load" => match slurp_string(arg) {
+                Ok(src) => {
+                    if let Some(r) = self.eval_complete(&src)? {
+                        self.print(&r)?;
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            ":type" => {
+                if let Some(name) = self.type_of(arg)? {
+                    println!("{}", name);
+                }
+            }
+            other => eprintln!("Unknown command `{}`, try `:help`", other),
+        }
+        Ok(true)
+    }
+
+    fn run<R: BufRead>(&mut self, input: R) -> Result<()> {
+        let mut input = input.lines();
+        // lines of an expression that is not complete yet
+        let mut pending = String::new();
+        loop {
+            if pending.is_empty() {
+                print!("tremor> ");
+            } else {
+                print!("   ...> ");
+            }
+            io::stdout().flush()?;
+            let line = if let Some(line) = input.next() {
+                line?
+            } else {
+                if !pending.is_empty() {
+                    eprintln!("Error: unexpected end of input");
+                }
+                println!();
+                return Ok(());
+            };
+            if pending.is_empty() {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                if line.starts_with(':') {
+                    let mut parts = line.splitn(2, char::is_whitespace);
+                    let cmd = parts.next().unwrap_or_default();
+                    let arg = parts.next().unwrap_or_default().trim();
+                    if !self.command(cmd, arg)? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            }
+            pending.push_str(&line);
+            pending.push('\n');
+            if self.line(&pending)? {
+                pending.clear();
+            }
+        }
+    }
+}
+
+pub(crate) fn run_cmd(_matches: &ArgMatches) -> Result<()> {
+    let stdin = io::stdin();
+    let mut repl = Repl::new();
+    println!("tremor repl, type `:help` for help");
+    repl.run(stdin.lock())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn repl(input: &str) -> Result<Repl> {
+        let mut repl = Repl::new();
+        repl.run(Cursor::new(input))?;
+        Ok(repl)
+    }
+
+    #[test]
+    fn bindings_persist_across_lines() -> Result<()> {
+        let repl = repl("let x = {\"a\": [1, 2]}\nlet y = x.a[1] + 1\nlet x = \"snot\"\n")?;
+        assert_eq!(repl.local("x"), Some(&Value::from("snot")));
+        assert_eq!(repl.local("y").and_then(ValueTrait::as_i64), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn bindings_are_not_literals() -> Result<()> {
+        // `{badger}` would be interpolated if the binding was re-parsed
+        // as a string literal
+        let repl = repl(":event {\"msg\": \"snot {badger}\"}\nlet s = event.msg\nlet t = s\n")?;
+        assert_eq!(repl.local("t"), Some(&Value::from("snot {badger}")));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_lines_keep_bindings() -> Result<()> {
+        let repl = repl("let x = 1\nlet x = [1][3]\nlet y = x\n")?;
+        assert_eq!(repl.local("y").and_then(ValueTrait::as_i64), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn type_does_not_commit() -> Result<()> {
+        let input = r#":event {"a": 1}
+let x = 1
+:type let x = "snot"
+:type let y = 2
+:type let event.a = 2
+:type let state = 3
+"#;
+        let repl = repl(input)?;
+        assert_eq!(repl.local("x").and_then(ValueTrait::as_i64), Some(1));
+        assert_eq!(repl.local("y"), None);
+        assert_eq!(repl.event.get("a").and_then(ValueTrait::as_i64), Some(1));
+        assert!(repl.state.is_null());
+        Ok(())
+    }
+
+    #[test]
+    fn type_of() -> Result<()> {
+        let mut repl = repl(":event {\"a\": 1}\n")?;
+        assert_eq!(repl.type_of("event.a")?, Some("integer"));
+        assert_eq!(repl.type_of("{}")?, Some("record"));
+        assert_eq!(repl.type_of("emit event")?, Some("record"));
+        assert_eq!(repl.type_of("drop")?, Some("drop"));
+        Ok(())
+    }
+
+    #[test]
+    fn multi_line_input() -> Result<()> {
+        let input = r#"let x = match event of
+  case %{ present snot } => "snot"
+  default => "badger"
+end
+let y = [
+  x,
+  x
+]
+"#;
+        let repl = repl(input)?;
+        assert_eq!(repl.local("x"), Some(&Value::from("badger")));
+        assert_eq!(
+            repl.local("y"),
+            Some(&Value::from(vec![
+                Value::from("badger"),
+                Value::from("badger")
+            ]))
+        );
+        Ok(())
+    }
+}
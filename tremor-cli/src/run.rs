@@ -15,48 +15,113 @@
 use crate::errors::{Error, Result};
 use crate::util::{get_source_kind, highlight, slurp_string, SourceKind};
 use clap::ArgMatches;
-use simd_json::borrowed::Value;
+use simd_json::borrowed::{Object, Value};
 use simd_json::prelude::*;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
 use tremor_common::file;
 use tremor_common::time::nanotime;
 use tremor_pipeline::{Event, Ids};
 use tremor_runtime::codec::Codec;
-use tremor_runtime::postprocessor::Postprocessor;
-use tremor_runtime::preprocessor::Preprocessor;
+use tremor_runtime::postprocessor::{self, Postprocessors};
+use tremor_runtime::preprocessor;
+use tremor_runtime::url::TremorURL;
 use tremor_script::ctx::{EventContext, EventOriginUri};
 use tremor_script::highlighter::{Highlighter, Term as TermHighlighter};
+use tremor_script::interpreter::{Mutation, Tracer};
 use tremor_script::path::load as load_module_path;
+use tremor_script::pos::Range;
 use tremor_script::query::Query;
 use tremor_script::registry;
 use tremor_script::registry::Registry;
 use tremor_script::script::{AggrType, Return, Script};
 use tremor_script::LineValue;
 use tremor_script::ValueAndMeta;
+/// Name of the stream or port used when none is given
+const DEFAULT_STREAM: &str = "in";
+const DEFAULT_PORT: &str = "out";
+
+/// Ports written to stderr unless they have an output of their own, they
+/// are written even if other ports are selected
+const ERROR_PORTS: [&str; 3] = ["err", "error", "stderr"];
+
+/// Splits a `NAME=PATH` argument, plain paths have no name. Names are made
+/// of alphanumeric characters and `_`, an existing file is always taken as
+/// a plain path.
+fn split_named(arg: &str) -> (Option<&str>, &str) {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(path))
+            if !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !Path::new(arg).exists() =>
+        {
+            (Some(name), path)
+        }
+        _ => (None, arg),
+    }
+}
+
+/// Checks if events of a port are written given the selected ports
+fn is_selected(selected: &[String], port: &str) -> bool {
+    selected.is_empty() || ERROR_PORTS.contains(&port) || selected.iter().any(|p| p == port)
+}
+
+/// A named input stream
+struct Input {
+    stream: String,
+    name: String,
+    buffer: Box<dyn BufRead>,
+}
+
 struct Ingress {
     is_interactive: bool,
     is_pretty: bool,
     buf: [u8; 4096],
-    buffer: Box<dyn BufRead>,
-    preprocessor: Box<dyn Preprocessor>,
+    inputs: Vec<Input>,
+    preprocessors: Vec<String>,
     codec: Box<dyn Codec>,
+    url: TremorURL,
+}
+
+/// Whether to keep reading input after an event was handled
+enum Flow {
+    Continue,
+    Quit,
 }
 
 type IngressHandler<T> =
-    dyn Fn(&mut T, &mut u64, &mut Egress, u64, simd_json::BorrowedValue) -> Result<()>;
+    dyn Fn(&mut T, &mut u64, &mut Egress, &str, u64, simd_json::BorrowedValue) -> Result<Flow>;
 
 impl Ingress {
     fn from_args(matches: &ArgMatches) -> Result<Self> {
-        let codec_pre = matches.value_of("PREPROCESSOR").unwrap_or("lines");
         let codec_decoder = matches.value_of("DECODER").unwrap_or("json");
         let is_interactive = matches.is_present("interactive");
         let is_pretty = matches.is_present("pretty");
 
-        let buffer: Box<dyn BufRead> = match matches.value_of("INFILE") {
-            None | Some("-") => Box::new(BufReader::new(io::stdin())),
-            Some(data) => Box::new(BufReader::new(crate::open_file(data, None)?)),
-        };
+        let mut inputs = vec![];
+        for arg in matches.values_of("INFILE").into_iter().flatten() {
+            let (stream, path) = split_named(arg);
+            let buffer: Box<dyn BufRead> = match path {
+                "-" => Box::new(BufReader::new(io::stdin())),
+                data => Box::new(BufReader::new(crate::open_file(data, None)?)),
+            };
+            inputs.push(Input {
+                stream: stream.unwrap_or(DEFAULT_STREAM).to_string(),
+                name: path.to_string(),
+                buffer,
+            });
+        }
+        if inputs.is_empty() {
+            inputs.push(Input {
+                stream: DEFAULT_STREAM.to_string(),
+                name: "-".to_string(),
+                buffer: Box::new(BufReader::new(io::stdin())),
+            });
+        }
 
         let codec = tremor_runtime::codec::lookup(codec_decoder);
         if let Err(_e) = codec {
@@ -65,24 +130,33 @@ impl Ingress {
             std::process::exit(1);
         }
         let codec = codec?;
-        let preprocessor = tremor_runtime::preprocessor::lookup(codec_pre);
-        if let Err(_e) = preprocessor {
-            eprintln!("Error Preprocessor {} not found error.", codec_pre);
+        let preprocessors: Vec<String> = matches.values_of("PREPROCESSOR").map_or_else(
+            || vec!["lines".to_string()],
+            |names| names.map(ToString::to_string).collect(),
+        );
+        if let Err(e) = preprocessor::make_preprocessors(&preprocessors) {
+            eprintln!("Error {}", e);
             // ALLOW: main.rs
             std::process::exit(1);
         }
-        let preprocessor = preprocessor?;
 
         Ok(Self {
             is_interactive,
             is_pretty,
             buf: [0_u8; 4096],
-            preprocessor,
+            inputs,
+            preprocessors,
             codec,
-            buffer,
+            url: TremorURL::from_onramp_id("tremor-run")?,
         })
     }
 
+    /// The names of all input streams
+    fn streams(&self) -> impl Iterator<Item = &str> {
+        self.inputs.iter().map(|i| i.stream.as_str())
+    }
+
+    /// Reads the inputs one after the other, in the order they were given
     fn process<T>(
         &mut self,
         runnable: &mut T,
@@ -90,61 +164,92 @@ impl Ingress {
         mut egress: &mut Egress,
         handler: &IngressHandler<T>,
     ) -> Result<()> {
-        loop {
-            match self.buffer.read(&mut self.buf) {
-                Ok(0) => {
-                    // ALLOW: main.rs
-                    return Ok(());
+        for input in &mut self.inputs {
+            // every input gets its own chain so no partial data leaks between them
+            let mut preprocessors = preprocessor::make_preprocessors(&self.preprocessors)?;
+            loop {
+                let n = input.buffer.read(&mut self.buf)?;
+                if n == 0 {
+                    break;
                 }
-                Ok(n) => {
-                    let mut at = nanotime();
-                    let x = self.preprocessor.process(&mut at, &self.buf[0..n])?;
-                    for mut data in x {
-                        let event = match self.codec.decode(data.as_mut_slice(), at) {
-                            Ok(Some(data)) => data,
-                            Ok(None) => continue,
-                            Err(e) => return Err(e.into()),
-                        };
-                        let event = event.clone();
-
-                        if self.is_interactive {
-                            eprintln!(
-                                "ingress> [codec: {}], [preprocessor: {}]",
-                                self.codec.name(),
-                                self.preprocessor.name()
-                            );
-                            highlight(self.is_pretty, &event)?;
-                        }
-                        handler(runnable, &mut id, &mut egress, at, event)?;
+                let mut at = nanotime();
+                let x = preprocessor::preprocess(
+                    &mut preprocessors,
+                    &mut at,
+                    self.buf[0..n].to_vec(),
+                    &self.url,
+                )?;
+                for mut data in x {
+                    let event = match self.codec.decode(data.as_mut_slice(), at) {
+                        Ok(Some(data)) => data,
+                        Ok(None) => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    let event = event.clone();
+
+                    if self.is_interactive {
+                        eprintln!(
+                            "ingress> [stream: {}], [input: {}], [codec: {}], [preprocessors: {}]",
+                            input.stream,
+                            input.name,
+                            self.codec.name(),
+                            self.preprocessors.join(", ")
+                        );
+                        highlight(self.is_pretty, &event)?;
+                    }
+                    if let Flow::Quit =
+                        handler(runnable, &mut id, &mut egress, &input.stream, at, event)?
+                    {
+                        return Ok(());
                     }
-                }
-                Err(e) => {
-                    return Err(e.into());
                 }
             }
         }
+        Ok(())
+    }
+}
+
+/// Where events of one or more ports are written to
+struct Output {
+    buffer: Box<dyn Write>,
+    postprocessors: Postprocessors,
+}
+
+impl Output {
+    fn new(path: &str, postprocessors: &[String]) -> Result<Self> {
+        let buffer: Box<dyn Write> = match path {
+            "-" => Box::new(BufWriter::new(io::stdout())),
+            data => Box::new(BufWriter::new(file::create(data)?)),
+        };
+        Ok(Self {
+            buffer,
+            postprocessors: postprocessor::make_postprocessors(postprocessors)?,
+        })
     }
 }
 
 struct Egress {
     is_interactive: bool,
     is_pretty: bool,
-    buffer: Box<dyn Write>,
+    is_tagged: bool,
+    /// Only these ports are written, all if empty
+    ports: Vec<String>,
+    /// Output for ports without an output of their own
+    default: Output,
+    by_port: HashMap<String, Output>,
     codec: Box<dyn Codec>,
-    postprocessor: Box<dyn Postprocessor>,
+    postprocessors: Vec<String>,
 }
 
 impl Egress {
     fn from_args(matches: &ArgMatches) -> Result<Self> {
-        let codec_post = matches.value_of("POSTPROCESSOR").unwrap_or("lines");
         let codec_encoder = matches.value_of("ENCODER").unwrap_or("json");
         let is_interactive = matches.is_present("interactive");
         let is_pretty = matches.is_present("pretty");
-
-        let buffer: Box<dyn Write> = match matches.value_of("OUTFILE") {
-            None | Some("-") => Box::new(BufWriter::new(io::stdout())),
-            Some(data) => Box::new(BufWriter::new(file::create(data)?)),
-        };
+        let is_tagged = matches.is_present("tagged");
+        let ports: Vec<String> = matches
+            .values_of("output-port")
+            .map_or_else(Vec::new, |ports| ports.map(ToString::to_string).collect());
 
         let codec = tremor_runtime::codec::lookup(codec_encoder);
         if let Err(_e) = codec {
@@ -154,69 +259,101 @@ impl Egress {
         }
         let codec = codec?;
 
-        let postprocessor = tremor_runtime::postprocessor::lookup(codec_post);
-        if let Err(_e) = postprocessor {
-            eprintln!("Error Postprocessor {} not found error.", codec_post);
+        let postprocessors: Vec<String> = matches.values_of("POSTPROCESSOR").map_or_else(
+            || vec!["lines".to_string()],
+            |names| names.map(ToString::to_string).collect(),
+        );
+        if let Err(e) = postprocessor::make_postprocessors(&postprocessors) {
+            eprintln!("Error {}", e);
             // ALLOW: main.rs
             std::process::exit(1);
         }
-        let postprocessor = postprocessor?;
+
+        let mut default = None;
+        let mut by_port = HashMap::new();
+        for arg in matches.values_of("OUTFILE").into_iter().flatten() {
+            match split_named(arg) {
+                (Some(port), path) => {
+                    by_port.insert(port.to_string(), Output::new(path, &postprocessors)?);
+                }
+                (None, path) => default = Some(Output::new(path, &postprocessors)?),
+            }
+        }
+        let default = match default {
+            Some(default) => default,
+            None => Output::new("-", &postprocessors)?,
+        };
 
         Ok(Self {
             is_interactive,
             is_pretty,
-            buffer,
-            postprocessor,
+            is_tagged,
+            ports,
+            default,
+            by_port,
             codec,
+            postprocessors,
         })
     }
 
-    fn process(&mut self, _src: &str, event: &Value, ret: Result<Return>) -> Result<()> {
+    fn emit(&mut self, port: &str, value: &Value) -> Result<()> {
+        if !is_selected(&self.ports, port) {
+            return Ok(());
+        }
+        let output = match self.by_port.get_mut(port) {
+            Some(output) => output,
+            None if ERROR_PORTS.contains(&port) => {
+                eprintln!("{}", value.encode());
+                return Ok(());
+            }
+            None => &mut self.default,
+        };
+        let tagged;
+        let value = if self.is_tagged {
+            let mut record = Object::with_capacity(2);
+            record.insert("port".into(), Value::from(port.to_string()));
+            record.insert("event".into(), value.clone());
+            tagged = Value::from(record);
+            &tagged
+        } else {
+            value
+        };
+
+        if self.is_interactive {
+            eprintln!(
+                "egress> [port: {}], [codec: {}], [postprocessors: {}]",
+                port,
+                self.codec.name(),
+                self.postprocessors.join(", ")
+            );
+            highlight(self.is_pretty, value)?;
+        }
+
+        let encoded = self.codec.encode(value)?;
+        let ppd = postprocessor::postprocess(&mut output.postprocessors, nanotime(), encoded)?;
+        for packet in ppd {
+            output.buffer.write_all(&packet)?;
+        }
+        output.buffer.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.default.buffer.flush()?;
+        for output in self.by_port.values_mut() {
+            output.buffer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, event: &Value, ret: Result<Return>) -> Result<()> {
         match ret {
             Ok(Return::Drop) => Ok(()),
             Ok(Return::Emit { value, port }) => {
-                match port.unwrap_or_else(|| String::from("out")).as_str() {
-                    "error" | "stderr" => {
-                        self.buffer
-                            .write_all(format!("{}\n", value.encode()).as_bytes())?;
-                        self.buffer.flush()?;
-                    }
-                    _ => {
-                        if self.is_interactive {
-                            eprintln!(
-                                "egress> [codec: {}], [postprocessor: {}]",
-                                self.codec.name(),
-                                self.postprocessor.name()
-                            );
-                            highlight(self.is_pretty, &value)?;
-                        }
-
-                        let encoded = self.codec.encode(&value);
-
-                        let ppd = self
-                            .postprocessor
-                            .process(nanotime(), nanotime(), &encoded?);
-                        for packet in ppd? {
-                            self.buffer.write_all(&packet)?;
-                            self.buffer.flush()?;
-                        }
-                    }
-                };
-                self.buffer.flush()?;
-                Ok(())
+                self.emit(port.as_deref().unwrap_or(DEFAULT_PORT), &value)
             }
             Ok(Return::EmitEvent { port }) => {
-                match port.unwrap_or_else(|| String::from("out")).as_str() {
-                    "error" | "stderr" => {
-                        eprintln!("{}", event.encode());
-                    }
-                    _ => {
-                        self.buffer
-                            .write_all(format!("{}\n", event.encode()).as_bytes())?;
-                        self.buffer.flush()?;
-                    }
-                };
-                Ok(())
+                self.emit(port.as_deref().unwrap_or(DEFAULT_PORT), event)
             }
             Err(e) => {
                 eprintln!("error processing event: {}", e);
@@ -226,6 +363,92 @@ impl Egress {
     }
 }
 
+/// Prints the path an event takes through a script to stderr, optionally
+/// pausing at a given line
+struct TermTracer {
+    break_at: Option<usize>,
+    breaking: Cell<bool>,
+    /// Set once the user asked to quit at a breakpoint
+    quit: Cell<bool>,
+}
+
+impl TermTracer {
+    fn from_args(matches: &ArgMatches) -> Result<Self> {
+        let break_at = match matches.value_of("break-at") {
+            Some(line) => Some(
+                line.parse::<usize>()
+                    .map_err(|e| Error::from(format!("Invalid line for --break-at: {}", e)))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            break_at,
+            breaking: Cell::new(break_at.is_some()),
+            quit: Cell::new(false),
+        })
+    }
+
+    fn trace(&self, at: Range, what: &str, detail: &str) {
+        if self.quit.get() {
+            return;
+        }
+        let start = at.start();
+        eprintln!(
+            "trace> {:>4}:{:<4} {:<8} {}",
+            start.line(),
+            start.column(),
+            what,
+            detail
+        );
+        if self.breaking.get() && self.break_at == Some(start.line()) {
+            self.pause();
+        }
+    }
+
+    fn pause(&self) {
+        eprint!("break> [enter] continue, [c] continue without breaking, [q] quit: ");
+        let mut line = String::new();
+        // stdin might be carrying the events so we talk to the terminal directly
+        match file::open("/dev/tty").map(BufReader::new) {
+            Ok(mut tty) => {
+                if let Err(e) = tty.read_line(&mut line) {
+                    eprintln!("\nError reading from terminal: {}", e);
+                }
+            }
+            Err(e) => {
+                eprintln!("\nError opening terminal, disabling breakpoints: {}", e);
+                self.breaking.set(false);
+            }
+        };
+        match line.trim() {
+            "c" => self.breaking.set(false),
+            "q" => {
+                self.breaking.set(false);
+                self.quit.set(true);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Tracer for TermTracer {
+    fn case(&self, at: Range, hit: bool) {
+        self.trace(at, "case", if hit { "hit" } else { "miss" });
+    }
+
+    fn guard(&self, at: Range, result: bool) {
+        self.trace(at, "guard", if result { "true" } else { "false" });
+    }
+
+    fn assign(&self, at: Range, path: &str, value: &Value) {
+        self.trace(at, "let", &format!("{} = {}", path, value.encode()));
+    }
+
+    fn mutate(&self, at: Range, kind: Mutation, value: &Value) {
+        self.trace(at, &kind.to_string(), &value.encode());
+    }
+}
+
 fn run_tremor_source(matches: &ArgMatches, src: String) -> Result<()> {
     let raw = slurp_string(&src);
     if let Err(e) = raw {
@@ -244,32 +467,63 @@ fn run_tremor_source(matches: &ArgMatches, src: String) -> Result<()> {
             script.format_warnings_with(&mut h)?;
 
             let mut ingress = Ingress::from_args(&matches)?;
+            if let Some(stream) = ingress.streams().find(|s| *s != DEFAULT_STREAM) {
+                return Err(format!(
+                    "Input stream `{}` given but tremor scripts only have an `{}` stream",
+                    stream, DEFAULT_STREAM
+                )
+                .into());
+            }
             let mut egress = Egress::from_args(&matches)?;
+            let tracer = if matches.is_present("trace") || matches.is_present("break-at") {
+                Some(TermTracer::from_args(&matches)?)
+            } else {
+                None
+            };
             let id = 0_u64;
 
             ingress.process(
                 &mut script,
                 id,
                 &mut egress,
-                &move |runnable, _id, egress, at, event| {
+                &move |runnable, id, egress, _stream, at, event| {
                     let mut global_map = Value::object();
                     let mut state = Value::null();
                     let mut event = event.clone_static();
-                    match runnable.run(
-                        &EventContext::new(at, Some(EventOriginUri::default())),
-                        AggrType::Tick,
-                        &mut event,
-                        &mut state,
-                        &mut global_map,
-                    ) {
-                        Ok(r) => egress.process(&src, &event, Ok(r)),
-                        Err(e) => egress.process(&src, &event, Err(e.into())),
+                    let ctx = EventContext::new(at, Some(EventOriginUri::default()));
+                    let r = if let Some(tracer) = &tracer {
+                        eprintln!("trace> event {}", id);
+                        runnable.run_traced(
+                            &ctx,
+                            AggrType::Tick,
+                            &mut event,
+                            &mut state,
+                            &mut global_map,
+                            tracer,
+                        )
+                    } else {
+                        runnable.run(
+                            &ctx,
+                            AggrType::Tick,
+                            &mut event,
+                            &mut state,
+                            &mut global_map,
+                        )
+                    };
+                    *id += 1;
+                    if tracer.as_ref().map_or(false, |t| t.quit.get()) {
+                        // the event was abandoned halfway, so nothing of it is emitted
+                        return Ok(Flow::Quit);
+                    }
+                    match r {
+                        Ok(r) => egress.process(&event, Ok(r)),
+                        Err(e) => egress.process(&event, Err(e.into())),
                     }?;
-                    Ok(())
+                    Ok(Flow::Continue)
                 },
             )?;
 
-            Ok(())
+            egress.flush()
         }
         Err(e) => {
             if let Err(e) = Script::format_error_from_script(&raw, &mut h, &e) {
@@ -281,6 +535,9 @@ fn run_tremor_source(matches: &ArgMatches, src: String) -> Result<()> {
 }
 
 fn run_trickle_source(matches: &ArgMatches, src: &str) -> Result<()> {
+    if matches.is_present("trace") || matches.is_present("break-at") {
+        return Err("Tracing is only supported for tremor scripts".into());
+    }
     let raw = slurp_string(&src);
     if let Err(e) = raw {
         eprintln!("Error processing file {}: {}", &src, e);
@@ -313,13 +570,16 @@ fn run_trickle_source(matches: &ArgMatches, src: &str) -> Result<()> {
     let runnable = tremor_pipeline::query::Query(runnable);
     let mut uid = 0_u64;
     let mut pipeline = runnable.to_pipe(&mut uid)?;
+    if let Some(stream) = ingress.streams().find(|s| !pipeline.has_input(s)) {
+        return Err(format!("Query `{}` has no input stream `{}`", src, stream).into());
+    }
     let id = 0_u64;
 
     ingress.process(
         &mut pipeline,
         id,
         &mut egress,
-        &move |runnable, id, egress, at, event| {
+        &move |runnable, id, egress, stream, at, event| {
             let value = LineValue::new(vec![], |_| unsafe {
                 std::mem::transmute(ValueAndMeta::from(event.clone()))
             });
@@ -327,7 +587,7 @@ fn run_trickle_source(matches: &ArgMatches, src: &str) -> Result<()> {
             let mut continuation = vec![];
 
             runnable.enqueue(
-                "in",
+                stream,
                 Event {
                     id: Ids::new(0, *id),
                     data: value.clone(),
@@ -340,7 +600,6 @@ fn run_trickle_source(matches: &ArgMatches, src: &str) -> Result<()> {
 
             for (port, rvalue) in continuation.drain(..) {
                 egress.process(
-                    &simd_json::to_string_pretty(&value.suffix().value())?,
                     &event,
                     Ok(Return::Emit {
                         value: rvalue.data.suffix().value().clone_static(),
@@ -349,9 +608,10 @@ fn run_trickle_source(matches: &ArgMatches, src: &str) -> Result<()> {
                 )?;
             }
 
-            Ok(())
+            Ok(Flow::Continue)
         },
     )?;
+    egress.flush()?;
 
     h.finalize()?;
 
@@ -375,3 +635,43 @@ pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn named() {
+        assert_eq!(split_named("in.json"), (None, "in.json"));
+        assert_eq!(split_named("-"), (None, "-"));
+        assert_eq!(split_named("snot=in.json"), (Some("snot"), "in.json"));
+        assert_eq!(split_named("snot_2=-"), (Some("snot_2"), "-"));
+        assert_eq!(split_named("=in.json"), (None, "=in.json"));
+        assert_eq!(split_named("./a=b.json"), (None, "./a=b.json"));
+        assert_eq!(split_named("a.b=c.json"), (None, "a.b=c.json"));
+    }
+
+    #[test]
+    fn named_existing_file() -> Result<()> {
+        let path = "run-test-snot=badger.json";
+        std::fs::write(path, "{}")?;
+        let split = split_named(path);
+        std::fs::remove_file(path)?;
+        assert_eq!(split, (None, path));
+        Ok(())
+    }
+
+    #[test]
+    fn selected() {
+        let none: Vec<String> = Vec::new();
+        assert!(is_selected(&none, "out"));
+        assert!(is_selected(&none, "snot"));
+
+        let out = vec!["out".to_string()];
+        assert!(is_selected(&out, "out"));
+        assert!(!is_selected(&out, "snot"));
+        assert!(is_selected(&out, "err"));
+        assert!(is_selected(&out, "error"));
+        assert!(is_selected(&out, "stderr"));
+    }
+}
@@ -16,8 +16,12 @@ use crate::errors::{Error, Result};
 use crate::util::{get_source_kind, SourceKind};
 use async_std::task;
 use clap::{App, ArgMatches};
+use signal_hook::iterator::Signals;
+use signal_hook::{SIGINT, SIGTERM};
+use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
 use std::{io::BufReader, sync::atomic::Ordering};
 use tremor_api as api;
 use tremor_common::file;
@@ -28,6 +32,8 @@ use tremor_runtime::system::World;
 use tremor_runtime::url::TremorURL;
 use tremor_runtime::{self, config, version};
 
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 #[cfg(not(tarpaulin_include))]
 pub(crate) async fn load_file(world: &World, file_name: &str) -> Result<usize> {
     info!("Loading configuration from {}", file_name);
@@ -74,10 +80,9 @@ pub(crate) async fn load_file(world: &World, file_name: &str) -> Result<usize> {
 }
 
 #[cfg(not(tarpaulin_include))]
-pub(crate) async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
+pub(crate) fn parse_query_file(file_name: &str) -> Result<(TremorURL, Query)> {
     use std::ffi::OsStr;
     use std::io::Read;
-    info!("Loading configuration from {}", file_name);
     let path = Path::new(file_name);
     let file_id = path
         .file_stem()
@@ -103,12 +108,179 @@ pub(crate) async fn load_query_file(world: &World, file_name: &str) -> Result<us
     let id = query.id().unwrap_or_else(|| &file_id);
 
     let id = TremorURL::parse(&format!("/pipeline/{}", id))?;
-    info!("Loading {} from file {}.", id, file_id);
+    Ok((id, query))
+}
+
+#[cfg(not(tarpaulin_include))]
+pub(crate) async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
+    info!("Loading configuration from {}", file_name);
+    let (id, query) = parse_query_file(file_name)?;
+    info!("Loading {} from file {}.", id, file_name);
     world.repo.publish_pipeline(&id, false, query).await?;
 
     Ok(1)
 }
 
+/// Reloads a changed config file, new artefacts are published and changed
+/// ones are republished if they have no running instances.
+#[cfg(not(tarpaulin_include))]
+async fn reload_file(world: &World, file_name: &str) -> Result<usize> {
+    info!("Reloading configuration from {}", file_name);
+    let mut count = 0;
+    let file = crate::open_file(file_name, None)?;
+    let buffered_reader = BufReader::new(file);
+    let config: config::Config = serde_yaml::from_reader(buffered_reader)?;
+    let config = tremor_runtime::incarnate(config)?;
+
+    for o in config.offramps {
+        let id = TremorURL::parse(&format!("/offramp/{}", o.id))?;
+        match world.repo.find_offramp(&id).await? {
+            None => {
+                world.repo.publish_offramp(&id, false, o).await?;
+            }
+            Some(w) if changed(&w.artefact.redacted(), &o)? => {
+                if w.instances.is_empty() {
+                    world.repo.unpublish_offramp(&id).await?;
+                    world.repo.publish_offramp(&id, false, o).await?;
+                } else {
+                    warn!("{} changed but is running, restart to apply.", id);
+                    continue;
+                }
+            }
+            Some(_) => continue,
+        }
+        info!("Reloaded {} from file.", id);
+        count += 1;
+    }
+
+    for o in config.onramps {
+        let id = TremorURL::parse(&format!("/onramp/{}", o.id))?;
+        match world.repo.find_onramp(&id).await? {
+            None => {
+                world.repo.publish_onramp(&id, false, o).await?;
+            }
+            Some(w) if changed(&w.artefact.redacted(), &o)? => {
+                if w.instances.is_empty() {
+                    world.repo.unpublish_onramp(&id).await?;
+                    world.repo.publish_onramp(&id, false, o).await?;
+                } else {
+                    warn!("{} changed but is running, restart to apply.", id);
+                    continue;
+                }
+            }
+            Some(_) => continue,
+        }
+        info!("Reloaded {} from file.", id);
+        count += 1;
+    }
+
+    for binding in config.bindings {
+        let id = TremorURL::parse(&format!("/binding/{}", binding.id))?;
+        let artefact = BindingArtefact {
+            binding,
+            mapping: None,
+        };
+        match world.repo.find_binding(&id).await? {
+            None => {
+                world.repo.publish_binding(&id, false, artefact).await?;
+            }
+            Some(w) if changed(&w.artefact.binding, &artefact.binding)? => {
+                if w.instances.is_empty() {
+                    world.repo.unpublish_binding(&id).await?;
+                    world.repo.publish_binding(&id, false, artefact).await?;
+                } else {
+                    warn!("{} changed but is running, restart to apply.", id);
+                    continue;
+                }
+            }
+            Some(_) => continue,
+        }
+        info!("Reloaded {} from file.", id);
+        count += 1;
+    }
+
+    for (binding, mapping) in config.mappings {
+        if world.reg.find_binding(&binding).await?.is_none() {
+            world.link_binding(&binding, mapping).await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Reloads a changed trickle file, running instances of the pipeline are
+/// swapped to the new query. If the query fails to compile the running
+/// pipeline is kept.
+#[cfg(not(tarpaulin_include))]
+async fn reload_query_file(world: &World, file_name: &str) -> Result<usize> {
+    info!("Reloading configuration from {}", file_name);
+    let (id, query) = parse_query_file(file_name)?;
+    match world.repo.find_pipeline(&id).await? {
+        None => {
+            world.repo.publish_pipeline(&id, false, query).await?;
+        }
+        Some(w) if w.artefact.source() == query.source() => return Ok(0),
+        Some(_) => {
+            let swapped = world.reload_pipeline(&id, query).await?;
+            info!("Swapped {} running instances of {}.", swapped, id);
+        }
+    }
+    info!("Reloaded {} from file {}.", id, file_name);
+    Ok(1)
+}
+
+fn changed<T: serde::Serialize>(old: &T, new: &T) -> Result<bool> {
+    Ok(serde_yaml::to_value(old)? != serde_yaml::to_value(new)?)
+}
+
+fn modified(file_name: &str) -> Option<SystemTime> {
+    std::fs::metadata(file_name).and_then(|m| m.modified()).ok()
+}
+
+/// Polls the artefact files for changes and reloads them, trickle files
+/// are reloaded before config files
+#[cfg(not(tarpaulin_include))]
+async fn watch(world: World, files: Vec<String>) {
+    let mut mtimes: HashMap<String, Option<SystemTime>> = files
+        .into_iter()
+        .map(|f| {
+            let m = modified(&f);
+            (f, m)
+        })
+        .collect();
+    loop {
+        task::sleep(WATCH_INTERVAL).await;
+        let mut changed_files: Vec<String> = mtimes
+            .iter_mut()
+            .filter_map(|(file, last)| {
+                let current = modified(file);
+                if current == *last {
+                    None
+                } else {
+                    *last = current;
+                    Some(file.clone())
+                }
+            })
+            .collect();
+        changed_files.sort_by_key(|f| get_source_kind(f) != SourceKind::Trickle);
+        for file in changed_files {
+            let r = match get_source_kind(&file) {
+                SourceKind::Trickle => reload_query_file(&world, &file).await,
+                SourceKind::Tremor => continue,
+                _ => reload_file(&world, &file).await,
+            };
+            match r {
+                Ok(0) => info!("No artefacts changed in {}", file),
+                Ok(n) => info!("Reloaded {} artefacts from {}", n, file),
+                Err(e) => error!(
+                    "Failed to reload {}, keeping the running artefacts: {}",
+                    file, e
+                ),
+            }
+        }
+    }
+}
+
 fn fix_tide(r: api::Result<tide::Response>) -> tide::Result {
     Ok(match r {
         Ok(r) => r,
@@ -116,13 +288,69 @@ fn fix_tide(r: api::Result<tide::Response>) -> tide::Result {
     })
 }
 
-fn api_server(world: &World) -> Result<tide::Server<api::State>> {
+/// Loads the security settings of the API
+fn load_api_config(file_name: &str) -> Result<api::auth::Config> {
+    let file = crate::open_file(file_name, None)?;
+    let config: api::auth::Config = serde_yaml::from_reader(BufReader::new(file))?;
+    Ok(config.resolve()?)
+}
+
+/// Builds the rustls config of the API listener, with a client CA only
+/// clients presenting a certificate signed by it are accepted
+fn tls_config(tls: &api::auth::Tls) -> Result<rustls::ServerConfig> {
+    use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+    let client_auth = if let Some(ca) = &tls.client_ca {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add_pem_file(&mut BufReader::new(std::fs::File::open(ca)?))
+            .map_err(|_| Error::from(format!("Invalid client CA `{}`", ca)))?;
+        rustls::AllowAnyAuthenticatedClient::new(roots)
+    } else {
+        rustls::NoClientAuth::new()
+    };
+    let mut config = rustls::ServerConfig::new(client_auth);
+    let certs = certs(&mut BufReader::new(std::fs::File::open(&tls.cert)?))
+        .map_err(|_| Error::from(format!("Invalid certificate `{}`", tls.cert)))?;
+    let invalid_key = |_| Error::from(format!("Invalid private key `{}`", tls.key));
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(std::fs::File::open(&tls.key)?))
+        .map_err(invalid_key)?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut BufReader::new(std::fs::File::open(&tls.key)?))
+            .map_err(invalid_key)?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::from(format!("No private key in `{}`", tls.key)))?;
+    config
+        .set_single_cert(certs, key)
+        .map_err(|e| Error::from(format!("Invalid certificate: {}", e)))?;
+    Ok(config)
+}
+
+fn api_server(
+    world: &World,
+    api_config: Option<api::auth::Config>,
+) -> Result<tide::Server<api::State>> {
     let mut app = tide::Server::with_state(api::State {
         world: world.clone(),
     });
+    if let Some(api_config) = api_config {
+        app.with(api::auth::Rbac::new(api_config));
+    } else {
+        warn!("The API is not authenticated, use --api-config to secure it");
+    }
 
     app.at("/version")
         .get(|r| async { fix_tide(api::version::get(r).await) });
+    app.at("/metrics")
+        .get(|r| async { fix_tide(api::metrics::get(r).await) });
+    app.at("/health/live")
+        .get(|r| async { fix_tide(api::health::live(r).await) });
+    app.at("/health/ready")
+        .get(|r| async { fix_tide(api::health::ready(r).await) });
+    app.at("/topology")
+        .get(|r| async { fix_tide(api::topology::get(r).await) });
     app.at("/binding")
         .get(|r| async { fix_tide(api::binding::list_artefact(r).await) })
         .post(|r| async { fix_tide(api::binding::publish_artefact(r).await) });
@@ -139,6 +367,16 @@ fn api_server(world: &World) -> Result<tide::Server<api::State>> {
     app.at("/pipeline/:aid")
         .get(|r| async { fix_tide(api::pipeline::get_artefact(r).await) })
         .delete(|r| async { fix_tide(api::pipeline::unpublish_artefact(r).await) });
+    app.at("/pipeline/:aid/:sid/:port/tap")
+        .get(|r| async { fix_tide(api::pipeline::tap(r).await) });
+    app.at("/dlq")
+        .get(|r| async { fix_tide(api::dlq::list_queues(r).await) });
+    app.at("/dlq/:aid")
+        .get(|r| async { fix_tide(api::dlq::list_letters(r).await) });
+    app.at("/dlq/:aid/:sid")
+        .get(|r| async { fix_tide(api::dlq::get_letter(r).await) })
+        .post(|r| async { fix_tide(api::dlq::reinject_letter(r).await) })
+        .delete(|r| async { fix_tide(api::dlq::delete_letter(r).await) });
     app.at("/onramp")
         .get(|r| async { fix_tide(api::onramp::list_artefact(r).await) })
         .post(|r| async { fix_tide(api::onramp::publish_artefact(r).await) });
@@ -155,6 +393,37 @@ fn api_server(world: &World) -> Result<tide::Server<api::State>> {
     Ok(app)
 }
 
+/// Drains the world on SIGTERM or SIGINT before stopping it, so in-flight
+/// events are delivered before sources commit
+#[cfg(not(tarpaulin_include))]
+fn handle_signals(world: World, timeout: Duration) -> Result<()> {
+    let signals = Signals::new(&[SIGTERM, SIGINT])?;
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                info!("Received signal {}, shutting down.", signal);
+                eprintln!("Shutting down, draining in-flight events...");
+                let res = task::block_on(async {
+                    // stop even if draining failed, but still report it
+                    let drained = world.drain(timeout).await;
+                    world.stop().await?;
+                    drained
+                });
+                let code = if let Err(e) = res {
+                    error!("Failed to shut down gracefully: {}", e);
+                    eprintln!("Failed to shut down gracefully: {}", e);
+                    1
+                } else {
+                    0
+                };
+                // ALLOW: we are done shutting down
+                ::std::process::exit(code);
+            }
+        })?;
+    Ok(())
+}
+
 #[cfg(not(tarpaulin_include))]
 pub(crate) async fn run_dun(matches: &ArgMatches) -> Result<()> {
     // Logging
@@ -189,11 +458,18 @@ pub(crate) async fn run_dun(matches: &ArgMatches) -> Result<()> {
         .ok_or_else(|| Error::from("invalid recursion limit"))?;
     tremor_script::RECURSION_LIMIT.store(l, Ordering::Relaxed);
 
+    let shutdown_timeout = matches
+        .value_of("shutdown-timeout")
+        .and_then(|t| t.parse().ok())
+        .map(Duration::from_secs)
+        .ok_or_else(|| Error::from("invalid shutdown timeout"))?;
+
     let storage_directory = matches
         .value_of("storage-directory")
         .map(std::string::ToString::to_string);
     // TODO: Allow configuring this for offramps and pipelines
     let (world, handle) = World::start(64, storage_directory).await?;
+    handle_signals(world.clone(), shutdown_timeout)?;
 
     if let Some(config_files) = matches.values_of("artefacts") {
         // We process trickle files first
@@ -212,15 +488,37 @@ pub(crate) async fn run_dun(matches: &ArgMatches) -> Result<()> {
         }
     }
 
+    if matches.is_present("watch") {
+        let files = matches
+            .values_of("artefacts")
+            .map(|files| files.map(String::from).collect())
+            .unwrap_or_default();
+        task::spawn(watch(world.clone(), files));
+    }
+
     if !matches.is_present("no-api") {
         let host = matches
             .value_of("api-host")
             .ok_or_else(|| Error::from("host argument missing"))?;
-        let app = api_server(&world)?;
-        eprintln!("Listening at: http://{}", host);
-        info!("Listening at: http://{}", host);
-
-        if let Err(e) = app.listen(host).await {
+        let api_config = matches
+            .value_of("api-config")
+            .map(load_api_config)
+            .transpose()?;
+        let tls = api_config.as_ref().and_then(|c| c.tls.clone());
+        let app = api_server(&world, api_config)?;
+        let res = if let Some(tls) = tls {
+            let listener = tide_rustls::TlsListener::build()
+                .addrs(host)
+                .config(tls_config(&tls)?);
+            eprintln!("Listening at: https://{}", host);
+            info!("Listening at: https://{}", host);
+            app.listen(listener).await
+        } else {
+            eprintln!("Listening at: http://{}", host);
+            info!("Listening at: http://{}", host);
+            app.listen(host).await
+        };
+        if let Err(e) = res {
             error!("API Error: {}", e);
         }
         warn!("API stopped");
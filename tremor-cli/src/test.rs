@@ -38,6 +38,9 @@ mod command;
 mod kind;
 mod metadata;
 mod process;
+mod property;
+mod runner;
+mod snapshot;
 pub mod stats;
 pub mod tag;
 mod unit;
@@ -200,6 +203,9 @@ fn suite_unit(
 
 #[allow(clippy::too_many_lines)]
 pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("record") {
+        return snapshot::run_record(matches);
+    }
     let kind: test::TestKind = matches.value_of("MODE").unwrap_or_default().try_into()?;
     let path = matches.value_of("PATH").unwrap_or_default();
     let report = matches.value_of("REPORT").unwrap_or_default();
@@ -233,6 +239,8 @@ pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
     let mut unit_stats = stats::Stats::new();
     let mut cmd_stats = stats::Stats::new();
     let mut integration_stats = stats::Stats::new();
+    let mut snapshot_stats = stats::Stats::new();
+    let mut property_stats = stats::Stats::new();
     let mut elapsed = 0;
 
     let cwd = std::env::current_dir()?;
@@ -288,6 +296,30 @@ pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
                 unit_stats.merge(&stats);
                 status::hr()?;
             }
+
+            if meta.kind == TestKind::Snapshot
+                && (kind == TestKind::All || kind == TestKind::Snapshot)
+            {
+                includes.push("snapshot".into());
+                let tag_filter = (includes.as_slice(), excludes.as_slice());
+                let (stats, test_reports) =
+                    snapshot::suite_snapshot(base, root, &meta, tag_filter)?;
+                reports.insert("snapshot".to_string(), test_reports);
+                snapshot_stats.merge(&stats);
+                status::hr()?;
+            }
+
+            if meta.kind == TestKind::Property
+                && (kind == TestKind::All || kind == TestKind::Property)
+            {
+                includes.push("property".into());
+                let tag_filter = (includes.as_slice(), excludes.as_slice());
+                let (stats, test_reports) =
+                    property::suite_property(base, root, &meta, tag_filter)?;
+                reports.insert("property".to_string(), test_reports);
+                property_stats.merge(&stats);
+                status::hr()?;
+            }
         }
 
         elapsed = nanotime() - start;
@@ -299,11 +331,15 @@ pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
     status::rollups("All Integration", &integration_stats)?;
     status::rollups("All Command", &cmd_stats)?;
     status::rollups("All Unit", &unit_stats)?;
+    status::rollups("All Snapshot", &snapshot_stats)?;
+    status::rollups("All Property", &property_stats)?;
     let mut all_stats = stats::Stats::new();
     all_stats.merge(&bench_stats);
     all_stats.merge(&integration_stats);
     all_stats.merge(&cmd_stats);
     all_stats.merge(&unit_stats);
+    all_stats.merge(&snapshot_stats);
+    all_stats.merge(&property_stats);
     status::rollups("Total", &all_stats)?;
     let mut stats_map = HashMap::new();
     stats_map.insert("all".to_string(), all_stats.clone());
@@ -311,6 +347,8 @@ pub(crate) fn run_cmd(matches: &ArgMatches) -> Result<()> {
     stats_map.insert("integration".to_string(), integration_stats);
     stats_map.insert("command".to_string(), cmd_stats);
     stats_map.insert("unit".to_string(), unit_stats);
+    stats_map.insert("snapshot".to_string(), snapshot_stats);
+    stats_map.insert("property".to_string(), property_stats);
     status::total_duration(elapsed)?;
 
     let test_run = report::TestRun {
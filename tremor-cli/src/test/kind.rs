@@ -21,6 +21,8 @@ pub(crate) enum TestKind {
     Integration,
     Command,
     Unit,
+    Snapshot,
+    Property,
     All,
     Unknown(String),
 }
@@ -32,7 +34,7 @@ pub struct UnknownKind(String);
 
 impl Display for UnknownKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Unknown test kind `{}`, please choose one of `all`, `api`, `bench`, `command`, `integration`, `rest`, or `unit`", self.0)
+        write!(f, "Unknown test kind `{}`, please choose one of `all`, `api`, `bench`, `command`, `integration`, `property`, `rest`, `snapshot`, or `unit`", self.0)
     }
 }
 
@@ -46,6 +48,8 @@ impl TryFrom<&str> for TestKind {
             "bench" | "benchmark" => Ok(TestKind::Bench),
             "it" | "integration" => Ok(TestKind::Integration),
             "unit" => Ok(TestKind::Unit),
+            "snapshot" => Ok(TestKind::Snapshot),
+            "fuzz" | "property" => Ok(TestKind::Property),
             default => Err(UnknownKind(default.into())),
         }
     }
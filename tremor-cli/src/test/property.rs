@@ -0,0 +1,879 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::{Error, Result};
+use crate::report;
+use crate::status;
+use crate::test::runner::Runner;
+use crate::test::stats;
+use crate::test::tag::{self, Tags};
+use crate::test::Meta;
+use crate::util::slurp_string;
+use globwalk::{FileType, GlobWalkerBuilder};
+use rand::distributions::Alphanumeric;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use simd_json::borrowed::{Object, Value};
+use simd_json::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tremor_common::time::nanotime;
+use tremor_script::ctx::{EventContext, EventOriginUri};
+use tremor_script::highlighter::Term as TermHighlighter;
+use tremor_script::path::load as load_module_path;
+use tremor_script::registry;
+use tremor_script::script::{AggrType, Return, Script};
+
+const SPEC_GLOB: &str = "**/property.yml";
+
+/// Probability of picking an edge case ( null, missing, bounds, empty )
+/// over a regular random value
+const EDGE_PROBABILITY: f64 = 0.2;
+/// Nesting limit for `any` values
+const MAX_DEPTH: usize = 3;
+/// Upper bound of runs spent on shrinking a single counterexample
+const MAX_SHRINK_RUNS: usize = 1000;
+
+const DEFAULT_FLOAT_MIN: f64 = -1_000_000.0;
+const DEFAULT_FLOAT_MAX: f64 = 1_000_000.0;
+
+/// A property scenario, runs `runs` random events conforming to `schema`
+/// through `source` and checks every output against `invariants`
+#[derive(Deserialize, Debug)]
+pub(crate) struct PropertySpec {
+    pub(crate) name: String,
+    pub(crate) source: String,
+    #[serde(default = "default_runs")]
+    pub(crate) runs: usize,
+    /// Fixed seed to reproduce a previous run
+    pub(crate) seed: Option<u64>,
+    /// The source may drop inputs, otherwise a dropped input violates every
+    /// invariant as there is no output to check
+    #[serde(default)]
+    pub(crate) allow_drops: bool,
+    pub(crate) schema: Schema,
+    pub(crate) invariants: Vec<InvariantSpec>,
+    pub(crate) tags: Option<Tags>,
+}
+
+fn default_runs() -> usize {
+    100
+}
+
+fn default_string_len() -> usize {
+    16
+}
+
+fn default_array_len() -> usize {
+    4
+}
+
+/// A tremor-script predicate, `event` is a record of `input`, `port` and
+/// `output` for every event the source emits
+#[derive(Deserialize, Debug)]
+pub(crate) struct InvariantSpec {
+    pub(crate) name: String,
+    pub(crate) check: String,
+}
+
+/// Describes the shape of generated events
+#[derive(Deserialize, Debug)]
+pub(crate) struct Schema {
+    #[serde(flatten)]
+    kind: Kind,
+    /// The field may be missing from its record
+    #[serde(default)]
+    optional: bool,
+    /// The value may be `null`
+    #[serde(default)]
+    nullable: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Kind {
+    Null,
+    Bool,
+    Integer {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Float {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    String {
+        #[serde(default = "default_string_len")]
+        max_len: usize,
+        #[serde(default)]
+        one_of: Vec<String>,
+    },
+    Array {
+        items: Box<Schema>,
+        #[serde(default = "default_array_len")]
+        max_len: usize,
+    },
+    Record {
+        #[serde(default)]
+        fields: BTreeMap<String, Schema>,
+    },
+    Any,
+}
+
+type Shrinker<'s> = &'s dyn Fn(&Value<'static>) -> Vec<Value<'static>>;
+
+impl Schema {
+    fn generate<R: Rng>(&self, rng: &mut R) -> Value<'static> {
+        if self.nullable && rng.gen_bool(EDGE_PROBABILITY) {
+            return Value::null();
+        }
+        match &self.kind {
+            Kind::Null => Value::null(),
+            Kind::Bool => Value::from(rng.gen::<bool>()),
+            Kind::Integer { min, max } => {
+                let min = min.unwrap_or(i64::MIN);
+                let max = max.unwrap_or(i64::MAX);
+                if max <= min {
+                    Value::from(min)
+                } else if rng.gen_bool(EDGE_PROBABILITY) {
+                    let edges = [min, max, int_target(min, max)];
+                    Value::from(edges[rng.gen_range(0, edges.len())])
+                } else {
+                    Value::from(rng.gen_range(min, max))
+                }
+            }
+            Kind::Float { min, max } => {
+                let min = min.unwrap_or(DEFAULT_FLOAT_MIN);
+                let max = max.unwrap_or(DEFAULT_FLOAT_MAX);
+                if max <= min {
+                    Value::from(min)
+                } else if rng.gen_bool(EDGE_PROBABILITY) {
+                    let edges = [min, max, float_target(min, max)];
+                    Value::from(edges[rng.gen_range(0, edges.len())])
+                } else {
+                    Value::from(rng.gen_range(min, max))
+                }
+            }
+            Kind::String { max_len, one_of } => {
+                if one_of.is_empty() {
+                    random_string(rng, *max_len)
+                } else {
+                    Value::from(one_of[rng.gen_range(0, one_of.len())].clone())
+                }
+            }
+            Kind::Array { items, max_len } => {
+                let len = rng.gen_range(0, max_len + 1);
+                Value::from((0..len).map(|_| items.generate(rng)).collect::<Vec<_>>())
+            }
+            Kind::Record { fields } => {
+                let mut record = Object::with_capacity(fields.len());
+                for (name, field) in fields {
+                    if field.optional && rng.gen_bool(EDGE_PROBABILITY) {
+                        continue;
+                    }
+                    record.insert(name.clone().into(), field.generate(rng));
+                }
+                Value::from(record)
+            }
+            Kind::Any => random_any(rng, 0),
+        }
+    }
+
+    /// Simpler variants of `value` that still conform to the schema,
+    /// simplest first
+    fn shrink(&self, value: &Value<'static>) -> Vec<Value<'static>> {
+        let mut candidates = vec![];
+        if value.is_null() {
+            return candidates;
+        }
+        if self.nullable {
+            candidates.push(Value::null());
+        }
+        match &self.kind {
+            Kind::Null => (),
+            Kind::Bool => {
+                if value.as_bool() == Some(true) {
+                    candidates.push(Value::from(false));
+                }
+            }
+            Kind::Integer { min, max } => {
+                if let Some(i) = value.as_i64() {
+                    let target = int_target(min.unwrap_or(i64::MIN), max.unwrap_or(i64::MAX));
+                    shrink_int(i, target, &mut candidates);
+                }
+            }
+            Kind::Float { min, max } => {
+                if let Some(f) = value.cast_f64() {
+                    let target = float_target(
+                        min.unwrap_or(DEFAULT_FLOAT_MIN),
+                        max.unwrap_or(DEFAULT_FLOAT_MAX),
+                    );
+                    shrink_float(f, target, &mut candidates);
+                }
+            }
+            Kind::String { one_of, .. } => {
+                if let Some(s) = value.as_str() {
+                    if one_of.is_empty() {
+                        shrink_string(s, &mut candidates);
+                    } else {
+                        // earlier alternatives are considered simpler
+                        candidates.extend(
+                            one_of
+                                .iter()
+                                .take_while(|o| o.as_str() != s)
+                                .map(|o| Value::from(o.clone())),
+                        );
+                    }
+                }
+            }
+            Kind::Array { items, .. } => {
+                if let Some(a) = value.as_array() {
+                    shrink_array(a, &|v| items.shrink(v), &mut candidates);
+                }
+            }
+            Kind::Record { fields } => {
+                if let Some(record) = value.as_object() {
+                    for (name, field) in fields {
+                        if field.optional && record.contains_key(name.as_str()) {
+                            let mut smaller = record.clone();
+                            smaller.remove(name.as_str());
+                            candidates.push(Value::from(smaller));
+                        }
+                    }
+                    for (name, field) in fields {
+                        if let Some(v) = record.get(name.as_str()) {
+                            for c in field.shrink(v) {
+                                let mut smaller = record.clone();
+                                smaller.insert(name.clone().into(), c);
+                                candidates.push(Value::from(smaller));
+                            }
+                        }
+                    }
+                }
+            }
+            Kind::Any => shrink_any(value, &mut candidates),
+        }
+        candidates
+    }
+}
+
+/// The simplest integer in `min..=max`, zero if it is in range
+fn int_target(min: i64, max: i64) -> i64 {
+    0.max(min).min(max)
+}
+
+fn float_target(min: f64, max: f64) -> f64 {
+    0_f64.max(min).min(max)
+}
+
+fn random_string<R: Rng>(rng: &mut R, max_len: usize) -> Value<'static> {
+    let len = if rng.gen_bool(EDGE_PROBABILITY) {
+        0
+    } else {
+        rng.gen_range(0, max_len + 1)
+    };
+    Value::from(rng.sample_iter(&Alphanumeric).take(len).collect::<String>())
+}
+
+fn random_any<R: Rng>(rng: &mut R, depth: usize) -> Value<'static> {
+    let kinds = if depth < MAX_DEPTH { 7 } else { 5 };
+    match rng.gen_range(0, kinds) {
+        0 => Value::null(),
+        1 => Value::from(rng.gen::<bool>()),
+        2 => Value::from(rng.gen::<i64>()),
+        3 => Value::from(rng.gen_range(DEFAULT_FLOAT_MIN, DEFAULT_FLOAT_MAX)),
+        4 => random_string(rng, default_string_len()),
+        5 => {
+            let len = rng.gen_range(0, default_array_len() + 1);
+            Value::from(
+                (0..len)
+                    .map(|_| random_any(rng, depth + 1))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        _ => {
+            let len = rng.gen_range(0, default_array_len() + 1);
+            let mut record = Object::with_capacity(len);
+            for _ in 0..len {
+                if let Some(key) = random_string(rng, 8).as_str() {
+                    record.insert(key.to_string().into(), random_any(rng, depth + 1));
+                }
+            }
+            Value::from(record)
+        }
+    }
+}
+
+fn shrink_int(i: i64, target: i64, candidates: &mut Vec<Value<'static>>) {
+    if i == target {
+        return;
+    }
+    candidates.push(Value::from(target));
+    let half = target + (i - target) / 2;
+    if half != target {
+        candidates.push(Value::from(half));
+    }
+    let step = i - (i - target).signum();
+    if step != half && step != target {
+        candidates.push(Value::from(step));
+    }
+}
+
+fn shrink_float(f: f64, target: f64, candidates: &mut Vec<Value<'static>>) {
+    if (f - target).abs() < f64::EPSILON {
+        return;
+    }
+    candidates.push(Value::from(target));
+    if (f.trunc() - f).abs() > f64::EPSILON {
+        candidates.push(Value::from(f.trunc()));
+    }
+    let half = target + (f - target) / 2.0;
+    if (half - target).abs() > f64::EPSILON {
+        candidates.push(Value::from(half));
+    }
+}
+
+fn shrink_string(s: &str, candidates: &mut Vec<Value<'static>>) {
+    if s.is_empty() {
+        return;
+    }
+    candidates.push(Value::from(String::new()));
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() > 2 {
+        candidates.push(Value::from(
+            chars[..chars.len() / 2].iter().collect::<String>(),
+        ));
+    }
+    if chars.len() > 1 {
+        candidates.push(Value::from(
+            chars[..chars.len() - 1].iter().collect::<String>(),
+        ));
+    }
+}
+
+fn shrink_array(a: &[Value<'static>], items: Shrinker, candidates: &mut Vec<Value<'static>>) {
+    if a.is_empty() {
+        return;
+    }
+    candidates.push(Value::array());
+    for i in 0..a.len() {
+        let mut smaller = a.to_vec();
+        smaller.remove(i);
+        candidates.push(Value::from(smaller));
+    }
+    for (i, v) in a.iter().enumerate() {
+        for c in items(v) {
+            let mut smaller = a.to_vec();
+            smaller[i] = c;
+            candidates.push(Value::from(smaller));
+        }
+    }
+}
+
+fn shrink_any(value: &Value<'static>, candidates: &mut Vec<Value<'static>>) {
+    if value.is_null() {
+        return;
+    }
+    candidates.push(Value::null());
+    if value.as_bool() == Some(true) {
+        candidates.push(Value::from(false));
+    } else if let Some(i) = value.as_i64() {
+        shrink_int(i, 0, candidates);
+    } else if let Some(f) = value.as_f64() {
+        shrink_float(f, 0.0, candidates);
+    } else if let Some(s) = value.as_str() {
+        shrink_string(s, candidates);
+    } else if let Some(a) = value.as_array() {
+        shrink_array(
+            a,
+            &|v| {
+                let mut c = vec![];
+                shrink_any(v, &mut c);
+                c
+            },
+            candidates,
+        );
+    } else if let Some(record) = value.as_object() {
+        for (k, v) in record.iter() {
+            let mut smaller = record.clone();
+            smaller.remove(k);
+            candidates.push(Value::from(smaller));
+            let mut shrunk = vec![];
+            shrink_any(v, &mut shrunk);
+            for c in shrunk {
+                let mut smaller = record.clone();
+                smaller.insert(k.clone(), c);
+                candidates.push(Value::from(smaller));
+            }
+        }
+    }
+}
+
+struct Invariant {
+    name: String,
+    script: Script,
+}
+
+impl Invariant {
+    fn load(spec: &InvariantSpec) -> Result<Self> {
+        let reg = registry::registry();
+        match Script::parse(&load_module_path(), &spec.name, spec.check.clone(), &reg) {
+            Ok(script) => Ok(Self {
+                name: spec.name.clone(),
+                script,
+            }),
+            Err(e) => {
+                let mut h = TermHighlighter::default();
+                if let Err(e) = Script::format_error_from_script(&spec.check, &mut h, &e) {
+                    eprintln!("Error: {}", e);
+                };
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Checks all outputs for an input, returns why the invariant does
+    /// not hold, if it doesn't
+    fn violation(
+        &self,
+        input: &Value<'static>,
+        outputs: &[(String, Value<'static>)],
+    ) -> Option<String> {
+        for (port, output) in outputs {
+            let mut event = Object::with_capacity(3);
+            event.insert("input".into(), input.clone());
+            event.insert("port".into(), Value::from(port.clone()));
+            event.insert("output".into(), output.clone());
+            let mut event = Value::from(event);
+            let mut state = Value::null();
+            let mut meta = Value::object();
+            let ctx = EventContext::new(nanotime(), Some(EventOriginUri::default()));
+            let result = self
+                .script
+                .run(&ctx, AggrType::Tick, &mut event, &mut state, &mut meta);
+            let reason = match result {
+                Ok(Return::Emit { value, .. }) if value.as_bool() == Some(true) => continue,
+                Ok(Return::Emit { value, .. }) => format!("returned `{}`", value.encode()),
+                Ok(Return::EmitEvent { .. }) | Ok(Return::Drop) => {
+                    "did not return a boolean".to_string()
+                }
+                Err(e) => format!("failed: {}", e),
+            };
+            return Some(format!(
+                "on port `{}` with output `{}` the check {}",
+                port,
+                output.encode(),
+                reason
+            ));
+        }
+        None
+    }
+}
+
+/// A counterexample after shrinking
+struct Counterexample {
+    input: Value<'static>,
+    reason: String,
+    shrinks: usize,
+}
+
+struct Scenario {
+    root: PathBuf,
+    spec: PropertySpec,
+}
+
+impl Scenario {
+    fn load(path: &Path) -> Result<Self> {
+        let root = path
+            .parent()
+            .ok_or_else(|| Error::from("Could not get parent of property spec"))?
+            .to_path_buf();
+        let raw = slurp_string(&path.to_string_lossy())?;
+        let spec = serde_yaml::from_str::<PropertySpec>(&raw).map_err(|e| {
+            Error::from(format!(
+                "Unable to load `property.yml` from path `{}`: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+        Ok(Self { root, spec })
+    }
+
+    /// Checks the outputs for an input against the invariant, returns why
+    /// it does not hold, if it doesn't
+    fn violation(
+        &self,
+        invariant: &Invariant,
+        input: &Value<'static>,
+        outputs: &[(String, Value<'static>)],
+    ) -> Option<String> {
+        if outputs.is_empty() && !self.spec.allow_drops {
+            Some("the input was dropped, set `allow_drops` if that is expected".to_string())
+        } else {
+            invariant.violation(input, outputs)
+        }
+    }
+
+    /// Runs a single, fresh, input through the source and checks it
+    /// against the invariant
+    fn check(
+        &self,
+        runner: &mut Runner,
+        invariant: &Invariant,
+        input: &Value<'static>,
+    ) -> Result<Option<String>> {
+        runner.reset()?;
+        let outputs = runner.run(0, input.clone());
+        Ok(self.violation(invariant, input, &outputs))
+    }
+
+    /// Greedily replaces the input with simpler ones for as long as they
+    /// still violate the invariant
+    fn shrink(
+        &self,
+        runner: &mut Runner,
+        invariant: &Invariant,
+        input: Value<'static>,
+        reason: String,
+    ) -> Result<Counterexample> {
+        let mut found = Counterexample {
+            input,
+            reason,
+            shrinks: 0,
+        };
+        let mut runs = 0;
+        'shrinking: while runs < MAX_SHRINK_RUNS {
+            for candidate in self.spec.schema.shrink(&found.input) {
+                if runs >= MAX_SHRINK_RUNS {
+                    break 'shrinking;
+                }
+                runs += 1;
+                if let Some(reason) = self.check(runner, invariant, &candidate)? {
+                    found = Counterexample {
+                        input: candidate,
+                        reason,
+                        shrinks: found.shrinks + 1,
+                    };
+                    continue 'shrinking;
+                }
+            }
+            break;
+        }
+        Ok(found)
+    }
+
+    fn run(&self) -> Result<(stats::Stats, Vec<report::TestElement>)> {
+        let source = self.root.join(&self.spec.source);
+        let mut runner = Runner::load(&source.to_string_lossy())?;
+        let invariants = self
+            .spec
+            .invariants
+            .iter()
+            .map(Invariant::load)
+            .collect::<Result<Vec<_>>>()?;
+        let seed = self.spec.seed.unwrap_or_else(nanotime);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let start = nanotime();
+        let mut failures: Vec<Option<Counterexample>> = invariants.iter().map(|_| None).collect();
+        for id in 0..self.spec.runs {
+            let input = self.spec.schema.generate(&mut rng);
+            runner.reset()?;
+            let outputs = runner.run(id as u64, input.clone());
+            for (invariant, failure) in invariants.iter().zip(failures.iter_mut()) {
+                if failure.is_some() {
+                    continue;
+                }
+                if let Some(reason) = self.violation(invariant, &input, &outputs) {
+                    *failure = Some(self.shrink(&mut runner, invariant, input.clone(), reason)?);
+                }
+            }
+            if failures.iter().all(Option::is_some) {
+                break;
+            }
+        }
+        let duration = nanotime() - start;
+
+        let mut stats = stats::Stats::new();
+        let mut elements = vec![];
+        for (counter, (invariant, failure)) in invariants.iter().zip(failures).enumerate() {
+            let info = failure.map(|f| {
+                format!(
+                    "Counterexample ( seed {}, shrunk {} times ): {}\n{}",
+                    seed,
+                    f.shrinks,
+                    f.input.encode(),
+                    f.reason
+                )
+            });
+            let success = info.is_none();
+            stats.assert();
+            status::assert_has(
+                "   ",
+                &format!("Assert {}", counter),
+                &format!("{} ( {} runs )", invariant.name, self.spec.runs),
+                info.as_ref(),
+                success,
+            )?;
+            elements.push(report::TestElement {
+                description: invariant.name.clone(),
+                info,
+                hidden: false,
+                keyword: report::KeywordKind::Predicate,
+                result: report::ResultKind {
+                    status: if success {
+                        stats.pass();
+                        report::StatusKind::Passed
+                    } else {
+                        stats.fail();
+                        report::StatusKind::Failed
+                    },
+                    duration,
+                },
+            });
+        }
+        Ok((stats, elements))
+    }
+}
+
+pub(crate) fn suite_property(
+    base: &Path,
+    root: &Path,
+    _meta: &Meta,
+    by_tag: (&[String], &[String]),
+) -> Result<(stats::Stats, Vec<report::TestReport>)> {
+    let specs = GlobWalkerBuilder::new(root, SPEC_GLOB)
+        .case_insensitive(true)
+        .file_type(FileType::FILE)
+        .build()
+        .map_err(|e| {
+            Error::from(format!(
+                "Unable to walk test path (`{}`) for property tests: {:?}",
+                root.to_str().unwrap_or_default(),
+                e
+            ))
+        })?;
+
+    status::h0("Framework", "Finding property test scenarios")?;
+
+    let mut suites = HashMap::new();
+    let mut property_stats = stats::Stats::new();
+    let report_start = nanotime();
+    for spec in specs.filter_map(std::result::Result::ok) {
+        let scenario = Scenario::load(spec.path())?;
+        let tags = tag::resolve(base, &scenario.root)?.join(scenario.spec.tags.clone());
+        let (matched, is_match) = tags.matches(&by_tag.0, &by_tag.1);
+        if !is_match {
+            property_stats.skip();
+            status::h1("Property ( Skipping )", &scenario.spec.name)?;
+            status::tags(&tags, Some(&matched), Some(&by_tag.1))?;
+            continue;
+        }
+        status::h1("Property", &scenario.spec.name)?;
+        status::tags(&tags, Some(&matched), Some(&by_tag.1))?;
+
+        let suite_start = nanotime();
+        let (case_stats, elements) = match scenario.run() {
+            Ok(r) => r,
+            Err(e) => {
+                let mut case_stats = stats::Stats::new();
+                case_stats.assert();
+                case_stats.fail();
+                let info = Some(e.to_string());
+                status::assert_has(
+                    "   ",
+                    "Assert 0",
+                    "Property scenario can be run",
+                    info.as_ref(),
+                    false,
+                )?;
+                let element = report::TestElement {
+                    description: "Property scenario can be run".into(),
+                    info,
+                    hidden: false,
+                    keyword: report::KeywordKind::Predicate,
+                    result: report::ResultKind {
+                        status: report::StatusKind::Failed,
+                        duration: 0,
+                    },
+                };
+                (case_stats, vec![element])
+            }
+        };
+        if case_stats.is_pass() {
+            property_stats.pass();
+        } else {
+            property_stats.fail();
+        }
+        property_stats.assert += case_stats.assert;
+        status::stats(&case_stats, "    Test")?;
+        suites.insert(
+            scenario.spec.name.clone(),
+            report::TestSuite {
+                name: scenario.spec.name.clone(),
+                description: "Property test".into(),
+                elements,
+                evidence: None,
+                stats: case_stats,
+                duration: nanotime() - suite_start,
+            },
+        );
+    }
+
+    status::rollups("\n  Property", &property_stats)?;
+
+    Ok((
+        property_stats.clone(),
+        vec![report::TestReport {
+            description: "Property test suite".into(),
+            elements: suites,
+            stats: property_stats,
+            duration: nanotime() - report_start,
+        }],
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema(yaml: &str) -> Result<Schema> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Shrinks `value` for as long as it satisfies `failing`, like a
+    /// scenario does with a failing invariant
+    fn minimize(
+        schema: &Schema,
+        value: Value<'static>,
+        failing: &dyn Fn(&Value<'static>) -> bool,
+    ) -> Value<'static> {
+        let mut value = value;
+        'shrinking: loop {
+            for candidate in schema.shrink(&value) {
+                if failing(&candidate) {
+                    value = candidate;
+                    continue 'shrinking;
+                }
+            }
+            return value;
+        }
+    }
+
+    const RECORD: &str = r#"
+type: record
+fields:
+  level:
+    type: string
+    one_of: [info, warn, error]
+    optional: true
+    nullable: true
+  count:
+    type: integer
+    min: 10
+    max: 100
+  tags:
+    type: array
+    max_len: 3
+    items:
+      type: string
+      max_len: 8
+"#;
+
+    #[test]
+    fn generate() -> Result<()> {
+        let schema = schema(RECORD)?;
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut again = StdRng::seed_from_u64(42);
+        for _ in 0..1000 {
+            let value = schema.generate(&mut rng);
+            assert_eq!(value, schema.generate(&mut again));
+
+            let level = value.get("level");
+            assert!(level.map_or(true, |l| l.is_null()
+                || ["info", "warn", "error"].contains(&l.as_str().unwrap_or_default())));
+            let count = value.get("count").and_then(ValueTrait::as_i64);
+            assert!(count.map_or(false, |c| (10..=100).contains(&c)));
+            let tags = value.get("tags").and_then(ValueTrait::as_array);
+            assert!(tags.map_or(false, |t| t.len() <= 3
+                && t.iter().all(|t| t.as_str().map_or(false, |t| t.len() <= 8))));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shrink() -> Result<()> {
+        let schema = schema(RECORD)?;
+        let mut rng = StdRng::seed_from_u64(42);
+        let failing = |v: &Value<'static>| {
+            v.get("count")
+                .and_then(ValueTrait::as_i64)
+                .unwrap_or_default()
+                >= 42
+        };
+        let value = (0..1000)
+            .map(|_| schema.generate(&mut rng))
+            .find(|v| failing(v) && v.get("level").is_some())
+            .ok_or_else(|| Error::from("no failing value generated"))?;
+
+        // the optional field is removed, the array emptied and the integer
+        // shrunk to the smallest failing one
+        let minimal = minimize(&schema, value, &failing);
+        let mut expected = Object::new();
+        expected.insert("count".into(), Value::from(42));
+        expected.insert("tags".into(), Value::array());
+        assert_eq!(minimal, Value::from(expected));
+        assert!(schema.shrink(&minimal).iter().all(|c| !failing(c)));
+        Ok(())
+    }
+
+    #[test]
+    fn shrink_targets() -> Result<()> {
+        let bounded = schema("{type: integer, min: 10, max: 100}")?;
+        assert_eq!(bounded.shrink(&Value::from(50))[0], 10);
+        assert!(bounded.shrink(&Value::from(10)).is_empty());
+        let nullable = schema("{type: bool, nullable: true}")?;
+        assert_eq!(
+            nullable.shrink(&Value::from(true)),
+            vec![Value::null(), Value::from(false)]
+        );
+        let string = schema("{type: string}")?;
+        assert_eq!(string.shrink(&Value::from("snot"))[0], "");
+        Ok(())
+    }
+
+    #[test]
+    fn drops() -> Result<()> {
+        let spec = r#"
+name: drops
+source: drops.tremor
+schema:
+  type: integer
+invariants:
+  - name: holds
+    check: "true"
+"#;
+        let mut scenario = Scenario {
+            root: PathBuf::new(),
+            spec: serde_yaml::from_str(spec)?,
+        };
+        let invariant = Invariant::load(&scenario.spec.invariants[0])?;
+        let input = Value::from(1);
+        let outputs = vec![("out".to_string(), Value::from(1))];
+        assert!(scenario.violation(&invariant, &input, &outputs).is_none());
+        assert!(scenario.violation(&invariant, &input, &[]).is_some());
+        scenario.spec.allow_drops = true;
+        assert!(scenario.violation(&invariant, &input, &[]).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::{Error, Result};
+use crate::util::{get_source_kind, slurp_string, SourceKind};
+use simd_json::borrowed::Value;
+use simd_json::prelude::*;
+use tremor_pipeline::{Event, ExecutableGraph, Ids};
+use tremor_script::ctx::{EventContext, EventOriginUri};
+use tremor_script::highlighter::Term as TermHighlighter;
+use tremor_script::path::load as load_module_path;
+use tremor_script::query::Query;
+use tremor_script::registry;
+use tremor_script::script::{AggrType, Return, Script};
+use tremor_script::{LineValue, ValueAndMeta};
+
+/// Port runtime errors are reported on
+pub(crate) const ERROR_PORT: &str = "error";
+
+/// Runs events through a tremor script or a trickle query outside of a
+/// deployment and collects what is emitted on which port
+pub(crate) enum Runner {
+    Script {
+        script: Script,
+        state: Value<'static>,
+    },
+    Query {
+        query: tremor_pipeline::query::Query,
+        pipeline: Box<ExecutableGraph>,
+    },
+}
+
+impl Runner {
+    pub(crate) fn load(source: &str) -> Result<Self> {
+        let raw = slurp_string(source)?;
+        let reg = registry::registry();
+        let module_path = load_module_path();
+        match get_source_kind(source) {
+            SourceKind::Tremor | SourceKind::Json | SourceKind::Default => {
+                match Script::parse(&module_path, source, raw.clone(), &reg) {
+                    Ok(script) => Ok(Runner::Script {
+                        script,
+                        state: Value::null(),
+                    }),
+                    Err(e) => {
+                        let mut h = TermHighlighter::default();
+                        if let Err(e) = Script::format_error_from_script(&raw, &mut h, &e) {
+                            eprintln!("Error: {}", e);
+                        };
+                        Err(e.into())
+                    }
+                }
+            }
+            SourceKind::Trickle => {
+                let aggr = registry::aggr();
+                match Query::parse(&module_path, source, &raw, vec![], &reg, &aggr) {
+                    Ok(query) => {
+                        let query = tremor_pipeline::query::Query(query);
+                        let mut uid = 0_u64;
+                        let pipeline = Box::new(query.to_pipe(&mut uid)?);
+                        Ok(Runner::Query { query, pipeline })
+                    }
+                    Err(e) => {
+                        let mut h = TermHighlighter::default();
+                        if let Err(e) = Script::format_error_from_script(&raw, &mut h, &e) {
+                            eprintln!("Error: {}", e);
+                        };
+                        Err(e.into())
+                    }
+                }
+            }
+            SourceKind::Unsupported => {
+                Err(Error::from(format!("Unsupported test source `{}`", source)))
+            }
+        }
+    }
+
+    /// Discards all state accumulated by previous runs
+    pub(crate) fn reset(&mut self) -> Result<()> {
+        match self {
+            Runner::Script { state, .. } => *state = Value::null(),
+            Runner::Query { query, pipeline } => {
+                let mut uid = 0_u64;
+                *pipeline = Box::new(query.to_pipe(&mut uid)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a single event, errors are reported as a string on the
+    /// `error` port
+    pub(crate) fn run(&mut self, id: u64, event: Value<'static>) -> Vec<(String, Value<'static>)> {
+        match self {
+            Runner::Script { script, state } => {
+                let mut event = event;
+                let mut meta = Value::object();
+                let ctx = EventContext::new(id, Some(EventOriginUri::default()));
+                let (port, value) =
+                    match script.run(&ctx, AggrType::Tick, &mut event, state, &mut meta) {
+                        Ok(Return::Emit { value, port }) => (port, value.clone_static()),
+                        Ok(Return::EmitEvent { port }) => (port, event.clone_static()),
+                        Ok(Return::Drop) => return vec![],
+                        Err(e) => (Some(ERROR_PORT.to_string()), Value::from(e.to_string())),
+                    };
+                vec![(port.unwrap_or_else(|| "out".to_string()), value)]
+            }
+            Runner::Query { pipeline, .. } => {
+                let data = LineValue::new(vec![], |_| ValueAndMeta::from(event));
+                let mut continuation = vec![];
+                if let Err(e) = pipeline.enqueue(
+                    "in",
+                    Event {
+                        id: Ids::new(0, id),
+                        data,
+                        ingest_ns: id,
+                        ..Event::default()
+                    },
+                    &mut continuation,
+                ) {
+                    return vec![(ERROR_PORT.to_string(), Value::from(e.to_string()))];
+                }
+                continuation
+                    .into_iter()
+                    .map(|(port, event)| {
+                        (port.to_string(), event.data.suffix().value().clone_static())
+                    })
+                    .collect()
+            }
+        }
+    }
+}
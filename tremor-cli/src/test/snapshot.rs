@@ -0,0 +1,407 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::{Error, Result};
+use crate::report;
+use crate::status;
+use crate::test::runner::Runner;
+use crate::test::stats;
+use crate::test::tag::{self, Tags};
+use crate::test::Meta;
+use crate::util::slurp_string;
+use clap::ArgMatches;
+use globwalk::{FileType, GlobWalkerBuilder};
+use simd_json::borrowed::Value;
+use simd_json::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use tremor_common::file;
+use tremor_common::time::nanotime;
+use xz2::read::XzDecoder;
+
+const SPEC_GLOB: &str = "**/snapshot.yml";
+
+/// A snapshot scenario, runs `input` through `source` and compares
+/// the output of every port against the recorded fixture in `expected`
+#[derive(Deserialize, Debug)]
+pub(crate) struct SnapshotSpec {
+    pub(crate) name: String,
+    pub(crate) source: String,
+    pub(crate) input: String,
+    #[serde(default = "default_expected")]
+    pub(crate) expected: String,
+    pub(crate) tags: Option<Tags>,
+}
+
+fn default_expected() -> String {
+    "expected".into()
+}
+
+/// Observed events by port
+type Outputs = BTreeMap<String, Vec<Value<'static>>>;
+
+struct Scenario {
+    root: PathBuf,
+    spec: SnapshotSpec,
+}
+
+impl Scenario {
+    fn load(path: &Path) -> Result<Self> {
+        let root = path
+            .parent()
+            .ok_or_else(|| Error::from("Could not get parent of snapshot spec"))?
+            .to_path_buf();
+        let raw = slurp_string(&path.to_string_lossy())?;
+        let spec = serde_yaml::from_str::<SnapshotSpec>(&raw).map_err(|e| {
+            Error::from(format!(
+                "Unable to load `snapshot.yml` from path `{}`: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+        Ok(Self { root, spec })
+    }
+
+    fn path(&self, rel: &str) -> String {
+        self.root.join(rel).to_string_lossy().to_string()
+    }
+
+    /// Runs the input through the source and captures what every port emits
+    fn capture(&self) -> Result<Outputs> {
+        let mut runner = Runner::load(&self.path(&self.spec.source))?;
+        let events = read_events(&self.path(&self.spec.input))?;
+        let mut outputs = Outputs::new();
+        for (id, event) in events.into_iter().enumerate() {
+            for (port, value) in runner.run(id as u64, event) {
+                outputs.entry(port).or_default().push(value);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Replaces the fixture with the given outputs
+    fn record(&self, outputs: &Outputs) -> Result<()> {
+        let expected = self.root.join(&self.spec.expected);
+        if expected.is_dir() {
+            for entry in std::fs::read_dir(&expected)? {
+                let path = entry?.path();
+                if path.is_file() && path.extension().map_or(false, |e| e == "json") {
+                    std::fs::remove_file(&path)?;
+                }
+            }
+        } else {
+            std::fs::create_dir_all(&expected)?;
+        }
+        for (port, events) in outputs {
+            let mut f = file::create(&expected.join(format!("{}.json", port)))?;
+            for event in events {
+                f.write_all(event.encode().as_bytes())?;
+                f.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the recorded fixture
+    fn fixture(&self) -> Result<Outputs> {
+        let expected = self.root.join(&self.spec.expected);
+        let mut outputs = Outputs::new();
+        if !expected.is_dir() {
+            return Err(Error::from(format!(
+                "No snapshot recorded in `{}`, run `tremor test record` first",
+                expected.to_string_lossy()
+            )));
+        }
+        for entry in std::fs::read_dir(&expected)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().map_or(false, |e| e == "json") {
+                if let Some(port) = path.file_stem() {
+                    outputs.insert(
+                        port.to_string_lossy().to_string(),
+                        read_events(&path.to_string_lossy())?,
+                    );
+                }
+            }
+        }
+        Ok(outputs)
+    }
+}
+
+/// Reads line delimited json, optionally xz compressed
+fn read_events(path: &str) -> Result<Vec<Value<'static>>> {
+    let f = file::open(path)?;
+    let reader: Box<dyn Read> = if path.ends_with(".xz") {
+        Box::new(XzDecoder::new(f))
+    } else {
+        Box::new(f)
+    };
+    let mut events = vec![];
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut bytes = line.into_bytes();
+        events.push(simd_json::to_borrowed_value(&mut bytes)?.clone_static());
+    }
+    Ok(events)
+}
+
+/// Collects the differences between two values as `path: description`
+fn diff(path: &str, expected: &Value, got: &Value, diffs: &mut Vec<String>) {
+    match (expected, got) {
+        (Value::Object(e), Value::Object(g)) => {
+            for (k, ev) in e.iter() {
+                let p = format!("{}.{}", path, k);
+                if let Some(gv) = g.get(k) {
+                    diff(&p, ev, gv, diffs);
+                } else {
+                    diffs.push(format!("{}: missing, expected {}", p, ev.encode()));
+                }
+            }
+            for (k, gv) in g.iter() {
+                if !e.contains_key(k) {
+                    diffs.push(format!("{}.{}: unexpected {}", path, k, gv.encode()));
+                }
+            }
+        }
+        (Value::Array(e), Value::Array(g)) => {
+            diff_list(path, e, g, diffs);
+        }
+        (e, g) => {
+            if e != g {
+                diffs.push(format!(
+                    "{}: expected {}, got {}",
+                    path,
+                    e.encode(),
+                    g.encode()
+                ));
+            }
+        }
+    }
+}
+
+fn diff_list(path: &str, expected: &[Value], got: &[Value], diffs: &mut Vec<String>) {
+    for (i, (e, g)) in expected.iter().zip(got.iter()).enumerate() {
+        diff(&format!("{}[{}]", path, i), e, g, diffs);
+    }
+    for (i, e) in expected.iter().enumerate().skip(got.len()) {
+        diffs.push(format!("{}[{}]: missing, expected {}", path, i, e.encode()));
+    }
+    for (i, g) in got.iter().enumerate().skip(expected.len()) {
+        diffs.push(format!("{}[{}]: unexpected {}", path, i, g.encode()));
+    }
+}
+
+fn compare(
+    scenario: &Scenario,
+    expected: &Outputs,
+    got: &Outputs,
+) -> Result<(stats::Stats, Vec<report::TestElement>)> {
+    let mut stats = stats::Stats::new();
+    let mut elements = vec![];
+    let no_events = vec![];
+    let mut ports: Vec<&String> = expected.keys().chain(got.keys()).collect();
+    ports.sort();
+    ports.dedup();
+    for (counter, port) in ports.into_iter().enumerate() {
+        let start = nanotime();
+        let mut diffs = vec![];
+        diff_list(
+            port,
+            expected.get(port).unwrap_or(&no_events),
+            got.get(port).unwrap_or(&no_events),
+            &mut diffs,
+        );
+        let success = diffs.is_empty();
+        let info = if success {
+            None
+        } else {
+            Some(diffs.join("\n"))
+        };
+        stats.assert();
+        status::assert_has(
+            "   ",
+            &format!("Assert {}", counter),
+            &format!(
+                "Port `{}` matches `{}/{}.json`",
+                port, scenario.spec.expected, port
+            ),
+            info.as_ref(),
+            success,
+        )?;
+        elements.push(report::TestElement {
+            description: format!("Port `{}` matches snapshot", port),
+            info,
+            hidden: false,
+            keyword: report::KeywordKind::Predicate,
+            result: report::ResultKind {
+                status: if success {
+                    stats.pass();
+                    report::StatusKind::Passed
+                } else {
+                    stats.fail();
+                    report::StatusKind::Failed
+                },
+                duration: nanotime() - start,
+            },
+        });
+    }
+    Ok((stats, elements))
+}
+
+pub(crate) fn suite_snapshot(
+    base: &Path,
+    root: &Path,
+    _meta: &Meta,
+    by_tag: (&[String], &[String]),
+) -> Result<(stats::Stats, Vec<report::TestReport>)> {
+    let specs = GlobWalkerBuilder::new(root, SPEC_GLOB)
+        .case_insensitive(true)
+        .file_type(FileType::FILE)
+        .build()
+        .map_err(|e| {
+            Error::from(format!(
+                "Unable to walk test path (`{}`) for snapshot tests: {:?}",
+                root.to_str().unwrap_or_default(),
+                e
+            ))
+        })?;
+
+    status::h0("Framework", "Finding snapshot test scenarios")?;
+
+    let mut suites = HashMap::new();
+    let mut snapshot_stats = stats::Stats::new();
+    let report_start = nanotime();
+    for spec in specs.filter_map(std::result::Result::ok) {
+        let scenario = Scenario::load(spec.path())?;
+        let tags = tag::resolve(base, &scenario.root)?.join(scenario.spec.tags.clone());
+        let (matched, is_match) = tags.matches(&by_tag.0, &by_tag.1);
+        if !is_match {
+            snapshot_stats.skip();
+            status::h1("Snapshot ( Skipping )", &scenario.spec.name)?;
+            status::tags(&tags, Some(&matched), Some(&by_tag.1))?;
+            continue;
+        }
+        status::h1("Snapshot", &scenario.spec.name)?;
+        status::tags(&tags, Some(&matched), Some(&by_tag.1))?;
+
+        let suite_start = nanotime();
+        let (case_stats, elements) = match (scenario.fixture(), scenario.capture()) {
+            (Ok(expected), Ok(got)) => compare(&scenario, &expected, &got)?,
+            (Err(e), _) | (_, Err(e)) => {
+                let mut case_stats = stats::Stats::new();
+                case_stats.assert();
+                case_stats.fail();
+                let info = Some(e.to_string());
+                status::assert_has(
+                    "   ",
+                    "Assert 0",
+                    "Snapshot can be captured",
+                    info.as_ref(),
+                    false,
+                )?;
+                let element = report::TestElement {
+                    description: "Snapshot can be captured".into(),
+                    info,
+                    hidden: false,
+                    keyword: report::KeywordKind::Predicate,
+                    result: report::ResultKind {
+                        status: report::StatusKind::Failed,
+                        duration: 0,
+                    },
+                };
+                (case_stats, vec![element])
+            }
+        };
+        if case_stats.is_pass() {
+            snapshot_stats.pass();
+        } else {
+            snapshot_stats.fail();
+        }
+        snapshot_stats.assert += case_stats.assert;
+        status::stats(&case_stats, "    Test")?;
+        suites.insert(
+            scenario.spec.name.clone(),
+            report::TestSuite {
+                name: scenario.spec.name.clone(),
+                description: "Snapshot test".into(),
+                elements,
+                evidence: None,
+                stats: case_stats,
+                duration: nanotime() - suite_start,
+            },
+        );
+    }
+
+    status::rollups("\n  Snapshot", &snapshot_stats)?;
+
+    Ok((
+        snapshot_stats.clone(),
+        vec![report::TestReport {
+            description: "Snapshot test suite".into(),
+            elements: suites,
+            stats: snapshot_stats,
+            duration: nanotime() - report_start,
+        }],
+    ))
+}
+
+/// Records the outputs of all snapshot scenarios under the given path as
+/// their expected outputs
+pub(crate) fn run_record(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("PATH").unwrap_or_default();
+    let path = Path::new(path);
+    let specs: Vec<PathBuf> = if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        GlobWalkerBuilder::new(path, SPEC_GLOB)
+            .case_insensitive(true)
+            .file_type(FileType::FILE)
+            .build()
+            .map_err(|e| {
+                Error::from(format!(
+                    "failed to walk directory `{}`: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            })?
+            .filter_map(std::result::Result::ok)
+            .map(|e| e.path().to_path_buf())
+            .collect()
+    };
+    if specs.is_empty() {
+        return Err(format!("No `snapshot.yml` found in `{}`", path.to_string_lossy()).into());
+    }
+    for spec in specs {
+        let scenario = Scenario::load(&spec)?;
+        let outputs = scenario.capture()?;
+        scenario.record(&outputs)?;
+        let counts: Vec<String> = outputs
+            .iter()
+            .map(|(port, events)| format!("{}: {}", port, events.len()))
+            .collect();
+        status::h1(
+            "Recorded",
+            &format!(
+                "{} into `{}` ( {} )",
+                scenario.spec.name,
+                scenario.path(&scenario.spec.expected),
+                counts.join(", ")
+            ),
+        )?;
+    }
+    Ok(())
+}
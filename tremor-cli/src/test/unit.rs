@@ -335,6 +335,7 @@ pub(crate) fn run_suite(
                 aggrs: &script.aggregates,
                 meta: &script.node_meta,
                 recursion_limit: tremor_script::recursion_limit(),
+                tracer: None,
             };
 
             let mut stat_s = stats::Stats::new();
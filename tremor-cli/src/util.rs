@@ -14,6 +14,7 @@
 
 use crate::errors::{Error, Result};
 use halfbrown::HashMap;
+use http_types::{headers, Method};
 use serde::Deserialize;
 use simd_json::BorrowedValue as Value;
 use std::ffi::OsStr;
@@ -21,6 +22,7 @@ use std::fs;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 use tremor_common::file as cfile;
 use tremor_script::highlighter::{Highlighter, Term as TermHighlighter};
 use tremor_script::lexer;
@@ -41,6 +43,27 @@ pub(crate) fn slurp_string(file: &str) -> Result<String> {
 #[derive(Deserialize, Debug, Serialize)]
 pub(crate) struct TargetConfig {
     pub(crate) instances: HashMap<String, Vec<String>>, // TODO TremorURL
+    /// credentials by target
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) credentials: HashMap<String, Credentials>,
+}
+
+/// Credentials to authenticate with the API of a target
+#[derive(Deserialize, Debug, Serialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Credentials {
+    /// bearer token, may be an `${env:..}` or `${file:..}` reference
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) token: Option<String>,
+    /// CA certificate to verify the server with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) ca: Option<String>,
+    /// client certificate for mutual TLS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) cert: Option<String>,
+    /// private key of the client certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) key: Option<String>,
 }
 
 pub(crate) struct TremorApp {
@@ -58,6 +81,60 @@ impl TremorApp {
             .and_then(|s| Ok(url::Url::parse(s)?))
     }
 
+    fn credentials(&self) -> Option<&Credentials> {
+        self.config.credentials.get("default")
+    }
+
+    /// A client for the default target, using its TLS credentials
+    fn client(&self) -> Result<surf::Client> {
+        use isahc::config::{CaCertificate, ClientCertificate, Configurable, PrivateKey};
+        match self.credentials() {
+            Some(creds) if creds.ca.is_some() || creds.cert.is_some() => {
+                let mut builder = isahc::HttpClient::builder();
+                if let Some(ca) = &creds.ca {
+                    builder = builder.ssl_ca_certificate(CaCertificate::file(ca));
+                }
+                match (&creds.cert, &creds.key) {
+                    (Some(cert), Some(key)) => {
+                        builder = builder.ssl_client_certificate(ClientCertificate::pem_file(
+                            cert,
+                            PrivateKey::pem_file(key, None),
+                        ));
+                    }
+                    (Some(_), None) | (None, Some(_)) => {
+                        return Err("Client certificate and key must be given together".into())
+                    }
+                    (None, None) => (),
+                }
+                let client = builder
+                    .build()
+                    .map_err(|e| Error::from(format!("Failed to create API client: {}", e)))?;
+                Ok(surf::Client::with_http_client(Arc::new(
+                    http_client::isahc::IsahcClient::from_client(client),
+                )))
+            }
+            _ => Ok(surf::Client::new()),
+        }
+    }
+
+    /// Creates an authenticated request to the default target
+    pub(crate) fn request(&self, method: Method, url: &url::Url) -> Result<surf::RequestBuilder> {
+        let client = self.client()?;
+        let request = match method {
+            Method::Get => client.get(url),
+            Method::Post => client.post(url),
+            Method::Delete => client.delete(url),
+            _ => return Err(format!("Unsupported method {}", method).into()),
+        };
+        if let Some(token) = self.credentials().and_then(|c| c.token.as_ref()) {
+            let token =
+                tremor_runtime::config::interpolate_str(token)?.unwrap_or_else(|| token.clone());
+            Ok(request.header(headers::AUTHORIZATION, format!("Bearer {}", token)))
+        } else {
+            Ok(request)
+        }
+    }
+
     pub(crate) fn endpoint(&self, endpoint: &str) -> Result<url::Url> {
         self.default_url().and_then(|mut url| {
             url.path_segments_mut()
@@ -114,6 +191,7 @@ pub(crate) fn load_config() -> Result<TargetConfig> {
     let dot_config = format!("{}/config.yaml", tremor_root);
     let mut default = TargetConfig {
         instances: HashMap::new(),
+        credentials: HashMap::new(),
     };
     default.instances.insert(
         "default".to_string(),
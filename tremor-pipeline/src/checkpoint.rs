@@ -0,0 +1,190 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Persistent operator state
+//!
+//! A checkpoint holds the `state` of an operator along with an optional
+//! snapshot of state the operator keeps internally, such as the open
+//! windows of a select. Checkpoints are keyed by pipeline and node id.
+//!
+//! Every checkpoint carries the version of the pipeline it was taken from,
+//! on restore checkpoints of a different version are ignored so incompatible
+//! query changes start with a clean state. The version can be set with
+//! `#!config state_version = "..."` and defaults to a hash of the query
+//! source.
+//!
+//! How often a running pipeline is checkpointed can be configured with
+//! `#!config checkpoint_interval_s = ...`.
+//!
+//! On a graceful shutdown windows are flushed before the last checkpoint is
+//! taken, so open windows are only resumed after a crash.
+
+use crate::errors::{Error, Result};
+use halfbrown::hashmap;
+use simd_json::prelude::*;
+use simd_json::BorrowedValue as Value;
+use std::path::Path;
+
+/// Default interval between two checkpoints in nanoseconds
+const DEFAULT_INTERVAL: u64 = 10_000_000_000;
+
+/// Checkpoint settings of a pipeline
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// version tag of the pipeline
+    pub(crate) version: String,
+    /// interval between two checkpoints in nanoseconds
+    pub(crate) interval: u64,
+    /// timestamp of the last checkpoint
+    pub(crate) last: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: String::new(),
+            interval: DEFAULT_INTERVAL,
+            last: 0,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn new(version: String, interval: Option<u64>) -> Self {
+        Self {
+            version,
+            interval: interval.unwrap_or(DEFAULT_INTERVAL),
+            last: 0,
+        }
+    }
+}
+
+/// Hashes a query source into a version tag, we use FNV-1a since it is
+/// stable across builds
+pub(crate) fn source_version(source: &str) -> String {
+    format!("{:016x}", crate::fnv1a(source.as_bytes()))
+}
+
+/// The checkpoint of a single operator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// Version of the pipeline the checkpoint was taken from
+    pub version: String,
+    /// The operator state
+    pub state: Value<'static>,
+    /// Internal state of the operator
+    pub snapshot: Option<Value<'static>>,
+}
+
+/// A sled backed store for checkpoints
+#[derive(Debug, Clone)]
+pub struct Store {
+    tree: sled::Tree,
+}
+
+impl Store {
+    /// Opens, or creates, a store in the given directory
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let db = sled::open(dir)?;
+        Ok(Self {
+            tree: db.open_tree("checkpoints")?,
+        })
+    }
+
+    /// Creates a store that is removed once it is dropped
+    pub fn temporary() -> Result<Self> {
+        let db = sled::Config::default().temporary(true).open()?;
+        Ok(Self {
+            tree: db.open_tree("checkpoints")?,
+        })
+    }
+
+    fn key(pipeline: &str, node: &str) -> String {
+        format!("{}/{}", pipeline, node)
+    }
+
+    /// Writes the checkpoint of a node
+    pub fn save(&self, pipeline: &str, node: &str, checkpoint: &Checkpoint) -> Result<()> {
+        let value = Value::from(hashmap! {
+            "version".into() => Value::from(checkpoint.version.clone()),
+            "state".into() => checkpoint.state.clone(),
+            "snapshot".into() => checkpoint.snapshot.clone().unwrap_or_else(Value::null),
+        });
+        self.tree
+            .insert(Self::key(pipeline, node), value.encode().as_bytes())?;
+        Ok(())
+    }
+
+    /// Reads the checkpoint of a node
+    pub fn load(&self, pipeline: &str, node: &str) -> Result<Option<Checkpoint>> {
+        if let Some(data) = self.tree.get(Self::key(pipeline, node))? {
+            let mut data = data.to_vec();
+            let value = simd_json::to_borrowed_value(&mut data)?.into_static();
+            let version = value
+                .get("version")
+                .and_then(Value::as_str)
+                .ok_or_else(|| Error::from("Checkpoint without version"))?
+                .to_string();
+            let state = value.get("state").cloned().unwrap_or_else(Value::null);
+            let snapshot = value.get("snapshot").filter(|s| !s.is_null()).cloned();
+            Ok(Some(Checkpoint {
+                version,
+                state,
+                snapshot,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Removes the checkpoint of a node
+    pub fn remove(&self, pipeline: &str, node: &str) -> Result<()> {
+        self.tree.remove(Self::key(pipeline, node))?;
+        Ok(())
+    }
+
+    /// Flushes all pending writes to disk
+    pub fn flush(&self) -> Result<()> {
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let store = Store::temporary()?;
+        assert_eq!(store.load("p", "n")?, None);
+        let checkpoint = Checkpoint {
+            version: source_version("select event from in into out;"),
+            state: Value::from(42),
+            snapshot: None,
+        };
+        store.save("p", "n", &checkpoint)?;
+        assert_eq!(store.load("p", "n")?, Some(checkpoint));
+        assert_eq!(store.load("p", "m")?, None);
+        store.remove("p", "n")?;
+        assert_eq!(store.load("p", "n")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(source_version(""), "cbf29ce484222325");
+        assert_ne!(source_version("snot"), source_version("badger"));
+    }
+}
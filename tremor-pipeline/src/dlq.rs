@@ -0,0 +1,279 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Dead letter queues
+//!
+//! Events a `qos::dlq` operator gave up on are persisted in its dead letter
+//! store along with the reason of their last failure. Stores of running
+//! operators are registered under the operator id so they can be listed,
+//! inspected and their events re-injected from the outside.
+//!
+//! Every operator instance holds its own registration, so when a pipeline is
+//! swapped the replacing instance keeps the store registered once the old
+//! one is dropped. A store that is already open is shared with the new
+//! instance, as its directory stays locked while it is open.
+//!
+//! Re-injected events are moved to a separate tree of the store, the
+//! operator picks them up on its next tick and delivers them again with a
+//! fresh set of retries.
+
+use crate::errors::Result;
+use crate::Event;
+use halfbrown::HashMap;
+use lazy_static::lazy_static;
+use simd_json_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+lazy_static! {
+    /// Stores by operator id, with the registration of each instance
+    static ref STORES: Mutex<HashMap<String, Vec<(u64, Store)>>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_REGISTRATION: AtomicU64 = AtomicU64::new(0);
+
+/// An event that failed to be delivered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The event
+    pub event: Event,
+    /// Reason of the last failure
+    pub reason: String,
+    /// Number of delivery attempts
+    pub attempts: u64,
+    /// Nanoseconds at when the event was dead lettered
+    pub failed_ns: u64,
+}
+
+/// The dead letter store of a `qos::dlq` operator
+#[derive(Clone)]
+pub struct Store {
+    dir: Option<String>,
+    db: sled::Db,
+    dead: sled::Tree,
+    reinjected: sled::Tree,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Store({} dead letters)", self.dead.len())
+    }
+}
+
+fn decode(mut raw: sled::IVec) -> Result<DeadLetter> {
+    let slice: &mut [u8] = &mut raw;
+    Ok(DeadLetter::from_slice(slice)?)
+}
+
+fn key(raw: &[u8]) -> u64 {
+    let mut id = [0_u8; 8];
+    id.copy_from_slice(raw.get(..8).unwrap_or(&[0; 8]));
+    u64::from_be_bytes(id)
+}
+
+impl Store {
+    /// Opens the store in `dir`, or a temporary one
+    pub(crate) fn open(dir: Option<&str>) -> Result<Self> {
+        let db = if let Some(dir) = dir {
+            sled::open(dir)?
+        } else {
+            sled::Config::default().temporary(true).open()?
+        };
+        let dead = db.open_tree("dead")?;
+        let reinjected = db.open_tree("reinjected")?;
+        Ok(Self {
+            dir: dir.map(ToString::to_string),
+            db,
+            dead,
+            reinjected,
+        })
+    }
+
+    /// Persists a dead letter and returns its id
+    pub(crate) fn insert(&self, letter: &DeadLetter) -> Result<u64> {
+        let id = self.db.generate_id()?;
+        self.dead.insert(id.to_be_bytes(), letter.json_vec()?)?;
+        Ok(id)
+    }
+
+    /// Takes up to `max` re-injected events out of the store
+    pub(crate) fn take_reinjected(&self, max: usize) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+        while events.len() < max {
+            if let Some((_, raw)) = self.reinjected.pop_min()? {
+                events.push(decode(raw)?.event);
+            } else {
+                break;
+            }
+        }
+        Ok(events)
+    }
+
+    /// Number of dead letters
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.dead.len()
+    }
+
+    /// Whether the store holds no dead letters
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dead.is_empty()
+    }
+
+    /// All dead letters, oldest first
+    pub fn list(&self) -> Result<Vec<(u64, DeadLetter)>> {
+        let mut letters = Vec::new();
+        for entry in self.dead.iter() {
+            let (id, raw) = entry?;
+            letters.push((key(&id), decode(raw)?));
+        }
+        Ok(letters)
+    }
+
+    /// A single dead letter
+    pub fn get(&self, id: u64) -> Result<Option<DeadLetter>> {
+        self.dead.get(id.to_be_bytes())?.map(decode).transpose()
+    }
+
+    /// Discards a dead letter, returns it if it existed
+    pub fn remove(&self, id: u64) -> Result<Option<DeadLetter>> {
+        self.dead.remove(id.to_be_bytes())?.map(decode).transpose()
+    }
+
+    /// Hands a dead letter back to the operator to be delivered again,
+    /// returns false if it doesn't exist
+    pub fn reinject(&self, id: u64) -> Result<bool> {
+        if let Some(raw) = self.dead.remove(id.to_be_bytes())? {
+            self.reinjected.insert(id.to_be_bytes(), raw)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+/// Opens the store in `dir` for an instance of the operator `id` and
+/// registers it, a store already open in `dir` is shared. Returns the store
+/// and the registration to unregister the instance with.
+pub(crate) fn register(id: &str, dir: &str) -> Result<(Store, u64)> {
+    let mut stores = STORES.lock()?;
+    let open = stores
+        .values()
+        .flat_map(|registrations| registrations.iter())
+        .find(|(_, store)| store.dir.as_deref() == Some(dir))
+        .map(|(_, store)| store.clone());
+    let store = if let Some(store) = open {
+        store
+    } else {
+        Store::open(Some(dir))?
+    };
+    let registration = NEXT_REGISTRATION.fetch_add(1, Ordering::Relaxed);
+    stores
+        .entry(id.to_string())
+        .or_insert_with(Vec::new)
+        .push((registration, store.clone()));
+    Ok((store, registration))
+}
+
+/// Removes the registration of an instance of the operator `id`, the store
+/// stays registered as long as another instance uses it
+pub(crate) fn unregister(id: &str, registration: u64) -> Result<()> {
+    let mut stores = STORES.lock()?;
+    if let Some(registrations) = stores.get_mut(id) {
+        registrations.retain(|(r, _)| *r != registration);
+        if registrations.is_empty() {
+            stores.remove(id);
+        }
+    }
+    Ok(())
+}
+
+/// Ids of the operators with a registered store
+pub fn stores() -> Result<Vec<String>> {
+    let mut ids: Vec<String> = STORES.lock()?.keys().cloned().collect();
+    ids.sort();
+    Ok(ids)
+}
+
+/// The store of the operator `id`, the one of its latest instance
+pub fn store(id: &str) -> Result<Option<Store>> {
+    Ok(STORES
+        .lock()?
+        .get(id)
+        .and_then(|registrations| registrations.last())
+        .map(|(_, store)| store.clone()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn letter(ingest_ns: u64) -> DeadLetter {
+        DeadLetter {
+            event: Event {
+                ingest_ns,
+                ..Event::default()
+            },
+            reason: "snot".to_string(),
+            attempts: 3,
+            failed_ns: 42,
+        }
+    }
+
+    #[test]
+    fn store() -> Result<()> {
+        let store = Store::open(None)?;
+        let first = store.insert(&letter(1))?;
+        let second = store.insert(&letter(2))?;
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(first)?, Some(letter(1)));
+        let ids: Vec<u64> = store.list()?.into_iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec![first, second]);
+
+        assert!(store.reinject(first)?);
+        assert!(!store.reinject(first)?);
+        assert_eq!(store.len(), 1);
+        let events = store.take_reinjected(10)?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].ingest_ns, 1);
+        assert!(store.take_reinjected(10)?.is_empty());
+
+        assert_eq!(store.remove(second)?, Some(letter(2)));
+        assert!(store.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn registry() -> Result<()> {
+        let dir = tempfile::Builder::new().tempdir()?;
+        let dir = dir.path().to_string_lossy().to_string();
+        let (old, first) = register("dlq-registry-test", &dir)?;
+        assert!(stores()?.contains(&"dlq-registry-test".to_string()));
+        assert!(super::store("dlq-registry-test")?.is_some());
+
+        // a replacing instance shares the open store
+        let (new, second) = register("dlq-registry-test", &dir)?;
+        let id = old.insert(&letter(1))?;
+        assert_eq!(new.get(id)?, Some(letter(1)));
+
+        // and keeps it registered once the old instance is gone
+        unregister("dlq-registry-test", first)?;
+        assert!(super::store("dlq-registry-test")?.is_some());
+        unregister("dlq-registry-test", second)?;
+        assert!(super::store("dlq-registry-test")?.is_none());
+        Ok(())
+    }
+}
@@ -168,6 +168,28 @@ impl Event {
             ..Event::default()
         }
     }
+
+    /// Attaches the reason of a failure to a fail insight, it is kept as
+    /// the `error` metadata of the insight
+    #[must_use]
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        let mut meta = BorrowedValue::object_with_capacity(1);
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert("error".into(), BorrowedValue::from(reason.to_string()));
+        }
+        self.data = (BorrowedValue::null(), meta).into();
+        self
+    }
+
+    /// The reason attached to a fail insight, if any
+    #[must_use]
+    pub fn reason(&self) -> Option<&str> {
+        self.data
+            .suffix()
+            .meta()
+            .get("error")
+            .and_then(ValueTrait::as_str)
+    }
 }
 
 /// Iterator over the event value and metadata
@@ -326,6 +348,10 @@ mod test {
         );
         assert_eq!(Event::cb_fail(0, Ids::default()).cb, CBAction::Fail);
         assert_eq!(e.insight_fail().cb, CBAction::Fail);
+
+        let fail = Event::cb_fail(0, Ids::default());
+        assert_eq!(fail.reason(), None);
+        assert_eq!(fail.with_reason("snot").reason(), Some("snot"));
     }
 
     #[test]
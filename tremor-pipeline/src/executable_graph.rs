@@ -12,17 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{borrow::Cow, fmt, fmt::Display, sync::Arc};
+use std::{borrow::Cow, convert::TryFrom, fmt, fmt::Display, sync::Arc, time::Instant};
 
+use crate::checkpoint::{self, Checkpoint};
 use crate::{
     common_cow,
     errors::Result,
-    influx_value,
+    influx_gauges, influx_histogram, influx_value,
     op::{prelude::IN, trickle::select::WindowImpl},
     ConfigMap, ExecPortIndexMap, NodeLookupFn,
 };
 use crate::{op::EventAndInsights, Event, NodeKind, Operator};
 use halfbrown::HashMap;
+use hdrhistogram::Histogram;
+use simd_json::prelude::*;
 use simd_json::BorrowedValue;
 use tremor_script::{query::StmtRentalWrapper, LineValue, ValueAndMeta};
 
@@ -166,12 +169,27 @@ impl Operator for OperatorNode {
     fn skippable(&self) -> bool {
         self.op.skippable()
     }
+
+    fn snapshot(&self) -> Result<Option<BorrowedValue<'static>>> {
+        self.op.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &BorrowedValue) -> Result<()> {
+        self.op.restore(snapshot)
+    }
 }
 
+/// Measurement for the time operators take to process an event
+const OPERATOR_LATENCY: Cow<'static, str> = Cow::Borrowed("operator_latency");
+/// Measurement for the number of events waiting to enter the pipeline
+const PIPELINE_QUEUE: Cow<'static, str> = Cow::Borrowed("pipeline_queue");
+
 #[derive(Debug, Default, Clone)]
 pub(crate) struct NodeMetrics {
     inputs: HashMap<Cow<'static, str>, u64>,
     outputs: HashMap<Cow<'static, str>, u64>,
+    /// processing time in nanoseconds since the last report
+    processing: Option<Histogram<u64>>,
 }
 
 impl NodeMetrics {
@@ -212,8 +230,18 @@ impl NodeMetrics {
         *v += increment;
     }
 
+    pub(crate) fn record_processing(&mut self, ns: u64) {
+        if self.processing.is_none() {
+            // auto resizing, so this only fails on invalid significant figures
+            self.processing = Histogram::new(2).ok();
+        }
+        if let Some(processing) = &mut self.processing {
+            processing.saturating_record(ns);
+        }
+    }
+
     fn to_value(
-        &self,
+        &mut self,
         metric_name: &str,
         tags: &mut HashMap<Cow<'static, str>, BorrowedValue<'static>>,
         timestamp: u64,
@@ -239,10 +267,30 @@ impl NodeMetrics {
                 timestamp,
             ));
         }
+        if let Some(processing) = &mut self.processing {
+            if processing.len() > 0 {
+                tags.remove("direction");
+                tags.remove("port");
+                res.push(influx_histogram(
+                    OPERATOR_LATENCY,
+                    tags.clone(),
+                    processing,
+                    timestamp,
+                ));
+                processing.reset();
+            }
+        }
         Ok(res)
     }
 }
 
+/// Queue depth of the pipeline as reported by its task
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct QueueDepth {
+    last: usize,
+    max: usize,
+}
+
 /// An executable graph, this is the executable
 /// form of a pipeline
 #[derive(Debug)]
@@ -260,6 +308,8 @@ pub struct ExecutableGraph {
     pub(crate) metrics_idx: usize,
     pub(crate) last_metrics: u64,
     pub(crate) metric_interval: Option<u64>,
+    pub(crate) queue_depth: Option<QueueDepth>,
+    pub(crate) checkpoint: checkpoint::Config,
     /// snot
     pub insights: Vec<(usize, Event)>,
     /// the dot representation of the graph
@@ -416,6 +466,137 @@ impl ExecutableGraph {
         }
         Some(did_chage)
     }
+
+    /// Records the number of events waiting to be enqueued, reported with
+    /// the next metrics
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        let q = self.queue_depth.get_or_insert_with(QueueDepth::default);
+        q.last = depth;
+        q.max = q.max.max(depth);
+    }
+
+    /// Checks if the interval since the last checkpoint has passed
+    #[must_use]
+    pub fn checkpoint_due(&self, timestamp: u64) -> bool {
+        timestamp.saturating_sub(self.checkpoint.last) >= self.checkpoint.interval
+    }
+
+    /// Writes the state of all operators to the checkpoint store
+    pub fn checkpoint(&mut self, store: &checkpoint::Store, timestamp: u64) -> Result<()> {
+        for (node, state) in self.graph.iter().zip(&self.state.ops) {
+            let snapshot = node.snapshot()?;
+            if state.is_null() && snapshot.is_none() {
+                store.remove(&self.id, &node.id)?;
+            } else {
+                let checkpoint = Checkpoint {
+                    version: self.checkpoint.version.clone(),
+                    state: state.clone(),
+                    snapshot,
+                };
+                store.save(&self.id, &node.id, &checkpoint)?;
+            }
+        }
+        store.flush()?;
+        self.checkpoint.last = timestamp;
+        Ok(())
+    }
+
+    /// Restores the state of all operators from the checkpoint store,
+    /// checkpoints taken with a different version are ignored.
+    /// Either all operators are restored or, if any of them rejects its
+    /// checkpoint, none are.
+    /// Returns the number of restored operators.
+    pub fn restore(&mut self, store: &checkpoint::Store) -> Result<usize> {
+        // read everything before touching any operator
+        let mut checkpoints = Vec::with_capacity(self.graph.len());
+        for node in &self.graph {
+            let checkpoint = match store.load(&self.id, &node.id)? {
+                Some(checkpoint) if checkpoint.version != self.checkpoint.version => {
+                    warn!(
+                        "[Pipeline:{}] Ignoring checkpoint of {} from version {}, running {}",
+                        self.id, node.id, checkpoint.version, self.checkpoint.version
+                    );
+                    None
+                }
+                checkpoint => checkpoint,
+            };
+            checkpoints.push(checkpoint);
+        }
+
+        // the previous state of every touched operator, to roll back to
+        let mut previous = Vec::new();
+        let mut errors = Vec::new();
+        let nodes = self.graph.iter_mut().zip(self.state.ops.iter_mut());
+        for (idx, ((node, state), checkpoint)) in nodes.zip(checkpoints).enumerate() {
+            if let Some(checkpoint) = checkpoint {
+                if let Some(snapshot) = &checkpoint.snapshot {
+                    let before = match node.snapshot() {
+                        Ok(before) => before,
+                        Err(e) => {
+                            errors.push(format!("{}: {}", node.id, e));
+                            continue;
+                        }
+                    };
+                    previous.push((idx, before, state.clone()));
+                    if let Err(e) = node.restore(snapshot) {
+                        errors.push(format!("{}: {}", node.id, e));
+                        continue;
+                    }
+                } else {
+                    previous.push((idx, None, state.clone()));
+                }
+                *state = checkpoint.state;
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(previous.len())
+        } else {
+            for (idx, snapshot, state) in previous {
+                if let Some(snapshot) = snapshot {
+                    self.graph[idx].restore(&snapshot)?;
+                }
+                self.state.ops[idx] = state;
+            }
+            Err(format!("Invalid checkpoints: {}", errors.join(", ")).into())
+        }
+    }
+
+    /// Takes over the uids of the operators of `old` that kept their id and
+    /// type, so contraflow for events that went through `old` still reaches
+    /// the operators replacing them
+    pub fn adopt_uids(&mut self, old: &Self) {
+        let uids: HashMap<(&str, &str), u64> = old
+            .graph
+            .iter()
+            .map(|node| ((node.id.as_ref(), node.op_type.as_str()), node.uid))
+            .collect();
+        for node in &mut self.graph {
+            if let Some(uid) = uids.get(&(node.id.as_ref(), node.op_type.as_str())) {
+                node.uid = *uid;
+            }
+        }
+    }
+
+    /// Checks if the graph has an input stream of the given name
+    #[must_use]
+    pub fn has_input(&self, stream_name: &str) -> bool {
+        self.inputs.contains_key(stream_name)
+    }
+
+    /// Checks if the graph has an output port of the given name
+    #[must_use]
+    pub fn has_output(&self, port: &str) -> bool {
+        self.graph
+            .iter()
+            .any(|node| node.kind == NodeKind::Output && node.id == port)
+    }
+
+    /// The names of the input streams of the graph
+    pub fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.inputs.keys().map(AsRef::as_ref)
+    }
+
     /// This is a performance critial function!
     pub fn enqueue(
         &mut self,
@@ -458,8 +639,14 @@ impl ExecutableGraph {
             if node.kind == NodeKind::Output {
                 returns.push((node.id.clone(), event));
             } else {
+                // only pay for the clock if anyone is looking at the metrics
+                let start = self.metric_interval.map(|_| Instant::now());
                 let EventAndInsights { events, insights } =
                     node.on_event(0, &port, &mut self.state.ops[idx], event)?;
+                if let Some(start) = start {
+                    let ns = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+                    unsafe { self.metrics.get_unchecked_mut(idx) }.record_processing(ns);
+                }
 
                 for (out_port, _) in &events {
                     unsafe { self.metrics.get_unchecked_mut(idx) }.inc_output(out_port);
@@ -482,7 +669,25 @@ impl ExecutableGraph {
         mut tags: HashMap<Cow<'static, str>, BorrowedValue<'static>>,
         timestamp: u64,
     ) {
-        for (i, m) in self.metrics.iter().enumerate() {
+        if let Some(q) = self.queue_depth.take() {
+            let value = influx_gauges(
+                PIPELINE_QUEUE,
+                tags.clone(),
+                vec![("depth", q.last.into()), ("max_depth", q.max.into())],
+                timestamp,
+            );
+            self.stack.push((
+                self.metrics_idx,
+                IN,
+                Event {
+                    data: LineValue::new(vec![], |_| ValueAndMeta::from(value)),
+                    ingest_ns: timestamp,
+                    origin_uri: None,
+                    ..Event::default()
+                },
+            ));
+        }
+        for (i, m) in self.metrics.iter_mut().enumerate() {
             tags.insert("node".into(), unsafe {
                 self.graph.get_unchecked(i).id.clone().into()
             });
@@ -707,6 +912,32 @@ mod test {
         }
     }
 
+    fn split_latencies(metrics: Vec<Event>) -> (Vec<Event>, Vec<Event>) {
+        metrics.into_iter().partition(|e| {
+            e.data.suffix().value().get("measurement").unwrap() == "operator_latency"
+        })
+    }
+
+    fn test_latencies(latencies: Vec<Event>, n: u64) {
+        // in, all-1 and all-2 process events, out and metrics only collect them
+        let mut nodes: Vec<_> = latencies
+            .iter()
+            .map(|e| {
+                let data = e.data.suffix().value();
+                assert_eq!(data.get("fields").unwrap().get("samples").unwrap(), &n);
+                data.get("tags")
+                    .unwrap()
+                    .get("node")
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        nodes.sort();
+        assert_eq!(nodes, vec!["all-1", "all-2", "in"]);
+    }
+
     fn test_metrics(mut metrics: Vec<Event>, n: u64) {
         // out/in
         let this = metrics.pop().unwrap();
@@ -806,9 +1037,15 @@ mod test {
             metrics_idx: 4,
             last_metrics: 0,
             metric_interval: Some(1),
+            queue_depth: None,
+            checkpoint: checkpoint::Config::default(),
             insights: vec![],
             dot: String::from(""),
         };
+        assert!(g.has_output("out"));
+        assert!(g.has_output("metrics"));
+        assert!(!g.has_output("all-1"));
+        assert!(!g.has_output("snot"));
 
         // Test with one event
         let e = Event::default();
@@ -821,6 +1058,8 @@ mod test {
         g.run(&mut returns).unwrap();
         let (ports, metrics): (Vec<_>, Vec<_>) = returns.drain(..).unzip();
         assert!(ports.iter().all(|v| v == "metrics"));
+        let (latencies, metrics) = split_latencies(metrics);
+        test_latencies(latencies, 1);
         test_metrics(metrics, 1);
 
         // Test with two events
@@ -840,7 +1079,23 @@ mod test {
         g.run(&mut returns).unwrap();
         let (ports, metrics): (Vec<_>, Vec<_>) = returns.drain(..).unzip();
         assert!(ports.iter().all(|v| v == "metrics"));
+        // latencies are reset with every report
+        let (latencies, metrics) = split_latencies(metrics);
+        test_latencies(latencies, 2);
         test_metrics(metrics, 3);
+
+        g.set_queue_depth(7);
+        g.set_queue_depth(2);
+        g.enqueue_metrics("test-metric", HashMap::new(), 123);
+        g.run(&mut returns).unwrap();
+        let queue = returns
+            .drain(..)
+            .map(|(_, e)| e)
+            .find(|e| e.data.suffix().value().get("measurement").unwrap() == "pipeline_queue")
+            .unwrap();
+        let fields = queue.data.suffix().value().get("fields").unwrap();
+        assert_eq!(fields.get("depth").unwrap(), &2);
+        assert_eq!(fields.get("max_depth").unwrap(), &7);
     }
 
     #[test]
@@ -907,6 +1162,8 @@ mod test {
             metrics_idx: 5,
             last_metrics: 0,
             metric_interval: Some(1),
+            queue_depth: None,
+            checkpoint: checkpoint::Config::default(),
             insights: vec![],
             dot: String::from(""),
         };
@@ -37,6 +37,7 @@ use crate::errors::{ErrorKind, Result};
 use crate::op::prelude::*;
 use executable_graph::NodeConfig;
 use halfbrown::HashMap;
+use hdrhistogram::Histogram;
 use lazy_static::lazy_static;
 use op::trickle::select::WindowImpl;
 use petgraph::graph::{self, NodeIndex};
@@ -51,6 +52,8 @@ use tremor_script::prelude::*;
 use tremor_script::query::StmtRentalWrapper;
 
 /// Pipeline Errors
+pub mod checkpoint;
+pub mod dlq;
 pub mod errors;
 mod event;
 mod executable_graph;
@@ -174,6 +177,13 @@ lazy_static! {
     };
 }
 
+/// FNV-1a hash of `bytes`, unlike the std hasher it is stable across builds
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 pub(crate) fn common_cow(s: &str) -> Cow<'static, str> {
     macro_rules! cows {
         ($target:expr, $($cow:expr),*) => {
@@ -346,10 +356,15 @@ fn factory(node: &NodeConfig) -> Result<Box<dyn InitializableOperator>> {
     #[cfg(feature = "bert")]
     use op::bert::{SequenceClassificationFactory, SummerizationFactory};
     use op::debug::EventHistoryFactory;
-    use op::generic::{BatchFactory, CounterFactory};
+    use op::generic::{
+        BatchFactory, CounterFactory, DedupFactory, LookupFactory, SampleFactory, ValidateFactory,
+    };
     use op::grouper::BucketGrouperFactory;
     use op::identity::PassthroughFactory;
-    use op::qos::{BackpressureFactory, PercentileFactory, RoundRobinFactory, WalFactory};
+    use op::qos::{
+        BackpressureFactory, ConcurrencyFactory, DlqFactory, HashFactory, PercentileFactory,
+        RoundRobinFactory, WalFactory,
+    };
     let name_parts: Vec<&str> = node.op_type.split("::").collect();
     let factory = match name_parts.as_slice() {
         ["passthrough"] => PassthroughFactory::new_boxed(),
@@ -361,8 +376,15 @@ fn factory(node: &NodeConfig) -> Result<Box<dyn InitializableOperator>> {
             BackpressureFactory::new_boxed()
         }
         ["generic", "counter"] => CounterFactory::new_boxed(),
+        ["generic", "dedup"] => DedupFactory::new_boxed(),
+        ["generic", "lookup"] => LookupFactory::new_boxed(),
+        ["generic", "sample"] => SampleFactory::new_boxed(),
+        ["generic", "validate"] => ValidateFactory::new_boxed(),
         ["qos", "backpressure"] => BackpressureFactory::new_boxed(),
+        ["qos", "concurrency"] => ConcurrencyFactory::new_boxed(),
+        ["qos", "dlq"] => DlqFactory::new_boxed(),
         ["qos", "roundrobin"] => RoundRobinFactory::new_boxed(),
+        ["qos", "hash"] => HashFactory::new_boxed(),
         ["qos", "wal"] => WalFactory::new_boxed(),
         ["qos", "percentile"] => PercentileFactory::new_boxed(),
         #[cfg(feature = "bert")]
@@ -411,6 +433,57 @@ pub fn influx_value(
     res
 }
 
+/// Takes a name, tags and a set of fields and creates a influx codec
+/// compatible Value, unlike `influx_value` the fields are not counters
+#[must_use]
+pub fn influx_gauges(
+    metric_name: Cow<'static, str>,
+    tags: HashMap<Cow<'static, str>, BorrowedValue<'static>>,
+    fields: Vec<(&'static str, BorrowedValue<'static>)>,
+    timestamp: u64,
+) -> BorrowedValue<'static> {
+    let mut res = BorrowedValue::object_with_capacity(4);
+    let mut obj = BorrowedValue::object_with_capacity(fields.len());
+    if let Some(obj) = obj.as_object_mut() {
+        for (k, v) in fields {
+            obj.insert(k.into(), v);
+        }
+    };
+    if let Some(res) = res.as_object_mut() {
+        res.insert("measurement".into(), metric_name.into());
+        res.insert("tags".into(), BorrowedValue::from(tags));
+        res.insert("fields".into(), obj);
+        res.insert("timestamp".into(), timestamp.into());
+    }
+    res
+}
+
+/// Takes a name, tags and a histogram and creates a influx codec compatible
+/// Value with the number of samples, min, max, mean and percentiles as fields
+#[must_use]
+pub fn influx_histogram(
+    metric_name: Cow<'static, str>,
+    tags: HashMap<Cow<'static, str>, BorrowedValue<'static>>,
+    histogram: &Histogram<u64>,
+    timestamp: u64,
+) -> BorrowedValue<'static> {
+    influx_gauges(
+        metric_name,
+        tags,
+        vec![
+            ("samples", histogram.len().into()),
+            ("min", histogram.min().into()),
+            ("max", histogram.max().into()),
+            ("mean", histogram.mean().into()),
+            ("p50", histogram.value_at_quantile(0.5).into()),
+            ("p90", histogram.value_at_quantile(0.9).into()),
+            ("p99", histogram.value_at_quantile(0.99).into()),
+            ("p999", histogram.value_at_quantile(0.999).into()),
+        ],
+        timestamp,
+    )
+}
+
 pub(crate) type ConfigGraph = graph::DiGraph<NodeConfig, u8>;
 
 #[cfg(test)]
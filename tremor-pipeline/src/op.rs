@@ -122,6 +122,21 @@ pub trait Operator: std::fmt::Debug + Send {
     fn skippable(&self) -> bool {
         false
     }
+
+    /// Returns a snapshot of state the operator keeps outside of the `state`
+    /// passed to `on_event` (such as open windows) so it can be checkpointed,
+    /// defaults to `None` for operators without such state.
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        // Make the trait signature nicer
+        Ok(None)
+    }
+
+    /// Restores state from a snapshot previously returned by `snapshot`,
+    /// defaults to a noop.
+    fn restore(&mut self, _snapshot: &Value) -> Result<()> {
+        // Make the trait signature nicer
+        Ok(())
+    }
 }
 
 /// Initialisable trait that can be turned from a `NodeConfig`
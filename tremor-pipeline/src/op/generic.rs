@@ -14,6 +14,15 @@
 
 pub mod batch;
 pub mod counter;
+pub mod dedup;
+pub(crate) mod expr;
+pub mod lookup;
+pub mod sample;
+pub mod validate;
 
 pub use batch::BatchFactory;
 pub use counter::CounterFactory;
+pub use dedup::DedupFactory;
+pub use lookup::LookupFactory;
+pub use sample::SampleFactory;
+pub use validate::ValidateFactory;
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::op::generic::expr::Expr;
 use crate::op::prelude::*;
+use crate::SignalKind;
 use std::mem::swap;
 use tremor_script::prelude::*;
 
@@ -23,21 +25,64 @@ pub struct Config {
     /// The amount time between messags to flush
     #[serde(default = "Default::default")]
     pub timeout: Option<u64>,
+    /// Maximum size of a batch in bytes, estimated from the JSON encoding
+    /// of the events. A batch is flushed before an event would make it
+    /// exceed this, events larger than it are sent in a batch of their own.
+    #[serde(default = "Default::default")]
+    pub max_bytes: Option<usize>,
+    /// tremor-script expression the key of an event is evaluated with, such
+    /// as `$index`. Events of each key are batched separately.
+    #[serde(default = "Default::default")]
+    pub key: Option<String>,
+    /// Maximum number of keys batched at once, when a new key exceeds this
+    /// the oldest batch is flushed to make room for it
+    #[serde(default = "d_max_keys")]
+    pub max_keys: usize,
 }
 
 impl ConfigImpl for Config {}
 
+fn d_max_keys() -> usize {
+    64
+}
+
+/// The events collected for a single key
 #[derive(Debug, Clone)]
-pub struct Batch {
-    pub config: Config,
+pub struct Pending {
     pub data: LineValue,
     pub len: usize,
-    pub max_delay_ns: Option<u64>,
+    pub bytes: usize,
     pub first_ns: u64,
-    pub id: Cow<'static, str>,
     pub event_ids: Ids,
 }
 
+impl Default for Pending {
+    fn default() -> Self {
+        Self {
+            data: empty(),
+            len: 0,
+            bytes: 0,
+            first_ns: 0,
+            event_ids: Ids::default(),
+        }
+    }
+}
+
+pub struct Batch {
+    pub config: Config,
+    pub max_delay_ns: Option<u64>,
+    pub id: Cow<'static, str>,
+    key: Option<Expr>,
+    pending: HashMap<String, Pending>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Batch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Batch({}, {} pending)", self.id, self.pending.len())
+    }
+}
+
 pub fn empty() -> LineValue {
     LineValue::new(vec![], |_| ValueAndMeta::from(Value::array()))
 }
@@ -45,33 +90,15 @@ pub fn empty() -> LineValue {
 op!(BatchFactory(node) {
 if let Some(map) = &node.config {
     let config: Config = Config::new(map)?;
-    let max_delay_ns = if let Some(max_delay_ms) = config.timeout {
-        Some(max_delay_ms * 1_000_000)
-    } else {
-        None
-    };
-    Ok(Box::new(Batch {
-        data: empty(),
-        len: 0,
-        config,
-        max_delay_ns,
-        first_ns: 0,
-        id: node.id.clone(),
-        event_ids: Ids::default(),
-    }))
+    Ok(Box::new(Batch::new(node.id.clone(), config)?))
 } else {
     Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
 
 }});
 
-impl Operator for Batch {
-    fn on_event(
-        &mut self,
-        _uid: u64,
-        _port: &str,
-        _state: &mut Value<'static>,
-        event: Event,
-    ) -> Result<EventAndInsights> {
+impl Pending {
+    /// Adds an event to the batch
+    fn push(&mut self, event: Event, bytes: usize) -> Result<()> {
         // TODO: This is ugly
         let Event {
             id,
@@ -110,29 +137,133 @@ impl Operator for Batch {
             },
         )?;
         self.len += 1;
+        self.bytes += bytes;
         if self.len == 1 {
             self.first_ns = ingest_ns;
         };
+        Ok(())
+    }
+
+    /// Turns the collected events into a batch event
+    fn flush(&mut self) -> Event {
+        //TODO: This is ugly
+        let mut data = empty();
+        swap(&mut data, &mut self.data);
+        self.len = 0;
+        self.bytes = 0;
+        let mut event = Event {
+            data,
+            ingest_ns: self.first_ns,
+            is_batch: true,
+            ..Event::default()
+        };
+        swap(&mut self.event_ids, &mut event.id);
+        event
+    }
+}
+
+impl Batch {
+    fn new(id: Cow<'static, str>, config: Config) -> Result<Self> {
+        let max_delay_ns = config
+            .timeout
+            .map(|max_delay_ms| max_delay_ms.saturating_mul(1_000_000));
+        let key = if let Some(key) = &config.key {
+            Some(Expr::new("<batch>", key)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            config,
+            max_delay_ns,
+            id,
+            key,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Flushes and forgets the batch of `key`
+    fn flush(&mut self, key: &str) -> Option<Event> {
+        self.pending.remove(key).map(|mut p| p.flush())
+    }
+
+    /// Flushes all batches for which `f` holds, oldest first
+    fn flush_where<F>(&mut self, f: F) -> EventAndInsights
+    where
+        F: Fn(&Pending) -> bool,
+    {
+        let mut keys: Vec<(u64, String)> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| f(p))
+            .map(|(k, p)| (p.first_ns, k.clone()))
+            .collect();
+        keys.sort();
+        let events: Vec<(Cow<'static, str>, Event)> = keys
+            .iter()
+            .filter_map(|(_, key)| self.flush(key))
+            .map(|e| (OUT, e))
+            .collect();
+        events.into()
+    }
+}
+
+impl Operator for Batch {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let key = if let Some(expr) = &self.key {
+            if let Some(key) = expr.key(&mut event, state) {
+                key
+            } else {
+                return Ok(vec![(ERR, event)].into());
+            }
+        } else {
+            String::new()
+        };
+        let bytes = if self.config.max_bytes.is_some() {
+            event.data.suffix().value().encode().len()
+        } else {
+            0
+        };
+        let mut events = Vec::new();
+
+        if !self.pending.contains_key(&key) && self.pending.len() >= self.config.max_keys {
+            // make room for the new key by flushing the oldest batch
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, p)| p.first_ns)
+                .map(|(k, _)| k.clone());
+            if let Some(e) = oldest.and_then(|k| self.flush(&k)) {
+                events.push((OUT, e));
+            }
+        }
+        let max_bytes = self.config.max_bytes;
+        let pending = self.pending.entry(key.clone()).or_default();
+        if let Some(max_bytes) = max_bytes {
+            if pending.len > 0 && pending.bytes + bytes > max_bytes {
+                events.push((OUT, pending.flush()));
+            }
+        }
+        let ingest_ns = event.ingest_ns;
+        pending.push(event, bytes)?;
         let flush = match self.max_delay_ns {
-            Some(t) if ingest_ns - self.first_ns > t => true,
-            _ => self.len == self.config.count,
+            Some(t) if ingest_ns.saturating_sub(pending.first_ns) > t => true,
+            _ => {
+                pending.len == self.config.count
+                    || max_bytes.map_or(false, |max_bytes| pending.bytes >= max_bytes)
+            }
         };
         if flush {
-            //TODO: This is ugly
-            let mut data = empty();
-            swap(&mut data, &mut self.data);
-            self.len = 0;
-            let mut event = Event {
-                data,
-                ingest_ns: self.first_ns,
-                is_batch: true,
-                ..Event::default()
-            };
-            swap(&mut self.event_ids, &mut event.id);
-            Ok(event.into())
-        } else {
-            Ok(EventAndInsights::default())
+            if let Some(e) = self.flush(&key) {
+                events.push((OUT, e));
+            }
         }
+        Ok(events.into())
     }
 
     fn handles_signal(&self) -> bool {
@@ -140,25 +271,12 @@ impl Operator for Batch {
     }
 
     fn on_signal(&mut self, _uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
-        if let Some(delay_ns) = self.max_delay_ns {
-            if signal.ingest_ns - self.first_ns > delay_ns {
-                // We don't want to modify the original signal we clone it to
-                // create a new event.
-
-                let mut data = empty();
-                swap(&mut data, &mut self.data);
-                self.len = 0;
-                let mut event = Event {
-                    data,
-                    ingest_ns: self.first_ns,
-                    is_batch: true,
-                    ..Event::default()
-                };
-                swap(&mut self.event_ids, &mut event.id);
-                Ok(EventAndInsights::from(event))
-            } else {
-                Ok(EventAndInsights::default())
-            }
+        if signal.kind == Some(SignalKind::Shutdown) {
+            // flush what we have so it isn't lost on shutdown
+            Ok(self.flush_where(|_| true))
+        } else if let Some(delay_ns) = self.max_delay_ns {
+            let now = signal.ingest_ns;
+            Ok(self.flush_where(|p| now.saturating_sub(p.first_ns) > delay_ns))
         } else {
             Ok(EventAndInsights::default())
         }
@@ -173,18 +291,17 @@ mod test {
 
     #[test]
     fn size() {
-        let mut op = Batch {
-            config: Config {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
                 count: 2,
                 timeout: None,
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
             },
-            event_ids: Ids::default(),
-            first_ns: 0,
-            max_delay_ns: None,
-            data: empty(),
-            len: 0,
-            id: "badger".into(),
-        };
+        )
+        .expect("could not create operator");
         let event1 = Event {
             id: 1.into(),
             ingest_ns: 1,
@@ -231,20 +348,35 @@ mod test {
         assert_eq!(r.len(), 0);
     }
 
+    #[test]
+    fn huge_timeout() {
+        let op = Batch::new(
+            "badger".into(),
+            Config {
+                count: 2,
+                timeout: Some(u64::MAX),
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
+            },
+        )
+        .expect("could not create operator");
+        assert_eq!(op.max_delay_ns, Some(u64::MAX));
+    }
+
     #[test]
     fn time() {
-        let mut op = Batch {
-            config: Config {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
                 count: 100,
                 timeout: Some(1),
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
             },
-            event_ids: Ids::default(),
-            first_ns: 0,
-            max_delay_ns: Some(1_000_000),
-            data: empty(),
-            len: 0,
-            id: "badger".into(),
-        };
+        )
+        .expect("could not create operator");
         let event1 = Event {
             id: 1.into(),
             ingest_ns: 1,
@@ -309,18 +441,17 @@ mod test {
 
     #[test]
     fn signal() {
-        let mut op = Batch {
-            config: Config {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
                 count: 100,
                 timeout: Some(1),
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
             },
-            event_ids: Ids::default(),
-            first_ns: 0,
-            max_delay_ns: Some(1_000_000),
-            data: empty(),
-            len: 0,
-            id: "badger".into(),
-        };
+        )
+        .expect("could not create operator");
         let event1 = Event {
             id: 1.into(),
             ingest_ns: 1,
@@ -377,4 +508,239 @@ mod test {
             .expect("failed to run piepeline");
         assert_eq!(r.len(), 0);
     }
+
+    #[test]
+    fn shutdown() {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
+                count: 100,
+                timeout: None,
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
+            },
+        )
+        .expect("could not create operator");
+        let mut signal = Event {
+            ingest_ns: 3,
+            kind: Some(SignalKind::Shutdown),
+            ..Event::default()
+        };
+
+        // nothing to flush
+        let r = op
+            .on_signal(0, &mut signal)
+            .expect("failed to run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let event1 = Event {
+            id: 1.into(),
+            ingest_ns: 1,
+            data: Value::from("snot").into(),
+            ..Event::default()
+        };
+        let mut state = Value::null();
+        let r = op
+            .on_event(0, "in", &mut state, event1.clone())
+            .expect("failed to run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let mut r = op
+            .on_signal(0, &mut signal)
+            .expect("failed to run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        let (out, event) = r.pop().expect("empty resultset");
+        assert_eq!("out", out);
+        assert!(event.is_batch);
+        let events: Vec<&Value> = event.value_iter().collect();
+        assert_eq!(events, vec![event1.data.suffix().value()]);
+    }
+
+    fn event(ingest_ns: u64, value: Value<'static>, index: &'static str) -> Event {
+        let mut meta = Value::object();
+        meta.insert("index", index).expect("meta is an object");
+        Event {
+            id: ingest_ns.into(),
+            ingest_ns,
+            data: (value, meta).into(),
+            ..Event::default()
+        }
+    }
+
+    fn values(event: &Event) -> Vec<Value> {
+        event.value_iter().cloned().collect()
+    }
+
+    #[test]
+    fn keyed() {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
+                count: 2,
+                timeout: Some(1),
+                max_bytes: None,
+                key: Some("$index".to_string()),
+                max_keys: 2,
+            },
+        )
+        .expect("could not create operator");
+        let mut state = Value::null();
+
+        let r = op
+            .on_event(0, "in", &mut state, event(1, Value::from(1), "snot"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        let r = op
+            .on_event(0, "in", &mut state, event(2, Value::from(2), "badger"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+
+        // each key is flushed once it reaches its own count
+        let mut r = op
+            .on_event(0, "in", &mut state, event(3, Value::from(3), "snot"))
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        let (out, batch) = r.pop().expect("no results");
+        assert_eq!("out", out);
+        assert_eq!(values(&batch), vec![Value::from(1), Value::from(3)]);
+        assert_eq!(batch.id.get(0), Some(3));
+
+        // a third key flushes the oldest batch to make room
+        let r = op
+            .on_event(0, "in", &mut state, event(4, Value::from(4), "snot"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        let mut r = op
+            .on_event(0, "in", &mut state, event(5, Value::from(5), "meep"))
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        let (_, batch) = r.pop().expect("no results");
+        assert_eq!(values(&batch), vec![Value::from(2)]);
+
+        // timeouts are tracked per key, the remaining batches are flushed
+        // oldest first
+        let mut signal = Event {
+            ingest_ns: 1_000_005,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &mut signal)
+            .expect("failed to run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        assert_eq!(values(&r[0].1), vec![Value::from(4)]);
+        signal.kind = Some(SignalKind::Shutdown);
+        let r = op
+            .on_signal(0, &mut signal)
+            .expect("failed to run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        assert_eq!(values(&r[0].1), vec![Value::from(5)]);
+
+        // events without a key are errors
+        let mut r = op
+            .on_event(0, "in", &mut state, Event::default())
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        assert_eq!(r.pop().expect("no results").0, "err");
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
+                count: 100,
+                timeout: Some(1),
+                max_bytes: None,
+                key: None,
+                max_keys: 64,
+            },
+        )
+        .expect("could not create operator");
+        let mut state = Value::null();
+
+        // events and ticks older than the batch don't flush it
+        let r = op
+            .on_event(0, "in", &mut state, event(10, Value::from(1), "i"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        let r = op
+            .on_event(0, "in", &mut state, event(5, Value::from(2), "i"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        let mut signal = Event {
+            ingest_ns: 1,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &mut signal)
+            .expect("failed to run pipeline");
+        assert_eq!(r.len(), 0);
+    }
+
+    #[test]
+    fn max_bytes() {
+        let mut op = Batch::new(
+            "badger".into(),
+            Config {
+                count: 100,
+                timeout: None,
+                max_bytes: Some(12),
+                key: None,
+                max_keys: 64,
+            },
+        )
+        .expect("could not create operator");
+        let mut state = Value::null();
+
+        // "snot" is 6 bytes encoded
+        let r = op
+            .on_event(0, "in", &mut state, event(1, Value::from("snot"), "i"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        // the batch is flushed before it would exceed max_bytes
+        let mut r = op
+            .on_event(0, "in", &mut state, event(2, Value::from("badger"), "i"))
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        let (_, batch) = r.pop().expect("no results");
+        assert_eq!(values(&batch), vec![Value::from("snot")]);
+
+        // and once it reaches it
+        let mut r = op
+            .on_event(0, "in", &mut state, event(3, Value::from("snot"), "i"))
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 1);
+        let (_, batch) = r.pop().expect("no results");
+        assert_eq!(
+            values(&batch),
+            vec![Value::from("badger"), Value::from("snot")]
+        );
+
+        // events exceeding max_bytes are sent on their own
+        let r = op
+            .on_event(0, "in", &mut state, event(4, Value::from("a"), "i"))
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+        let r = op
+            .on_event(
+                0,
+                "in",
+                &mut state,
+                event(5, Value::from("snot badger"), "i"),
+            )
+            .expect("could not run pipeline")
+            .events;
+        assert_eq!(r.len(), 2);
+        assert_eq!(values(&r[0].1), vec![Value::from("a")]);
+        assert_eq!(values(&r[1].1), vec![Value::from("snot badger")]);
+    }
 }
@@ -0,0 +1,297 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Time bounded deduplication
+//!
+//! Drops events whose key was already seen within a time to live. The key
+//! is a tremor-script expression evaluated against each event, so it can
+//! refer to the event as well as to its metadata, e.g. `event.id` or
+//! `$kafka.key`. String keys are compared as they are, other values by
+//! their JSON encoding.
+//!
+//! Keys are kept in a LRU cache of bounded cardinality, once it is full the
+//! least recently seen key is forgotten.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Duplicates are routed to the `duplicate` output, events the key can't be
+//! evaluated for are routed to `err`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: dedup
+//!   op: generic::dedup
+//!   config:
+//!     key: "$kafka.key"
+//!     ttl: 60000
+//! ```
+
+use super::expr::Expr;
+use crate::influx_value;
+use crate::op::prelude::*;
+use lru::LruCache;
+use tremor_script::prelude::*;
+
+const DUPLICATE: Cow<'static, str> = Cow::Borrowed("duplicate");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// tremor-script expression the key of an event is evaluated with
+    pub key: String,
+    /// Time in milliseconds a key is remembered for
+    pub ttl: u64,
+    /// Maximum number of keys to remember, at least 1 (default: 100000)
+    #[serde(default = "d_cardinality")]
+    pub cardinality: usize,
+}
+
+impl ConfigImpl for Config {}
+
+fn d_cardinality() -> usize {
+    100_000
+}
+
+pub struct Dedup {
+    pub config: Config,
+    key: Expr,
+    /// the time each key was first seen at
+    seen: LruCache<String, u64>,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Dedup")
+    }
+}
+
+impl Dedup {
+    fn new(config: Config) -> Result<Self> {
+        if config.cardinality == 0 {
+            return Err(
+                ErrorKind::BadOpConfig("cardinality must be greater than 0".to_string()).into(),
+            );
+        }
+        let key = Expr::new("<dedup>", &config.key)?;
+        Ok(Self {
+            seen: LruCache::new(config.cardinality),
+            config,
+            key,
+            hits: 0,
+            misses: 0,
+        })
+    }
+}
+
+op!(DedupFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Dedup::new(config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Operator for Dedup {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let key = if let Some(key) = self.key.key(&mut event, state) {
+            key
+        } else {
+            return Ok(vec![(ERR, event)].into());
+        };
+        let now = event.ingest_ns;
+        let ttl_ns = self.config.ttl.saturating_mul(1_000_000);
+        let duplicate = self
+            .seen
+            .get(&key)
+            .map_or(false, |first| now.saturating_sub(*first) < ttl_ns);
+        if duplicate {
+            self.hits += 1;
+            Ok(vec![(DUPLICATE, event)].into())
+        } else {
+            self.misses += 1;
+            self.seen.put(key, now);
+            Ok(event.into())
+        }
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const DEDUP: Cow<'static, str> = Cow::Borrowed("dedup");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const HIT: Cow<'static, str> = Cow::Borrowed("hit");
+        const MISS: Cow<'static, str> = Cow::Borrowed("miss");
+
+        tags.insert(ACTION, HIT.into());
+        let hits = influx_value(DEDUP, tags.clone(), self.hits, timestamp);
+        tags.insert(ACTION, MISS.into());
+        let misses = influx_value(DEDUP, tags, self.misses, timestamp);
+        Ok(vec![hits, misses])
+    }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        // oldest first so restoring them keeps the order of the cache
+        let mut seen: Vec<Value<'static>> = self
+            .seen
+            .iter()
+            .map(|(key, first)| Value::from(vec![Value::from(key.clone()), Value::from(*first)]))
+            .collect();
+        seen.reverse();
+        Ok(Some(Value::from(seen)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        for entry in snapshot.as_array().map(Vec::as_slice).unwrap_or_default() {
+            if let Some([key, first]) = entry.as_array().map(Vec::as_slice) {
+                if let (Some(key), Some(first)) = (key.as_str(), first.as_u64()) {
+                    self.seen.put(key.to_string(), first);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn event(ingest_ns: u64, data: Value<'static>) -> Event {
+        Event {
+            id: ingest_ns.into(),
+            ingest_ns,
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            key: "event.id".to_string(),
+            ttl: 1000,
+            cardinality: 10,
+        }
+    }
+
+    fn port(op: &mut Dedup, event: Event) -> Result<Cow<'static, str>> {
+        let mut state = Value::null();
+        let mut r = op.on_event(0, "in", &mut state, event)?;
+        let (port, _) = r.events.pop().ok_or_else(|| Error::from("no event"))?;
+        assert!(r.events.is_empty());
+        Ok(port)
+    }
+
+    #[test]
+    fn dedup() -> Result<()> {
+        let mut op = Dedup::new(config())?;
+        let one = Value::from(json!({"id": 1}));
+        let two = Value::from(json!({"id": 2}));
+
+        assert_eq!(port(&mut op, event(1, one.clone()))?, "out");
+        assert_eq!(port(&mut op, event(2, one.clone()))?, "duplicate");
+        assert_eq!(port(&mut op, event(3, two))?, "out");
+        assert_eq!(port(&mut op, event(1_000_000_001, one))?, "out");
+        assert_eq!(port(&mut op, event(4, Value::from("snot")))?, "err");
+
+        let mut m = op.metrics(HashMap::new(), 0)?;
+        let miss = m.pop().ok_or_else(|| Error::from("no metric"))?;
+        let hit = m.pop().ok_or_else(|| Error::from("no metric"))?;
+        assert!(m.is_empty());
+        assert_eq!(hit["tags"]["action"], "hit");
+        assert_eq!(hit["fields"]["count"], 1);
+        assert_eq!(miss["tags"]["action"], "miss");
+        assert_eq!(miss["fields"]["count"], 3);
+        Ok(())
+    }
+
+    #[test]
+    fn huge_ttl() -> Result<()> {
+        let mut op = Dedup::new(Config {
+            ttl: u64::MAX,
+            ..config()
+        })?;
+        let one = Value::from(json!({"id": 1}));
+        assert_eq!(port(&mut op, event(1, one.clone()))?, "out");
+        assert_eq!(port(&mut op, event(u64::MAX, one))?, "duplicate");
+        Ok(())
+    }
+
+    #[test]
+    fn bad_cardinality() {
+        assert!(Dedup::new(Config {
+            cardinality: 0,
+            ..config()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn string_keys() -> Result<()> {
+        let mut op = Dedup::new(Config {
+            key: "event.key".to_string(),
+            ..config()
+        })?;
+        let snot = Value::from(json!({"key": "snot"}));
+        assert_eq!(port(&mut op, event(1, snot.clone()))?, "out");
+        assert_eq!(port(&mut op, event(2, snot))?, "duplicate");
+        // strings aren't encoded
+        assert_eq!(op.seen.peek(&"snot".to_string()), Some(&1));
+        Ok(())
+    }
+
+    #[test]
+    fn cardinality() -> Result<()> {
+        let mut op = Dedup::new(Config {
+            cardinality: 1,
+            ..config()
+        })?;
+        let one = Value::from(json!({"id": 1}));
+        let two = Value::from(json!({"id": 2}));
+        assert_eq!(port(&mut op, event(1, one.clone()))?, "out");
+        assert_eq!(port(&mut op, event(2, two))?, "out");
+        // the first key was evicted
+        assert_eq!(port(&mut op, event(3, one))?, "out");
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> Result<()> {
+        let mut op = Dedup::new(config())?;
+        let one = Value::from(json!({"id": 1}));
+        assert_eq!(port(&mut op, event(1, one.clone()))?, "out");
+        let snapshot = op.snapshot()?.ok_or_else(|| Error::from("no snapshot"))?;
+
+        let mut op = Dedup::new(config())?;
+        op.restore(&snapshot)?;
+        assert_eq!(port(&mut op, event(2, one))?, "duplicate");
+        Ok(())
+    }
+}
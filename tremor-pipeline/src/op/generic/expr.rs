@@ -0,0 +1,84 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::op::prelude::*;
+use crate::FN_REGISTRY;
+use tremor_script::prelude::*;
+use tremor_script::Script;
+
+/// A tremor-script expression operators evaluate against events, such as
+/// the key of an event
+pub(crate) struct Expr {
+    script: Script,
+}
+
+impl Expr {
+    /// Compiles an expression, `name` is used to report errors
+    pub(crate) fn new(name: &str, source: &str) -> Result<Self> {
+        let script = Script::parse(
+            &tremor_script::path::load(),
+            name,
+            source.to_string(),
+            &*FN_REGISTRY.lock()?,
+        )
+        .map_err(CompilerError::error)?;
+        Ok(Self { script })
+    }
+
+    /// Evaluates the expression and passes the result to `f`, returns `None`
+    /// if evaluation fails or the expression drops the event
+    pub(crate) fn eval<T, F>(
+        &self,
+        event: &mut Event,
+        state: &mut Value<'static>,
+        f: F,
+    ) -> Option<T>
+    where
+        F: FnOnce(&Value) -> T,
+    {
+        let context = EventContext::new(event.ingest_ns, event.origin_uri.take());
+        let res = {
+            let (value, meta) = event.data.parts();
+            match self
+                .script
+                .run(&context, AggrType::Emit, value, state, meta)
+            {
+                Ok(Return::Emit { value, .. }) => Some(f(&value)),
+                Ok(Return::EmitEvent { .. }) => Some(f(value)),
+                Ok(Return::Drop) | Err(_) => None,
+            }
+        };
+        event.origin_uri = context.origin_uri;
+        res
+    }
+
+    /// Evaluates the expression as a key, strings are used as they are
+    /// while other values are encoded
+    pub(crate) fn key(&self, event: &mut Event, state: &mut Value<'static>) -> Option<String> {
+        self.eval(event, state, key_of)
+    }
+
+    /// Evaluates the expression as a predicate, anything but `true` is false
+    pub(crate) fn test(&self, event: &mut Event, state: &mut Value<'static>) -> bool {
+        self.eval(event, state, |v| v.as_bool() == Some(true))
+            .unwrap_or_default()
+    }
+}
+
+/// The key of a value, strings are used as they are
+pub(crate) fn key_of(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.encode(), ToString::to_string)
+}
@@ -0,0 +1,445 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Enrichment from reference tables
+//!
+//! Looks up each event in a reference table and merges the matching row
+//! into the event or its metadata. The table is loaded from a CSV file with
+//! a header row, or from a JSON or YAML file holding an array of objects, and
+//! is indexed by one of its columns.
+//!
+//! The lookup key is a tremor-script expression evaluated against each
+//! event, e.g. `event.host` or `$kafka.key`. Keys are either matched
+//! exactly or, in `cidr` mode, the key is an IP address that is matched
+//! against the networks in the key column, the most specific network wins.
+//!
+//! The file is checked for changes periodically and reloaded, if reloading
+//! fails the previous table is kept.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Events without a matching row are passed on unchanged, events the key
+//! can't be evaluated for or that aren't records are routed to `err`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: team
+//!   op: generic::lookup
+//!   config:
+//!     file: teams.csv
+//!     column: network
+//!     mode: cidr
+//!     key: event.client_ip
+//! ```
+
+use super::expr::{key_of, Expr};
+use crate::op::prelude::*;
+use crate::{influx_value, SignalKind};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+use tremor_script::prelude::*;
+use tremor_script::Cidr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// keys have to be equal
+    Exact,
+    /// the key is an IP address contained in the network of the row
+    Cidr,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Self::Exact
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// the event value
+    Event,
+    /// the event metadata
+    Meta,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Self::Event
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Path of the reference table, a `.csv`, `.json` or `.yaml` file
+    pub file: String,
+    /// Column the table is indexed by
+    pub column: String,
+    /// tremor-script expression the lookup key of an event is evaluated with
+    pub key: String,
+    /// How keys are matched, `exact` or `cidr` (default: `exact`)
+    #[serde(default)]
+    pub mode: Mode,
+    /// Where the matching row is merged into, `event` or `meta`
+    /// (default: `event`)
+    #[serde(default)]
+    pub target: Target,
+    /// Interval in milliseconds the file is checked for changes at
+    /// (default: 1000)
+    #[serde(default = "d_reload")]
+    pub reload: u64,
+}
+
+impl ConfigImpl for Config {}
+
+fn d_reload() -> u64 {
+    1000
+}
+
+/// Rows of a reference table by key
+#[derive(Debug)]
+enum Index {
+    Exact(HashMap<String, Value<'static>>),
+    /// networks ordered from the most to the least specific
+    Cidr(Vec<(Cidr, Value<'static>)>),
+}
+
+impl Index {
+    fn get(&self, key: &str) -> Option<&Value<'static>> {
+        match self {
+            Index::Exact(rows) => rows.get(key),
+            Index::Cidr(rows) => {
+                let ip: IpAddr = key.parse().ok()?;
+                rows.iter()
+                    .find(|(net, _)| net.contains(ip))
+                    .map(|(_, row)| row)
+            }
+        }
+    }
+}
+
+/// The modification time and size of a file, used to detect changes
+fn version(file: &str) -> Option<(SystemTime, u64)> {
+    let meta = fs::metadata(file).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn load_rows(file: &str) -> Result<Vec<Value<'static>>> {
+    let ext = Path::new(file)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or_default();
+    let table: Value<'static> = match ext {
+        "csv" => {
+            let mut reader = csv::Reader::from_path(file)?;
+            let headers = reader.headers()?.clone();
+            let mut rows = Vec::new();
+            for record in reader.records() {
+                let record = record?;
+                let row: Object<'static> = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(column, v)| (column.to_string().into(), v.to_string().into()))
+                    .collect();
+                rows.push(Value::from(row));
+            }
+            Value::from(rows)
+        }
+        "json" => {
+            let mut raw = fs::read(file)?;
+            simd_json::to_owned_value(&mut raw)?.into()
+        }
+        "yaml" | "yml" => serde_yaml::from_slice::<OwnedValue>(&fs::read(file)?)?.into(),
+        _ => return Err(format!("Unsupported reference table format: {}", file).into()),
+    };
+    if let Value::Array(rows) = table {
+        Ok(rows)
+    } else {
+        Err(format!("Reference table {} isn't an array of records", file).into())
+    }
+}
+
+fn load(config: &Config) -> Result<Index> {
+    let rows = load_rows(&config.file)?;
+    let keyed = rows.into_iter().filter_map(|row| {
+        let key = row.get(config.column.as_str()).map(key_of)?;
+        Some((key, row))
+    });
+    Ok(match config.mode {
+        Mode::Exact => Index::Exact(keyed.collect()),
+        Mode::Cidr => {
+            let mut rows = Vec::new();
+            for (key, row) in keyed {
+                let net = Cidr::from_str(&key).map_err(|e| Error::from(e.msg))?;
+                rows.push((net, row));
+            }
+            rows.sort_by_key(|(net, _)| std::cmp::Reverse(net.bits()));
+            Index::Cidr(rows)
+        }
+    })
+}
+
+pub struct Lookup {
+    pub config: Config,
+    key: Expr,
+    index: Index,
+    version: Option<(SystemTime, u64)>,
+    last_check: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Lookup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Lookup({})", self.config.file)
+    }
+}
+
+impl Lookup {
+    fn new(config: Config) -> Result<Self> {
+        let key = Expr::new("<lookup>", &config.key)?;
+        let version = version(&config.file);
+        let index = load(&config)?;
+        Ok(Self {
+            config,
+            key,
+            index,
+            version,
+            last_check: 0,
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Reloads the table if the file changed
+    fn reload(&mut self) {
+        let version = version(&self.config.file);
+        if version != self.version {
+            match load(&self.config) {
+                Ok(index) => {
+                    info!("Reloaded reference table {}", self.config.file);
+                    self.index = index;
+                    self.version = version;
+                }
+                Err(e) => error!(
+                    "Failed to reload reference table {}: {}",
+                    self.config.file, e
+                ),
+            }
+        }
+    }
+}
+
+op!(LookupFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Lookup::new(config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Operator for Lookup {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let key = if let Some(key) = self.key.key(&mut event, state) {
+            key
+        } else {
+            return Ok(vec![(ERR, event)].into());
+        };
+        if let Some(row) = self.index.get(&key) {
+            let (value, meta) = event.data.parts();
+            let target = match self.config.target {
+                Target::Event => value,
+                Target::Meta => meta,
+            };
+            if let (Some(target), Some(row)) = (target.as_object_mut(), row.as_object()) {
+                for (column, v) in row.iter() {
+                    target.insert(column.clone(), v.clone());
+                }
+                self.hits += 1;
+                Ok(event.into())
+            } else {
+                Ok(vec![(ERR, event)].into())
+            }
+        } else {
+            self.misses += 1;
+            Ok(event.into())
+        }
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(&mut self, _uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        if signal.kind == Some(SignalKind::Tick)
+            && signal.ingest_ns.saturating_sub(self.last_check)
+                >= self.config.reload.saturating_mul(1_000_000)
+        {
+            self.last_check = signal.ingest_ns;
+            self.reload();
+        }
+        Ok(EventAndInsights::default())
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const LOOKUP: Cow<'static, str> = Cow::Borrowed("lookup");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const HIT: Cow<'static, str> = Cow::Borrowed("hit");
+        const MISS: Cow<'static, str> = Cow::Borrowed("miss");
+
+        tags.insert(ACTION, HIT.into());
+        let hits = influx_value(LOOKUP, tags.clone(), self.hits, timestamp);
+        tags.insert(ACTION, MISS.into());
+        let misses = influx_value(LOOKUP, tags, self.misses, timestamp);
+        Ok(vec![hits, misses])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+    use std::io::Write;
+
+    fn table(ext: &str, content: &str) -> Result<tempfile::NamedTempFile> {
+        let mut file = tempfile::Builder::new()
+            .suffix(&format!(".{}", ext))
+            .tempfile()?;
+        file.write_all(content.as_bytes())?;
+        Ok(file)
+    }
+
+    fn config(file: &tempfile::NamedTempFile, column: &str, key: &str) -> Config {
+        Config {
+            file: file.path().to_string_lossy().to_string(),
+            column: column.to_string(),
+            key: key.to_string(),
+            mode: Mode::Exact,
+            target: Target::Event,
+            reload: 0,
+        }
+    }
+
+    fn run(op: &mut Lookup, data: Value<'static>) -> Result<(Cow<'static, str>, Event)> {
+        let mut state = Value::null();
+        let event = Event {
+            data: data.into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?;
+        let res = r.events.pop().ok_or_else(|| Error::from("no event"))?;
+        assert!(r.events.is_empty());
+        Ok(res)
+    }
+
+    #[test]
+    fn exact() -> Result<()> {
+        let file = table("csv", "host,team\nsnot,badger\nbadger,snot\n")?;
+        let mut op = Lookup::new(config(&file, "host", "event.host"))?;
+
+        let (port, event) = run(&mut op, Value::from(json!({"host": "snot"})))?;
+        assert_eq!(port, "out");
+        assert_eq!(event.data.suffix().value()["team"], "badger");
+
+        let (port, event) = run(&mut op, Value::from(json!({"host": "cake"})))?;
+        assert_eq!(port, "out");
+        assert_eq!(event.data.suffix().value().get("team"), None);
+
+        let (port, _) = run(&mut op, Value::from(json!({"name": "snot"})))?;
+        assert_eq!(port, "err");
+        Ok(())
+    }
+
+    #[test]
+    fn cidr() -> Result<()> {
+        let file = table(
+            "json",
+            r#"[{"net": "10.0.0.0/8", "team": "wide"}, {"net": "10.1.0.0/16", "team": "narrow"}]"#,
+        )?;
+        let mut op = Lookup::new(Config {
+            mode: Mode::Cidr,
+            target: Target::Meta,
+            ..config(&file, "net", "event.ip")
+        })?;
+
+        let (_, event) = run(&mut op, Value::from(json!({"ip": "10.1.2.3"})))?;
+        assert_eq!(event.data.suffix().meta()["team"], "narrow");
+        let (_, event) = run(&mut op, Value::from(json!({"ip": "10.2.2.3"})))?;
+        assert_eq!(event.data.suffix().meta()["team"], "wide");
+        let (_, event) = run(&mut op, Value::from(json!({"ip": "192.168.0.1"})))?;
+        assert_eq!(event.data.suffix().meta().get("team"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn reload() -> Result<()> {
+        let file = table("yaml", "- host: snot\n  team: badger\n")?;
+        let mut op = Lookup::new(config(&file, "host", "event.host"))?;
+        fs::write(
+            file.path(),
+            "- host: snot\n  team: ferret\n- host: cake\n  team: ferret\n",
+        )?;
+        let mut tick = Event {
+            ingest_ns: 1,
+            kind: Some(SignalKind::Tick),
+            ..Event::default()
+        };
+        op.on_signal(0, &mut tick)?;
+
+        let (_, event) = run(&mut op, Value::from(json!({"host": "snot"})))?;
+        assert_eq!(event.data.suffix().value()["team"], "ferret");
+        Ok(())
+    }
+
+    #[test]
+    fn huge_reload() -> Result<()> {
+        let file = table("yaml", "- host: snot\n  team: badger\n")?;
+        let mut op = Lookup::new(Config {
+            reload: u64::MAX,
+            ..config(&file, "host", "event.host")
+        })?;
+        fs::write(file.path(), "- host: snot\n  team: ferret\n")?;
+        let mut tick = Event {
+            ingest_ns: 1,
+            kind: Some(SignalKind::Tick),
+            ..Event::default()
+        };
+        op.on_signal(0, &mut tick)?;
+
+        // not due for a reload yet
+        let (_, event) = run(&mut op, Value::from(json!({"host": "snot"})))?;
+        assert_eq!(event.data.suffix().value()["team"], "badger");
+        Ok(())
+    }
+}
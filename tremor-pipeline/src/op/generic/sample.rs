@@ -0,0 +1,533 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Sampling
+//!
+//! Keeps a sample of the events passing through, in one of four modes:
+//!
+//! * `probability` keeps each event with a fixed probability
+//! * `hash` keeps the events of a fixed share of keys, every event of a key,
+//!   e.g. a trace id, is either kept or dropped on every tremor instance
+//! * `reservoir` keeps a uniform sample of at most `size` events per window,
+//!   the sample is emitted once the window closes
+//! * `tail` buffers the events of each key for a window and keeps all of them
+//!   if any matches a predicate, once a key matched its following events are
+//!   passed on right away, at most `max_traces` keys are tracked at once
+//!
+//! Keys and predicates are tremor-script expressions evaluated against each
+//! event.
+//!
+//! ## Configuration
+//!
+//! See [Config](enum.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Events that aren't sampled are discarded, events the key can't be
+//! evaluated for are routed to `err`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: errors
+//!   op: generic::sample
+//!   config:
+//!     mode: tail
+//!     key: event.trace_id
+//!     predicate: event.level == "error"
+//!     window: 10000
+//! ```
+
+use super::expr::Expr;
+use crate::op::prelude::*;
+use crate::{fnv1a, influx_value, SignalKind};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::collections::BTreeSet;
+use std::mem;
+use tremor_script::prelude::*;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum Config {
+    /// Keeps each event with a probability of `rate`
+    Probability {
+        /// share of events to keep, between `0.0` and `1.0`
+        rate: f64,
+    },
+    /// Keeps the events of a share of `rate` of the keys
+    Hash {
+        /// share of keys to keep, between `0.0` and `1.0`
+        rate: f64,
+        /// tremor-script expression the key of an event is evaluated with
+        key: String,
+    },
+    /// Keeps up to `size` events per window
+    Reservoir {
+        /// maximum number of events to keep per window
+        size: usize,
+        /// window length in milliseconds
+        window: u64,
+    },
+    /// Keeps all events of a key if any of them matches the predicate
+    /// within the window
+    Tail {
+        /// tremor-script expression the key of an event is evaluated with
+        key: String,
+        /// tremor-script expression, events are kept if it is `true` for any
+        /// event of their key
+        predicate: String,
+        /// window length in milliseconds
+        window: u64,
+        /// maximum number of keys tracked at once, the key with the earliest
+        /// window is dropped to make room for a new one (default: 10000)
+        #[serde(default = "d_max_traces")]
+        max_traces: usize,
+    },
+}
+
+impl ConfigImpl for Config {}
+
+fn d_max_traces() -> usize {
+    10_000
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    passed: u64,
+    dropped: u64,
+}
+
+struct Reservoir {
+    size: usize,
+    window_ns: u64,
+    start: u64,
+    seen: usize,
+    events: Vec<Event>,
+}
+
+impl Reservoir {
+    fn add(&mut self, rng: &mut SmallRng, event: Event) {
+        self.seen += 1;
+        if self.events.len() < self.size {
+            self.events.push(event);
+        } else {
+            let i = rng.gen_range(0, self.seen);
+            if let Some(slot) = self.events.get_mut(i) {
+                *slot = event;
+            }
+        }
+    }
+
+    /// Closes the window if it ended by `now`, or if forced, and returns the
+    /// sample
+    fn close(&mut self, now: u64, force: bool, stats: &mut Stats) -> Vec<Event> {
+        if force || now.saturating_sub(self.start) >= self.window_ns {
+            self.start = now;
+            let events = mem::take(&mut self.events);
+            stats.passed += events.len() as u64;
+            stats.dropped += (self.seen - events.len()) as u64;
+            self.seen = 0;
+            events
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+struct Trace {
+    keep: bool,
+    events: Vec<Event>,
+}
+
+struct Tail {
+    key: Expr,
+    predicate: Expr,
+    window_ns: u64,
+    max_traces: usize,
+    traces: HashMap<String, Trace>,
+    /// keys ordered by the start of their window
+    starts: BTreeSet<(u64, String)>,
+}
+
+impl Tail {
+    fn add(&mut self, key: String, keep: bool, event: Event, stats: &mut Stats) -> Vec<Event> {
+        if !self.traces.contains_key(&key) {
+            if self.traces.len() >= self.max_traces {
+                self.expire_first(stats);
+            }
+            self.starts.insert((event.ingest_ns, key.clone()));
+        }
+        let trace = self.traces.entry(key).or_insert_with(|| Trace {
+            keep: false,
+            events: Vec::new(),
+        });
+        if trace.keep {
+            stats.passed += 1;
+            vec![event]
+        } else if keep {
+            trace.keep = true;
+            let mut events = mem::take(&mut trace.events);
+            events.push(event);
+            stats.passed += events.len() as u64;
+            events
+        } else {
+            trace.events.push(event);
+            Vec::new()
+        }
+    }
+
+    /// Forgets keys whose window ended by `now`, or all keys if forced, the
+    /// buffered events of keys that didn't match are dropped
+    fn close(&mut self, now: u64, force: bool, stats: &mut Stats) {
+        while let Some((start, _)) = self.starts.iter().next() {
+            if force || now.saturating_sub(*start) >= self.window_ns {
+                self.expire_first(stats);
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Forgets the key with the earliest window
+    fn expire_first(&mut self, stats: &mut Stats) {
+        if let Some(first) = self.starts.iter().next().cloned() {
+            self.starts.remove(&first);
+            if let Some(trace) = self.traces.remove(&first.1) {
+                stats.dropped += trace.events.len() as u64;
+            }
+        }
+    }
+}
+
+enum Sampler {
+    Probability(f64),
+    Hash(f64, Expr),
+    Reservoir(Reservoir),
+    Tail(Tail),
+}
+
+pub struct Sample {
+    sampler: Sampler,
+    rng: SmallRng,
+    stats: Stats,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Sample")
+    }
+}
+
+fn check_rate(rate: f64) -> Result<f64> {
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(
+            ErrorKind::BadOpConfig(format!("rate must be between 0.0 and 1.0, got {}", rate))
+                .into(),
+        )
+    }
+}
+
+impl Sample {
+    fn new(config: Config) -> Result<Self> {
+        let sampler = match config {
+            Config::Probability { rate } => Sampler::Probability(check_rate(rate)?),
+            Config::Hash { rate, key } => {
+                Sampler::Hash(check_rate(rate)?, Expr::new("<sample>", &key)?)
+            }
+            Config::Reservoir { size, window } => Sampler::Reservoir(Reservoir {
+                size,
+                window_ns: window.saturating_mul(1_000_000),
+                start: 0,
+                seen: 0,
+                events: Vec::new(),
+            }),
+            Config::Tail {
+                key,
+                predicate,
+                window,
+                max_traces,
+            } => Sampler::Tail(Tail {
+                key: Expr::new("<sample>", &key)?,
+                predicate: Expr::new("<sample>", &predicate)?,
+                window_ns: window.saturating_mul(1_000_000),
+                max_traces,
+                traces: HashMap::new(),
+                starts: BTreeSet::new(),
+            }),
+        };
+        Ok(Self {
+            sampler,
+            rng: SmallRng::from_entropy(),
+            stats: Stats::default(),
+        })
+    }
+
+    fn pass(&mut self, keep: bool, event: Event) -> EventAndInsights {
+        if keep {
+            self.stats.passed += 1;
+            event.into()
+        } else {
+            self.stats.dropped += 1;
+            EventAndInsights::default()
+        }
+    }
+}
+
+fn out(events: Vec<Event>) -> EventAndInsights {
+    events
+        .into_iter()
+        .map(|event| (OUT, event))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+op!(SampleFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Sample::new(config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Operator for Sample {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        match &mut self.sampler {
+            Sampler::Probability(rate) => {
+                let keep = self.rng.gen::<f64>() < *rate;
+                Ok(self.pass(keep, event))
+            }
+            Sampler::Hash(rate, key) => {
+                if let Some(key) = key.key(&mut event, state) {
+                    #[allow(clippy::cast_precision_loss)]
+                    let share = fnv1a(key.as_bytes()) as f64 / u64::MAX as f64;
+                    let keep = share < *rate;
+                    Ok(self.pass(keep, event))
+                } else {
+                    Ok(vec![(ERR, event)].into())
+                }
+            }
+            Sampler::Reservoir(reservoir) => {
+                let sample = reservoir.close(event.ingest_ns, false, &mut self.stats);
+                reservoir.add(&mut self.rng, event);
+                Ok(out(sample))
+            }
+            Sampler::Tail(tail) => {
+                tail.close(event.ingest_ns, false, &mut self.stats);
+                if let Some(key) = tail.key.key(&mut event, state) {
+                    let keep = tail.predicate.test(&mut event, state);
+                    Ok(out(tail.add(key, keep, event, &mut self.stats)))
+                } else {
+                    Ok(vec![(ERR, event)].into())
+                }
+            }
+        }
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(&mut self, _uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        // flush what we have on shutdown so it isn't lost
+        let force = signal.kind == Some(SignalKind::Shutdown);
+        match &mut self.sampler {
+            Sampler::Reservoir(reservoir) => Ok(out(reservoir.close(
+                signal.ingest_ns,
+                force,
+                &mut self.stats,
+            ))),
+            Sampler::Tail(tail) => {
+                tail.close(signal.ingest_ns, force, &mut self.stats);
+                Ok(EventAndInsights::default())
+            }
+            Sampler::Probability(_) | Sampler::Hash(..) => Ok(EventAndInsights::default()),
+        }
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const SAMPLE: Cow<'static, str> = Cow::Borrowed("sample");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const PASS: Cow<'static, str> = Cow::Borrowed("pass");
+        const DROP: Cow<'static, str> = Cow::Borrowed("drop");
+
+        tags.insert(ACTION, PASS.into());
+        let passed = influx_value(SAMPLE, tags.clone(), self.stats.passed, timestamp);
+        tags.insert(ACTION, DROP.into());
+        let dropped = influx_value(SAMPLE, tags, self.stats.dropped, timestamp);
+        Ok(vec![passed, dropped])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn event(ingest_ns: u64, data: Value<'static>) -> Event {
+        Event {
+            id: ingest_ns.into(),
+            ingest_ns,
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    fn run(op: &mut Sample, event: Event) -> Result<Vec<u64>> {
+        let mut state = Value::null();
+        let r = op.on_event(0, "in", &mut state, event)?;
+        Ok(r.events.into_iter().map(|(_, e)| e.ingest_ns).collect())
+    }
+
+    fn tick(op: &mut Sample, ingest_ns: u64, kind: SignalKind) -> Result<Vec<u64>> {
+        let mut signal = Event {
+            ingest_ns,
+            kind: Some(kind),
+            ..Event::default()
+        };
+        let r = op.on_signal(0, &mut signal)?;
+        Ok(r.events.into_iter().map(|(_, e)| e.ingest_ns).collect())
+    }
+
+    #[test]
+    fn probability() -> Result<()> {
+        let mut all = Sample::new(Config::Probability { rate: 1.0 })?;
+        let mut none = Sample::new(Config::Probability { rate: 0.0 })?;
+        for i in 1..10 {
+            assert_eq!(run(&mut all, event(i, Value::null()))?, vec![i]);
+            assert!(run(&mut none, event(i, Value::null()))?.is_empty());
+        }
+        let m = none.metrics(HashMap::new(), 0)?;
+        assert_eq!(m[1]["tags"]["action"], "drop");
+        assert_eq!(m[1]["fields"]["count"], 9);
+        Ok(())
+    }
+
+    #[test]
+    fn hash() -> Result<()> {
+        let mut op = Sample::new(Config::Hash {
+            rate: 0.5,
+            key: "event.trace".to_string(),
+        })?;
+        let mut kept = 0;
+        for trace in 0..100 {
+            let first = run(&mut op, event(1, Value::from(json!({ "trace": trace }))))?;
+            let second = run(&mut op, event(2, Value::from(json!({ "trace": trace }))))?;
+            // every event of a key shares its fate
+            assert_eq!(first.is_empty(), second.is_empty());
+            if !first.is_empty() {
+                kept += 1;
+            }
+        }
+        assert!(kept > 0 && kept < 100);
+        Ok(())
+    }
+
+    #[test]
+    fn reservoir() -> Result<()> {
+        let mut op = Sample::new(Config::Reservoir { size: 2, window: 1 })?;
+        assert!(run(&mut op, event(1, Value::null()))?.is_empty());
+        for i in 2..10 {
+            assert!(run(&mut op, event(i, Value::null()))?.is_empty());
+        }
+        // the window closed
+        let sample = tick(&mut op, 1_000_001, SignalKind::Tick)?;
+        assert_eq!(sample.len(), 2);
+        assert!(run(&mut op, event(1_000_002, Value::null()))?.is_empty());
+        assert_eq!(
+            tick(&mut op, 1_000_003, SignalKind::Shutdown)?,
+            vec![1_000_002]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tail() -> Result<()> {
+        let mut op = Sample::new(Config::Tail {
+            key: "event.trace".to_string(),
+            predicate: "event.level == \"error\"".to_string(),
+            window: 1,
+            max_traces: 10,
+        })?;
+        let info =
+            |ns: u64, trace: &str| event(ns, Value::from(json!({"trace": trace, "level": "info"})));
+        let error = |ns: u64, trace: &str| {
+            event(ns, Value::from(json!({"trace": trace, "level": "error"})))
+        };
+
+        assert!(run(&mut op, info(1, "a"))?.is_empty());
+        assert!(run(&mut op, info(2, "b"))?.is_empty());
+        assert!(run(&mut op, info(3, "a"))?.is_empty());
+        assert_eq!(run(&mut op, error(4, "a"))?, vec![1, 3, 4]);
+        assert_eq!(run(&mut op, info(5, "a"))?, vec![5]);
+        // both windows end, the buffered event of `b` is dropped
+        assert!(tick(&mut op, 1_000_002, SignalKind::Tick)?.is_empty());
+        assert!(run(&mut op, info(1_000_003, "a"))?.is_empty());
+
+        let m = op.metrics(HashMap::new(), 0)?;
+        assert_eq!(m[0]["fields"]["count"], 4);
+        assert_eq!(m[1]["fields"]["count"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn tail_max_traces() -> Result<()> {
+        let mut op = Sample::new(Config::Tail {
+            key: "event.trace".to_string(),
+            predicate: "event.level == \"error\"".to_string(),
+            window: 1,
+            max_traces: 2,
+        })?;
+        let info =
+            |ns: u64, trace: &str| event(ns, Value::from(json!({"trace": trace, "level": "info"})));
+        let error = |ns: u64, trace: &str| {
+            event(ns, Value::from(json!({"trace": trace, "level": "error"})))
+        };
+
+        assert!(run(&mut op, info(1, "a"))?.is_empty());
+        assert!(run(&mut op, info(2, "b"))?.is_empty());
+        // `a` has the earliest window and makes room for `c`
+        assert!(run(&mut op, info(3, "c"))?.is_empty());
+        assert_eq!(run(&mut op, error(4, "a"))?, vec![4]);
+        assert_eq!(run(&mut op, error(5, "c"))?, vec![3, 5]);
+
+        let m = op.metrics(HashMap::new(), 0)?;
+        assert_eq!(m[1]["fields"]["count"], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_rate() {
+        assert!(Sample::new(Config::Probability { rate: 1.5 }).is_err());
+        assert!(Sample::new(Config::Probability { rate: f64::NAN }).is_err());
+        assert!(Sample::new(Config::Hash {
+            rate: -0.1,
+            key: "event.trace".to_string(),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,816 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # JSON Schema validation
+//!
+//! Validates each event against a JSON Schema (draft 7) loaded from a JSON or
+//! YAML file when the operator is created.
+//!
+//! All draft 7 validation keywords are supported. `format` is treated as an
+//! annotation, and `$ref` can only point into the schema document itself,
+//! e.g. `#/definitions/host`. References that lead back to themselves without
+//! descending into the event are rejected, and schemas nested deeper than 128
+//! levels while validating an event are reported as a violation.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Valid events are routed to `out`. Invalid events are routed to `err`,
+//! their `$validation` metadata is set to a list of records with the JSON
+//! pointer `path` of each violation in the event and a `message`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: contract
+//!   op: generic::validate
+//!   config:
+//!     schema: schemas/logs.json
+//! ```
+
+use crate::influx_value;
+use crate::op::prelude::*;
+use regex::Regex;
+use simd_json::{OwnedValue, ValueType};
+use std::fs;
+use std::path::Path;
+use tremor_script::prelude::*;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Path to the schema, a `.json`, `.yaml` or `.yml` file
+    pub schema: String,
+}
+
+impl ConfigImpl for Config {}
+
+/// Maximum nesting of (sub) schemas while validating an event
+const MAX_DEPTH: usize = 128;
+
+/// A single reason an event isn't valid
+#[derive(Debug, Clone, PartialEq)]
+struct Violation {
+    /// JSON pointer to the offending part of the event
+    path: String,
+    message: String,
+}
+
+impl Violation {
+    fn into_value(self) -> Value<'static> {
+        let mut record = Object::with_capacity(2);
+        record.insert("path".into(), Value::from(self.path));
+        record.insert("message".into(), Value::from(self.message));
+        Value::from(record)
+    }
+}
+
+/// Appends a segment to a JSON pointer
+fn child(path: &str, segment: &str) -> String {
+    format!("{}/{}", path, segment.replace('~', "~0").replace('/', "~1"))
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value.value_type() {
+        ValueType::Null => "null",
+        ValueType::Bool => "boolean",
+        ValueType::String => "string",
+        ValueType::I64 | ValueType::U64 => "integer",
+        ValueType::F64 => "number",
+        ValueType::Array => "array",
+        ValueType::Object => "object",
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value.value_type() {
+        ValueType::I64 | ValueType::U64 | ValueType::F64 => value.cast_f64(),
+        _ => None,
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match value.value_type() {
+        ValueType::F64 if name == "integer" => value
+            .as_f64()
+            .map_or(false, |f| f.fract().abs() < f64::EPSILON),
+        ValueType::I64 | ValueType::U64 if name == "number" => true,
+        _ => type_name(value) == name,
+    }
+}
+
+/// Equality as defined by JSON Schema, numbers are equal by value
+fn json_eq(a: &Value, b: &Value) -> bool {
+    if let (Some(a), Some(b)) = (a.as_array(), b.as_array()) {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| json_eq(a, b))
+    } else if let (Some(a), Some(b)) = (a.as_object(), b.as_object()) {
+        a.len() == b.len()
+            && a.iter()
+                .all(|(k, v)| b.get(k).map_or(false, |w| json_eq(v, w)))
+    } else if let (Some(a), Some(b)) = (number(a), number(b)) {
+        (a - b).abs() < f64::EPSILON
+    } else {
+        a == b
+    }
+}
+
+/// A loaded schema with its patterns compiled
+struct Schema {
+    root: Value<'static>,
+    patterns: HashMap<String, Regex>,
+}
+
+impl Schema {
+    fn load(file: &str) -> Result<Self> {
+        let ext = Path::new(file)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or_default();
+        let root: Value<'static> = match ext {
+            "json" => {
+                let mut raw = fs::read(file)?;
+                simd_json::to_owned_value(&mut raw)?.into()
+            }
+            "yaml" | "yml" => serde_yaml::from_slice::<OwnedValue>(&fs::read(file)?)?.into(),
+            _ => return Err(format!("Unsupported schema format: {}", file).into()),
+        };
+        Self::new(root)
+    }
+
+    fn new(root: Value<'static>) -> Result<Self> {
+        let mut schema = Self {
+            root,
+            patterns: HashMap::new(),
+        };
+        let mut patterns = HashMap::new();
+        schema.compile(&schema.root, &mut patterns)?;
+        schema.patterns = patterns;
+        Ok(schema)
+    }
+
+    /// Compiles the patterns of a (sub) schema and checks its references
+    fn compile(&self, schema: &Value, patterns: &mut HashMap<String, Regex>) -> Result<()> {
+        let schema = if let Some(schema) = schema.as_object() {
+            schema
+        } else {
+            return Ok(());
+        };
+        if let Some(reference) = schema.get("$ref").and_then(ValueTrait::as_str) {
+            self.check_reference(reference, &mut Vec::new())?;
+        }
+        let mut regexes: Vec<&str> = Vec::new();
+        if let Some(pattern) = schema.get("pattern").and_then(ValueTrait::as_str) {
+            regexes.push(pattern);
+        }
+        for (keyword, sub) in schema.iter() {
+            match &**keyword {
+                "properties" | "patternProperties" | "definitions" | "$defs" | "dependencies" => {
+                    for (key, sub) in sub.as_object().into_iter().flat_map(|subs| subs.iter()) {
+                        if keyword == "patternProperties" {
+                            regexes.push(key);
+                        }
+                        self.compile(sub, patterns)?;
+                    }
+                }
+                "items" | "allOf" | "anyOf" | "oneOf" => {
+                    if let Some(subs) = sub.as_array() {
+                        for sub in subs {
+                            self.compile(sub, patterns)?;
+                        }
+                    } else {
+                        self.compile(sub, patterns)?;
+                    }
+                }
+                "additionalItems"
+                | "additionalProperties"
+                | "contains"
+                | "propertyNames"
+                | "not"
+                | "if"
+                | "then"
+                | "else" => self.compile(sub, patterns)?,
+                _ => (),
+            }
+        }
+        for regex in regexes {
+            let compiled = Regex::new(regex)
+                .map_err(|e| Error::from(format!("Invalid schema pattern {}: {}", regex, e)))?;
+            patterns.insert(regex.to_string(), compiled);
+        }
+        Ok(())
+    }
+
+    /// Follows a reference through the keywords that apply to the same value,
+    /// reaching a reference that is being visited again means validation
+    /// would never end
+    fn check_reference(&self, reference: &str, visiting: &mut Vec<String>) -> Result<()> {
+        if visiting.iter().any(|visited| visited == reference) {
+            visiting.push(reference.to_string());
+            return Err(format!("Cyclic schema reference: {}", visiting.join(" -> ")).into());
+        }
+        let target = self
+            .resolve(reference)
+            .ok_or_else(|| Error::from(format!("Unresolvable schema reference: {}", reference)))?;
+        visiting.push(reference.to_string());
+        self.check_in_place(target, visiting)?;
+        visiting.pop();
+        Ok(())
+    }
+
+    fn check_in_place(&self, schema: &Value, visiting: &mut Vec<String>) -> Result<()> {
+        let schema = if let Some(schema) = schema.as_object() {
+            schema
+        } else {
+            return Ok(());
+        };
+        if let Some(reference) = schema.get("$ref").and_then(ValueTrait::as_str) {
+            return self.check_reference(reference, visiting);
+        }
+        for (keyword, sub) in schema.iter() {
+            match &**keyword {
+                "allOf" | "anyOf" | "oneOf" => {
+                    for sub in sub.as_array().into_iter().flat_map(|subs| subs.iter()) {
+                        self.check_in_place(sub, visiting)?;
+                    }
+                }
+                "dependencies" => {
+                    for (_, sub) in sub.as_object().into_iter().flat_map(|subs| subs.iter()) {
+                        self.check_in_place(sub, visiting)?;
+                    }
+                }
+                "not" | "if" | "then" | "else" => self.check_in_place(sub, visiting)?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a reference within the schema document
+    fn resolve(&self, reference: &str) -> Option<&Value<'static>> {
+        let pointer = reference.strip_prefix('#')?;
+        let mut target = &self.root;
+        for segment in pointer.split('/').skip(1) {
+            let segment = segment.replace("~1", "/").replace("~0", "~");
+            target = if let Some(array) = target.as_array() {
+                array.get(segment.parse::<usize>().ok()?)?
+            } else {
+                target.get(segment.as_str())?
+            };
+        }
+        Some(target)
+    }
+
+    fn matches(&self, pattern: &str, s: &str) -> bool {
+        self.patterns
+            .get(pattern)
+            .map_or(true, |regex| regex.is_match(s))
+    }
+
+    fn is_valid(&self, schema: &Value, value: &Value, depth: usize) -> bool {
+        let mut violations = Vec::new();
+        self.validate(schema, value, "", depth, &mut violations);
+        violations.is_empty()
+    }
+
+    /// Validates the event against the whole schema
+    fn check(&self, value: &Value) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.validate(&self.root, value, "", 0, &mut violations);
+        violations
+    }
+
+    fn validate(
+        &self,
+        schema: &Value,
+        value: &Value,
+        path: &str,
+        depth: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        if depth >= MAX_DEPTH {
+            let message = format!("schema is nested deeper than {} levels", MAX_DEPTH);
+            return fail(out, path, message);
+        }
+        let depth = depth + 1;
+        let schema = match (schema.as_bool(), schema.as_object()) {
+            (Some(false), _) => return fail(out, path, "no value is allowed here".to_string()),
+            (_, Some(schema)) => schema,
+            _ => return,
+        };
+        // in draft 7 all other keywords next to a reference are ignored
+        if let Some(reference) = schema.get("$ref").and_then(ValueTrait::as_str) {
+            if let Some(target) = self.resolve(reference) {
+                self.validate(target, value, path, depth, out);
+            }
+            return;
+        }
+        if let Some(expected) = schema.get("type") {
+            let ok = if let Some(names) = expected.as_array() {
+                names
+                    .iter()
+                    .filter_map(ValueTrait::as_str)
+                    .any(|name| has_type(value, name))
+            } else {
+                expected.as_str().map_or(true, |name| has_type(value, name))
+            };
+            if !ok {
+                let expected = expected
+                    .as_str()
+                    .map_or_else(|| expected.encode(), ToString::to_string);
+                let message = format!("expected type {}, got {}", expected, type_name(value));
+                fail(out, path, message);
+            }
+        }
+        if let Some(options) = schema.get("enum").and_then(ValueTrait::as_array) {
+            if !options.iter().any(|option| json_eq(option, value)) {
+                let options = Value::from(options.clone()).encode();
+                fail(out, path, format!("must be one of {}", options));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if !json_eq(expected, value) {
+                fail(out, path, format!("must be {}", expected.encode()));
+            }
+        }
+        if let Some(n) = number(value) {
+            validate_number(schema, n, path, out);
+        } else if let Some(s) = value.as_str() {
+            self.validate_string(schema, s, path, out);
+        } else if let Some(items) = value.as_array() {
+            self.validate_array(schema, items, path, depth, out);
+        } else if let Some(object) = value.as_object() {
+            self.validate_object(schema, value, object, path, depth, out);
+        }
+        self.validate_combinators(schema, value, path, depth, out);
+    }
+
+    fn validate_string(&self, schema: &Object, s: &str, path: &str, out: &mut Vec<Violation>) {
+        let len = s.chars().count();
+        if let Some(min) = bound(schema, "minLength") {
+            if len < min {
+                fail(
+                    out,
+                    path,
+                    format!("must be at least {} characters long", min),
+                );
+            }
+        }
+        if let Some(max) = bound(schema, "maxLength") {
+            if len > max {
+                fail(
+                    out,
+                    path,
+                    format!("must be at most {} characters long", max),
+                );
+            }
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(ValueTrait::as_str) {
+            if !self.matches(pattern, s) {
+                fail(out, path, format!("must match the pattern {}", pattern));
+            }
+        }
+    }
+
+    fn validate_array(
+        &self,
+        schema: &Object,
+        items: &[Value],
+        path: &str,
+        depth: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(min) = bound(schema, "minItems") {
+            if items.len() < min {
+                fail(out, path, format!("must have at least {} items", min));
+            }
+        }
+        if let Some(max) = bound(schema, "maxItems") {
+            if items.len() > max {
+                fail(out, path, format!("must have at most {} items", max));
+            }
+        }
+        if schema.get("uniqueItems").and_then(ValueTrait::as_bool) == Some(true) {
+            let duplicate = items
+                .iter()
+                .enumerate()
+                .any(|(i, a)| items[i + 1..].iter().any(|b| json_eq(a, b)));
+            if duplicate {
+                fail(out, path, "items must be unique".to_string());
+            }
+        }
+        if let Some(contains) = schema.get("contains") {
+            if !items
+                .iter()
+                .any(|item| self.is_valid(contains, item, depth))
+            {
+                fail(out, path, "no item matches the contains schema".to_string());
+            }
+        }
+        let additional = schema.get("additionalItems");
+        for (i, item) in items.iter().enumerate() {
+            let sub = match schema.get("items") {
+                Some(positional) if positional.is_array() => positional.get_idx(i).or(additional),
+                sub => sub,
+            };
+            if let Some(sub) = sub {
+                self.validate(sub, item, &child(path, &i.to_string()), depth, out);
+            }
+        }
+    }
+
+    fn validate_object(
+        &self,
+        schema: &Object,
+        value: &Value,
+        object: &Object,
+        path: &str,
+        depth: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(min) = bound(schema, "minProperties") {
+            if object.len() < min {
+                fail(out, path, format!("must have at least {} properties", min));
+            }
+        }
+        if let Some(max) = bound(schema, "maxProperties") {
+            if object.len() > max {
+                fail(out, path, format!("must have at most {} properties", max));
+            }
+        }
+        if let Some(required) = schema.get("required").and_then(ValueTrait::as_array) {
+            for name in required.iter().filter_map(ValueTrait::as_str) {
+                if !object.contains_key(name) {
+                    fail(out, path, format!("missing required property {}", name));
+                }
+            }
+        }
+        if let Some(dependencies) = schema.get("dependencies").and_then(ValueTrait::as_object) {
+            for (key, dependency) in dependencies.iter() {
+                if !object.contains_key(key) {
+                    continue;
+                }
+                if let Some(names) = dependency.as_array() {
+                    for name in names.iter().filter_map(ValueTrait::as_str) {
+                        if !object.contains_key(name) {
+                            let message = format!("property {} requires property {}", key, name);
+                            fail(out, path, message);
+                        }
+                    }
+                } else {
+                    self.validate(dependency, value, path, depth, out);
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(ValueTrait::as_object);
+        let patterns = schema
+            .get("patternProperties")
+            .and_then(ValueTrait::as_object);
+        let additional = schema.get("additionalProperties");
+        let names = schema.get("propertyNames");
+        for (key, v) in object.iter() {
+            let key_path = child(path, key);
+            if let Some(names) = names {
+                if !self.is_valid(names, &Value::from(key.to_string()), depth) {
+                    fail(out, &key_path, format!("invalid property name {}", key));
+                }
+            }
+            let mut matched = false;
+            if let Some(sub) = properties.and_then(|p| p.get(key)) {
+                matched = true;
+                self.validate(sub, v, &key_path, depth, out);
+            }
+            if let Some(patterns) = patterns {
+                for (pattern, sub) in patterns.iter() {
+                    if self.matches(pattern, key) {
+                        matched = true;
+                        self.validate(sub, v, &key_path, depth, out);
+                    }
+                }
+            }
+            if let (false, Some(additional)) = (matched, additional) {
+                self.validate(additional, v, &key_path, depth, out);
+            }
+        }
+    }
+
+    fn validate_combinators(
+        &self,
+        schema: &Object,
+        value: &Value,
+        path: &str,
+        depth: usize,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(all) = schema.get("allOf").and_then(ValueTrait::as_array) {
+            for sub in all {
+                self.validate(sub, value, path, depth, out);
+            }
+        }
+        if let Some(any) = schema.get("anyOf").and_then(ValueTrait::as_array) {
+            if !any.iter().any(|sub| self.is_valid(sub, value, depth)) {
+                let message = "must match at least one schema of anyOf".to_string();
+                fail(out, path, message);
+            }
+        }
+        if let Some(one) = schema.get("oneOf").and_then(ValueTrait::as_array) {
+            if one
+                .iter()
+                .filter(|sub| self.is_valid(sub, value, depth))
+                .count()
+                != 1
+            {
+                let message = "must match exactly one schema of oneOf".to_string();
+                fail(out, path, message);
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.is_valid(not, value, depth) {
+                fail(out, path, "must not match the schema of not".to_string());
+            }
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.is_valid(condition, value, depth) {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.validate(branch, value, path, depth, out);
+            }
+        }
+    }
+}
+
+fn fail(out: &mut Vec<Violation>, path: &str, message: String) {
+    out.push(Violation {
+        path: path.to_string(),
+        message,
+    });
+}
+
+fn bound(schema: &Object, keyword: &str) -> Option<usize> {
+    schema.get(keyword).and_then(ValueTrait::as_usize)
+}
+
+fn validate_number(schema: &Object, n: f64, path: &str, out: &mut Vec<Violation>) {
+    let limit = |keyword: &str| schema.get(keyword).and_then(number);
+    if let Some(min) = limit("minimum") {
+        if n < min {
+            fail(out, path, format!("must be at least {}", min));
+        }
+    }
+    if let Some(max) = limit("maximum") {
+        if n > max {
+            fail(out, path, format!("must be at most {}", max));
+        }
+    }
+    if let Some(min) = limit("exclusiveMinimum") {
+        if n <= min {
+            fail(out, path, format!("must be greater than {}", min));
+        }
+    }
+    if let Some(max) = limit("exclusiveMaximum") {
+        if n >= max {
+            fail(out, path, format!("must be less than {}", max));
+        }
+    }
+    if let Some(factor) = limit("multipleOf") {
+        let q = n / factor;
+        if (q - q.round()).abs() > 1e-9 {
+            fail(out, path, format!("must be a multiple of {}", factor));
+        }
+    }
+}
+
+pub struct Validate {
+    pub config: Config,
+    schema: Schema,
+    valid: u64,
+    invalid: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Validate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Validate({})", self.config.schema)
+    }
+}
+
+impl Validate {
+    fn new(config: Config) -> Result<Self> {
+        let schema = Schema::load(&config.schema)?;
+        Ok(Self {
+            config,
+            schema,
+            valid: 0,
+            invalid: 0,
+        })
+    }
+}
+
+op!(ValidateFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Validate::new(config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Operator for Validate {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let violations = self.schema.check(event.data.suffix().value());
+        if violations.is_empty() {
+            self.valid += 1;
+            return Ok(event.into());
+        }
+        self.invalid += 1;
+        let violations: Vec<Value<'static>> =
+            violations.into_iter().map(Violation::into_value).collect();
+        let (_, meta) = event.data.parts();
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert("validation".into(), Value::from(violations));
+        }
+        Ok(vec![(ERR, event)].into())
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const VALIDATE: Cow<'static, str> = Cow::Borrowed("validate");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const VALID: Cow<'static, str> = Cow::Borrowed("valid");
+        const INVALID: Cow<'static, str> = Cow::Borrowed("invalid");
+
+        tags.insert(ACTION, VALID.into());
+        let valid = influx_value(VALIDATE, tags.clone(), self.valid, timestamp);
+        tags.insert(ACTION, INVALID.into());
+        let invalid = influx_value(VALIDATE, tags, self.invalid, timestamp);
+        Ok(vec![valid, invalid])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+    use std::io::Write;
+
+    fn schema(value: OwnedValue) -> Result<Schema> {
+        Schema::new(value.into())
+    }
+
+    fn paths(schema: &Schema, value: OwnedValue) -> Vec<String> {
+        schema
+            .check(&Value::from(value))
+            .into_iter()
+            .map(|v| v.path)
+            .collect()
+    }
+
+    #[test]
+    fn operator() -> Result<()> {
+        let mut file = tempfile::Builder::new().suffix(".yaml").tempfile()?;
+        file.write_all(
+            br#"
+type: object
+required: [host]
+properties:
+  host: {type: string}
+  tags:
+    type: array
+    items: {type: string}
+"#,
+        )?;
+        let mut op = Validate::new(Config {
+            schema: file.path().to_string_lossy().to_string(),
+        })?;
+        let mut state = Value::null();
+
+        let event = Event {
+            data: Value::from(json!({"host": "snot", "tags": ["badger"]})).into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?;
+        let (port, _) = r.events.pop().ok_or_else(|| Error::from("no event"))?;
+        assert_eq!(port, "out");
+
+        let event = Event {
+            data: Value::from(json!({"tags": ["badger", 42]})).into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?;
+        let (port, event) = r.events.pop().ok_or_else(|| Error::from("no event"))?;
+        assert_eq!(port, "err");
+        let validation = &event.data.suffix().meta()["validation"];
+        assert_eq!(validation[0]["path"], "");
+        assert_eq!(validation[0]["message"], "missing required property host");
+        assert_eq!(validation[1]["path"], "/tags/1");
+        assert_eq!(
+            validation[1]["message"],
+            "expected type string, got integer"
+        );
+
+        let m = op.metrics(HashMap::new(), 0)?;
+        assert_eq!(m[0]["fields"]["count"], 1);
+        assert_eq!(m[1]["fields"]["count"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn keywords() -> Result<()> {
+        let s = schema(json!({
+            "definitions": {"port": {"type": "integer", "minimum": 1, "maximum": 65535}},
+            "properties": {
+                "port": {"$ref": "#/definitions/port"},
+                "level": {"enum": ["info", "error"]},
+                "name": {"type": "string", "pattern": "^[a-z]+$", "maxLength": 5},
+                "ratio": {"type": "number", "exclusiveMaximum": 1},
+                "id": {"oneOf": [{"type": "string"}, {"type": "integer"}]},
+                "a/b": {"not": {"type": "null"}}
+            },
+            "patternProperties": {"^x-": {"type": "boolean"}},
+            "additionalProperties": false,
+            "if": {"properties": {"level": {"const": "error"}}},
+            "then": {"required": ["id"]}
+        }))?;
+
+        let valid =
+            json!({"port": 80, "level": "info", "name": "snot", "ratio": 0.5, "x-ok": true});
+        assert!(paths(&s, valid).is_empty());
+        assert_eq!(paths(&s, json!({"port": 0})), vec!["/port"]);
+        assert_eq!(paths(&s, json!({"port": 80.0})), Vec::<String>::new());
+        assert_eq!(paths(&s, json!({"level": "warn"})), vec!["/level"]);
+        assert_eq!(paths(&s, json!({"name": "Badger"})), vec!["/name", "/name"]);
+        assert_eq!(paths(&s, json!({"ratio": 1})), vec!["/ratio"]);
+        assert_eq!(paths(&s, json!({"id": 1.5})), vec!["/id"]);
+        assert_eq!(paths(&s, json!({ "a/b": null })), vec!["/a~1b"]);
+        assert_eq!(paths(&s, json!({"x-no": 1})), vec!["/x-no"]);
+        assert_eq!(paths(&s, json!({"snot": 1})), vec!["/snot"]);
+        assert_eq!(paths(&s, json!({"level": "error"})), vec![""]);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_schema() {
+        assert!(schema(json!({"pattern": "("})).is_err());
+        assert!(schema(json!({"items": {"$ref": "#/definitions/snot"}})).is_err());
+        assert!(schema(json!({"items": {"$ref": "#"}})).is_ok());
+    }
+
+    #[test]
+    fn cyclic_references() {
+        assert!(schema(json!({"$ref": "#"})).is_err());
+        assert!(schema(json!({
+            "definitions": {
+                "a": {"$ref": "#/definitions/b"},
+                "b": {"allOf": [{"$ref": "#/definitions/a"}]}
+            },
+            "properties": {"snot": {"$ref": "#/definitions/a"}}
+        }))
+        .is_err());
+        assert!(schema(json!({"anyOf": [{"type": "null"}, {"not": {"$ref": "#"}}]})).is_err());
+        // descending into the event ends the recursion
+        assert!(schema(json!({"properties": {"child": {"$ref": "#"}}})).is_ok());
+    }
+
+    #[test]
+    fn max_depth() -> Result<()> {
+        let s = schema(json!({
+            "type": "object",
+            "properties": {"child": {"$ref": "#"}}
+        }))?;
+        let mut value = json!({});
+        for _ in 0..MAX_DEPTH {
+            value = json!({ "child": value });
+        }
+        let violations = s.check(&Value::from(value));
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            violations[0].message,
+            format!("schema is nested deeper than {} levels", MAX_DEPTH)
+        );
+        Ok(())
+    }
+}
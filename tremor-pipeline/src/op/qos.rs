@@ -13,11 +13,17 @@
 // limitations under the License.
 
 pub mod backpressure;
+pub mod concurrency;
+pub mod dlq;
+pub mod hash;
 pub mod percentile;
 pub mod rr;
 pub mod wal;
 
 pub use backpressure::BackpressureFactory;
+pub use concurrency::ConcurrencyFactory;
+pub use dlq::DlqFactory;
+pub use hash::HashFactory;
 pub use percentile::PercentileFactory;
 pub use rr::RoundRobinFactory;
 pub use wal::WalFactory;
@@ -0,0 +1,586 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Adaptive concurrency limiter
+//!
+//! Limits the number of events in flight, sent on but neither acknowledged
+//! nor failed by the offramps behind it yet. Instead of being configured up
+//! front the limit adapts to the latencies and failures reported in
+//! insights:
+//!
+//! * `aimd` grows the limit by one for every success while the limit is in
+//!   use, and backs off multiplicatively on failures.
+//! * `gradient` compares the latency of every success with its long term
+//!   average. The limit shrinks as latency grows past `tolerance` times the
+//!   average and grows while it stays below. Failures back off as with
+//!   `aimd`.
+//!
+//! Latencies are taken from the `time` metadata (in milliseconds) of
+//! insights where offramps provide it, otherwise from the time between
+//! sending an event and receiving its insight.
+//!
+//! Every insight settles the single event it is for. Events without an
+//! outcome after `timeout` count as failed, this includes all but the last
+//! event of a batch formed behind the limiter, as ids of batched events are
+//! merged.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Events exceeding the limit are queued, up to `queue` of them, and
+//! released with the next event or tick once there is room. Events that
+//! don't fit in the queue are routed to `overflow`.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: limit
+//!   op: qos::concurrency
+//!   config:
+//!     algorithm: gradient
+//!     max_limit: 200
+//!     queue: 50
+//! ```
+
+use crate::errors::{ErrorKind, Result};
+use crate::op::prelude::*;
+use crate::{influx_value, SignalKind};
+use std::collections::{BTreeMap, VecDeque};
+use tremor_script::prelude::*;
+
+const OVERFLOW: Cow<'static, str> = Cow::Borrowed("overflow");
+
+/// Number of samples the long term latency average spans
+const LONG_WINDOW: f64 = 100.0;
+
+/// How the limit reacts to outcomes
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// Additive increase, multiplicative decrease
+    Aimd,
+    /// Latency gradient
+    Gradient,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Self::Gradient
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// `aimd` or `gradient` (default: `gradient`)
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Limit to start with (default: 20)
+    #[serde(default = "d_initial_limit")]
+    pub initial_limit: usize,
+    /// Lowest the limit can go (default: 1)
+    #[serde(default = "d_min_limit")]
+    pub min_limit: usize,
+    /// Highest the limit can go (default: 1000)
+    #[serde(default = "d_max_limit")]
+    pub max_limit: usize,
+    /// Factor the limit is multiplied with on failures, as a float between
+    /// `0.0` and `1.0` (default: `0.9`)
+    #[serde(default = "d_backoff")]
+    pub backoff: f64,
+    /// How many times the long term average latency is tolerated before the
+    /// `gradient` limit shrinks (default: `1.5`)
+    #[serde(default = "d_tolerance")]
+    pub tolerance: f64,
+    /// How fast the `gradient` limit moves towards its target, as a float
+    /// between `0.0` and `1.0` (default: `0.2`)
+    #[serde(default = "d_smoothing")]
+    pub smoothing: f64,
+    /// Number of events queued while the limit is reached (default: 0)
+    #[serde(default)]
+    pub queue: usize,
+    /// Time in milliseconds after which events without an outcome count as
+    /// failed (default: 10000)
+    #[serde(default = "d_timeout")]
+    pub timeout: u64,
+}
+
+impl ConfigImpl for Config {}
+
+fn d_initial_limit() -> usize {
+    20
+}
+
+fn d_min_limit() -> usize {
+    1
+}
+
+fn d_max_limit() -> usize {
+    1000
+}
+
+fn d_backoff() -> f64 {
+    0.9
+}
+
+fn d_tolerance() -> f64 {
+    1.5
+}
+
+fn d_smoothing() -> f64 {
+    0.2
+}
+
+fn d_timeout() -> u64 {
+    10_000
+}
+
+#[derive(Debug)]
+pub struct Concurrency {
+    pub config: Config,
+    limit: f64,
+    next_id: u64,
+    /// send time of the events in flight by id
+    inflight: BTreeMap<u64, u64>,
+    queue: VecDeque<Event>,
+    /// long term average latency in milliseconds
+    long_rtt: Option<f64>,
+    passed: u64,
+    queued: u64,
+    shed: u64,
+}
+
+op!(ConcurrencyFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        check(&config)?;
+        Ok(Box::new(Concurrency::from(config)))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+/// Checks the limits satisfy `0 < min_limit <= initial_limit <= max_limit`,
+/// `backoff` and `smoothing` are between `0.0` and `1.0` and `tolerance` is
+/// positive
+fn check(config: &Config) -> Result<()> {
+    let message = if config.max_limit == 0 {
+        "max_limit must be greater than 0"
+    } else if config.min_limit == 0 {
+        "min_limit must be greater than 0"
+    } else if config.min_limit > config.max_limit {
+        "min_limit must not be greater than max_limit"
+    } else if config.initial_limit < config.min_limit || config.initial_limit > config.max_limit {
+        "initial_limit must be between min_limit and max_limit"
+    } else if !(0.0..=1.0).contains(&config.backoff) {
+        "backoff must be between 0.0 and 1.0"
+    } else if !(0.0..=1.0).contains(&config.smoothing) {
+        "smoothing must be between 0.0 and 1.0"
+    } else if config.tolerance.is_nan() || config.tolerance <= 0.0 {
+        "tolerance must be greater than 0.0"
+    } else {
+        return Ok(());
+    };
+    Err(ErrorKind::BadOpConfig(message.to_string()).into())
+}
+
+impl From<Config> for Concurrency {
+    fn from(config: Config) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let limit = config
+            .initial_limit
+            .max(config.min_limit)
+            .min(config.max_limit) as f64;
+        Self {
+            config,
+            limit,
+            next_id: 0,
+            inflight: BTreeMap::new(),
+            queue: VecDeque::new(),
+            long_rtt: None,
+            passed: 0,
+            queued: 0,
+            shed: 0,
+        }
+    }
+}
+
+impl Concurrency {
+    /// The current limit on events in flight
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn limit(&self) -> usize {
+        self.limit as usize
+    }
+
+    fn clamp(&self, limit: f64) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let (min, max) = (self.config.min_limit as f64, self.config.max_limit as f64);
+        limit.max(min).min(max)
+    }
+
+    /// Sends an event on and starts tracking it
+    fn send(&mut self, uid: u64, mut event: Event, now: u64) -> (Cow<'static, str>, Event) {
+        let id = self.next_id;
+        self.next_id += 1;
+        event.id.add_id(uid, id);
+        event.transactional = true;
+        self.inflight.insert(id, now);
+        self.passed += 1;
+        (OUT, event)
+    }
+
+    /// Sends on queued events as long as there is room
+    fn release(&mut self, uid: u64, now: u64, events: &mut Vec<(Cow<'static, str>, Event)>) {
+        while self.inflight.len() < self.limit() {
+            if let Some(event) = self.queue.pop_front() {
+                events.push(self.send(uid, event, now));
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn on_failure(&mut self) {
+        self.limit = self.clamp(self.limit * self.config.backoff);
+    }
+
+    /// Adapts the limit to a success, `in_use` is the number of events that
+    /// were in flight and `rtt` the latency in milliseconds if known
+    #[allow(clippy::cast_precision_loss)]
+    fn on_success(&mut self, in_use: usize, rtt: Option<f64>) {
+        let saturated = in_use as f64 * 2.0 >= self.limit;
+        match self.config.algorithm {
+            Algorithm::Aimd => {
+                if saturated {
+                    self.limit = self.clamp(self.limit + 1.0);
+                }
+            }
+            Algorithm::Gradient => {
+                let rtt = if let Some(rtt) = rtt {
+                    rtt
+                } else {
+                    return;
+                };
+                let long_rtt = self
+                    .long_rtt
+                    .map_or(rtt, |long_rtt| long_rtt + (rtt - long_rtt) / LONG_WINDOW);
+                self.long_rtt = Some(long_rtt);
+                let tolerated = self.config.tolerance * long_rtt;
+                let gradient = if rtt <= tolerated {
+                    1.0
+                } else {
+                    (tolerated / rtt).max(0.5)
+                };
+                // don't grow a limit that isn't used
+                if gradient < 1.0 || saturated {
+                    let target = self.limit * gradient + self.limit.sqrt();
+                    let smoothing = self.config.smoothing;
+                    self.limit = self.clamp(self.limit * (1.0 - smoothing) + target * smoothing);
+                }
+            }
+        }
+    }
+}
+
+impl Operator for Concurrency {
+    fn on_event(
+        &mut self,
+        uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let now = event.ingest_ns;
+        let mut events = Vec::new();
+        self.release(uid, now, &mut events);
+        if self.queue.is_empty() && self.inflight.len() < self.limit() {
+            events.push(self.send(uid, event, now));
+        } else if self.queue.len() < self.config.queue {
+            self.queue.push_back(event);
+            self.queued += 1;
+        } else {
+            self.shed += 1;
+            events.push((OVERFLOW, event));
+        }
+        Ok(events.into())
+    }
+
+    fn handles_contraflow(&self) -> bool {
+        true
+    }
+
+    fn on_contraflow(&mut self, uid: u64, insight: &mut Event) {
+        let id = if let Some(id) = insight.id.get(uid) {
+            id
+        } else {
+            // This is not for us
+            return;
+        };
+        if insight.cb != CBAction::Ack && insight.cb != CBAction::Fail {
+            return;
+        }
+        let in_use = self.inflight.len();
+        let sent_ns = if let Some(sent_ns) = self.inflight.remove(&id) {
+            sent_ns
+        } else {
+            // the outcome was already handled
+            return;
+        };
+        if insight.cb == CBAction::Fail {
+            self.on_failure();
+        } else {
+            let ingest_ns = insight.ingest_ns;
+            let (_, meta) = insight.data.parts();
+            #[allow(clippy::cast_precision_loss)]
+            let rtt = meta.get("time").and_then(Value::cast_f64).or_else(|| {
+                ingest_ns
+                    .checked_sub(sent_ns)
+                    .map(|ns| ns as f64 / 1_000_000.0)
+            });
+            self.on_success(in_use, rtt);
+        }
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(&mut self, uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        if signal.kind != Some(SignalKind::Tick) {
+            return Ok(EventAndInsights::default());
+        }
+        let now = signal.ingest_ns;
+        let timeout_ns = self.config.timeout.saturating_mul(1_000_000);
+        let expired: Vec<u64> = self
+            .inflight
+            .iter()
+            .filter(|(_, sent_ns)| now.saturating_sub(**sent_ns) >= timeout_ns)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.inflight.remove(id);
+        }
+        if !expired.is_empty() {
+            self.on_failure();
+        }
+        let mut events = Vec::new();
+        self.release(uid, now, &mut events);
+        Ok(events.into())
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const CONCURRENCY: Cow<'static, str> = Cow::Borrowed("concurrency");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const PASS: Cow<'static, str> = Cow::Borrowed("pass");
+        const QUEUE: Cow<'static, str> = Cow::Borrowed("queue");
+        const SHED: Cow<'static, str> = Cow::Borrowed("shed");
+
+        tags.insert(ACTION, PASS.into());
+        let passed = influx_value(CONCURRENCY, tags.clone(), self.passed, timestamp);
+        tags.insert(ACTION, QUEUE.into());
+        let queued = influx_value(CONCURRENCY, tags.clone(), self.queued, timestamp);
+        tags.insert(ACTION, SHED.into());
+        let shed = influx_value(CONCURRENCY, tags, self.shed, timestamp);
+        Ok(vec![passed, queued, shed])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Ids;
+
+    fn config(algorithm: Algorithm) -> Config {
+        Config {
+            algorithm,
+            initial_limit: 2,
+            min_limit: 1,
+            max_limit: 10,
+            backoff: 0.5,
+            tolerance: 1.5,
+            smoothing: 1.0,
+            queue: 1,
+            timeout: 10,
+        }
+    }
+
+    fn send(op: &mut Concurrency, ingest_ns: u64) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let mut state = Value::null();
+        let event = Event {
+            ingest_ns,
+            ..Event::default()
+        };
+        Ok(op.on_event(0, "in", &mut state, event)?.events)
+    }
+
+    fn outcome(op: &mut Concurrency, id: u64, cb: CBAction, time: Option<f64>) {
+        let mut meta = Value::object();
+        if let Some(time) = time {
+            meta.insert("time", time).expect("meta is an object");
+        }
+        let mut insight = Event {
+            id: Ids::new(0, id),
+            cb,
+            data: (Value::null(), meta).into(),
+            ..Event::default()
+        };
+        op.on_contraflow(0, &mut insight);
+        assert_eq!(insight.cb, cb);
+    }
+
+    fn tick(op: &mut Concurrency, ingest_ns: u64) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let mut signal = Event {
+            ingest_ns,
+            kind: Some(SignalKind::Tick),
+            ..Event::default()
+        };
+        Ok(op.on_signal(0, &mut signal)?.events)
+    }
+
+    #[test]
+    fn aimd() -> Result<()> {
+        let mut op = Concurrency::from(config(Algorithm::Aimd));
+
+        let r = send(&mut op, 1)?;
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].0, "out");
+        assert!(r[0].1.transactional);
+        assert_eq!(r[0].1.id.get(0), Some(0));
+        assert_eq!(send(&mut op, 1)?.len(), 1);
+        // the limit is reached, the next event is queued
+        assert!(send(&mut op, 1)?.is_empty());
+        // and the one after is shed
+        let r = send(&mut op, 1)?;
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].0, "overflow");
+
+        // a success grows the limit while it is in use
+        outcome(&mut op, 0, CBAction::Ack, None);
+        assert_eq!(op.limit(), 3);
+        // an outcome is only counted once
+        outcome(&mut op, 0, CBAction::Ack, None);
+        assert_eq!(op.limit(), 3);
+
+        // the queued event is released first
+        let r = send(&mut op, 2)?;
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].1.ingest_ns, 1);
+        assert_eq!(r[1].1.ingest_ns, 2);
+        assert_eq!(op.inflight.len(), 3);
+
+        // an outcome only settles its own event
+        outcome(&mut op, 2, CBAction::Fail, None);
+        assert_eq!(op.limit(), 1);
+        assert_eq!(op.inflight.len(), 2);
+        assert!(op.inflight.contains_key(&1));
+        assert!(send(&mut op, 3)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn gradient() -> Result<()> {
+        let mut op = Concurrency::from(Config {
+            initial_limit: 8,
+            max_limit: 20,
+            ..config(Algorithm::Gradient)
+        });
+
+        // latencies around the average grow a limit in use
+        for _ in 0..8 {
+            assert_eq!(send(&mut op, 1)?.len(), 1);
+        }
+        outcome(&mut op, 7, CBAction::Ack, Some(10.0));
+        assert_eq!(op.limit(), 10);
+        for id in 0..7 {
+            outcome(&mut op, id, CBAction::Ack, None);
+        }
+        assert!(op.inflight.is_empty());
+        // but not one that isn't
+        send(&mut op, 1)?;
+        outcome(&mut op, 8, CBAction::Ack, Some(12.0));
+        assert_eq!(op.limit(), 10);
+
+        // a latency spike shrinks it
+        for _ in 0..3 {
+            send(&mut op, 2)?;
+        }
+        outcome(&mut op, 9, CBAction::Ack, Some(100.0));
+        assert_eq!(op.limit(), 8);
+
+        // so does a timeout
+        send(&mut op, 3)?;
+        assert!(tick(&mut op, 3 + 5_000_000)?.is_empty());
+        assert_eq!(op.inflight.len(), 3);
+        assert!(tick(&mut op, 3 + 10_000_000)?.is_empty());
+        assert!(op.inflight.is_empty());
+        assert_eq!(op.limit(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn timeout() -> Result<()> {
+        let mut op = Concurrency::from(config(Algorithm::Aimd));
+        send(&mut op, 5_000_000)?;
+        send(&mut op, 1)?;
+        // only the event sent first times out, regardless of its id
+        assert!(tick(&mut op, 10_000_001)?.is_empty());
+        assert_eq!(op.inflight.keys().collect::<Vec<_>>(), vec![&0]);
+        assert_eq!(op.limit(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn bad_config() {
+        let bad = |f: fn(&mut Config)| {
+            let mut config = config(Algorithm::Aimd);
+            f(&mut config);
+            check(&config).is_err()
+        };
+        assert!(!bad(|_| ()));
+        assert!(bad(|c| c.max_limit = 0));
+        assert!(bad(|c| c.min_limit = 0));
+        assert!(bad(|c| c.min_limit = 11));
+        assert!(bad(|c| c.initial_limit = 0));
+        assert!(bad(|c| c.initial_limit = 11));
+        assert!(bad(|c| c.backoff = -0.1));
+        assert!(bad(|c| c.backoff = 1.1));
+        assert!(bad(|c| c.backoff = f64::NAN));
+        assert!(bad(|c| c.smoothing = -0.1));
+        assert!(bad(|c| c.smoothing = 1.1));
+        assert!(bad(|c| c.tolerance = 0.0));
+        assert!(bad(|c| c.tolerance = -1.0));
+        assert!(bad(|c| c.tolerance = f64::NAN));
+        assert!(!bad(|c| c.backoff = 0.0));
+        assert!(!bad(|c| c.smoothing = 1.0));
+    }
+
+    #[test]
+    fn release_on_tick() -> Result<()> {
+        let mut op = Concurrency::from(config(Algorithm::Aimd));
+        send(&mut op, 1)?;
+        send(&mut op, 1)?;
+        assert!(send(&mut op, 2)?.is_empty());
+        outcome(&mut op, 1, CBAction::Ack, None);
+        let r = tick(&mut op, 3)?;
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].1.ingest_ns, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,539 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Dead letter queue
+//!
+//! Tracks the events passing through until they are acknowledged or failed
+//! by the offramps behind it. Failed events are retried with an exponential
+//! backoff, once they run out of retries they are dead lettered and
+//! acknowledged upstream.
+//!
+//! Dead letters are persisted in a local store if a `dir` is configured,
+//! they can be listed, inspected and re-injected with `tremor api dlq` or
+//! the `/dlq` API. Stores are addressed by operator id, so ids of `qos::dlq`
+//! operators should be unique across pipelines. Without a `dir` dead
+//! letters are emitted on the `dead` port on the next tick, or on shutdown,
+//! with the reason of the last failure and the number of attempts in their
+//! `$dlq` metadata.
+//!
+//! Dead letters are new events: they aren't transactional and get an id of
+//! their own, as the upstream is done with the original event.
+//!
+//! Events waiting for an outcome or a retry are kept in memory.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: dead-letters
+//!   op: qos::dlq
+//!   config:
+//!     retries: 5
+//!     backoff: 500
+//!     dir: /var/lib/tremor/dlq
+//! ```
+
+use crate::dlq::{self, DeadLetter, Store};
+use crate::errors::{ErrorKind, Result};
+use crate::op::prelude::*;
+use crate::{influx_value, Ids, SignalKind};
+use std::collections::BTreeMap;
+use std::mem;
+use tremor_script::prelude::*;
+
+const DEAD: Cow<'static, str> = Cow::Borrowed("dead");
+
+/// Maximum number of re-injected events picked up per tick
+const REINJECT_BATCH: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Number of times a failed event is retried before it is dead lettered
+    /// (default: 3)
+    #[serde(default = "d_retries")]
+    pub retries: u64,
+    /// Time in milliseconds to wait before the first retry, doubled with
+    /// every following retry (default: 1000)
+    #[serde(default = "d_backoff")]
+    pub backoff: u64,
+    /// Time in milliseconds after which events that were neither
+    /// acknowledged nor failed are forgotten (default: 60000)
+    #[serde(default = "d_timeout")]
+    pub timeout: u64,
+    /// Directory to persist dead letters in, if unset they are emitted on
+    /// the `dead` port
+    pub dir: Option<String>,
+}
+
+impl ConfigImpl for Config {}
+
+fn d_retries() -> u64 {
+    3
+}
+
+fn d_backoff() -> u64 {
+    1000
+}
+
+fn d_timeout() -> u64 {
+    60_000
+}
+
+#[derive(Debug)]
+struct Inflight {
+    event: Event,
+    attempts: u64,
+    sent_ns: u64,
+    /// when the event is due to be retried, if it failed
+    retry_at: Option<u64>,
+    reason: String,
+}
+
+#[derive(Debug)]
+pub struct Dlq {
+    id: String,
+    pub config: Config,
+    store: Option<Store>,
+    /// registration of the store, to unregister it once dropped
+    registration: Option<u64>,
+    next_id: u64,
+    inflight: BTreeMap<u64, Inflight>,
+    /// dead letters waiting to be emitted on the `dead` port
+    dead: Vec<DeadLetter>,
+    retried: u64,
+    dead_lettered: u64,
+}
+
+op!(DlqFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Dlq::new(node.id.to_string(), config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Dlq {
+    fn new(id: String, config: Config) -> Result<Self> {
+        let (store, registration) = if let Some(dir) = &config.dir {
+            let (store, registration) = dlq::register(&id, dir)?;
+            (Some(store), Some(registration))
+        } else {
+            (None, None)
+        };
+        Ok(Self {
+            id,
+            config,
+            store,
+            registration,
+            next_id: 0,
+            inflight: BTreeMap::new(),
+            dead: Vec::new(),
+            retried: 0,
+            dead_lettered: 0,
+        })
+    }
+
+    /// Starts tracking an event before it is sent on
+    fn track(&mut self, uid: u64, mut event: Event, now: u64) -> Event {
+        let id = self.next_id;
+        self.next_id += 1;
+        event.id.add_id(uid, id);
+        event.transactional = true;
+        self.inflight.insert(
+            id,
+            Inflight {
+                event: event.clone(),
+                attempts: 1,
+                sent_ns: now,
+                retry_at: None,
+                reason: String::new(),
+            },
+        );
+        event
+    }
+
+    /// Handles a failed event, returns if the failure was taken care of
+    fn fail(&mut self, uid: u64, id: u64, reason: &str, now: u64) -> bool {
+        let retries = self.config.retries;
+        let backoff_ns = self.config.backoff.saturating_mul(1_000_000);
+        let entry = if let Some(entry) = self.inflight.get_mut(&id) {
+            entry
+        } else {
+            return false;
+        };
+        entry.reason = reason.to_string();
+        if entry.attempts <= retries {
+            let factor = 1_u64 << (entry.attempts - 1).min(32);
+            entry.retry_at = Some(now.saturating_add(backoff_ns.saturating_mul(factor)));
+            return true;
+        }
+        if let Some(entry) = self.inflight.remove(&id) {
+            let mut event = entry.event;
+            // nothing upstream waits for the dead letter any more
+            event.transactional = false;
+            event.id = Ids::new(uid, self.next_id);
+            self.next_id += 1;
+            let letter = DeadLetter {
+                event,
+                reason: entry.reason,
+                attempts: entry.attempts,
+                failed_ns: now,
+            };
+            if let Some(store) = &self.store {
+                if let Err(e) = store.insert(&letter) {
+                    // let it fail upstream so the event isn't lost
+                    error!("[DLQ::{}] Failed to persist dead letter: {}", self.id, e);
+                    return false;
+                }
+            } else {
+                self.dead.push(letter);
+            }
+            self.dead_lettered += 1;
+        }
+        true
+    }
+
+    /// Retries failed events that are due, forgets timed out ones and picks
+    /// up re-injected events
+    fn tick(
+        &mut self,
+        uid: u64,
+        now: u64,
+        events: &mut Vec<(Cow<'static, str>, Event)>,
+    ) -> Result<()> {
+        let timeout_ns = self.config.timeout.saturating_mul(1_000_000);
+        let mut expired = Vec::new();
+        for (id, entry) in &mut self.inflight {
+            match entry.retry_at {
+                Some(at) if at <= now => {
+                    entry.retry_at = None;
+                    entry.attempts += 1;
+                    entry.sent_ns = now;
+                    self.retried += 1;
+                    events.push((OUT, entry.event.clone()));
+                }
+                None if now.saturating_sub(entry.sent_ns) >= timeout_ns => expired.push(*id),
+                _ => (),
+            }
+        }
+        for id in expired {
+            self.inflight.remove(&id);
+        }
+
+        self.emit_dead(events);
+
+        if let Some(store) = self.store.clone() {
+            for mut event in store.take_reinjected(REINJECT_BATCH)? {
+                // the upstream was acknowledged when the event was dead
+                // lettered, so only we track it from here on
+                event.id = Ids::default();
+                events.push((OUT, self.track(uid, event, now)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits the dead letters waiting on the `dead` port
+    fn emit_dead(&mut self, events: &mut Vec<(Cow<'static, str>, Event)>) {
+        for letter in mem::take(&mut self.dead) {
+            let mut event = letter.event;
+            let (_, meta) = event.data.parts();
+            if let Some(meta) = meta.as_object_mut() {
+                let mut dlq = Value::object_with_capacity(2);
+                if let Some(dlq) = dlq.as_object_mut() {
+                    dlq.insert("reason".into(), Value::from(letter.reason));
+                    dlq.insert("attempts".into(), Value::from(letter.attempts));
+                }
+                meta.insert("dlq".into(), dlq);
+            }
+            events.push((DEAD, event));
+        }
+    }
+}
+
+impl Drop for Dlq {
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration {
+            if let Err(e) = dlq::unregister(&self.id, registration) {
+                error!("[DLQ::{}] Failed to unregister store: {}", self.id, e);
+            }
+        }
+    }
+}
+
+impl Operator for Dlq {
+    fn on_event(
+        &mut self,
+        uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let now = event.ingest_ns;
+        Ok(self.track(uid, event, now).into())
+    }
+
+    fn handles_contraflow(&self) -> bool {
+        true
+    }
+
+    fn on_contraflow(&mut self, uid: u64, insight: &mut Event) {
+        let id = if let Some(id) = insight.id.get(uid) {
+            id
+        } else {
+            // This is not for us
+            return;
+        };
+        match insight.cb {
+            CBAction::Ack => {
+                self.inflight.remove(&id);
+            }
+            CBAction::Fail => {
+                let reason = insight.reason().unwrap_or("unknown").to_string();
+                if self.fail(uid, id, &reason, insight.ingest_ns) {
+                    // a retry is pending or the event is dead lettered, either
+                    // way the upstream is done with it
+                    let retrying = self.inflight.contains_key(&id);
+                    insight.cb = if retrying {
+                        CBAction::None
+                    } else {
+                        CBAction::Ack
+                    };
+                }
+            }
+            CBAction::None | CBAction::Open | CBAction::Close => (),
+        }
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(&mut self, uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        let mut events = Vec::new();
+        match signal.kind {
+            Some(SignalKind::Tick) => self.tick(uid, signal.ingest_ns, &mut events)?,
+            // dead letters are already acknowledged upstream, flush them so
+            // they aren't lost
+            Some(SignalKind::Shutdown) => self.emit_dead(&mut events),
+            _ => (),
+        }
+        Ok(EventAndInsights {
+            events,
+            ..EventAndInsights::default()
+        })
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        const DLQ: Cow<'static, str> = Cow::Borrowed("dlq");
+        const ACTION: Cow<'static, str> = Cow::Borrowed("action");
+        const RETRY: Cow<'static, str> = Cow::Borrowed("retry");
+        const DEAD: Cow<'static, str> = Cow::Borrowed("dead");
+
+        tags.insert(ACTION, RETRY.into());
+        let retried = influx_value(DLQ, tags.clone(), self.retried, timestamp);
+        tags.insert(ACTION, DEAD.into());
+        let dead = influx_value(DLQ, tags, self.dead_lettered, timestamp);
+        Ok(vec![retried, dead])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            retries: 1,
+            backoff: 1,
+            timeout: 10,
+            dir: None,
+        }
+    }
+
+    fn send(op: &mut Dlq, ingest_ns: u64) -> Result<Event> {
+        let mut state = Value::null();
+        let event = Event {
+            id: Ids::new(42, ingest_ns),
+            ingest_ns,
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?.events;
+        let (_, event) = r.pop().ok_or_else(|| Error::from("no event"))?;
+        Ok(event)
+    }
+
+    fn tick(op: &mut Dlq, ingest_ns: u64) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let mut signal = Event {
+            ingest_ns,
+            kind: Some(SignalKind::Tick),
+            ..Event::default()
+        };
+        Ok(op.on_signal(0, &mut signal)?.events)
+    }
+
+    fn fail(op: &mut Dlq, event: &Event, ingest_ns: u64) -> Event {
+        let mut insight = Event::cb_fail(ingest_ns, event.id.clone()).with_reason("snot");
+        op.on_contraflow(0, &mut insight);
+        insight
+    }
+
+    #[test]
+    fn retry_and_dead_letter() -> Result<()> {
+        let mut op = Dlq::new("test".to_string(), config())?;
+        let event = send(&mut op, 1)?;
+        assert!(event.transactional);
+
+        // the first failure is retried after the backoff
+        assert_eq!(fail(&mut op, &event, 10).cb, CBAction::None);
+        assert!(tick(&mut op, 1_000_009)?.is_empty());
+        let mut retries = tick(&mut op, 1_000_010)?;
+        let (port, retry) = retries.pop().ok_or_else(|| Error::from("no retry"))?;
+        assert_eq!(port, "out");
+        assert_eq!(retry.id, event.id);
+
+        // the second one is dead lettered and acknowledged upstream
+        assert_eq!(fail(&mut op, &retry, 1_000_020).cb, CBAction::Ack);
+        let mut dead = tick(&mut op, 1_000_030)?;
+        let (port, dead) = dead.pop().ok_or_else(|| Error::from("no dead letter"))?;
+        assert_eq!(port, "dead");
+        assert_eq!(dead.data.suffix().meta()["dlq"]["reason"], "snot");
+        assert_eq!(dead.data.suffix().meta()["dlq"]["attempts"], 2);
+        assert!(!dead.transactional);
+        assert_ne!(dead.id, event.id);
+        assert_eq!(dead.id.get(42), None);
+        assert!(op.inflight.is_empty());
+
+        let m = op.metrics(HashMap::new(), 0)?;
+        assert_eq!(m[0]["fields"]["count"], 1);
+        assert_eq!(m[1]["fields"]["count"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn huge_backoff() -> Result<()> {
+        let mut op = Dlq::new(
+            "test".to_string(),
+            Config {
+                backoff: u64::MAX,
+                ..config()
+            },
+        )?;
+        let event = send(&mut op, 1)?;
+        assert_eq!(fail(&mut op, &event, 10).cb, CBAction::None);
+        let retry_at = op.inflight.values().next().and_then(|e| e.retry_at);
+        assert_eq!(retry_at, Some(u64::MAX));
+        Ok(())
+    }
+
+    #[test]
+    fn ack_and_timeout() -> Result<()> {
+        let mut op = Dlq::new("test".to_string(), config())?;
+        let acked = send(&mut op, 1)?;
+        send(&mut op, 2)?;
+        let mut insight = Event::cb_ack(3, acked.id.clone());
+        op.on_contraflow(0, &mut insight);
+        assert_eq!(insight.cb, CBAction::Ack);
+        assert_eq!(op.inflight.len(), 1);
+        // nothing heard back in time
+        tick(&mut op, 10_000_002)?;
+        assert!(op.inflight.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn store() -> Result<()> {
+        let dir = tempfile::Builder::new().tempdir()?;
+        let mut op = Dlq::new(
+            "dlq-store-test".to_string(),
+            Config {
+                retries: 0,
+                dir: Some(dir.path().to_string_lossy().to_string()),
+                ..config()
+            },
+        )?;
+        let event = send(&mut op, 1)?;
+        assert_eq!(fail(&mut op, &event, 2).cb, CBAction::Ack);
+        assert!(tick(&mut op, 3)?.is_empty());
+
+        let store = dlq::store("dlq-store-test")?.ok_or_else(|| Error::from("no store"))?;
+        let letters = store.list()?;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].1.reason, "snot");
+        assert!(store.reinject(letters[0].0)?);
+
+        let mut events = tick(&mut op, 4)?;
+        let (port, event) = events.pop().ok_or_else(|| Error::from("no event"))?;
+        assert_eq!(port, "out");
+        assert_eq!(event.id.get(42), None);
+        assert_eq!(op.inflight.len(), 1);
+
+        drop(op);
+        assert!(dlq::store("dlq-store-test")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn flush_on_shutdown() -> Result<()> {
+        let mut op = Dlq::new(
+            "test".to_string(),
+            Config {
+                retries: 0,
+                ..config()
+            },
+        )?;
+        let event = send(&mut op, 1)?;
+        assert_eq!(fail(&mut op, &event, 2).cb, CBAction::Ack);
+        let mut signal = Event {
+            ingest_ns: 3,
+            kind: Some(SignalKind::Shutdown),
+            ..Event::default()
+        };
+        let mut events = op.on_signal(0, &mut signal)?.events;
+        let (port, _) = events.pop().ok_or_else(|| Error::from("no dead letter"))?;
+        assert_eq!(port, "dead");
+        Ok(())
+    }
+
+    #[test]
+    fn swap() -> Result<()> {
+        let dir = tempfile::Builder::new().tempdir()?;
+        let config = Config {
+            retries: 0,
+            dir: Some(dir.path().to_string_lossy().to_string()),
+            ..config()
+        };
+        let mut old = Dlq::new("dlq-swap-test".to_string(), config.clone())?;
+        // the replacing instance is created while the old one is running
+        let new = Dlq::new("dlq-swap-test".to_string(), config)?;
+        let event = send(&mut old, 1)?;
+        assert_eq!(fail(&mut old, &event, 2).cb, CBAction::Ack);
+
+        drop(old);
+        let store = dlq::store("dlq-swap-test")?.ok_or_else(|| Error::from("no store"))?;
+        assert_eq!(store.len(), 1);
+        drop(new);
+        assert!(dlq::store("dlq-swap-test")?.is_none());
+        Ok(())
+    }
+}
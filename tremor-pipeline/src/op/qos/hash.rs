@@ -0,0 +1,267 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Consistent hash partitioning
+//!
+//! Routes events over a list of outputs by a key, using the same jump
+//! consistent hash as `chash::jump`, so all events of a key end up on the
+//! same output.
+//!
+//! When an output's circuit breaker is triggered its keys spill over to the
+//! next healthy output, they return once it is restored.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Events are routed to `overflow` if no output is healthy, and to `err` if
+//! their key can't be evaluated.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: partition
+//!   op: qos::hash
+//!   config:
+//!     key: event.customer
+//!     outputs: [out, out2, out3]
+//! ```
+
+use crate::errors::{ErrorKind, Result};
+use crate::op::generic::expr::Expr;
+use crate::op::prelude::*;
+use tremor_script::jump_hash;
+use tremor_script::prelude::*;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// tremor-script expression the key of an event is evaluated with
+    pub key: String,
+    /// List of outputs to partition over
+    #[serde(default = "d_outputs")]
+    pub outputs: Vec<String>,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Debug, Clone)]
+pub struct Output {
+    open: bool,
+    output: String,
+}
+
+impl From<String> for Output {
+    fn from(output: String) -> Self {
+        Self { output, open: true }
+    }
+}
+
+fn d_outputs() -> Vec<String> {
+    vec![String::from("out")]
+}
+
+pub struct Hash {
+    pub config: Config,
+    pub outputs: Vec<Output>,
+    key: Expr,
+    first: bool,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Hash({})", self.config.key)
+    }
+}
+
+impl Hash {
+    fn new(config: Config) -> Result<Self> {
+        let key = Expr::new("<hash>", &config.key)?;
+        let outputs = config.outputs.iter().cloned().map(Output::from).collect();
+        Ok(Self {
+            config,
+            outputs,
+            key,
+            first: true,
+        })
+    }
+}
+
+op!(HashFactory(node) {
+if let Some(map) = &node.config {
+    let config: Config = Config::new(map)?;
+    if config.outputs.is_empty() {
+        error!("No outputs supplied for hash operators");
+        return Err(ErrorKind::MissingOpConfig(node.id.to_string()).into());
+    };
+    Ok(Box::new(Hash::new(config)?))
+} else {
+    Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+
+}});
+
+impl Operator for Hash {
+    fn on_event(
+        &mut self,
+        uid: u64,
+        _port: &str,
+        state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let key = if let Some(key) = self.key.key(&mut event, state) {
+            key
+        } else {
+            return Ok(vec![(ERR, event)].into());
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        let slots = self.outputs.len() as u32;
+        let slot = jump_hash(&key, slots) as usize;
+        // spill over to the next healthy output
+        let output = (0..self.outputs.len())
+            .map(|n| (slot + n) % self.outputs.len())
+            .find(|id| self.outputs[*id].open);
+        if let Some(oid) = output {
+            event.op_meta.insert(uid, oid);
+            let out = self.outputs[oid].output.clone();
+            Ok(vec![(out.into(), event)].into())
+        } else {
+            Ok(vec![("overflow".into(), event)].into())
+        }
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+    fn on_signal(&mut self, _uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        if self.first && self.outputs.iter().any(|o| o.open) {
+            let mut e = Event::cb_restore(signal.ingest_ns);
+            e.origin_uri = None;
+            self.first = false;
+
+            Ok(EventAndInsights {
+                insights: vec![e],
+                ..EventAndInsights::default()
+            })
+        } else {
+            Ok(EventAndInsights::default())
+        }
+    }
+    fn handles_contraflow(&self) -> bool {
+        true
+    }
+
+    fn on_contraflow(&mut self, uid: u64, insight: &mut Event) {
+        let outputs = &mut self.outputs;
+
+        let any_were_available = outputs.iter().any(|o| o.open);
+        if let Some(o) = insight
+            .op_meta
+            .get(uid)
+            .and_then(OwnedValue::as_usize)
+            .and_then(|id| outputs.get_mut(id))
+        {
+            if insight.cb == CBAction::Close {
+                o.open = false;
+            } else if insight.cb == CBAction::Open {
+                o.open = true;
+            }
+        }
+        let any_available = outputs.iter().any(|o| o.open);
+
+        if any_available && !any_were_available {
+            insight.cb = CBAction::Open;
+        } else if any_were_available && !any_available {
+            insight.cb = CBAction::Close;
+        } else if insight.cb.is_cb() {
+            insight.cb = CBAction::None;
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn route(op: &mut Hash, key: &str) -> Result<Cow<'static, str>> {
+        let mut state = Value::null();
+        let event = Event {
+            data: Value::from(json!({ "key": key })).into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?.events;
+        let (port, _) = r.pop().ok_or_else(|| Error::from("no event"))?;
+        assert!(r.is_empty());
+        Ok(port)
+    }
+
+    fn trigger(op: &mut Hash, output: usize, cb: CBAction) {
+        let mut op_meta = OpMeta::default();
+        op_meta.insert(0, output);
+        let mut insight = Event {
+            cb,
+            op_meta,
+            ..Event::default()
+        };
+        op.on_contraflow(0, &mut insight);
+    }
+
+    #[test]
+    fn consistent() -> Result<()> {
+        let mut op = Hash::new(Config {
+            key: "event.key".to_string(),
+            outputs: vec!["out".into(), "out2".into(), "out3".into()],
+        })?;
+        let keys: Vec<String> = (0..30).map(|i| format!("key{}", i)).collect();
+        let mut ports = Vec::new();
+        for key in &keys {
+            let port = route(&mut op, key)?;
+            let slot = jump_hash(key, 3) as usize;
+            assert_eq!(port, op.config.outputs[slot]);
+            // the same key always goes to the same output
+            assert_eq!(route(&mut op, key)?, port);
+            ports.push(port);
+        }
+
+        // keys of a broken output spill over to the next one
+        trigger(&mut op, 0, CBAction::Close);
+        for (key, port) in keys.iter().zip(&ports) {
+            let expected: &str = if port == "out" { "out2" } else { port };
+            assert_eq!(route(&mut op, key)?, expected);
+        }
+        // and return once it recovers
+        trigger(&mut op, 0, CBAction::Open);
+        for (key, port) in keys.iter().zip(&ports) {
+            assert_eq!(&route(&mut op, key)?, port);
+        }
+
+        trigger(&mut op, 0, CBAction::Close);
+        trigger(&mut op, 1, CBAction::Close);
+        trigger(&mut op, 2, CBAction::Close);
+        assert_eq!(route(&mut op, "key0")?, "overflow");
+        Ok(())
+    }
+
+    #[test]
+    fn bad_key() -> Result<()> {
+        let mut op = Hash::new(Config {
+            key: "event.snot".to_string(),
+            outputs: vec!["out".into()],
+        })?;
+        assert_eq!(route(&mut op, "key0")?, "err");
+        Ok(())
+    }
+}
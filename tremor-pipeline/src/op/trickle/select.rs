@@ -16,7 +16,7 @@
 
 use crate::errors::{Error, ErrorKind, Result};
 use crate::op::prelude::*;
-use crate::{Event, Operator};
+use crate::{Event, Operator, SignalKind};
 use halfbrown::HashMap;
 use simd_json::borrowed::Value;
 use std::borrow::Cow;
@@ -33,6 +33,7 @@ use tremor_script::{
     },
     prelude::*,
     query::StmtRental,
+    TremorAggrFnWrapper,
 };
 
 pub type Aggrs<'script> = Vec<InvokeAggrFn<'script>>;
@@ -45,6 +46,44 @@ pub struct GroupData<'groups> {
     id: Ids,
 }
 type Groups<'groups> = HashMap<String, GroupData<'groups>>;
+
+impl<'groups> GroupData<'groups> {
+    /// Snapshot of the window progress and the aggregate state of this
+    /// group, fails with the first aggregate that can't be snapshotted
+    fn snapshot(&self) -> std::result::Result<Value<'static>, &TremorAggrFnWrapper> {
+        let aggrs: Vec<Value<'static>> = self
+            .aggrs
+            .iter()
+            .map(|aggr| aggr.invocable.snapshot().ok_or(&aggr.invocable))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(Value::from(hashmap! {
+            "group".into() => self.group.clone(),
+            "window".into() => self.window.snapshot(),
+            "aggrs".into() => Value::from(aggrs),
+        }))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        if let Some(window) = snapshot.get("window") {
+            self.window.restore(window)?;
+        }
+        let aggrs = snapshot
+            .get("aggrs")
+            .and_then(Value::as_array)
+            .filter(|aggrs| aggrs.len() == self.aggrs.len())
+            .ok_or_else(|| Error::from("Snapshot doesn't match the aggregates of the select"))?;
+        for (aggr, snapshot) in self.aggrs.iter_mut().zip(aggrs) {
+            aggr.invocable.restore(snapshot).map_err(|e| {
+                Error::from(format!(
+                    "Failed to restore aggregate {:?}: {:?}",
+                    aggr.invocable, e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+}
+
 rental! {
     pub mod rentals {
         use std::sync::Arc;
@@ -104,6 +143,67 @@ pub struct Window {
 }
 
 impl Window {
+    fn snapshot(&self) -> std::result::Result<Value<'static>, &TremorAggrFnWrapper> {
+        Ok(Value::from(hashmap! {
+            "name".into() => Value::from(self.name.clone()),
+            "next_swap".into() => Value::from(self.next_swap),
+            "groups".into() => Self::snapshot_groups(&self.dims.groups)?,
+            "last_groups".into() => Self::snapshot_groups(&self.last_dims.groups)?,
+        }))
+    }
+
+    fn snapshot_groups(
+        groups: &Groups,
+    ) -> std::result::Result<Value<'static>, &TremorAggrFnWrapper> {
+        let mut snapshot: HashMap<Cow<'static, str>, Value<'static>> =
+            HashMap::with_capacity(groups.len());
+        for (key, group) in groups {
+            snapshot.insert(key.clone().into(), group.snapshot()?);
+        }
+        Ok(Value::from(snapshot))
+    }
+
+    fn restore(&mut self, snapshot: &Value, aggregates: &Aggrs<'static>) -> Result<()> {
+        if snapshot.get("name").and_then(Value::as_str) != Some(self.name.as_str()) {
+            return Err(Error::from(format!(
+                "Snapshot doesn't match window {}",
+                self.name
+            )));
+        }
+        self.dims.groups = self.restore_groups(snapshot.get("groups"), aggregates)?;
+        self.last_dims.groups = self.restore_groups(snapshot.get("last_groups"), aggregates)?;
+        self.next_swap = snapshot
+            .get("next_swap")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        Ok(())
+    }
+
+    fn restore_groups(
+        &self,
+        snapshot: Option<&Value>,
+        aggregates: &Aggrs<'static>,
+    ) -> Result<Groups<'static>> {
+        let snapshot = snapshot
+            .and_then(Value::as_object)
+            .ok_or_else(|| Error::from("Invalid window group snapshot"))?;
+        let mut groups = HashMap::with_capacity(snapshot.len());
+        for (key, group) in snapshot {
+            let mut data = GroupData {
+                window: self.window_impl.clone(),
+                aggrs: aggregates.clone(),
+                group: group
+                    .get("group")
+                    .ok_or_else(|| Error::from("Invalid window group snapshot"))?
+                    .clone_static(),
+                id: Ids::default(),
+            };
+            data.restore(group)?;
+            groups.insert(key.to_string(), data);
+        }
+        Ok(groups)
+    }
+
     pub(crate) fn module_path(fqwn: &str) -> Vec<String> {
         let segments: Vec<String> = fqwn
             .split("::")
@@ -129,6 +229,34 @@ pub enum WindowImpl {
     No(NoWindow),
 }
 
+impl WindowImpl {
+    /// The progress of the window, this doesn't include the configuration
+    /// which is taken from the query on restore
+    fn snapshot(&self) -> Value<'static> {
+        match self {
+            Self::TumblingTimeBased(w) => w.next_window.map_or_else(Value::null, Value::from),
+            Self::TumblingCountBased(w) => Value::from(w.count),
+            Self::No(w) => Value::from(w.open),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let valid = match self {
+            Self::TumblingTimeBased(w) => {
+                w.next_window = snapshot.as_u64();
+                w.next_window.is_some() || snapshot.is_null()
+            }
+            Self::TumblingCountBased(w) => snapshot.as_u64().map(|c| w.count = c).is_some(),
+            Self::No(w) => snapshot.as_bool().map(|o| w.open = o).is_some(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::from("Invalid window snapshot"))
+        }
+    }
+}
+
 impl std::default::Default for WindowImpl {
     fn default() -> Self {
         TumblingWindowOnTime {
@@ -462,6 +590,7 @@ impl Operator for TrickleSelect {
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
                 recursion_limit: tremor_script::recursion_limit(),
+                tracer: None,
             };
             let test = guard.run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
             if let Some(test) = test.as_bool() {
@@ -504,6 +633,7 @@ impl Operator for TrickleSelect {
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
                 recursion_limit: tremor_script::recursion_limit(),
+                tracer: None,
             };
             let value =
                 stmt.target
@@ -615,6 +745,7 @@ impl Operator for TrickleSelect {
                         aggrs: &this_group.aggrs,
                         meta: &node_meta,
                         recursion_limit: tremor_script::recursion_limit(),
+                        tracer: None,
                     };
                     let result = stmt.target.run(
                         opts,
@@ -778,6 +909,7 @@ impl Operator for TrickleSelect {
                     aggrs: &NO_AGGRS,
                     meta: &node_meta,
                     recursion_limit: tremor_script::recursion_limit(),
+                    tracer: None,
                 };
                 for aggr in &mut this_group.aggrs {
                     let invocable = &mut aggr.invocable;
@@ -812,6 +944,7 @@ impl Operator for TrickleSelect {
                     aggrs: &NO_AGGRS,
                     meta: &node_meta,
                     recursion_limit: tremor_script::recursion_limit(),
+                    tracer: None,
                 };
                 let value =
                     stmt.target
@@ -848,6 +981,129 @@ impl Operator for TrickleSelect {
         }
         Ok(events.into())
     }
+
+    fn handles_signal(&self) -> bool {
+        !self.windows.is_empty()
+    }
+
+    /// On shutdown all windows are flushed, the narrower windows are tilted
+    /// into the wider ones and every open group is emitted as if its window
+    /// had closed.
+    #[allow(mutable_transmutes, clippy::transmute_ptr_to_ptr)]
+    fn on_signal(&mut self, _uid: u64, signal: &mut Event) -> Result<EventAndInsights> {
+        if signal.kind != Some(SignalKind::Shutdown) {
+            return Ok(EventAndInsights::default());
+        }
+        let opts = Self::opts();
+        // TODO: reason about soundness
+        let SelectStmt {
+            stmt,
+            consts,
+            locals,
+            node_meta,
+            ..
+        }: &mut SelectStmt = unsafe { mem::transmute(self.select.suffix()) };
+        let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
+        consts[ARGS_CONST_ID] = Value::null();
+        let ctx = EventContext::new(signal.ingest_ns, None);
+
+        let mut events = vec![];
+        let mut narrower: Groups<'static> = HashMap::new();
+        for window in &mut self.windows {
+            window.last_dims.groups.clear();
+            let mut groups = mem::replace(&mut window.dims.groups, HashMap::new());
+            for (group_str, prev_group) in narrower {
+                if let Some(this_group) = groups.get_mut(&group_str) {
+                    for (aggr, prev) in this_group.aggrs.iter_mut().zip(&prev_group.aggrs) {
+                        aggr.invocable.merge(&prev.invocable).map_err(|e| {
+                            let r: Option<&Registry> = None;
+                            e.into_err(prev, prev, r, &node_meta)
+                        })?;
+                    }
+                    this_group.id.merge(&prev_group.id);
+                } else {
+                    groups.insert(group_str, prev_group);
+                }
+            }
+
+            consts[WINDOW_CONST_ID] = Value::from(window.name.to_string());
+            for (group_str, group) in &groups {
+                consts[GROUP_CONST_ID] = group.group.clone();
+                consts[GROUP_CONST_ID].push(group_str.clone()).ok();
+                let env = Env {
+                    context: &ctx,
+                    consts: &consts,
+                    aggrs: &group.aggrs,
+                    meta: &node_meta,
+                    recursion_limit: tremor_script::recursion_limit(),
+                    tracer: None,
+                };
+                let result = stmt
+                    .target
+                    .run(opts, &env, &NULL, &NULL, &NULL, &local_stack)?;
+                if let Some(guard) = &stmt.maybe_having {
+                    let test = guard.run(opts, &env, &result, &NULL, &NULL, &local_stack)?;
+                    if let Some(test) = test.as_bool() {
+                        if !test {
+                            continue;
+                        }
+                    } else {
+                        let s: &Select = &stmt;
+                        return tremor_script::errors::query_guard_not_bool(
+                            s, guard, &test, &node_meta,
+                        )?;
+                    }
+                }
+                events.push((
+                    OUT,
+                    Event {
+                        id: group.id.clone(),
+                        ingest_ns: signal.ingest_ns,
+                        data: (result.into_owned().into_static(), Value::object()).into(),
+                        ..Event::default()
+                    },
+                ));
+            }
+            narrower = groups;
+        }
+        Ok(events.into())
+    }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        if self.windows.is_empty() {
+            return Ok(None);
+        }
+        let mut windows = Vec::with_capacity(self.windows.len());
+        for window in &self.windows {
+            match window.snapshot() {
+                Ok(snapshot) => windows.push(snapshot),
+                Err(aggr) => {
+                    warn!(
+                        "[Select::{}] Not checkpointing the select, aggregate {:?} in window {} doesn't support snapshots",
+                        self.id, aggr, window.name
+                    );
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(Value::from(windows)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let windows = snapshot
+            .as_array()
+            .filter(|windows| windows.len() == self.windows.len())
+            .ok_or_else(|| Error::from("Snapshot doesn't match the windows of the select"))?;
+        // This is sound since the aggregates only live in the groups of this
+        // operator, which also holds the `Arc` to the referenced statement
+        let aggregates = unsafe {
+            mem::transmute::<Aggrs<'_>, Aggrs<'static>>(self.select.suffix().aggregates.clone())
+        };
+        for (window, snapshot) in self.windows.iter_mut().zip(windows) {
+            window.restore(snapshot, &aggregates)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -857,6 +1113,7 @@ mod test {
     use simd_json::borrowed::Value;
     use simd_json::json;
     use tremor_script::ast::{self, Ident, ImutExpr, Literal};
+    use tremor_script::registry::FResult;
 
     fn test_target<'test>() -> ast::ImutExpr<'test> {
         let target: ast::ImutExpr<'test> = ImutExpr::from(ast::Literal {
@@ -1024,6 +1281,57 @@ mod test {
         Ok(())
     }
 
+    #[derive(Clone, Debug)]
+    struct NoSnapshot;
+
+    impl tremor_script::TremorAggrFn for NoSnapshot {
+        fn accumulate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+            Ok(())
+        }
+        fn compensate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+            Ok(())
+        }
+        fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+            Ok(Value::null())
+        }
+        fn init(&mut self) {}
+        fn merge(&mut self, _src: &dyn tremor_script::TremorAggrFn) -> FResult<()> {
+            Ok(())
+        }
+        fn boxed_clone(&self) -> Box<dyn tremor_script::TremorAggrFn> {
+            Box::new(self.clone())
+        }
+        fn arity(&self) -> std::ops::RangeInclusive<usize> {
+            0..=0
+        }
+    }
+
+    #[test]
+    fn snapshot_without_aggregate_support() -> Result<()> {
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::count() from in into out;",
+        )?;
+        assert!(try_enqueue(&mut op, test_event(0))?.is_none());
+        assert!(op.snapshot()?.is_some());
+
+        let groups = &mut op.windows[0].dims.groups;
+        assert!(!groups.is_empty());
+        for group in groups.values_mut() {
+            for aggr in &mut group.aggrs {
+                aggr.invocable = TremorAggrFnWrapper::new(
+                    "test".to_string(),
+                    "no_snapshot".to_string(),
+                    Box::new(NoSnapshot),
+                );
+            }
+        }
+        // a window that can't be snapshotted is never left out of the
+        // checkpoint, the select as a whole isn't checkpointed
+        assert!(op.snapshot()?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn count_tilt() -> Result<()> {
         // Windows are 15s and 30s
@@ -138,6 +138,7 @@ impl Query {
     /// Turn a query into a executable pipeline graph
     #[allow(clippy::too_many_lines)]
     pub fn to_pipe(&self, uid: &mut u64) -> Result<crate::ExecutableGraph> {
+        use crate::checkpoint::{self, source_version};
         use crate::{ExecutableGraph, NodeMetrics, State};
         use std::iter;
 
@@ -156,6 +157,18 @@ impl Query {
             .and_then(Value::as_u64)
             .map(|i| i * 1_000_000_000);
 
+        let checkpoint_interval = query
+            .config
+            .get("checkpoint_interval_s")
+            .and_then(Value::as_u64)
+            .map(|i| i * 1_000_000_000);
+
+        let state_version = query
+            .config
+            .get("state_version")
+            .and_then(Value::as_str)
+            .map_or_else(|| source_version(self.source()), ToString::to_string);
+
         let pipeline_id = query
             .config
             .get("id")
@@ -552,6 +565,8 @@ impl Query {
                 contraflow,
                 signalflow,
                 metric_interval,
+                queue_depth: None,
+                checkpoint: checkpoint::Config::new(state_version, checkpoint_interval),
                 insights: Vec::new(),
                 dot: format!("{}", dot),
             };
@@ -751,4 +766,204 @@ mod test {
         assert_eq!(out.id, "test_out");
         assert_eq!(out.kind, NodeKind::Output);
     }
+
+    #[test]
+    fn checkpoint_restore() -> Result<()> {
+        use crate::checkpoint::Store;
+        use crate::{Event, Ids};
+        let module_path = &tremor_script::path::ModulePath { mounts: Vec::new() };
+        let aggr_reg = tremor_script::aggr_registry();
+        let parse = |src: &str| {
+            Query::parse(
+                &module_path,
+                src,
+                "<test>",
+                Vec::new(),
+                &*crate::FN_REGISTRY.lock().unwrap(),
+                &aggr_reg,
+            )
+            .unwrap()
+        };
+        let event = |id| Event {
+            id: Ids::new(0, id),
+            ingest_ns: id,
+            ..Event::default()
+        };
+
+        let src = "define tumbling window by_3 with size = 3 end;\n\
+                   select aggr::stats::count() from in[by_3] into out;";
+        let q = parse(src);
+        let store = Store::temporary()?;
+        let mut uid = 0;
+        let mut returns = vec![];
+
+        let mut g = q.to_pipe(&mut uid)?;
+        g.enqueue("in", event(1), &mut returns)?;
+        g.enqueue("in", event(2), &mut returns)?;
+        assert!(returns.is_empty());
+        assert!(g.checkpoint_due(10_000_000_000));
+        g.checkpoint(&store, 10_000_000_000)?;
+        assert!(!g.checkpoint_due(10_000_000_001));
+
+        // A new pipeline continues with the open window
+        let mut g = q.to_pipe(&mut uid)?;
+        assert_eq!(g.restore(&store)?, 1);
+        g.enqueue("in", event(3), &mut returns)?;
+        assert!(returns.is_empty());
+        g.enqueue("in", event(4), &mut returns)?;
+        assert_eq!(returns.len(), 1);
+        assert_eq!(returns[0].1.data.suffix().value(), &3);
+
+        // A new version of the query ignores the old state
+        let q = parse(&format!("#!config state_version = \"2\"\n{}", src));
+        let mut g = q.to_pipe(&mut uid)?;
+        assert_eq!(g.restore(&store)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn adopt_uids() -> Result<()> {
+        let module_path = &tremor_script::path::ModulePath { mounts: Vec::new() };
+        let aggr_reg = tremor_script::aggr_registry();
+        let parse = |src: &str| {
+            Query::parse(
+                &module_path,
+                src,
+                "<test>",
+                Vec::new(),
+                &*crate::FN_REGISTRY.lock().unwrap(),
+                &aggr_reg,
+            )
+            .unwrap()
+        };
+        let mut uid = 0;
+        let old = parse("select event from in into out;").to_pipe(&mut uid)?;
+        let mut new = parse(
+            "define generic::batch operator batch with count = 2 end;\n\
+             create operator batch;\n\
+             select event from in into batch;\n\
+             select event from batch into out;",
+        )
+        .to_pipe(&mut uid)?;
+        new.adopt_uids(&old);
+        let uid_of = |g: &crate::ExecutableGraph, id: &str| {
+            g.graph.iter().find(|n| n.id == id).map(|n| n.uid)
+        };
+        assert_eq!(uid_of(&new, "in"), uid_of(&old, "in"));
+        assert_eq!(uid_of(&new, "out"), uid_of(&old, "out"));
+        assert!(uid_of(&new, "batch") > uid_of(&old, "out"));
+        Ok(())
+    }
+
+    #[test]
+    fn restore_all_or_nothing() -> Result<()> {
+        use crate::checkpoint::Store;
+        use crate::{Event, Ids};
+        let module_path = &tremor_script::path::ModulePath { mounts: Vec::new() };
+        let aggr_reg = tremor_script::aggr_registry();
+        let src = "define tumbling window by_3 with size = 3 end;\n\
+                   select aggr::stats::count() from in[by_3] into out;\n\
+                   select aggr::stats::count() from in[by_3] into err;";
+        let q = Query::parse(
+            &module_path,
+            src,
+            "<test>",
+            Vec::new(),
+            &*crate::FN_REGISTRY.lock().unwrap(),
+            &aggr_reg,
+        )
+        .unwrap();
+        let event = |id| Event {
+            id: Ids::new(0, id),
+            ingest_ns: id,
+            ..Event::default()
+        };
+        let store = Store::temporary()?;
+        let mut uid = 0;
+        let mut returns = vec![];
+
+        let mut g = q.to_pipe(&mut uid)?;
+        g.enqueue("in", event(1), &mut returns)?;
+        g.enqueue("in", event(2), &mut returns)?;
+        g.checkpoint(&store, 0)?;
+
+        // break the checkpoint of the last select
+        let mut selects = Vec::new();
+        for node in &g.graph {
+            if let Some(checkpoint) = store.load(&g.id, &node.id)? {
+                if checkpoint.snapshot.is_some() {
+                    selects.push((node.id.clone(), checkpoint));
+                }
+            }
+        }
+        assert_eq!(selects.len(), 2);
+        let (id, mut checkpoint) = selects.pop().unwrap();
+        checkpoint.snapshot = Some("snot".into());
+        store.save(&g.id, &id, &checkpoint)?;
+
+        // the valid select isn't restored either
+        let mut g = q.to_pipe(&mut uid)?;
+        assert!(g.restore(&store).is_err());
+        g.enqueue("in", event(3), &mut returns)?;
+        g.enqueue("in", event(4), &mut returns)?;
+        assert!(returns.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_flush() -> Result<()> {
+        use crate::{Event, Ids, SignalKind};
+        let module_path = &tremor_script::path::ModulePath { mounts: Vec::new() };
+        let aggr_reg = tremor_script::aggr_registry();
+        let src = "define tumbling window by_3 with size = 3 end;\n\
+                   define tumbling window by_6 with size = 2 end;\n\
+                   select aggr::stats::count() from in[by_3, by_6] into out;";
+        let q = Query::parse(
+            &module_path,
+            src,
+            "<test>",
+            Vec::new(),
+            &*crate::FN_REGISTRY.lock().unwrap(),
+            &aggr_reg,
+        )
+        .unwrap();
+        let mut uid = 0;
+        let mut returns = vec![];
+        let mut g = q.to_pipe(&mut uid)?;
+        for id in 1..=5 {
+            let event = Event {
+                id: Ids::new(0, id),
+                ingest_ns: id,
+                ..Event::default()
+            };
+            g.enqueue("in", event, &mut returns)?;
+        }
+        // the first by_3 window was emitted on the 4th event
+        assert_eq!(returns.len(), 1);
+        returns.clear();
+
+        let signal = Event {
+            ingest_ns: 6,
+            kind: Some(SignalKind::Shutdown),
+            ..Event::default()
+        };
+        g.enqueue_signal(signal, &mut returns)?;
+        let counts: Vec<_> = returns
+            .iter()
+            .map(|(_, e)| e.data.suffix().value().clone())
+            .collect();
+        // the open by_3 window holds 2 events, by_6 all 5 once tilted
+        assert_eq!(counts, vec![2, 5]);
+
+        // windows are empty after the flush
+        returns.clear();
+        let signal = Event {
+            ingest_ns: 7,
+            kind: Some(SignalKind::Shutdown),
+            ..Event::default()
+        };
+        g.enqueue_signal(signal, &mut returns)?;
+        assert!(returns.is_empty());
+        Ok(())
+    }
 }
@@ -21,7 +21,9 @@ mod support;
 mod upable;
 use crate::errors::{error_generic, error_no_consts, error_no_locals, ErrorKind, Result};
 use crate::impl_expr2;
-use crate::interpreter::{exec_binary, exec_unary, AggrType, Cont, Env, ExecOpts, LocalStack};
+use crate::interpreter::{
+    exec_binary, exec_unary, AggrType, Cont, Env, ExecOpts, LocalStack, Tracer,
+};
 pub use crate::lexer::CompilationUnit;
 use crate::pos::{Location, Range};
 use crate::registry::FResult;
@@ -491,9 +493,38 @@ where
         event: &'run mut Value<'event>,
         state: &'run mut Value<'static>,
         meta: &'run mut Value<'event>,
+    ) -> Result<Return<'event>> {
+        self.run_traced(context, aggr, event, state, meta, None)
+    }
+
+    /// Runs the script and evaluates to a resulting event, reporting
+    /// execution to a tracer if one is provided
+    pub fn run_traced(
+        &'script self,
+        context: &'run crate::EventContext,
+        aggr: AggrType,
+        event: &'run mut Value<'event>,
+        state: &'run mut Value<'static>,
+        meta: &'run mut Value<'event>,
+        tracer: Option<&'run dyn Tracer>,
     ) -> Result<Return<'event>> {
         let mut local = LocalStack::with_size(self.locals);
+        self.run_with_stack(context, aggr, event, state, meta, tracer, &mut local)
+    }
 
+    /// Runs the script against a given local stack, so locals can outlive
+    /// a single run
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn run_with_stack(
+        &'script self,
+        context: &'run crate::EventContext,
+        aggr: AggrType,
+        event: &'run mut Value<'event>,
+        state: &'run mut Value<'static>,
+        meta: &'run mut Value<'event>,
+        tracer: Option<&'run dyn Tracer>,
+        local: &mut LocalStack<'event>,
+    ) -> Result<Return<'event>> {
         let mut exprs = self.exprs.iter().peekable();
         let opts = ExecOpts {
             result_needed: true,
@@ -506,11 +537,12 @@ where
             aggrs: &self.aggregates,
             meta: &self.node_meta,
             recursion_limit: crate::recursion_limit(),
+            tracer,
         };
 
         while let Some(expr) = exprs.next() {
             if exprs.peek().is_none() {
-                match stry!(expr.run(opts.with_result(), &env, event, state, meta, &mut local)) {
+                match stry!(expr.run(opts.with_result(), &env, event, state, meta, local)) {
                     Cont::Drop => return Ok(Return::Drop),
                     Cont::Emit(value, port) => return Ok(Return::Emit { value, port }),
                     Cont::EmitEvent(port) => {
@@ -524,7 +556,7 @@ where
                     }
                 }
             } else {
-                match stry!(expr.run(opts.without_result(), &env, event, state, meta, &mut local)) {
+                match stry!(expr.run(opts.without_result(), &env, event, state, meta, local)) {
                     Cont::Drop => return Ok(Return::Drop),
                     Cont::Emit(value, port) => return Ok(Return::Emit { value, port }),
                     Cont::EmitEvent(port) => {
@@ -887,6 +919,7 @@ impl<'script> Invoke<'script> {
                 aggrs: &NO_AGGRS,
                 meta: &helper.meta,
                 recursion_limit: crate::recursion_limit(),
+                tracer: None,
             };
 
             let v = self
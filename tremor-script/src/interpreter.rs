@@ -32,8 +32,11 @@
 
 mod expr;
 mod imut_expr;
+mod trace;
 
 pub use self::expr::Cont;
+pub(crate) use self::trace::path_name;
+pub use self::trace::{Mutation, Tracer};
 use crate::ast::{
     ArrayPattern, ArrayPredicatePattern, BaseExpr, BinOpKind, GroupBy, GroupByInt, ImutExprInt,
     InvokeAggrFn, NodeMetas, Patch, PatchOperation, Path, Pattern, PredicatePattern, RecordPattern,
@@ -91,6 +94,8 @@ where
     pub meta: &'run NodeMetas,
     /// Maximal recursion depth in custom functions
     pub recursion_limit: u32,
+    /// Optional tracer, instrumenting the execution
+    pub tracer: Option<&'run dyn Tracer>,
 }
 
 impl<'run, 'event, 'script> Env<'run, 'event, 'script>
@@ -920,6 +925,9 @@ where
     if let Some(guard) = guard {
         let test = stry!(guard.run(opts, env, event, state, meta, local));
         if let Some(b) = test.as_bool() {
+            if let Some(tracer) = env.tracer {
+                tracer.guard(guard.extent(&env.meta), b);
+            }
             Ok(b)
         } else {
             error_guard_not_bool(outer, guard, &test, &env.meta)
@@ -1487,6 +1495,7 @@ impl<'script> GroupByInt<'script> {
             aggrs: &NO_AGGRS,
             meta: node_meta,
             recursion_limit: crate::recursion_limit(),
+            tracer: None,
         };
         match self {
             GroupByInt::Expr { expr, .. } => {
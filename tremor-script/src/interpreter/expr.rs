@@ -13,8 +13,8 @@
 // limitations under the License.
 
 use super::{
-    merge_values, patch_value, resolve, set_local_shadow, test_guard, test_predicate_expr, Env,
-    ExecOpts, LocalStack, NULL,
+    merge_values, patch_value, path_name, resolve, set_local_shadow, test_guard,
+    test_predicate_expr, Env, ExecOpts, LocalStack, Mutation, NULL,
 };
 use crate::ast::{
     BaseExpr, Comprehension, EmitExpr, EventPath, Expr, ImutExprInt, Match, Merge, Patch, Path,
@@ -102,7 +102,7 @@ where
         let target = stry!(expr.target.run(opts, env, event, state, meta, local));
 
         for predicate in &expr.patterns {
-            if stry!(test_predicate_expr(
+            let hit = stry!(test_predicate_expr(
                 self,
                 opts,
                 env,
@@ -113,7 +113,11 @@ where
                 &target,
                 &predicate.pattern,
                 &predicate.guard,
-            )) {
+            ));
+            if let Some(tracer) = env.tracer {
+                tracer.case(predicate.extent(&env.meta), hit);
+            }
+            if hit {
                 return self.execute_effectors(
                     opts,
                     env,
@@ -186,6 +190,9 @@ where
         stry!(patch_value(
             self, opts, env, event, state, meta, local, v, expr
         ));
+        if let Some(tracer) = env.tracer {
+            tracer.mutate(self.extent(&env.meta), Mutation::Patch, v);
+        }
         Ok(value)
     }
 
@@ -217,6 +224,9 @@ where
 
             if replacement.is_object() {
                 stry!(merge_values(self, &expr.expr, value, &replacement));
+                if let Some(tracer) = env.tracer {
+                    tracer.mutate(self.extent(&env.meta), Mutation::Merge, value);
+                }
                 Ok(value_cow)
             } else {
                 error_need_obj(self, &expr.expr, replacement.value_type(), &env.meta)
@@ -326,6 +336,9 @@ where
         path: &'script Path,
         value: Value<'event>,
     ) -> Result<Cow<'run, Value<'event>>> {
+        if let Some(tracer) = env.tracer {
+            tracer.assign(self.extent(&env.meta), &path_name(path, &env.meta), &value);
+        }
         if path.segments().is_empty() {
             self.assign_direct(opts, env, event, state, meta, local, path, value)
         } else {
@@ -14,7 +14,7 @@
 
 use super::{
     exec_binary, exec_unary, merge_values, patch_value, resolve, set_local_shadow, test_guard,
-    test_predicate_expr, AggrType, Env, ExecOpts, LocalStack, FALSE, TRUE,
+    test_predicate_expr, AggrType, Env, ExecOpts, LocalStack, Mutation, FALSE, TRUE,
 };
 
 use crate::ast::{
@@ -315,7 +315,7 @@ where
         let target = stry!(expr.target.run(opts, env, event, state, meta, local));
 
         for predicate in &expr.patterns {
-            if stry!(test_predicate_expr(
+            let hit = stry!(test_predicate_expr(
                 self,
                 opts,
                 env,
@@ -326,7 +326,11 @@ where
                 &target,
                 &predicate.pattern,
                 &predicate.guard,
-            )) {
+            ));
+            if let Some(tracer) = env.tracer {
+                tracer.case(predicate.extent(&env.meta), hit);
+            }
+            if hit {
                 return self.execute_effectors(
                     opts,
                     env,
@@ -639,6 +643,9 @@ where
         stry!(patch_value(
             self, opts, env, event, state, meta, local, &mut value, expr,
         ));
+        if let Some(tracer) = env.tracer {
+            tracer.mutate(self.extent(&env.meta), Mutation::Patch, &value);
+        }
         Ok(Cow::Owned(value))
     }
 
@@ -662,6 +669,9 @@ where
 
             if replacement.is_object() {
                 stry!(merge_values(self, &expr.expr, &mut value, &replacement));
+                if let Some(tracer) = env.tracer {
+                    tracer.mutate(self.extent(&env.meta), Mutation::Merge, &value);
+                }
                 Ok(Cow::Owned(value))
             } else {
                 error_need_obj(self, &expr.expr, replacement.value_type(), &env.meta)
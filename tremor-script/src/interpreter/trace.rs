@@ -0,0 +1,195 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::ast::{NodeMetas, Path, Segment};
+use crate::pos::Range;
+use simd_json::borrowed::Value;
+use std::fmt;
+
+/// Kind of an in place mutation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mutation {
+    /// `patch ... of ... end`
+    Patch,
+    /// `merge ... of ... end`
+    Merge,
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Patch => write!(f, "patch"),
+            Self::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+/// Instrumentation hooks into the interpreter.
+///
+/// The hooks are only invoked if a tracer is set on the `Env`, if none is
+/// set the only cost is checking for it.
+pub trait Tracer {
+    /// A `match` case was tested, `hit` is true if the case was taken
+    fn case(&self, at: Range, hit: bool);
+    /// A `when` guard evaluated to `result`
+    fn guard(&self, at: Range, result: bool);
+    /// `path` is about to be assigned `value` via `let`
+    fn assign(&self, at: Range, path: &str, value: &Value);
+    /// `value` was mutated in place
+    fn mutate(&self, at: Range, kind: Mutation, value: &Value);
+}
+
+/// Human readable name of a path, used for tracing
+pub(crate) fn path_name(path: &Path, meta: &NodeMetas) -> String {
+    let mut name = match path {
+        Path::Const(p) | Path::Local(p) => meta.name_dflt(p.mid),
+        Path::Event(_) => String::from("event"),
+        Path::State(_) => String::from("state"),
+        Path::Meta(_) => String::from("$"),
+    };
+    for segment in path.segments() {
+        match segment {
+            Segment::Id { key, .. } => {
+                if !name.ends_with('$') {
+                    name.push('.');
+                }
+                name.push_str(key.key());
+            }
+            Segment::Idx { idx, .. } => name.push_str(&format!("[{}]", idx)),
+            Segment::Element { .. } => name.push_str("[...]"),
+            Segment::Range { .. } => name.push_str("[...:...]"),
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::ModulePath;
+    use crate::prelude::*;
+    use crate::{registry, Script};
+    use halfbrown::hashmap;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Recorder {
+        trace: RefCell<Vec<String>>,
+    }
+
+    impl Tracer for Recorder {
+        fn case(&self, at: Range, hit: bool) {
+            self.trace
+                .borrow_mut()
+                .push(format!("{} case {}", at.start().line(), hit));
+        }
+        fn guard(&self, at: Range, result: bool) {
+            self.trace
+                .borrow_mut()
+                .push(format!("{} guard {}", at.start().line(), result));
+        }
+        fn assign(&self, at: Range, path: &str, value: &Value) {
+            self.trace.borrow_mut().push(format!(
+                "{} let {} = {}",
+                at.start().line(),
+                path,
+                value.encode()
+            ));
+        }
+        fn mutate(&self, at: Range, kind: Mutation, value: &Value) {
+            self.trace.borrow_mut().push(format!(
+                "{} {} {}",
+                at.start().line(),
+                kind,
+                value.encode()
+            ));
+        }
+    }
+
+    fn trace(src: &str) -> Vec<String> {
+        let reg = registry::registry();
+        let script = Script::parse(
+            &ModulePath { mounts: vec![] },
+            "<test>",
+            src.to_string(),
+            &reg,
+        )
+        .expect("parse failed");
+        let recorder = Recorder::default();
+        let mut event = Value::from(hashmap! { "a".into() => 1.into() });
+        let mut state = Value::null();
+        let mut meta = Value::object();
+        script
+            .run_traced(
+                &EventContext::new(0, None),
+                AggrType::Tick,
+                &mut event,
+                &mut state,
+                &mut meta,
+                &recorder,
+            )
+            .expect("run failed");
+        recorder.trace.into_inner()
+    }
+
+    #[test]
+    fn trace_script() {
+        let src = r#"let x = 1;
+match event of
+  case %{ a == 2 } => "no"
+  case %{ present a } when x == 1 => let event = patch event of insert "b" => x end
+  default => "no"
+end;
+let event = merge event of {"c": 3} end;
+let event.d = 4;
+event
+"#;
+        assert_eq!(
+            vec![
+                "1 let x = 1",
+                "3 case false",
+                "4 guard true",
+                "4 case true",
+                "4 patch {\"a\":1,\"b\":1}",
+                "7 merge {\"a\":1,\"b\":1,\"c\":3}",
+                "8 let event.d = 4",
+            ],
+            trace(src)
+        );
+    }
+
+    #[test]
+    fn trace_immutable_expressions() {
+        let src = r#"let y = match event of
+  case %{ a == 2 } => "no"
+  case %{ present a } when event.a == 1 => merge event of {"c": 3} end
+  default => "no"
+end;
+let z = patch y of insert "d" => 4 end;
+z
+"#;
+        assert_eq!(
+            vec![
+                "2 case false",
+                "3 guard true",
+                "3 case true",
+                "3 merge {\"a\":1,\"c\":3}",
+                "1 let y = {\"a\":1,\"c\":3}",
+                "6 patch {\"a\":1,\"c\":3,\"d\":4}",
+                "6 let z = {\"a\":1,\"c\":3,\"d\":4}",
+            ],
+            trace(src)
+        );
+    }
+}
@@ -81,6 +81,8 @@ pub use crate::registry::{
     TremorAggrFnWrapper, TremorFn, TremorFnWrapper,
 };
 pub use crate::script::{Return, Script};
+pub use crate::std_lib::chash::jump_hash;
+pub use crate::tilde::{Cidr, ExtractorError};
 
 pub use interpreter::{AggrType, FALSE, NULL, TRUE};
 pub use simd_json::value::borrowed::Object;
@@ -110,6 +110,16 @@ impl Range {
     pub fn cu(self) -> usize {
         self.0.unit_id
     }
+    /// The start of the range
+    #[must_use]
+    pub fn start(self) -> Location {
+        self.0
+    }
+    /// The end of the range
+    #[must_use]
+    pub fn end(self) -> Location {
+        self.1
+    }
 }
 
 impl From<(Location, Location)> for Range {
@@ -59,6 +59,15 @@ pub trait TremorAggrFn: DowncastSync + Sync + Send {
     fn warning(&self) -> Option<String> {
         None
     }
+    /// Returns the accumulated state so it can be persisted, functions
+    /// that can't be persisted return `None`
+    fn snapshot(&self) -> Option<Value<'static>> {
+        None
+    }
+    /// Restores the accumulated state from a value returned by `snapshot`
+    fn restore<'event>(&mut self, _snapshot: &Value<'event>) -> FResult<()> {
+        Err(Error::from("Aggregate function doesn't support snapshots").into())
+    }
 }
 impl_downcast!(sync TremorAggrFn);
 
@@ -677,6 +686,17 @@ impl TremorAggrFnWrapper {
         use std::borrow::Borrow;
         self.fun.merge(src.fun.borrow())
     }
+
+    /// Returns the accumulated state so it can be persisted
+    #[must_use]
+    pub fn snapshot(&self) -> Option<Value<'static>> {
+        self.fun.snapshot()
+    }
+
+    /// Restores the accumulated state from a value returned by `snapshot`
+    pub fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.fun.restore(snapshot)
+    }
 }
 
 impl fmt::Debug for TremorAggrFnWrapper {
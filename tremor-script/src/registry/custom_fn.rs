@@ -168,6 +168,7 @@ impl<'script> CustomFn<'script> {
             aggrs: &NO_AGGRS,
             meta: env.meta,
             recursion_limit: env.recursion_limit,
+            tracer: env.tracer,
         };
         let mut recursion_depth = 0;
         'recur: loop {
@@ -18,7 +18,7 @@ use crate::ctx::EventContext;
 use crate::errors::{CompilerError, Error, Result};
 use crate::highlighter::{Dumb as DumbHighlighter, Highlighter};
 pub use crate::interpreter::AggrType;
-use crate::interpreter::Cont;
+use crate::interpreter::{Cont, LocalStack, Tracer};
 use crate::lexer::{self};
 use crate::parser::g as grammar;
 use crate::path::ModulePath;
@@ -78,6 +78,8 @@ pub struct Script {
     pub source: String,
     /// A set of warnings if any
     pub warnings: Vec<Warning>,
+    /// Names of the locals of the script, in the order of their slots
+    pub locals: Vec<String>,
 }
 
 impl Script {
@@ -120,10 +122,24 @@ where
         reg: &Registry,
         // args: Option<Vec<&str>>,
         // aggr_reg: &AggrRegistry, - we really should shadow and provide a nice hygienic error TODO but not today
+    ) -> std::result::Result<Self, CompilerError> {
+        Self::parse_with_locals(module_path, file_name, script, reg, &[])
+    }
+
+    /// Parses a string and turns it into a script where `locals` are already
+    /// bound, they keep their slots so the script can be run with the locals
+    /// of a previous run via `run_with_locals`
+    pub fn parse_with_locals(
+        module_path: &ModulePath,
+        file_name: &str,
+        script: String,
+        reg: &Registry,
+        locals: &[String],
     ) -> std::result::Result<Self, CompilerError> {
         let mut include_stack = lexer::IncludeStack::default();
         let r = |include_stack: &mut lexer::IncludeStack| -> Result<Self> {
             let mut warnings = vec![];
+            let mut local_names = vec![];
 
             let rented_script =
                 rentals::Script::try_new(Box::new(script.clone()), |script: &mut String| {
@@ -143,9 +159,18 @@ where
                     let script_raw = grammar::ScriptParser::new().parse(filtered_tokens)?;
                     let fake_aggr_reg = AggrRegistry::default();
                     let mut helper = Helper::new(&reg, &fake_aggr_reg, include_stack.cus.clone());
+                    for (idx, name) in locals.iter().enumerate() {
+                        helper.locals.insert(name.clone(), idx);
+                    }
                     let (screw_rust, ws) = script_raw.up_script(&mut helper)?;
 
                     warnings = ws;
+                    local_names = vec![String::new(); helper.locals.len()];
+                    for (name, idx) in &helper.locals {
+                        if let Some(slot) = local_names.get_mut(*idx) {
+                            *slot = name.clone();
+                        }
+                    }
                     Ok(screw_rust)
                 })
                 .map_err(|e: rental::RentalError<Error, Box<String>>| e.0)?;
@@ -154,6 +179,7 @@ where
                 script: rented_script,
                 source: script,
                 warnings,
+                locals: local_names,
             })
         }(&mut include_stack);
         r.map_err(|error| CompilerError {
@@ -342,4 +368,44 @@ where
     ) -> Result<Return<'event>> {
         self.script.suffix().run(context, aggr, event, state, meta)
     }
+
+    /// Runs an event through this script with the locals of a previous run,
+    /// `locals` holds the locals of this run afterwards
+    pub fn run_with_locals(
+        &'script self,
+        context: &'run EventContext,
+        aggr: AggrType,
+        event: &'run mut Value<'event>,
+        state: &'run mut Value<'static>,
+        meta: &'run mut Value<'event>,
+        locals: &mut Vec<Option<Value<'static>>>,
+    ) -> Result<Return<'event>> {
+        let script = self.script.suffix();
+        let mut stack = LocalStack::with_size(script.locals);
+        for (slot, value) in stack.values.iter_mut().zip(locals.iter()) {
+            *slot = value.clone();
+        }
+        let res = script.run_with_stack(context, aggr, event, state, meta, None, &mut stack);
+        *locals = stack
+            .values
+            .into_iter()
+            .map(|v| v.map(|v| v.clone_static()))
+            .collect();
+        res
+    }
+
+    /// Runs an event through this script, reporting execution to a tracer
+    pub fn run_traced(
+        &'script self,
+        context: &'run EventContext,
+        aggr: AggrType,
+        event: &'run mut Value<'event>,
+        state: &'run mut Value<'static>,
+        meta: &'run mut Value<'event>,
+        tracer: &'run dyn Tracer,
+    ) -> Result<Return<'event>> {
+        self.script
+            .suffix()
+            .run_traced(context, aggr, event, state, meta, Some(tracer))
+    }
 }
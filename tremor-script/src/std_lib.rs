@@ -19,8 +19,9 @@ macro_rules! assert_val {
     };
 }
 
+mod anomaly;
 mod array;
-mod chash;
+pub(crate) mod chash;
 mod datetime;
 mod dummy;
 mod float;
@@ -56,6 +57,7 @@ pub fn load(registry: &mut Registry) {
     range::load(registry);
     re::load(registry);
     record::load(registry);
+    stats::load(registry);
     string::load(registry);
     system::load(registry);
     test::load(registry);
@@ -64,6 +66,7 @@ pub fn load(registry: &mut Registry) {
 }
 
 pub fn load_aggr(registry: &mut AggrRegistry) {
+    anomaly::load_aggr(registry);
     stats::load_aggr(registry);
     win::load_aggr(registry);
 }
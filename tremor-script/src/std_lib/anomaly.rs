@@ -0,0 +1,713 @@
+// Copyright 2020, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Anomaly detection aggregates score the mean of the values of a window
+// against the history of the previous windows of the same group. Unlike the
+// other aggregates they keep that history when they are re-initialised for
+// the next window, only the values of the window itself are reset.
+
+#![allow(clippy::cast_precision_loss)]
+
+use crate::registry::{
+    mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
+};
+use halfbrown::hashmap;
+use simd_json::prelude::*;
+use simd_json::value::borrowed::Value;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+/// Scale of the modified z-score, makes the MAD consistent with the standard
+/// deviation of normally distributed values
+const MAD_SCALE: f64 = 0.6745;
+/// Number of windows `mad` needs to have seen before it flags anomalies
+const MAD_MIN_HISTORY: usize = 3;
+/// Relative tolerance below which `holt_winters` residuals are considered
+/// rounding noise
+const RESIDUAL_TOLERANCE: f64 = 1e-9;
+
+/// The values accumulated in a window
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Observation {
+    n: u64,
+    sum: f64,
+}
+
+impl Observation {
+    fn add(&mut self, f: &str, args: &[&Value]) -> FResult<()> {
+        let v = args[0].cast_f64().ok_or_else(|| FunctionError::BadType {
+            mfa: mfa("anomaly", f, args.len()),
+        })?;
+        self.n += 1;
+        self.sum += v;
+        Ok(())
+    }
+    fn remove(&mut self, f: &str, args: &[&Value]) -> FResult<()> {
+        let v = args[0].cast_f64().ok_or_else(|| FunctionError::BadType {
+            mfa: mfa("anomaly", f, args.len()),
+        })?;
+        self.n = self.n.saturating_sub(1);
+        self.sum -= v;
+        Ok(())
+    }
+    fn merge(&mut self, other: &Self) {
+        self.n += other.n;
+        self.sum += other.sum;
+    }
+    /// The mean of the window, `None` if it is empty
+    fn mean(&self) -> Option<f64> {
+        if self.n == 0 {
+            None
+        } else {
+            Some(self.sum / self.n as f64)
+        }
+    }
+    fn snapshot(&self) -> Value<'static> {
+        Value::from(vec![Value::from(self.n), Value::from(self.sum)])
+    }
+    fn restore(snapshot: &Value) -> Option<Self> {
+        if let Some([n, sum]) = snapshot.as_array().map(Vec::as_slice) {
+            Some(Self {
+                n: n.as_u64()?,
+                sum: sum.cast_f64()?,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the optional float argument `idx`, `default` if it is missing
+fn arg(f: &str, args: &[&Value], idx: usize, default: f64) -> FResult<f64> {
+    args.get(idx).map_or(Ok(default), |v| {
+        v.cast_f64().ok_or_else(|| FunctionError::BadType {
+            mfa: mfa("anomaly", f, args.len()),
+        })
+    })
+}
+
+/// Reads the optional smoothing factor `idx`, it has to be in `(0, 1]`
+fn factor(f: &str, args: &[&Value], idx: usize, default: f64) -> FResult<f64> {
+    let factor = arg(f, args, idx, default)?;
+    if factor > 0.0 && factor <= 1.0 {
+        Ok(factor)
+    } else {
+        Err(FunctionError::RuntimeError {
+            mfa: mfa("anomaly", f, args.len()),
+            error: format!("Smoothing factor {} isn't in (0, 1]", factor),
+        })
+    }
+}
+
+fn floats(values: &[f64]) -> Value<'static> {
+    Value::from(values.iter().copied().map(Value::from).collect::<Vec<_>>())
+}
+
+fn restore_floats(snapshot: &Value) -> Option<Vec<f64>> {
+    snapshot.as_array()?.iter().map(Value::cast_f64).collect()
+}
+
+fn bad_snapshot(f: &str) -> FunctionError {
+    FunctionError::RuntimeError {
+        mfa: mfa("anomaly", f, 1),
+        error: "Invalid snapshot".to_string(),
+    }
+}
+
+/// Median of a non empty, sorted slice
+fn median(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+fn sort(values: &mut [f64]) {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+/// Exponentially weighted moving average and variance, windows are flagged
+/// when their z-score exceeds the threshold
+#[derive(Clone, Debug)]
+struct Ewma {
+    window: Observation,
+    alpha: f64,
+    threshold: f64,
+    n: u64,
+    mean: f64,
+    var: f64,
+}
+
+impl Default for Ewma {
+    fn default() -> Self {
+        Self {
+            window: Observation::default(),
+            alpha: 0.3,
+            threshold: 3.0,
+            n: 0,
+            mean: 0.0,
+            var: 0.0,
+        }
+    }
+}
+
+impl TremorAggrFn for Ewma {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.alpha = factor("ewma", args, 1, self.alpha)?;
+        self.threshold = arg("ewma", args, 2, self.threshold)?;
+        self.window.add("ewma", args)
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.window.remove("ewma", args)
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let value = if let Some(value) = self.window.mean() {
+            value
+        } else {
+            return Ok(Value::null());
+        };
+        let (mean, stdev) = if self.n == 0 {
+            (value, 0.0)
+        } else {
+            (self.mean, self.var.sqrt())
+        };
+        let zscore = if stdev > 0.0 {
+            (value - mean) / stdev
+        } else {
+            0.0
+        };
+
+        let diff = value - mean;
+        let incr = self.alpha * diff;
+        self.mean = mean + incr;
+        self.var = (1.0 - self.alpha) * (self.var + diff * incr);
+        self.n += 1;
+
+        Ok(Value::from(hashmap! {
+            "value".into() => Value::from(value),
+            "mean".into() => Value::from(mean),
+            "stdev".into() => Value::from(stdev),
+            "zscore".into() => Value::from(zscore),
+            "anomaly".into() => Value::from(zscore.abs() > self.threshold),
+        }))
+    }
+    fn init(&mut self) {
+        self.window = Observation::default();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            self.window.merge(&other.window);
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=3
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(vec![
+            self.window.snapshot(),
+            Value::from(self.n),
+            Value::from(self.mean),
+            Value::from(self.var),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([window, n, mean, var]) = snapshot.as_array().map(Vec::as_slice) {
+            if let (Some(window), Some(n), Some(mean), Some(var)) = (
+                Observation::restore(window),
+                n.as_u64(),
+                mean.cast_f64(),
+                var.cast_f64(),
+            ) {
+                self.window = window;
+                self.n = n;
+                self.mean = mean;
+                self.var = var;
+                return Ok(());
+            }
+        }
+        Err(bad_snapshot("ewma"))
+    }
+}
+
+/// Additive Holt-Winters forecast, windows are flagged when their residual
+/// exceeds the threshold times the smoothed residual of their season slot
+#[derive(Clone, Debug)]
+struct HoltWinters {
+    window: Observation,
+    season: usize,
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    threshold: f64,
+    /// number of windows seen
+    t: u64,
+    level: f64,
+    trend: f64,
+    seasonal: Vec<f64>,
+    deviation: Vec<f64>,
+    /// observations of the first season, used to initialise the model
+    first: Vec<f64>,
+}
+
+impl Default for HoltWinters {
+    fn default() -> Self {
+        Self {
+            window: Observation::default(),
+            season: 1,
+            alpha: 0.3,
+            beta: 0.1,
+            gamma: 0.1,
+            threshold: 3.0,
+            t: 0,
+            level: 0.0,
+            trend: 0.0,
+            seasonal: Vec::new(),
+            deviation: Vec::new(),
+            first: Vec::new(),
+        }
+    }
+}
+
+impl HoltWinters {
+    fn record<'event>(value: f64, forecast: f64, deviation: f64, anomaly: bool) -> Value<'event> {
+        Value::from(hashmap! {
+            "value".into() => Value::from(value),
+            "forecast".into() => Value::from(forecast),
+            "residual".into() => Value::from(value - forecast),
+            "deviation".into() => Value::from(deviation),
+            "anomaly".into() => Value::from(anomaly),
+        })
+    }
+}
+
+impl TremorAggrFn for HoltWinters {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        let season = args
+            .get(1)
+            .and_then(|v| v.as_usize())
+            .filter(|s| *s > 0)
+            .ok_or_else(|| FunctionError::RuntimeError {
+                mfa: mfa("anomaly", "holt_winters", args.len()),
+                error: "The season needs to be a positive integer".to_string(),
+            })?;
+        // the model can't adapt to a different season once it is learned
+        if self.t == 0 {
+            self.season = season;
+        }
+        self.alpha = factor("holt_winters", args, 2, self.alpha)?;
+        self.beta = factor("holt_winters", args, 3, self.beta)?;
+        self.gamma = factor("holt_winters", args, 4, self.gamma)?;
+        self.threshold = arg("holt_winters", args, 5, self.threshold)?;
+        self.window.add("holt_winters", args)
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.window.remove("holt_winters", args)
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let value = if let Some(value) = self.window.mean() {
+            value
+        } else {
+            return Ok(Value::null());
+        };
+        let season = self.season as u64;
+        #[allow(clippy::cast_possible_truncation)]
+        let slot = (self.t % season) as usize;
+        self.t += 1;
+
+        if self.seasonal.is_empty() {
+            // the first season initialises the model
+            self.first.push(value);
+            if self.first.len() == self.season {
+                self.level = self.first.iter().sum::<f64>() / self.season as f64;
+                self.trend = 0.0;
+                let level = self.level;
+                self.seasonal = self.first.drain(..).map(|v| v - level).collect();
+                self.deviation = vec![0.0; self.season];
+            }
+            return Ok(Self::record(value, value, 0.0, false));
+        }
+
+        let forecast = self.level + self.trend + self.seasonal[slot];
+        let residual = (value - forecast).abs();
+        // the deviation of a single slot is learned from few windows, so the
+        // average deviation of the season is used as a floor
+        let average = self.deviation.iter().sum::<f64>() / self.season as f64;
+        let deviation = self.deviation[slot].max(average);
+        // deviations are learned over the second season
+        let learned = self.t > 2 * season;
+        let anomaly = learned
+            && residual > self.threshold * deviation + RESIDUAL_TOLERANCE * forecast.abs().max(1.0);
+
+        let level = self.level;
+        self.level =
+            self.alpha * (value - self.seasonal[slot]) + (1.0 - self.alpha) * (level + self.trend);
+        self.trend = self.beta * (self.level - level) + (1.0 - self.beta) * self.trend;
+        self.seasonal[slot] =
+            self.gamma * (value - self.level) + (1.0 - self.gamma) * self.seasonal[slot];
+        self.deviation[slot] = if learned {
+            self.gamma * residual + (1.0 - self.gamma) * self.deviation[slot]
+        } else {
+            residual
+        };
+
+        Ok(Self::record(value, forecast, deviation, anomaly))
+    }
+    fn init(&mut self) {
+        self.window = Observation::default();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if self.t == 0 {
+                self.season = other.season;
+            }
+            self.window.merge(&other.window);
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        2..=6
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(vec![
+            self.window.snapshot(),
+            Value::from(self.season as u64),
+            Value::from(self.t),
+            Value::from(self.level),
+            Value::from(self.trend),
+            floats(&self.seasonal),
+            floats(&self.deviation),
+            floats(&self.first),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([window, season, t, level, trend, seasonal, deviation, first]) =
+            snapshot.as_array().map(Vec::as_slice)
+        {
+            if let (
+                Some(window),
+                Some(season),
+                Some(t),
+                Some(level),
+                Some(trend),
+                Some(seasonal),
+                Some(deviation),
+                Some(first),
+            ) = (
+                Observation::restore(window),
+                season.as_usize(),
+                t.as_u64(),
+                level.cast_f64(),
+                trend.cast_f64(),
+                restore_floats(seasonal),
+                restore_floats(deviation),
+                restore_floats(first),
+            ) {
+                self.window = window;
+                self.season = season;
+                self.t = t;
+                self.level = level;
+                self.trend = trend;
+                self.seasonal = seasonal;
+                self.deviation = deviation;
+                self.first = first;
+                return Ok(());
+            }
+        }
+        Err(bad_snapshot("holt_winters"))
+    }
+}
+
+/// Median absolute deviation over the previous windows, windows are flagged
+/// when their modified z-score exceeds the threshold
+#[derive(Clone, Debug)]
+struct Mad {
+    window: Observation,
+    size: usize,
+    threshold: f64,
+    history: VecDeque<f64>,
+}
+
+impl Default for Mad {
+    fn default() -> Self {
+        Self {
+            window: Observation::default(),
+            size: 30,
+            threshold: 3.5,
+            history: VecDeque::new(),
+        }
+    }
+}
+
+impl TremorAggrFn for Mad {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        if let Some(size) = args.get(1) {
+            self.size =
+                size.as_usize()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| FunctionError::RuntimeError {
+                        mfa: mfa("anomaly", "mad", args.len()),
+                        error: "The history size needs to be a positive integer".to_string(),
+                    })?;
+        }
+        self.threshold = arg("mad", args, 2, self.threshold)?;
+        self.window.add("mad", args)
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.window.remove("mad", args)
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let value = if let Some(value) = self.window.mean() {
+            value
+        } else {
+            return Ok(Value::null());
+        };
+        let mut sorted: Vec<f64> = self.history.iter().copied().collect();
+        let (median, mad, zscore) = if sorted.is_empty() {
+            (value, 0.0, Some(0.0))
+        } else {
+            sort(&mut sorted);
+            let median = median(&sorted);
+            let mut deviations: Vec<f64> = sorted.iter().map(|v| (v - median).abs()).collect();
+            sort(&mut deviations);
+            let mad = self::median(&deviations);
+            let zscore = if mad > 0.0 {
+                Some(MAD_SCALE * (value - median) / mad)
+            } else if (value - median).abs() > 0.0 {
+                // any deviation from a constant history is an outlier
+                None
+            } else {
+                Some(0.0)
+            };
+            (median, mad, zscore)
+        };
+        let anomaly = self.history.len() >= MAD_MIN_HISTORY
+            && zscore.map_or(true, |z| z.abs() > self.threshold);
+
+        self.history.push_back(value);
+        while self.history.len() > self.size {
+            self.history.pop_front();
+        }
+
+        Ok(Value::from(hashmap! {
+            "value".into() => Value::from(value),
+            "median".into() => Value::from(median),
+            "mad".into() => Value::from(mad),
+            "zscore".into() => zscore.map_or_else(Value::null, Value::from),
+            "anomaly".into() => Value::from(anomaly),
+        }))
+    }
+    fn init(&mut self) {
+        self.window = Observation::default();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            self.window.merge(&other.window);
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=3
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let history: Vec<f64> = self.history.iter().copied().collect();
+        Some(Value::from(vec![self.window.snapshot(), floats(&history)]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([window, history]) = snapshot.as_array().map(Vec::as_slice) {
+            if let (Some(window), Some(history)) =
+                (Observation::restore(window), restore_floats(history))
+            {
+                self.window = window;
+                self.history = history.into_iter().collect();
+                return Ok(());
+            }
+        }
+        Err(bad_snapshot("mad"))
+    }
+}
+
+pub fn load_aggr(registry: &mut AggrRegistry) {
+    registry
+        .insert(TremorAggrFnWrapper::new(
+            "anomaly".to_string(),
+            "ewma".to_string(),
+            Box::new(Ewma::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "anomaly".to_string(),
+            "holt_winters".to_string(),
+            Box::new(HoltWinters::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "anomaly".to_string(),
+            "mad".to_string(),
+            Box::new(Mad::default()),
+        ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::FResult as Result;
+    use float_cmp::approx_eq;
+
+    /// Runs a window with a single value
+    fn window(a: &mut dyn TremorAggrFn, args: &[Value]) -> Result<Value<'static>> {
+        let args: Vec<&Value> = args.iter().collect();
+        a.accumulate(&args)?;
+        Ok(a.emit_and_init()?.into_static())
+    }
+
+    fn anomaly(v: &Value) -> bool {
+        v.get("anomaly").and_then(Value::as_bool) == Some(true)
+    }
+
+    #[test]
+    fn ewma() -> Result<()> {
+        let mut a = Ewma::default();
+        a.init();
+        let alpha = Value::from(0.5);
+        for v in &[10, 12, 10, 12, 10, 12] {
+            let r = window(&mut a, &[Value::from(*v), alpha.clone()])?;
+            assert!(!anomaly(&r));
+        }
+        let r = window(&mut a, &[Value::from(40), alpha.clone()])?;
+        assert!(anomaly(&r));
+        assert_eq!(r.get("value"), Some(&Value::from(40.0)));
+        assert!(
+            r.get("zscore")
+                .and_then(Value::cast_f64)
+                .unwrap_or_default()
+                > 3.0
+        );
+
+        // the window is the mean of its values
+        let mut a = Ewma::default();
+        a.init();
+        a.accumulate(&[&Value::from(1)])?;
+        a.accumulate(&[&Value::from(3)])?;
+        let mut b = Ewma::default();
+        b.init();
+        b.accumulate(&[&Value::from(5)])?;
+        a.merge(&b)?;
+        let r = a.emit()?;
+        assert_eq!(r.get("value"), Some(&Value::from(3.0)));
+        assert_eq!(r.get("zscore"), Some(&Value::from(0.0)));
+
+        assert!(a.accumulate(&[&Value::from(1), &Value::from(2.0)]).is_err());
+        assert!(a.accumulate(&[&Value::from("snot")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn holt_winters() -> Result<()> {
+        let mut a = HoltWinters::default();
+        a.init();
+        let season = Value::from(4);
+        let pattern = [10.0, 20.0, 30.0, 20.0];
+        for i in 0..12 {
+            let v = pattern[i % 4] + (i % 3) as f64 * 0.5;
+            let r = window(&mut a, &[Value::from(v), season.clone()])?;
+            assert!(!anomaly(&r));
+            if i == 8 {
+                // the seasonal pattern is forecast
+                let forecast = r.get("forecast").and_then(Value::cast_f64);
+                assert!(forecast.map_or(false, |f| (f - 10.0).abs() < 2.0));
+            }
+        }
+        // a value expected in another slot of the season is an anomaly
+        let r = window(&mut a, &[Value::from(30), season.clone()])?;
+        assert!(anomaly(&r));
+
+        assert!(a.accumulate(&[&Value::from(1)]).is_err());
+        assert!(a.accumulate(&[&Value::from(1), &Value::from(0)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mad() -> Result<()> {
+        let mut a = Mad::default();
+        a.init();
+        let size = Value::from(5);
+        for v in &[10, 11, 9, 10, 12] {
+            let r = window(&mut a, &[Value::from(*v), size.clone()])?;
+            assert!(!anomaly(&r));
+        }
+        let r = window(&mut a, &[Value::from(30), size.clone()])?;
+        assert!(anomaly(&r));
+        assert_eq!(r.get("median"), Some(&Value::from(10.0)));
+        assert_eq!(r.get("mad"), Some(&Value::from(1.0)));
+        // the outlier doesn't move the median
+        let r = window(&mut a, &[Value::from(11), size.clone()])?;
+        assert!(!anomaly(&r));
+        assert_eq!(r.get("median"), Some(&Value::from(11.0)));
+        assert_eq!(a.history.len(), 5);
+
+        // deviations from a constant history are outliers
+        let mut a = Mad::default();
+        a.init();
+        for _ in 0..3 {
+            assert!(!anomaly(&window(&mut a, &[Value::from(0)])?));
+        }
+        let r = window(&mut a, &[Value::from(1)])?;
+        assert!(anomaly(&r));
+        assert_eq!(r.get("zscore"), Some(&Value::null()));
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> Result<()> {
+        let season = Value::from(2);
+        let mut a = HoltWinters::default();
+        a.init();
+        for v in &[1, 2, 1, 2, 1] {
+            window(&mut a, &[Value::from(*v), season.clone()])?;
+        }
+        a.accumulate(&[&Value::from(3), &season])?;
+        let mut b = HoltWinters::default();
+        b.restore(&a.snapshot().expect("holt_winters supports snapshots"))?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let mut a = Ewma::default();
+        window(&mut a, &[Value::from(1)])?;
+        window(&mut a, &[Value::from(2)])?;
+        let mut b = Ewma::default();
+        b.restore(&a.snapshot().expect("ewma supports snapshots"))?;
+        assert!(approx_eq!(f64, a.mean, b.mean));
+        assert_eq!(
+            window(&mut a, &[Value::from(3)])?,
+            window(&mut b, &[Value::from(3)])?
+        );
+
+        let mut a = Mad::default();
+        window(&mut a, &[Value::from(1)])?;
+        let mut b = Mad::default();
+        b.restore(&a.snapshot().expect("mad supports snapshots"))?;
+        assert_eq!(b.history, a.history);
+        assert!(b.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
+}
@@ -14,14 +14,16 @@
 #![allow(clippy::cast_precision_loss)]
 
 use crate::registry::{
-    mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
+    mfa, Aggr as AggrRegistry, FResult, FunctionError, Registry, TremorAggrFn, TremorAggrFnWrapper,
 };
+use crate::tremor_const_fn;
 use halfbrown::hashmap;
 use hdrhistogram::Histogram;
 use simd_json::prelude::*;
 use simd_json::value::borrowed::Value;
 use sketches_ddsketch::{Config as DDSketchConfig, DDSketch};
 use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
 use std::f64;
 use std::marker::Send;
 use std::ops::RangeInclusive;
@@ -76,6 +78,15 @@ impl TremorAggrFn for Count {
     fn arity(&self) -> RangeInclusive<usize> {
         0..=0
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = snapshot.as_i64().ok_or_else(|| FunctionError::BadType {
+            mfa: mfa("stats", "count", 0),
+        })?;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -121,6 +132,15 @@ impl TremorAggrFn for Sum {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = snapshot.cast_f64().ok_or_else(|| FunctionError::BadType {
+            mfa: mfa("stats", "sum", 1),
+        })?;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -174,6 +194,35 @@ impl TremorAggrFn for Mean {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(vec![Value::from(self.0), Value::from(self.1)]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([count, sum]) = snapshot.as_array().map(Vec::as_slice) {
+            if let (Some(count), Some(sum)) = (count.as_i64(), sum.cast_f64()) {
+                self.0 = count;
+                self.1 = sum;
+                return Ok(());
+            }
+        }
+        Err(FunctionError::BadType {
+            mfa: mfa("stats", "mean", 1),
+        })
+    }
+}
+
+/// Restores an optional float snapshot as used by `min` and `max`
+fn restore_option(snapshot: &Value, name: &str) -> FResult<Option<f64>> {
+    if snapshot.is_null() {
+        Ok(None)
+    } else {
+        snapshot
+            .cast_f64()
+            .map(Some)
+            .ok_or_else(|| FunctionError::BadType {
+                mfa: mfa("stats", name, 1),
+            })
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -231,6 +280,13 @@ impl TremorAggrFn for Min {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = restore_option(snapshot, "min")?;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -274,6 +330,13 @@ impl TremorAggrFn for Max {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = restore_option(snapshot, "max")?;
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -343,6 +406,30 @@ impl TremorAggrFn for Var {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(vec![
+            Value::from(self.n),
+            Value::from(self.k),
+            Value::from(self.ex),
+            Value::from(self.ex2),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([n, k, ex, ex2]) = snapshot.as_array().map(Vec::as_slice) {
+            if let (Some(n), Some(k), Some(ex), Some(ex2)) =
+                (n.as_u64(), k.cast_f64(), ex.cast_f64(), ex2.cast_f64())
+            {
+                self.n = n;
+                self.k = k;
+                self.ex = ex;
+                self.ex2 = ex2;
+                return Ok(());
+            }
+        }
+        Err(FunctionError::BadType {
+            mfa: mfa("stats", "var", 1),
+        })
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -381,37 +468,149 @@ impl TremorAggrFn for Stdev {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        self.0.snapshot()
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0.restore(snapshot)
+    }
 }
 
-struct Dds {
-    histo: Option<DDSketch>,
-    cache: Vec<f64>,
-    percentiles: Vec<(String, f64)>,
-    percentiles_set: bool,
-    //    digits_significant_precision: usize,
+/// The names of the percentiles a histogram reports, they are parsed again
+/// on restore
+fn snapshot_percentiles(percentiles: &[(String, f64)]) -> Value<'static> {
+    Value::from(
+        percentiles
+            .iter()
+            .map(|(name, _)| Value::from(name.clone()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn restore_percentiles(snapshot: &Value) -> Option<Vec<(String, f64)>> {
+    snapshot
+        .as_array()?
+        .iter()
+        .map(|name| {
+            let name = name.as_str()?;
+            Some((name.to_string(), name.parse().ok()?))
+        })
+        .collect()
 }
 
-impl std::clone::Clone for Dds {
-    // DDSKetch does not implement clone
-    fn clone(&self) -> Self {
+/// A `DDSketch` along with the number of values in each of its bins and
+/// their sum. The sketch doesn't expose those, but they are needed to
+/// snapshot and restore it.
+#[derive(Clone)]
+struct DdsSketch {
+    config: DDSketchConfig,
+    sketch: DDSketch,
+    bins: BTreeMap<i32, u64>,
+    sum: f64,
+}
+
+impl DdsSketch {
+    fn new() -> Self {
+        let config = DDSketchConfig::defaults();
         Self {
-            histo: match &self.histo {
-                Some(dds) => {
-                    let config = DDSketchConfig::defaults();
-                    let mut histo = DDSketch::new(config);
-                    histo.merge(&dds).ok();
-                    Some(histo)
+            config,
+            sketch: DDSketch::new(config),
+            bins: BTreeMap::new(),
+            sum: 0.0,
+        }
+    }
+
+    fn add(&mut self, v: f64) {
+        self.sketch.add(v);
+        *self.bins.entry(self.config.key(v)).or_default() += 1;
+        self.sum += v;
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.sketch.merge(&other.sketch).ok();
+        for (key, count) in &other.bins {
+            *self.bins.entry(*key).or_default() += count;
+        }
+        self.sum += other.sum;
+    }
+
+    /// The value the sketch reports for a bin
+    fn bin_value(&self, key: i32) -> f64 {
+        let gamma = self.config.gamma;
+        if key > 0 {
+            2.0 * self.config.pow_gamma(key - self.config.offset) / (1.0 + gamma)
+        } else if key < 0 {
+            -2.0 * self.config.pow_gamma(-key - self.config.offset) / (1.0 + gamma)
+        } else {
+            0.0
+        }
+    }
+
+    fn snapshot(&self) -> Value<'static> {
+        let bins: Vec<Value<'static>> = self
+            .bins
+            .iter()
+            .map(|(key, count)| Value::from(vec![Value::from(*key), Value::from(*count)]))
+            .collect();
+        Value::from(vec![
+            self.sketch.min().map_or_else(Value::null, Value::from),
+            self.sketch.max().map_or_else(Value::null, Value::from),
+            Value::from(self.sum),
+            Value::from(bins),
+        ])
+    }
+
+    /// Rebuilds the sketch by adding a value of every bin as often as the
+    /// bin counts, the minimum and maximum are added to their bins so the
+    /// sketch bounds its quantiles by them as before
+    fn restore(snapshot: &Value) -> Option<Self> {
+        if let Some([min, max, sum, bins]) = snapshot.as_array().map(Vec::as_slice) {
+            let mut restored = Self::new();
+            let config = restored.config;
+            let bounds = min
+                .cast_f64()
+                .zip(max.cast_f64())
+                .map(|(min, max)| (min, config.key(min), max, config.key(max)));
+            for bin in bins.as_array()? {
+                if let Some([key, count]) = bin.as_array().map(Vec::as_slice) {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let key = key.as_i64()? as i32;
+                    let count = count.as_u64()?;
+                    let value = restored.bin_value(key);
+                    for i in 0..count {
+                        let v = match bounds {
+                            Some((min, min_key, _, max_key))
+                                if key == min_key && (i == 0 || key != max_key) =>
+                            {
+                                min
+                            }
+                            Some((_, _, max, max_key)) if key == max_key => max,
+                            _ => value,
+                        };
+                        restored.sketch.add(v);
+                    }
+                    restored.bins.insert(key, count);
+                } else {
+                    return None;
                 }
-                None => None,
-            },
-            cache: self.cache.clone(),
-            percentiles: self.percentiles.clone(),
-            percentiles_set: self.percentiles_set,
-            //            digits_significant_precision: 2,
+            }
+            restored.sum = sum.cast_f64()?;
+            Some(restored)
+        } else {
+            None
         }
     }
 }
 
+#[derive(Clone)]
+struct Dds {
+    histo: Option<DdsSketch>,
+    cache: Vec<f64>,
+    percentiles: Vec<(String, f64)>,
+    percentiles_set: bool,
+    //    digits_significant_precision: usize,
+}
+
 impl std::default::Default for Dds {
     fn default() -> Self {
         Self {
@@ -462,10 +661,11 @@ impl TremorAggrFn for Dds {
             } else {
                 self.cache.push(v);
                 if self.cache.len() == HIST_MAX_CACHE_SIZE {
-                    let mut histo: DDSketch = DDSketch::new(DDSketchConfig::defaults());
+                    let mut histo = DdsSketch::new();
                     for v in self.cache.drain(..) {
                         histo.add(v);
                     }
+                    self.histo = Some(histo);
                 }
             }
         }
@@ -486,7 +686,7 @@ impl TremorAggrFn for Dds {
         let histo = if let Some(histo) = self.histo.as_ref() {
             histo
         } else {
-            let mut histo: DDSketch = DDSketch::new(DDSketchConfig::defaults());
+            let mut histo = DdsSketch::new();
             for v in self.cache.drain(..) {
                 histo.add(v);
             }
@@ -499,7 +699,7 @@ impl TremorAggrFn for Dds {
             }
         };
 
-        let count = histo.count();
+        let count = histo.sketch.count();
         let (min, max, sum) = if count == 0 {
             for (pcn, _percentile) in &self.percentiles {
                 p.insert(pcn.clone().into(), Value::from(0.0));
@@ -507,7 +707,7 @@ impl TremorAggrFn for Dds {
             (0_f64, f64::MAX, 0_f64)
         } else {
             for (pcn, percentile) in &self.percentiles {
-                if let Ok(Some(quantile)) = histo.quantile(*percentile) {
+                if let Ok(Some(quantile)) = histo.sketch.quantile(*percentile) {
                     let quantile_dsp = ceil(quantile, 1); // Round for equiv with HDR ( 2 digits )
                     p.insert(pcn.clone().into(), Value::from(quantile_dsp));
                 } else {
@@ -518,9 +718,15 @@ impl TremorAggrFn for Dds {
                 }
             }
             (
-                histo.min().ok_or_else(|| err(&"Unable to calculate min"))?,
-                histo.max().ok_or_else(|| err(&"Unable to calculate max"))?,
-                histo.sum().ok_or_else(|| err(&"Unable to calculate sum"))?,
+                histo
+                    .sketch
+                    .min()
+                    .ok_or_else(|| err(&"Unable to calculate min"))?,
+                histo
+                    .sketch
+                    .max()
+                    .ok_or_else(|| err(&"Unable to calculate max"))?,
+                histo.sum,
             )
         };
         Ok(Value::from(hashmap! {
@@ -549,7 +755,7 @@ impl TremorAggrFn for Dds {
                 //  If this is a histogram and we merge
                 if let Some(ref other) = other.histo {
                     // If the other was also a histogram merge them
-                    histo.merge(other).ok();
+                    histo.merge(other);
                 } else {
                     // if the other was still a cache add it's values
                     for v in &other.cache {
@@ -561,8 +767,8 @@ impl TremorAggrFn for Dds {
                 match other.histo {
                     Some(ref other) => {
                         // If the other was a histogram clone it and empty our values
-                        let mut histo: DDSketch = DDSketch::new(DDSketchConfig::defaults());
-                        histo.merge(other).ok();
+                        let mut histo = DdsSketch::new();
+                        histo.merge(other);
                         for v in self.cache.drain(..) {
                             histo.add(v);
                         }
@@ -572,7 +778,7 @@ impl TremorAggrFn for Dds {
                         // If both are caches
                         if self.cache.len() + other.cache.len() > HIST_MAX_CACHE_SIZE {
                             // If the cache size exceeds our maximal cache size drain them into a histogram
-                            let mut histo: DDSketch = DDSketch::new(DDSketchConfig::defaults());
+                            let mut histo = DdsSketch::new();
                             for v in self.cache.drain(..) {
                                 histo.add(v);
                             }
@@ -601,6 +807,44 @@ impl TremorAggrFn for Dds {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=2
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let cache: Vec<Value<'static>> = self.cache.iter().copied().map(Value::from).collect();
+        Some(Value::from(vec![
+            snapshot_percentiles(&self.percentiles),
+            Value::from(self.percentiles_set),
+            Value::from(cache),
+            self.histo
+                .as_ref()
+                .map_or_else(Value::null, DdsSketch::snapshot),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        let bad = || FunctionError::BadType {
+            mfa: mfa("stats", "dds", 2),
+        };
+        if let Some([percentiles, percentiles_set, cache, histo]) =
+            snapshot.as_array().map(Vec::as_slice)
+        {
+            let percentiles = restore_percentiles(percentiles).ok_or_else(bad)?;
+            let percentiles_set = percentiles_set.as_bool().ok_or_else(bad)?;
+            let cache = cache
+                .as_array()
+                .and_then(|cache| cache.iter().map(|v| v.cast_f64()).collect())
+                .ok_or_else(bad)?;
+            let histo = if histo.is_null() {
+                None
+            } else {
+                Some(DdsSketch::restore(histo).ok_or_else(bad)?)
+            };
+            self.percentiles = percentiles;
+            self.percentiles_set = percentiles_set;
+            self.cache = cache;
+            self.histo = histo;
+            Ok(())
+        } else {
+            Err(bad())
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -693,6 +937,7 @@ impl TremorAggrFn for Hdr {
                             error: format!("failed to record value: {:?}", e),
                         })?;
                     }
+                    self.histo = Some(histo);
                 }
             }
         }
@@ -839,6 +1084,534 @@ impl TremorAggrFn for Hdr {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=2
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let cache: Vec<Value<'static>> = self.cache.iter().copied().map(Value::from).collect();
+        // every recorded value stands for all values equivalent to it, so
+        // recording them again restores the same counts
+        let histo = self.histo.as_ref().map_or_else(Value::null, |histo| {
+            let recorded: Vec<Value<'static>> = histo
+                .iter_recorded()
+                .map(|v| {
+                    Value::from(vec![
+                        Value::from(v.value_iterated_to()),
+                        Value::from(v.count_at_value()),
+                    ])
+                })
+                .collect();
+            Value::from(recorded)
+        });
+        Some(Value::from(vec![
+            snapshot_percentiles(&self.percentiles),
+            Value::from(self.percentiles_set),
+            Value::from(self.max),
+            Value::from(cache),
+            histo,
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        let bad = || FunctionError::BadType {
+            mfa: mfa("stats", "hdr", 2),
+        };
+        if let Some([percentiles, percentiles_set, max_value, cache, histo]) =
+            snapshot.as_array().map(Vec::as_slice)
+        {
+            let mut restored = Self {
+                histo: None,
+                cache: cache
+                    .as_array()
+                    .and_then(|cache| cache.iter().map(|v| v.as_u64()).collect())
+                    .ok_or_else(bad)?,
+                percentiles: restore_percentiles(percentiles).ok_or_else(bad)?,
+                percentiles_set: percentiles_set.as_bool().ok_or_else(bad)?,
+                max: max_value.as_u64().ok_or_else(bad)?,
+            };
+            if !histo.is_null() {
+                let mut histo_restored: Histogram<u64> =
+                    Histogram::new_with_bounds(1, restored.max(), 2).map_err(|e| {
+                        FunctionError::RuntimeError {
+                            mfa: mfa("stats", "hdr", 2),
+                            error: format!("failed to init historgrams: {:?}", e),
+                        }
+                    })?;
+                histo_restored.auto(true);
+                for recorded in histo.as_array().ok_or_else(bad)? {
+                    if let Some([value, count]) = recorded.as_array().map(Vec::as_slice) {
+                        let value = value.as_u64().ok_or_else(bad)?;
+                        let count = count.as_u64().ok_or_else(bad)?;
+                        histo_restored.record_n(value, count).map_err(|e| {
+                            FunctionError::RuntimeError {
+                                mfa: mfa("stats", "hdr", 2),
+                                error: format!("failed to record value: {:?}", e),
+                            }
+                        })?;
+                    } else {
+                        return Err(bad());
+                    }
+                }
+                restored.histo = Some(histo_restored);
+            }
+            *self = restored;
+            Ok(())
+        } else {
+            Err(bad())
+        }
+    }
+}
+
+/// Counters `topk` keeps for every entry it reports
+const TOPK_COUNTERS_PER_ENTRY: usize = 10;
+/// `distinct_count` uses 2^12 registers for a standard error of about 1.6%
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+const BLOOM_CAPACITY: u64 = 10_000;
+const BLOOM_ERROR_RATE: f64 = 0.01;
+
+/// The key sketches count a value by, strings are used as they are while
+/// other values are encoded
+fn sketch_key(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.encode(), ToString::to_string)
+}
+
+/// FNV-1a hash of the sketch key, finished with the murmur3 finalizer so the
+/// high bits `distinct_count` indexes registers by are mixed. Unlike the std
+/// hasher it is the same across builds and instances, so snapshots can be
+/// restored and filters tested anywhere.
+fn sketch_hash(value: &Value) -> u64 {
+    let hash = sketch_key(value)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[derive(Clone, Debug)]
+struct TopKCounter {
+    value: Value<'static>,
+    count: u64,
+    /// how much `count` may overestimate the real count
+    error: u64,
+}
+
+/// The most frequent values, tracked with the space-saving algorithm in
+/// `k * TOPK_COUNTERS_PER_ENTRY` counters
+#[derive(Clone, Debug, Default)]
+struct TopK {
+    k: usize,
+    counters: HashMap<String, TopKCounter>,
+}
+
+impl TopK {
+    fn capacity(&self) -> usize {
+        self.k.saturating_mul(TOPK_COUNTERS_PER_ENTRY)
+    }
+    /// The counters ordered by count, highest first
+    fn sorted(&self) -> Vec<(&String, &TopKCounter)> {
+        let mut sorted: Vec<_> = self.counters.iter().collect();
+        sorted.sort_by(|(k1, c1), (k2, c2)| c2.count.cmp(&c1.count).then_with(|| k1.cmp(k2)));
+        sorted
+    }
+}
+
+impl TremorAggrFn for TopK {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.k = args
+            .get(1)
+            .and_then(|k| k.as_usize())
+            .filter(|k| *k > 0)
+            .ok_or_else(|| FunctionError::BadType {
+                mfa: mfa("stats", "topk", 2),
+            })?;
+        let key = sketch_key(args[0]);
+        if let Some(counter) = self.counters.get_mut(&key) {
+            counter.count += 1;
+        } else if self.counters.len() < self.capacity() {
+            let counter = TopKCounter {
+                value: args[0].clone_static(),
+                count: 1,
+                error: 0,
+            };
+            self.counters.insert(key, counter);
+        } else {
+            // the new value replaces the least frequent one and inherits
+            // its count as error
+            let min = self
+                .counters
+                .iter()
+                .min_by_key(|(_, c)| c.count)
+                .map(|(k, c)| (k.clone(), c.count));
+            if let Some((min_key, min_count)) = min {
+                self.counters.remove(&min_key);
+                let counter = TopKCounter {
+                    value: args[0].clone_static(),
+                    count: min_count + 1,
+                    error: min_count,
+                };
+                self.counters.insert(key, counter);
+            }
+        }
+        Ok(())
+    }
+    fn compensate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+        // space-saving counters can't forget values
+        Ok(())
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        Ok(Value::from(
+            self.sorted()
+                .into_iter()
+                .take(self.k)
+                .map(|(_, c)| {
+                    Value::from(hashmap! {
+                        "value".into() => c.value.clone(),
+                        "count".into() => Value::from(c.count),
+                        "error".into() => Value::from(c.error),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ))
+    }
+    fn init(&mut self) {
+        self.counters.clear();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            self.k = max(self.k, other.k);
+            for (key, counter) in &other.counters {
+                if let Some(mine) = self.counters.get_mut(key) {
+                    mine.count += counter.count;
+                    mine.error += counter.error;
+                } else {
+                    self.counters.insert(key.clone(), counter.clone());
+                }
+            }
+            if self.counters.len() > self.capacity() {
+                let keep: Vec<String> = self
+                    .sorted()
+                    .into_iter()
+                    .take(self.capacity())
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                let mut counters = HashMap::with_capacity(keep.len());
+                for key in keep {
+                    if let Some(counter) = self.counters.remove(&key) {
+                        counters.insert(key, counter);
+                    }
+                }
+                self.counters = counters;
+            }
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        2..=2
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let counters: Vec<Value<'static>> = self
+            .sorted()
+            .into_iter()
+            .map(|(_, c)| {
+                Value::from(vec![
+                    c.value.clone(),
+                    Value::from(c.count),
+                    Value::from(c.error),
+                ])
+            })
+            .collect();
+        Some(Value::from(vec![
+            Value::from(self.k as u64),
+            Value::from(counters),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        let bad = || FunctionError::BadType {
+            mfa: mfa("stats", "topk", 2),
+        };
+        if let Some([k, counters]) = snapshot.as_array().map(Vec::as_slice) {
+            let k = k.as_usize().ok_or_else(bad)?;
+            let mut restored = HashMap::new();
+            for counter in counters.as_array().ok_or_else(bad)? {
+                if let Some([value, count, error]) = counter.as_array().map(Vec::as_slice) {
+                    let counter = TopKCounter {
+                        value: value.clone_static(),
+                        count: count.as_u64().ok_or_else(bad)?,
+                        error: error.as_u64().ok_or_else(bad)?,
+                    };
+                    restored.insert(sketch_key(value), counter);
+                } else {
+                    return Err(bad());
+                }
+            }
+            self.k = k;
+            self.counters = restored;
+            Ok(())
+        } else {
+            Err(bad())
+        }
+    }
+}
+
+/// The number of distinct values, estimated with HyperLogLog
+#[derive(Clone, Debug, Default)]
+struct DistinctCount {
+    registers: Vec<u8>,
+}
+
+impl TremorAggrFn for DistinctCount {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        if self.registers.is_empty() {
+            self.registers.resize(HLL_REGISTERS, 0);
+        }
+        let hash = sketch_hash(args[0]);
+        #[allow(clippy::cast_possible_truncation)]
+        let idx = (hash >> (64 - HLL_PRECISION)) as usize;
+        // the marker bit caps the rank for the bits not used by the index
+        #[allow(clippy::cast_possible_truncation)]
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() as u8 + 1;
+        if let Some(register) = self.registers.get_mut(idx) {
+            *register = max(*register, rank);
+        }
+        Ok(())
+    }
+    fn compensate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+        // HyperLogLog registers can't forget values
+        Ok(())
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        if self.registers.is_empty() {
+            return Ok(Value::from(0_u64));
+        }
+        let m = HLL_REGISTERS as f64;
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2_f64.powi(-i32::from(*r)))
+            .sum();
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let mut estimate = alpha * m * m / sum;
+        if estimate <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            estimate = m * (m / zeros as f64).ln();
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Ok(Value::from(estimate.round() as u64))
+    }
+    fn init(&mut self) {
+        self.registers.clear();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if self.registers.is_empty() {
+                self.registers = other.registers.clone();
+            } else {
+                for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+                    *mine = max(*mine, *theirs);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=1
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(base64::encode(&self.registers)))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        let registers = snapshot
+            .as_str()
+            .and_then(|s| base64::decode(s).ok())
+            .filter(|r| r.is_empty() || r.len() == HLL_REGISTERS)
+            .ok_or_else(|| FunctionError::BadType {
+                mfa: mfa("stats", "distinct_count", 1),
+            })?;
+        self.registers = registers;
+        Ok(())
+    }
+}
+
+/// The bits of a bloom filter of `size` bits that `value` sets, using
+/// double hashing
+fn bloom_bits(value: &Value, size: u64, hashes: u64) -> impl Iterator<Item = u64> {
+    let hash = sketch_hash(value);
+    let (h1, h2) = (hash & 0xFFFF_FFFF, hash >> 32);
+    (0..hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % size)
+}
+
+/// Tests if `value` is a member of a filter emitted by `stats::bloom`,
+/// `None` if the filter isn't valid
+fn bloom_contains(filter: &Value, value: &Value) -> Option<bool> {
+    let size = filter.get("size")?.as_u64()?;
+    let hashes = filter.get("hashes")?.as_u64()?;
+    let bytes = base64::decode(filter.get("filter")?.as_str()?).ok()?;
+    if size == 0 {
+        return Some(false);
+    }
+    let mut contains = true;
+    for bit in bloom_bits(value, size, hashes) {
+        #[allow(clippy::cast_possible_truncation)]
+        let byte = bytes.get((bit / 8) as usize)?;
+        contains &= byte & (1 << (bit % 8)) != 0;
+    }
+    Some(contains)
+}
+
+/// A bloom filter of the values
+#[derive(Clone, Debug, Default)]
+struct Bloom {
+    words: Vec<u64>,
+    size: u64,
+    hashes: u64,
+    count: u64,
+}
+
+impl TremorAggrFn for Bloom {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        if self.words.is_empty() {
+            let capacity = args.get(1).map_or(Some(BLOOM_CAPACITY), |c| c.as_u64());
+            let error_rate = args.get(2).map_or(Some(BLOOM_ERROR_RATE), |e| e.cast_f64());
+            let (capacity, error_rate) = match (capacity, error_rate) {
+                (Some(c), Some(e)) if c > 0 && e > 0.0 && e < 1.0 => (c as f64, e),
+                _ => return Err(FunctionError::RuntimeError {
+                    mfa: mfa("stats", "bloom", args.len()),
+                    error:
+                        "The capacity needs to be a positive integer and the error rate in (0, 1)"
+                            .to_string(),
+                }),
+            };
+            let ln2 = f64::consts::LN_2;
+            let size = (-capacity * error_rate.ln() / (ln2 * ln2)).ceil();
+            let hashes = (size / capacity * ln2).round().max(1.0);
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            {
+                self.size = size as u64;
+                self.hashes = hashes as u64;
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            self.words.resize(((self.size + 63) / 64) as usize, 0);
+        }
+        for bit in bloom_bits(args[0], self.size, self.hashes) {
+            #[allow(clippy::cast_possible_truncation)]
+            if let Some(word) = self.words.get_mut((bit / 64) as usize) {
+                *word |= 1 << (bit % 64);
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+    fn compensate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+        // bloom filters can't forget values
+        Ok(())
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let bytes: Vec<u8> = self
+            .words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        Ok(Value::from(hashmap! {
+            "count".into() => Value::from(self.count),
+            "size".into() => Value::from(self.size),
+            "hashes".into() => Value::from(self.hashes),
+            "filter".into() => Value::from(base64::encode(bytes)),
+        }))
+    }
+    fn init(&mut self) {
+        self.words.clear();
+        self.size = 0;
+        self.hashes = 0;
+        self.count = 0;
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if other.words.is_empty() {
+                return Ok(());
+            } else if self.words.is_empty() {
+                *self = other.clone();
+            } else if self.size == other.size && self.hashes == other.hashes {
+                for (mine, theirs) in self.words.iter_mut().zip(&other.words) {
+                    *mine |= *theirs;
+                }
+                self.count += other.count;
+            } else {
+                return Err(FunctionError::RuntimeError {
+                    mfa: mfa("stats", "bloom", 1),
+                    error: "Can't merge bloom filters of different sizes".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+    fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=3
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let bytes: Vec<u8> = self
+            .words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        Some(Value::from(vec![
+            Value::from(self.size),
+            Value::from(self.hashes),
+            Value::from(self.count),
+            Value::from(base64::encode(bytes)),
+        ]))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        if let Some([size, hashes, count, filter]) = snapshot.as_array().map(Vec::as_slice) {
+            if let (Some(size), Some(hashes), Some(count), Some(bytes)) = (
+                size.as_u64(),
+                hashes.as_u64(),
+                count.as_u64(),
+                filter.as_str().and_then(|f| base64::decode(f).ok()),
+            ) {
+                if bytes.len() as u64 == size.saturating_add(63) / 64 * 8 {
+                    self.words = bytes
+                        .chunks(8)
+                        .map(|chunk| {
+                            let mut word = [0_u8; 8];
+                            word.copy_from_slice(chunk);
+                            u64::from_le_bytes(word)
+                        })
+                        .collect();
+                    self.size = size;
+                    self.hashes = hashes;
+                    self.count = count;
+                    return Ok(());
+                }
+            }
+        }
+        Err(FunctionError::BadType {
+            mfa: mfa("stats", "bloom", 1),
+        })
+    }
+}
+
+pub fn load(registry: &mut Registry) {
+    registry.insert(
+        tremor_const_fn! (stats::bloom_contains(_context, _filter, _value) {
+            if let Some(contains) = bloom_contains(_filter, _value) {
+                Ok(Value::from(contains))
+            } else {
+                Err(FunctionError::BadType{mfa: this_mfa()})
+            }
+        }),
+    );
 }
 
 pub fn load_aggr(registry: &mut AggrRegistry) {
@@ -888,6 +1661,21 @@ pub fn load_aggr(registry: &mut AggrRegistry) {
             "stats".to_string(),
             "dds".to_string(),
             Box::new(Dds::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "topk".to_string(),
+            Box::new(TopK::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "distinct_count".to_string(),
+            Box::new(DistinctCount::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "bloom".to_string(),
+            Box::new(Bloom::default()),
         ));
 }
 
@@ -1024,6 +1812,80 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn snapshot() -> Result<()> {
+        let two = Value::from(2);
+        let four = Value::from(4);
+        let nineteen = Value::from(19);
+
+        let mut a = Var::default();
+        a.init();
+        a.accumulate(&[&two])?;
+        a.accumulate(&[&four])?;
+        let snapshot = a.snapshot().expect("var supports snapshots");
+        let mut b = Var::default();
+        b.init();
+        b.restore(&snapshot)?;
+        a.accumulate(&[&nineteen])?;
+        b.accumulate(&[&nineteen])?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let mut a = Min::default();
+        a.init();
+        let mut b = Min::default();
+        b.restore(&a.snapshot().expect("min supports snapshots"))?;
+        assert_eq!(b.0, None);
+        a.accumulate(&[&four])?;
+        b.restore(&a.snapshot().expect("min supports snapshots"))?;
+        assert_eq!(b.emit()?, 4.0);
+
+        let mut a = Mean::default();
+        a.init();
+        assert!(a.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn histogram_snapshots() -> Result<()> {
+        let percentiles = Value::from(vec!["0.5", "0.9", "0.99"]);
+
+        let mut a = Hdr::default();
+        a.init();
+        let mut b = Hdr::default();
+        b.init();
+        a.accumulate(&[&Value::from(42), &percentiles])?;
+        b.restore(&a.snapshot().expect("hdr supports snapshots"))?;
+        assert!(b.histo.is_none());
+        // fills the cache so the values are recorded in the histogram
+        for i in 0..HIST_MAX_CACHE_SIZE as u64 {
+            a.accumulate(&[&Value::from(i * 3), &percentiles])?;
+        }
+        assert!(a.histo.is_some());
+        b.restore(&a.snapshot().expect("hdr supports snapshots"))?;
+        a.accumulate(&[&Value::from(23), &percentiles])?;
+        b.accumulate(&[&Value::from(23), &percentiles])?;
+        assert_eq!(a.emit()?, b.emit()?);
+        assert!(b.restore(&Value::from(vec![1, 2, 3])).is_err());
+
+        let mut a = Dds::default();
+        a.init();
+        let mut b = Dds::default();
+        b.init();
+        a.accumulate(&[&Value::from(4.2), &percentiles])?;
+        b.restore(&a.snapshot().expect("dds supports snapshots"))?;
+        assert!(b.histo.is_none());
+        for i in 0..HIST_MAX_CACHE_SIZE {
+            a.accumulate(&[&Value::from(i as f64 * 1.5), &percentiles])?;
+        }
+        assert!(a.histo.is_some());
+        b.restore(&a.snapshot().expect("dds supports snapshots"))?;
+        a.accumulate(&[&Value::from(2.3), &percentiles])?;
+        b.accumulate(&[&Value::from(2.3), &percentiles])?;
+        assert_eq!(a.emit()?, b.emit()?);
+        assert!(b.restore(&Value::from(vec![1, 2, 3])).is_err());
+        Ok(())
+    }
+
     #[test]
     fn hdr() -> Result<()> {
         use simd_json::BorrowedValue;
@@ -1103,4 +1965,178 @@ mod test {
         assert_eq!(v, e);
         Ok(())
     }
+
+    #[test]
+    fn topk() -> Result<()> {
+        let k = Value::from(2);
+        let mut a = TopK::default();
+        a.init();
+        for v in &["a", "a", "b", "a", "c", "b", "a", "b", "a"] {
+            a.accumulate(&[&Value::from(*v), &k])?;
+        }
+        let e: Value = json!([
+            {"value": "a", "count": 5, "error": 0},
+            {"value": "b", "count": 3, "error": 0}
+        ])
+        .into();
+        assert_eq!(a.emit()?, e);
+
+        let mut b = TopK::default();
+        b.init();
+        for _ in 0..4 {
+            b.accumulate(&[&Value::from("c"), &k])?;
+        }
+        b.merge(&a)?;
+        let e: Value = json!([
+            {"value": "a", "count": 5, "error": 0},
+            {"value": "c", "count": 5, "error": 0}
+        ])
+        .into();
+        assert_eq!(b.emit()?, e);
+
+        let mut c = TopK::default();
+        assert!(c.accumulate(&[&Value::from("a"), &Value::from(0)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn topk_eviction() -> Result<()> {
+        let k = Value::from(1);
+        let mut a = TopK::default();
+        a.init();
+        for i in 0..TOPK_COUNTERS_PER_ENTRY {
+            a.accumulate(&[&Value::from(i), &k])?;
+        }
+        a.accumulate(&[&Value::from("x"), &k])?;
+        a.accumulate(&[&Value::from("x"), &k])?;
+        assert_eq!(a.counters.len(), TOPK_COUNTERS_PER_ENTRY);
+        let e: Value = json!([{"value": "x", "count": 3, "error": 1}]).into();
+        assert_eq!(a.emit()?, e);
+        Ok(())
+    }
+
+    #[test]
+    fn distinct_count() -> Result<()> {
+        let mut a = DistinctCount::default();
+        a.init();
+        assert_eq!(a.emit()?, 0);
+        for i in 0..10_000 {
+            a.accumulate(&[&Value::from(i)])?;
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let estimate = a.emit()?.cast_f64().unwrap_or_default();
+        assert!((estimate - 10_000.0).abs() < 500.0);
+
+        let mut b = DistinctCount::default();
+        b.init();
+        for i in 5_000..15_000 {
+            b.accumulate(&[&Value::from(i)])?;
+        }
+        b.merge(&a)?;
+        let estimate = b.emit()?.cast_f64().unwrap_or_default();
+        assert!((estimate - 15_000.0).abs() < 750.0);
+
+        let mut c = DistinctCount::default();
+        c.init();
+        for _ in 0..10 {
+            c.accumulate(&[&Value::from("snot")])?;
+            c.accumulate(&[&Value::from("badger")])?;
+        }
+        assert_eq!(c.emit()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn bloom() -> Result<()> {
+        let contains = crate::registry::fun("stats", "bloom_contains");
+        let capacity = Value::from(1000);
+        let error_rate = Value::from(0.01);
+        let mut a = Bloom::default();
+        a.init();
+        for i in 0..1000 {
+            a.accumulate(&[&Value::from(i), &capacity, &error_rate])?;
+        }
+        let filter = a.emit()?;
+        assert_eq!(filter.get("count"), Some(&Value::from(1000)));
+        assert_eq!(filter.get("size"), Some(&Value::from(9586)));
+        assert_eq!(filter.get("hashes"), Some(&Value::from(7)));
+        for i in 0..1000 {
+            assert_val!(contains(&[&filter, &Value::from(i)]), true);
+        }
+        let mut false_positives = 0;
+        for i in 1000..2000 {
+            if contains(&[&filter, &Value::from(i)])? == Value::from(true) {
+                false_positives += 1;
+            }
+        }
+        assert!(false_positives < 50);
+
+        let mut b = Bloom::default();
+        b.init();
+        b.accumulate(&[&Value::from("snot"), &capacity, &error_rate])?;
+        b.merge(&a)?;
+        let filter = b.emit()?;
+        assert_eq!(filter.get("count"), Some(&Value::from(1001)));
+        assert_val!(contains(&[&filter, &Value::from("snot")]), true);
+        assert_val!(contains(&[&filter, &Value::from(42)]), true);
+
+        let mut c = Bloom::default();
+        c.init();
+        c.accumulate(&[&Value::from("snot")])?;
+        assert!(c.merge(&a).is_err());
+        assert!(contains(&[&Value::from("snot"), &Value::from(42)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sketch_hash_is_stable() {
+        assert_eq!(sketch_hash(&Value::from("snot")), 0x5565_2d7d_01a7_a5d6);
+        assert_eq!(
+            sketch_hash(&Value::from(42)),
+            sketch_hash(&Value::from("42"))
+        );
+    }
+
+    #[test]
+    fn sketch_snapshots() -> Result<()> {
+        let k = Value::from(2);
+        let mut a = TopK::default();
+        a.init();
+        for v in &["a", "a", "b", "c"] {
+            a.accumulate(&[&Value::from(*v), &k])?;
+        }
+        let mut b = TopK::default();
+        b.restore(&a.snapshot().expect("topk supports snapshots"))?;
+        a.accumulate(&[&Value::from("b"), &k])?;
+        b.accumulate(&[&Value::from("b"), &k])?;
+        assert_eq!(a.emit()?, b.emit()?);
+        assert!(b.restore(&Value::from("snot")).is_err());
+
+        let mut a = DistinctCount::default();
+        a.init();
+        let mut b = DistinctCount::default();
+        b.restore(&a.snapshot().expect("distinct_count supports snapshots"))?;
+        assert_eq!(b.emit()?, 0);
+        for i in 0..100 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        b.restore(&a.snapshot().expect("distinct_count supports snapshots"))?;
+        a.accumulate(&[&Value::from("snot")])?;
+        b.accumulate(&[&Value::from("snot")])?;
+        assert_eq!(a.emit()?, b.emit()?);
+        assert!(b.restore(&Value::from("snot")).is_err());
+
+        let mut a = Bloom::default();
+        a.init();
+        for i in 0..10 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let mut b = Bloom::default();
+        b.restore(&a.snapshot().expect("bloom supports snapshots"))?;
+        a.accumulate(&[&Value::from("snot")])?;
+        b.accumulate(&[&Value::from("snot")])?;
+        assert_eq!(a.emit()?, b.emit()?);
+        assert!(b.restore(&Value::from(vec![1, 2, 3, 4])).is_err());
+        Ok(())
+    }
 }
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::registry::{Aggr as AggrRegistry, FResult, TremorAggrFn, TremorAggrFnWrapper};
+use crate::registry::{
+    mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
+};
 
 use simd_json::prelude::*;
 use simd_json::BorrowedValue as Value;
@@ -67,6 +69,22 @@ impl TremorAggrFn for First {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        // we wrap the value in an array to tell `null` and no value apart
+        Some(Value::from(self.0.iter().cloned().collect::<Vec<_>>()))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        match snapshot.as_array().map(Vec::as_slice) {
+            Some([]) => self.0 = None,
+            Some([v]) => self.0 = Some(v.clone_static()),
+            _ => {
+                return Err(FunctionError::BadType {
+                    mfa: mfa("win", "first", 1),
+                })
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -115,6 +133,22 @@ impl TremorAggrFn for Last {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        // we wrap the value in an array to tell `null` and no value apart
+        Some(Value::from(self.0.iter().cloned().collect::<Vec<_>>()))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        match snapshot.as_array().map(Vec::as_slice) {
+            Some([]) => self.0 = None,
+            Some([v]) => self.0 = Some(v.clone_static()),
+            _ => {
+                return Err(FunctionError::BadType {
+                    mfa: mfa("win", "last", 1),
+                })
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -154,6 +188,18 @@ impl TremorAggrFn for CollectFlattened {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0.clone()))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = snapshot
+            .as_array()
+            .map(|a| a.iter().map(Value::clone_static).collect())
+            .ok_or_else(|| FunctionError::BadType {
+                mfa: mfa("win", "collect_flattened", 1),
+            })?;
+        Ok(())
+    }
     fn warning(&self) -> Option<String> {
         Some(String::from(
             "Collect functions are very expensive memory wise, try avoiding them.",
@@ -198,6 +244,18 @@ impl TremorAggrFn for CollectNested {
     fn arity(&self) -> RangeInclusive<usize> {
         1..=1
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0.clone()))
+    }
+    fn restore<'event>(&mut self, snapshot: &Value<'event>) -> FResult<()> {
+        self.0 = snapshot
+            .as_array()
+            .map(|a| a.iter().map(Value::clone_static).collect())
+            .ok_or_else(|| FunctionError::BadType {
+                mfa: mfa("win", "collect_nested", 1),
+            })?;
+        Ok(())
+    }
     fn warning(&self) -> Option<String> {
         Some(String::from(
             "Collect functions are very expensive memory wise, try avoiding them.",
@@ -256,8 +256,10 @@ impl Clone for SnotCombiner {
     }
 }
 
+/// Error of an extractor
 #[derive(Clone, Debug, PartialEq)] // , Deserialize)]
 pub struct ExtractorError {
+    /// The error message
     pub msg: String,
 }
 
@@ -557,10 +559,12 @@ impl PartialEq<Extractor> for Extractor {
     }
 }
 
+/// A network as parsed by the `cidr` extractor
 #[derive(Debug)]
 pub struct Cidr(pub IpCidr);
 
 impl Cidr {
+    /// Parses an address or network, e.g. `10.0.0.0/8`
     pub fn from_str(s: &str) -> Result<Self, ExtractorError> {
         if let Some(cidr) = parse_ipv4_fast(s) {
             Ok(Self(cidr))
@@ -572,6 +576,49 @@ impl Cidr {
     }
 }
 
+impl Cidr {
+    /// If the network contains `ip`
+    #[must_use]
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (&self.0, ip) {
+            (IpCidr::V4(cidr), IpAddr::V4(ip)) => {
+                let mask = cidr.get_mask_as_u8_array();
+                let prefix = cidr.get_prefix_as_u8_array();
+                ip.octets()
+                    .iter()
+                    .zip(prefix.iter().zip(mask.iter()))
+                    .all(|(ip, (prefix, mask))| ip & mask == prefix & mask)
+            }
+            (IpCidr::V6(cidr), IpAddr::V6(ip)) => {
+                let mask = cidr.get_mask_as_u16_array();
+                let prefix = cidr.get_prefix_as_u16_array();
+                ip.segments()
+                    .iter()
+                    .zip(prefix.iter().zip(mask.iter()))
+                    .all(|(ip, (prefix, mask))| ip & mask == prefix & mask)
+            }
+            _ => false,
+        }
+    }
+
+    /// The length of the network prefix in bits
+    #[must_use]
+    pub fn bits(&self) -> u32 {
+        match &self.0 {
+            IpCidr::V4(cidr) => cidr
+                .get_mask_as_u8_array()
+                .iter()
+                .map(|m| m.count_ones())
+                .sum(),
+            IpCidr::V6(cidr) => cidr
+                .get_mask_as_u16_array()
+                .iter()
+                .map(|m| m.count_ones())
+                .sum(),
+        }
+    }
+}
+
 impl std::ops::Deref for Cidr {
     type Target = IpCidr;
 
@@ -753,6 +800,24 @@ mod test {
             _ => unreachable!(),
         }
     }
+    #[test]
+    fn test_cidr_contains() -> Result<(), ExtractorError> {
+        let net = Cidr::from_str("10.22.0.0/16")?;
+        assert!(
+            net.contains(IpAddr::from_str("10.22.1.2").map_err(|_| ExtractorError {
+                msg: "bad ip".into()
+            })?)
+        );
+        assert!(
+            !net.contains(IpAddr::from_str("10.23.1.2").map_err(|_| ExtractorError {
+                msg: "bad ip".into()
+            })?)
+        );
+        assert_eq!(net.bits(), 16);
+        assert_eq!(Cidr::from_str("10.22.0.1")?.bits(), 32);
+        Ok(())
+    }
+
     #[test]
     fn test_cidr_extractor() {
         let ex = Extractor::new("cidr", "").expect("");